@@ -0,0 +1,34 @@
+// Writes the first 20 Fibonacci numbers to RAM[100]..RAM[119]
+    @100
+    D=A
+    @pointer
+    M=D
+    A=D
+    M=0
+    A=A+1
+    M=1
+    @18
+    D=A
+    @count
+    M=D
+(LOOP)
+    @count
+    D=M
+    @END
+    D;JEQ
+    @pointer
+    A=M
+    D=M
+    A=A+1
+    D=D+M
+    A=A+1
+    M=D
+    @pointer
+    M=M+1
+    @count
+    M=M-1
+    @LOOP
+    0;JMP
+(END)
+    @END
+    0;JMP
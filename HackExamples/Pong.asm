@@ -0,0 +1,222 @@
+// name: Pong
+// A minimal Pong: move the paddle with the left and right arrow keys
+    @15
+    D=A
+    @ball_x
+    M=D
+    @ball_y
+    M=0
+    @dx
+    M=1
+    @4
+    D=A
+    @dy
+    M=D
+    @14
+    D=A
+    @paddle_x
+    M=D
+(FRAME)
+    // erase the ball
+    @value
+    M=0
+    @ERASED
+    D=A
+    @return
+    M=D
+    @BALL
+    0;JMP
+(ERASED)
+    // move horizontally, bouncing off the side walls
+    @dx
+    D=M
+    @ball_x
+    MD=D+M
+    @FLIP_X
+    D;JEQ
+    @31
+    D=D-A
+    @FLIP_X
+    D;JEQ
+    @MOVE_Y
+    0;JMP
+(FLIP_X)
+    @dx
+    M=-M
+(MOVE_Y)
+    @dy
+    D=M
+    @ball_y
+    MD=D+M
+    @GO_DOWN
+    D;JLE
+    @240
+    D=D-A
+    @DRAW
+    D;JLT
+    // the ball reached the paddle row
+    @paddle_x
+    D=M
+    @ball_x
+    D=M-D
+    @MISSED
+    D;JLT
+    @4
+    D=D-A
+    @MISSED
+    D;JGE
+    @4
+    D=-A
+    @dy
+    M=D
+    @DRAW
+    0;JMP
+(MISSED)
+    @ball_y
+    M=0
+(GO_DOWN)
+    @4
+    D=A
+    @dy
+    M=D
+(DRAW)
+    @value
+    M=-1
+    @DRAWN
+    D=A
+    @return
+    M=D
+    @BALL
+    0;JMP
+(DRAWN)
+    // move the paddle
+    @KBD
+    D=M
+    @130
+    D=D-A
+    @LEFT
+    D;JEQ
+    @2
+    D=D-A
+    @RIGHT
+    D;JEQ
+    @PADDLE
+    0;JMP
+(LEFT)
+    @paddle_x
+    D=M
+    @PADDLE
+    D;JEQ
+    @paddle_x
+    M=M-1
+    @PADDLE
+    0;JMP
+(RIGHT)
+    @paddle_x
+    D=M
+    @28
+    D=D-A
+    @PADDLE
+    D;JEQ
+    @paddle_x
+    M=M+1
+(PADDLE)
+    // redraw the bottom 8 rows of the screen
+    @24320
+    D=A
+    @pointer
+    M=D
+(CLEAR)
+    @pointer
+    A=M
+    M=0
+    @pointer
+    MD=M+1
+    @KBD
+    D=D-A
+    @CLEAR
+    D;JLT
+    @paddle_x
+    D=M
+    @24320
+    D=D+A
+    @pointer
+    M=D
+    @8
+    D=A
+    @rows
+    M=D
+(PADDLE_ROW)
+    @pointer
+    A=M
+    M=-1
+    A=A+1
+    M=-1
+    A=A+1
+    M=-1
+    A=A+1
+    M=-1
+    @32
+    D=A
+    @pointer
+    M=D+M
+    @rows
+    MD=M-1
+    @PADDLE_ROW
+    D;JGT
+    @5000
+    D=A
+(DELAY)
+    D=D-1
+    @DELAY
+    D;JGT
+    @FRAME
+    0;JMP
+
+// Draws an 8 row high, one word wide block with the contents of `value` at the ball's position
+(BALL)
+    @ball_y
+    D=M
+    @pointer
+    M=D
+    @5
+    D=A
+    @rows
+    M=D
+(SHIFT)
+    @pointer
+    D=M
+    M=D+M
+    @rows
+    MD=M-1
+    @SHIFT
+    D;JGT
+    @ball_x
+    D=M
+    @pointer
+    M=D+M
+    @SCREEN
+    D=A
+    @pointer
+    M=D+M
+    @8
+    D=A
+    @rows
+    M=D
+(BALL_ROW)
+    @value
+    D=M
+    @pointer
+    A=M
+    M=D
+    @32
+    D=A
+    @pointer
+    M=D+M
+    @rows
+    MD=M-1
+    @BALL_ROW
+    D;JGT
+    @return
+    A=M
+    0;JMP
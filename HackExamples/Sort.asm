@@ -0,0 +1,77 @@
+// Fills RAM[200]..RAM[215] with scrambled numbers and bubble sorts them in place
+    @200
+    D=A
+    @pointer
+    M=D
+    @value
+    M=0
+(FILL)
+    @value
+    D=M
+    @pointer
+    A=M
+    M=D
+    @13
+    D=D+A
+    @31
+    D=D&A
+    @value
+    M=D
+    @pointer
+    MD=M+1
+    @216
+    D=D-A
+    @FILL
+    D;JLT
+(OUTER)
+    @swapped
+    M=0
+    @200
+    D=A
+    @pointer
+    M=D
+(INNER)
+    @pointer
+    D=M
+    @215
+    D=D-A
+    @INNER_END
+    D;JGE
+    @pointer
+    A=M
+    D=M
+    A=A+1
+    D=D-M
+    @NEXT
+    D;JLE
+    @pointer
+    A=M
+    D=M
+    @temp
+    M=D
+    @pointer
+    A=M+1
+    D=M
+    @pointer
+    A=M
+    M=D
+    @temp
+    D=M
+    @pointer
+    A=M+1
+    M=D
+    @swapped
+    M=1
+(NEXT)
+    @pointer
+    M=M+1
+    @INNER
+    0;JMP
+(INNER_END)
+    @swapped
+    D=M
+    @OUTER
+    D;JNE
+(END)
+    @END
+    0;JMP
@@ -0,0 +1,41 @@
+// Draws diagonal stripes over the whole screen
+    @SCREEN
+    D=A
+    @pointer
+    M=D
+    @pattern
+    M=1
+(ROW)
+    @32
+    D=A
+    @column
+    M=D
+(COLUMN)
+    @pattern
+    D=M
+    @pointer
+    A=M
+    M=D
+    @pointer
+    M=M+1
+    @column
+    MD=M-1
+    @COLUMN
+    D;JGT
+    @pattern
+    D=M
+    MD=D+M
+    @SKIP
+    D;JNE
+    @pattern
+    M=1
+(SKIP)
+    @pointer
+    D=M
+    @KBD
+    D=D-A
+    @ROW
+    D;JLT
+(END)
+    @END
+    0;JMP
@@ -0,0 +1,125 @@
+use std::ops::Range;
+
+// A note shown while the step count is in `steps`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub steps: Range<u64>,
+    pub note: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnnotationScript {
+    pub annotations: Vec<Annotation>,
+}
+
+fn parse_steps(header: &str) -> Result<(u64, Option<u64>), String> {
+    let parse_step = |step: &str| {
+        step.trim()
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid step \"{}\"", step.trim()))
+    };
+    match header.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse_step(start)?, parse_step(end)?);
+            if end < start {
+                return Err(format!(
+                    "Step range {}-{} ends before it starts",
+                    start, end
+                ));
+            }
+            Ok((start, Some(end + 1)))
+        }
+        None => Ok((parse_step(header)?, None)),
+    }
+}
+
+impl AnnotationScript {
+    // Each note starts with a `[start-end]` or `[start]` line and runs until the next one. A note
+    // without an end lasts until the next note starts.
+    pub fn new(input: &str) -> Result<Self, String> {
+        let mut sections: Vec<(u64, Option<u64>, Vec<&str>)> = vec![];
+        for (line_index, line) in input.lines().enumerate() {
+            let header = line
+                .trim()
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'));
+            match (header, sections.last_mut()) {
+                (Some(header), _) => {
+                    let (start, end) = parse_steps(header)
+                        .map_err(|error| format!("Line {}: {}", line_index + 1, error))?;
+                    sections.push((start, end, vec![]));
+                }
+                (None, Some((_, _, lines))) => lines.push(line),
+                (None, None) if line.trim().is_empty() => {}
+                (None, None) => {
+                    return Err(format!(
+                        "Line {}: Notes must start with a [start-end] line",
+                        line_index + 1
+                    ))
+                }
+            }
+        }
+        sections.sort_by_key(|(start, _, _)| *start);
+
+        let next_starts: Vec<_> = sections
+            .iter()
+            .skip(1)
+            .map(|(start, _, _)| *start)
+            .chain([u64::MAX])
+            .collect();
+        let annotations = sections
+            .into_iter()
+            .zip(next_starts)
+            .map(|((start, end, lines), next_start)| Annotation {
+                steps: start..end.unwrap_or(next_start.max(start + 1)),
+                note: lines.join("\n").trim().to_owned(),
+            })
+            .collect();
+
+        Ok(AnnotationScript { annotations })
+    }
+
+    pub fn active(&self, ticks: u64) -> impl Iterator<Item = &Annotation> {
+        self.annotations
+            .iter()
+            .filter(move |annotation| annotation.steps.contains(&ticks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotation_script() {
+        let script = AnnotationScript::new(
+            "[0-2]\n# Setup\nThe first steps.\n\n[10]\nThe loop.\n[5]\nIn between.\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            script.annotations,
+            vec![
+                Annotation {
+                    steps: 0..3,
+                    note: "# Setup\nThe first steps.".to_owned(),
+                },
+                Annotation {
+                    steps: 5..10,
+                    note: "In between.".to_owned(),
+                },
+                Annotation {
+                    steps: 10..u64::MAX,
+                    note: "The loop.".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(script.active(2).count(), 1);
+        assert_eq!(script.active(3).count(), 0);
+        assert_eq!(script.active(1000).next().unwrap().note, "The loop.");
+
+        assert!(AnnotationScript::new("Notes\n[0]\n").is_err());
+        assert!(AnnotationScript::new("[5-2]\n").is_err());
+        assert!(AnnotationScript::new("[x]\n").is_err());
+    }
+}
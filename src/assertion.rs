@@ -0,0 +1,93 @@
+use crate::expression::{ExpressionContext, Invariant};
+
+const ASSERT_DIRECTIVE: &str = "@assert";
+
+// An `// @assert <expression>` comment, checked before the instruction or command it is on or
+// precedes is executed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assertion {
+    pub line: usize,
+    pub index: usize,
+    pub invariant: Invariant,
+}
+
+impl Assertion {
+    pub fn check(&self, context: &impl ExpressionContext) -> Option<String> {
+        self.invariant
+            .check(context)
+            .map(|report| format!("Assertion on line {} failed: {}", self.line, report))
+    }
+}
+
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match line.split_once("//") {
+        Some((code, comment)) => (code.trim(), Some(comment.trim())),
+        None => (line.trim(), None),
+    }
+}
+
+// `is_instruction` tells apart code lines that produce an instruction, e.g. not labels.
+pub fn parse_assertions(
+    input: &str,
+    is_instruction: impl Fn(&str) -> bool,
+) -> Result<Vec<Assertion>, String> {
+    let mut assertions = vec![];
+    let mut pending = vec![];
+    let mut index = 0;
+    for (line_index, line) in input.lines().enumerate() {
+        let (code, comment) = split_comment(line);
+        if let Some(source) = comment.and_then(|comment| comment.strip_prefix(ASSERT_DIRECTIVE)) {
+            let invariant = Invariant::new(source).map_err(|error| {
+                format!("Invalid assertion on line {}: {}", line_index + 1, error)
+            })?;
+            pending.push((line_index + 1, invariant));
+        }
+        if !code.is_empty() && is_instruction(code) {
+            assertions.extend(pending.drain(..).map(|(line, invariant)| Assertion {
+                line,
+                index,
+                invariant,
+            }));
+            index += 1;
+        }
+    }
+    assertions.extend(pending.into_iter().map(|(line, invariant)| Assertion {
+        line,
+        index,
+        invariant,
+    }));
+
+    Ok(assertions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::Hardware;
+
+    #[test]
+    fn test_parse_assertions() {
+        let program = "@2\n// @assert A == 2\n(LOOP)\nD=A // @assert D == 0\n0;JMP\n";
+        let assertions = parse_assertions(program, |code| !code.starts_with('(')).unwrap();
+
+        assert_eq!(
+            assertions
+                .iter()
+                .map(|assertion| (assertion.line, assertion.index))
+                .collect::<Vec<_>>(),
+            vec![(2, 1), (4, 1)]
+        );
+
+        let hardware = Hardware {
+            a: 2,
+            d: 1,
+            ..Default::default()
+        };
+        assert_eq!(assertions[0].check(&hardware), None);
+        assert_eq!(
+            assertions[1].check(&hardware),
+            Some("Assertion on line 4 failed: D == 0 violated (D = 1)".to_owned())
+        );
+        assert!(parse_assertions("// @assert D ==", |_| true).is_err());
+    }
+}
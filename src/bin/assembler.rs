@@ -0,0 +1,89 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+use std::path::{Path, PathBuf};
+
+use nand2tetris::hardware::Hardware;
+use nand2tetris::hardware_parse::{expand_includes, hack_binary, AssemblerMode};
+
+const USAGE: &str = "usage: nand2rust-asm [--strict] <file.asm> [-o <file.hack>]";
+
+// Assembles an .asm file into a .hack file next to it, or where -o says. Exits with 1 when the
+// program doesn't assemble and with 2 when the arguments are wrong.
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(assemble(&args));
+}
+
+fn assemble(args: &[String]) -> i32 {
+    let (mode, args) = match args {
+        [flag, rest @ ..] if flag == "--strict" => (AssemblerMode::Strict, rest),
+        _ => (AssemblerMode::Lenient, args),
+    };
+    let (asm_path, hack_path) = match args {
+        [asm_path] => (
+            Path::new(asm_path),
+            Path::new(asm_path).with_extension("hack"),
+        ),
+        [asm_path, flag, hack_path] if flag == "-o" => {
+            (Path::new(asm_path), PathBuf::from(hack_path))
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    if asm_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        != Some("asm")
+    {
+        eprintln!("{}: not an .asm file", asm_path.display());
+        return 2;
+    }
+
+    let contents = match std::fs::read_to_string(asm_path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            eprintln!("{}: {}", asm_path.display(), error);
+            return 1;
+        }
+    };
+    // Included files are looked for next to the assembled one.
+    let directory = asm_path.parent().unwrap_or(Path::new(""));
+    let name = asm_path.file_name().unwrap_or_default().to_string_lossy();
+    let hardware = match expand_includes(&name, &contents, &mut |included| {
+        std::fs::read_to_string(directory.join(included))
+            .map_err(|error| format!("{}: {}", included, error))
+    })
+    .map_err(|error| vec![error])
+    .and_then(|expansion| {
+        mode.source(&expansion.text)
+            .and_then(|source| Hardware::try_from_file_contents(&source))
+            .map_err(|errors| {
+                errors
+                    .into_iter()
+                    .map(|error| expansion.top_error(error))
+                    .collect()
+            })
+    }) {
+        Ok(hardware) => hardware,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}:{}: {}", asm_path.display(), error.line, error.message);
+            }
+            return 1;
+        }
+    };
+    if !hardware.data.is_empty() {
+        eprintln!(
+            "{}: .hack files only hold the ROM, the data directives are left out",
+            asm_path.display()
+        );
+    }
+
+    if let Err(error) = std::fs::write(&hack_path, hack_binary(&hardware.rom[..hardware.length])) {
+        eprintln!("{}: {}", hack_path.display(), error);
+        return 1;
+    }
+    0
+}
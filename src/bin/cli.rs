@@ -0,0 +1,11 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+// The emulator's subcommands in a console program, which Windows shows the output of.
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let status = nand2tetris::cli::run_subcommand(&args).unwrap_or_else(|| {
+        eprintln!("{}", nand2tetris::cli::USAGE);
+        2
+    });
+    std::process::exit(status);
+}
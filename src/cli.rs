@@ -0,0 +1,290 @@
+use crate::exam_archive::{pack_exam, SALT_LENGTH};
+use crate::format::{format_asm, format_vm};
+use crate::hardware_parse::AllocationOrder;
+use crate::project::{parse_project, run_headless, DEFAULT_HEADLESS_STEPS};
+use crate::selftest::{run_self_test, self_test_report};
+use crate::semantic_diff::{check_assembler_output, load_diff_program, semantic_diff};
+use crate::thread_tuning::ThreadTuning;
+
+// The subcommands, taken by both the emulator's binary and nand2tetris-cli. Release builds of the
+// emulator have no console on Windows, so nand2tetris-cli is the one to run them from there.
+pub const USAGE: &str =
+    "usage: nand2tetris <fmt|diff|check|selftest|run|pack|register> <argument>...";
+
+// Returns the exit code, none when the arguments don't start with a subcommand.
+pub fn run_subcommand(args: &[String]) -> Option<i32> {
+    let (command, args) = args.split_first()?;
+    Some(match command.as_str() {
+        "fmt" => fmt(args),
+        "diff" => diff(args),
+        "check" => check(args),
+        "selftest" => selftest(),
+        "run" => run(args),
+        "pack" => pack(args),
+        #[cfg(feature = "emulator")]
+        "register" => register(),
+        _ => return None,
+    })
+}
+
+// Formats .asm and .vm files in place, with --check it only lists the files that would change.
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<_> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        eprintln!("usage: nand2tetris fmt [--check] <file.asm|file.vm>...");
+        return 2;
+    }
+    let mut status = 0;
+    for path in paths {
+        let format = match std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("asm") => format_asm,
+            Some("vm") => format_vm,
+            _ => {
+                eprintln!("{}: not an .asm or .vm file", path);
+                status = 1;
+                continue;
+            }
+        };
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                status = 1;
+                continue;
+            }
+        };
+        let formatted = format(&contents);
+        if formatted == contents {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            status = 1;
+        } else if let Err(error) = std::fs::write(path, formatted) {
+            eprintln!("{}: {}", path, error);
+            status = 1;
+        }
+    }
+    status
+}
+
+// Compares two .asm or .hack programs, ignoring label names and the order variables were allocated
+// in.
+fn diff(args: &[String]) -> i32 {
+    let [left, right] = args else {
+        eprintln!("usage: nand2tetris diff <file.asm|file.hack> <file.asm|file.hack>");
+        return 2;
+    };
+    let load = |path: &String| {
+        std::fs::read_to_string(path)
+            .map_err(|error| format!("{}: {}", path, error))
+            .and_then(|contents| load_diff_program(path, &contents))
+    };
+    let (left, right) = match (load(left), load(right)) {
+        (Ok(left), Ok(right)) => (left, right),
+        (Err(error), _) | (_, Err(error)) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+    let differences = semantic_diff(&left, &right);
+    if differences.is_empty() {
+        println!("No behavioral differences");
+        return 0;
+    }
+    for difference in differences {
+        println!("{}", difference);
+    }
+    1
+}
+
+// Checks a .hack file against what the built-in assembler makes of the .asm source, with --order
+// the variables have to be allocated in that order too.
+fn check(args: &[String]) -> i32 {
+    let usage = || {
+        eprintln!(
+            "usage: nand2tetris check [--order first-appearance|alphabetical] <file.asm> <file.hack>"
+        );
+        2
+    };
+    let (order, args) = match args {
+        [flag, order, rest @ ..] if flag == "--order" => match order.parse::<AllocationOrder>() {
+            Ok(order) => (Some(order), rest),
+            Err(error) => {
+                eprintln!("{}", error);
+                return usage();
+            }
+        },
+        _ => (None, args),
+    };
+    let [asm_path, hack_path] = args else {
+        return usage();
+    };
+    let read = |path: &String| {
+        std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))
+    };
+    let result = read(asm_path).and_then(|asm| {
+        let hack = read(hack_path)?;
+        check_assembler_output(&asm, &hack, order)
+    });
+    match result {
+        Ok(None) => {
+            println!("{} matches {}", hack_path, asm_path);
+            0
+        }
+        Ok(Some(mismatch)) => {
+            println!("{}", mismatch);
+            1
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            2
+        }
+    }
+}
+
+// Runs the built-in checks of the CPU, the VM, the translator and the native OS, for builds that
+// might have been miscompiled.
+fn selftest() -> i32 {
+    let results = run_self_test();
+    print!("{}", self_test_report(&results));
+    if results.iter().all(|result| result.failure.is_none()) {
+        0
+    } else {
+        1
+    }
+}
+
+// Runs a .n2r project without the emulator and prints how it stopped and RAM[0] to RAM[15].
+// --core and --high-priority tune the thread it runs on, for steadier benchmarks.
+fn run(args: &[String]) -> i32 {
+    let usage = || {
+        eprintln!(
+            "usage: nand2tetris run [--steps <count>] [--core <index>] [--high-priority] \
+             <project.n2r>"
+        );
+        2
+    };
+    let mut max_steps = DEFAULT_HEADLESS_STEPS;
+    let mut tuning = ThreadTuning::default();
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--steps" => match args.next().and_then(|steps| steps.parse().ok()) {
+                Some(steps) => max_steps = steps,
+                None => return usage(),
+            },
+            "--core" => match args.next().and_then(|core| core.parse().ok()) {
+                Some(core) => tuning.core = Some(core),
+                None => return usage(),
+            },
+            "--high-priority" => tuning.high_priority = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return usage(),
+        }
+    }
+    let Some(path) = path else {
+        return usage();
+    };
+    if tuning != ThreadTuning::default() {
+        if let Err(error) = tuning.apply() {
+            eprintln!("{}", error);
+            return 2;
+        }
+    }
+    let path = std::path::Path::new(path);
+    let directory = path.parent().unwrap_or(std::path::Path::new(""));
+    let result = std::fs::read_to_string(path)
+        .map_err(|error| format!("{}: {}", path.display(), error))
+        .and_then(|contents| parse_project(&contents))
+        .and_then(|project| {
+            let sources = project.read_sources(directory)?;
+            run_headless(&project, sources, max_steps)
+        });
+    match result {
+        Ok(run) => {
+            print!("{}", run);
+            0
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            2
+        }
+    }
+}
+
+// Packs programs into a password-protected exam archive that the emulator loads with File > Load
+// Exam.
+fn pack(args: &[String]) -> i32 {
+    let (password, output, paths) = match args {
+        [flag, password, output_flag, output, paths @ ..]
+            if flag == "--password" && output_flag == "--output" && !paths.is_empty() =>
+        {
+            (password, output, paths)
+        }
+        _ => {
+            eprintln!(
+                "usage: nand2tetris pack --password <password> --output <exam.n2x> <file>..."
+            );
+            return 2;
+        }
+    };
+    let files: Result<Vec<_>, String> = paths
+        .iter()
+        .map(|path| {
+            let path = std::path::Path::new(path);
+            let contents = std::fs::read_to_string(path)
+                .map_err(|error| format!("{}: {}", path.display(), error))?;
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            Ok((name, contents))
+        })
+        .collect();
+    let files = match files {
+        Ok(files) => files,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+    let mut salt = [0; SALT_LENGTH];
+    if let Err(error) = getrandom::getrandom(&mut salt) {
+        eprintln!("Failed to get random bytes for the salt: {}", error);
+        return 2;
+    }
+    match std::fs::write(output, pack_exam(&files, password, &salt)) {
+        Ok(()) => {
+            println!("Packed {} files into {}", files.len(), output);
+            0
+        }
+        Err(error) => {
+            eprintln!("{}: {}", output, error);
+            2
+        }
+    }
+}
+
+// Registers the emulator as the program opening .hack and .vm files for the current user, .asm
+// files only get it in their Open With menu.
+#[cfg(feature = "emulator")]
+fn register() -> i32 {
+    match crate::emulator::register_file_associations() {
+        Ok(notes) => {
+            for note in notes {
+                println!("{}", note);
+            }
+            0
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::io::{Read, Write};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::recording::TraceEntry;
+
+pub const BLOCK_ENTRIES: usize = 1 << 16;
+
+const MAGIC: &[u8; 8] = b"N2RTRACE";
+
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+pub(crate) fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// Entries are stored as step deltas and locations, which are mostly small and repetitive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Block {
+    first_step: u64,
+    entry_count: usize,
+    compressed: Vec<u8>,
+}
+
+impl Block {
+    fn new(entries: &[TraceEntry]) -> Self {
+        let mut bytes = vec![];
+        let mut previous_step = entries[0].step;
+        for entry in entries {
+            write_varint(&mut bytes, entry.step - previous_step);
+            write_varint(&mut bytes, entry.location as u64);
+            previous_step = entry.step;
+        }
+        let mut encoder = DeflateEncoder::new(vec![], Compression::fast());
+        encoder
+            .write_all(&bytes)
+            .expect("writing to a Vec can't fail");
+
+        Block {
+            first_step: entries[0].step,
+            entry_count: entries.len(),
+            compressed: encoder.finish().expect("writing to a Vec can't fail"),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<TraceEntry>, String> {
+        let mut bytes = vec![];
+        DeflateDecoder::new(self.compressed.as_slice())
+            .read_to_end(&mut bytes)
+            .map_err(|error| format!("Corrupt trace block: {}", error))?;
+        let mut bytes = bytes.into_iter();
+        let mut step = self.first_step;
+        (0..self.entry_count)
+            .map(|_| {
+                let (Some(delta), Some(location)) =
+                    (read_varint(&mut bytes), read_varint(&mut bytes))
+                else {
+                    return Err("Truncated trace block".to_owned());
+                };
+                step += delta;
+                Ok(TraceEntry {
+                    step,
+                    location: location as usize,
+                })
+            })
+            .collect()
+    }
+}
+
+// A complete trace compressed in blocks as it is recorded. The first step of every block is
+// kept uncompressed, so looking up a step only decompresses the block containing it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompressedTrace {
+    blocks: Vec<Block>,
+    pending: Vec<TraceEntry>,
+}
+
+impl CompressedTrace {
+    // Steps must be pushed in increasing order.
+    pub fn push(&mut self, entry: TraceEntry) {
+        self.pending.push(entry);
+        if self.pending.len() == BLOCK_ENTRIES {
+            self.blocks.push(Block::new(&self.pending));
+            self.pending.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| block.entry_count)
+            .sum::<usize>()
+            + self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn compressed_size(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| block.compressed.len())
+            .sum::<usize>()
+            + self.pending.len() * std::mem::size_of::<TraceEntry>()
+    }
+
+    // Drops whole blocks from the start until the trace fits in `max_bytes`, the entries that
+    // aren't in a block yet always stay.
+    pub fn trim_to(&mut self, max_bytes: usize) {
+        let mut size = self.compressed_size();
+        let excess = self
+            .blocks
+            .iter()
+            .take_while(|block| {
+                let over = size > max_bytes;
+                size -= block.compressed.len();
+                over
+            })
+            .count();
+        self.blocks.drain(..excess);
+    }
+
+    // Entries with steps in `start..end`.
+    pub fn range(&self, start: u64, end: u64) -> Result<Vec<TraceEntry>, String> {
+        let first_block = self
+            .blocks
+            .partition_point(|block| block.first_step <= start)
+            .saturating_sub(1);
+        let mut entries = vec![];
+        for block in self.blocks[first_block..]
+            .iter()
+            .take_while(|block| block.first_step < end)
+        {
+            entries.extend(
+                block
+                    .entries()?
+                    .into_iter()
+                    .filter(|entry| (start..end).contains(&entry.step)),
+            );
+        }
+        entries.extend(
+            self.pending
+                .iter()
+                .filter(|entry| (start..end).contains(&entry.step)),
+        );
+
+        Ok(entries)
+    }
+
+    pub fn entry_at_step(&self, step: u64) -> Result<Option<TraceEntry>, String> {
+        Ok(self.range(step, step + 1)?.first().copied())
+    }
+
+    // The header is followed by an index of the blocks, so readers can find a step without
+    // decompressing everything before it.
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let mut blocks = self.blocks.clone();
+        if !self.pending.is_empty() {
+            blocks.push(Block::new(&self.pending));
+        }
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(blocks.len() as u64).to_le_bytes())?;
+        for block in &blocks {
+            writer.write_all(&block.first_step.to_le_bytes())?;
+            writer.write_all(&(block.entry_count as u64).to_le_bytes())?;
+            writer.write_all(&(block.compressed.len() as u64).to_le_bytes())?;
+        }
+        for block in &blocks {
+            writer.write_all(&block.compressed)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, String> {
+        let read_u64 = |reader: &mut dyn Read| {
+            let mut bytes = [0; 8];
+            reader
+                .read_exact(&mut bytes)
+                .map(|_| u64::from_le_bytes(bytes))
+                .map_err(|error| format!("Failed to read trace: {}", error))
+        };
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|error| format!("Failed to read trace: {}", error))?;
+        if &magic != MAGIC {
+            return Err("Not a trace file".to_owned());
+        }
+        let block_count = read_u64(reader)?;
+        let mut index = vec![];
+        for _ in 0..block_count {
+            index.push((read_u64(reader)?, read_u64(reader)?, read_u64(reader)?));
+        }
+        let mut blocks = vec![];
+        for (first_step, entry_count, compressed_len) in index {
+            let mut compressed = vec![];
+            reader
+                .take(compressed_len)
+                .read_to_end(&mut compressed)
+                .map_err(|error| format!("Failed to read trace: {}", error))?;
+            if compressed.len() as u64 != compressed_len {
+                return Err("Truncated trace file".to_owned());
+            }
+            blocks.push(Block {
+                first_step,
+                entry_count: entry_count as usize,
+                compressed,
+            });
+        }
+
+        Ok(CompressedTrace {
+            blocks,
+            pending: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_trace() {
+        let mut trace = CompressedTrace::default();
+        let step_count = BLOCK_ENTRIES as u64 * 2 + 100;
+        for step in (0..step_count).filter(|step| step % 1000 != 999) {
+            trace.push(TraceEntry {
+                step,
+                location: (step % 7) as usize,
+            });
+        }
+
+        assert_eq!(trace.len(), 131041);
+        // A full block of a tight loop takes less than a byte per step.
+        assert!(trace.blocks[0].compressed.len() < BLOCK_ENTRIES);
+        assert_eq!(
+            trace.entry_at_step(70000).unwrap(),
+            Some(TraceEntry {
+                step: 70000,
+                location: 0,
+            })
+        );
+        assert_eq!(trace.entry_at_step(999).unwrap(), None);
+        assert_eq!(
+            trace
+                .range(BLOCK_ENTRIES as u64 * 2 - 1, step_count)
+                .unwrap()
+                .len(),
+            101
+        );
+
+        let mut bytes = vec![];
+        trace.write(&mut bytes).unwrap();
+        let read = CompressedTrace::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.len(), trace.len());
+        assert_eq!(read.range(0, step_count), trace.range(0, step_count));
+        assert!(CompressedTrace::read(&mut &bytes[..20]).is_err());
+        assert!(CompressedTrace::read(&mut "not a trace".as_bytes()).is_err());
+    }
+}
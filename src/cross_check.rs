@@ -0,0 +1,218 @@
+use crate::expression::is_address;
+use crate::hardware::{Emulator, InstructionType, Word, MEM_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChipOutput {
+    A,
+    D,
+    PC,
+    RAM(Word),
+}
+
+impl std::fmt::Display for ChipOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChipOutput::A => write!(f, "A"),
+            ChipOutput::D => write!(f, "D"),
+            ChipOutput::PC => write!(f, "PC"),
+            ChipOutput::RAM(address) => write!(f, "RAM[{}]", address),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: u64,
+    pub pc: Word,
+    pub output: ChipOutput,
+    pub expected: Word,
+    pub actual: Word,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "step {} (ROM[{}]): {} is {}, expected {}",
+            self.step, self.pc, self.output, self.actual, self.expected
+        )
+    }
+}
+
+fn compare(
+    step: u64,
+    pc: Word,
+    output: ChipOutput,
+    expected: Word,
+    actual: Word,
+) -> Option<Divergence> {
+    (expected != actual).then_some(Divergence {
+        step,
+        pc,
+        output,
+        expected,
+        actual,
+    })
+}
+
+fn compare_registers(
+    step: u64,
+    pc: Word,
+    reference: &impl Emulator,
+    candidate: &impl Emulator,
+) -> Option<Divergence> {
+    compare(step, pc, ChipOutput::PC, reference.pc(), candidate.pc())
+        .or_else(|| compare(step, pc, ChipOutput::A, reference.a(), candidate.a()))
+        .or_else(|| compare(step, pc, ChipOutput::D, reference.d(), candidate.d()))
+}
+
+fn compare_memory(
+    step: u64,
+    pc: Word,
+    reference: &impl Emulator,
+    candidate: &impl Emulator,
+) -> Option<Divergence> {
+    (0..MEM_SIZE).find_map(|address| {
+        let address = address as Word;
+        compare(
+            step,
+            pc,
+            ChipOutput::RAM(address),
+            reference.get_ram_value(address),
+            candidate.get_ram_value(address),
+        )
+    })
+}
+
+// Runs both emulators in lockstep and reports the first output of `candidate` that differs
+// from `reference`. Hack only ever writes to RAM[A], so after a step writing M only the cell
+// addressed by A before the step is compared; the whole RAM is compared before the first and
+// after the last step.
+//
+// Meant for checking another implementation of the Hack CPU against Hardware, such as a
+// gate-level one. Hardware is the only implementation so far.
+pub fn run_lockstep(
+    reference: &mut impl Emulator,
+    candidate: &mut impl Emulator,
+    step_count: u64,
+) -> Option<Divergence> {
+    if let Some(divergence) = compare_registers(0, reference.pc(), reference, candidate)
+        .or_else(|| compare_memory(0, reference.pc(), reference, candidate))
+    {
+        return Some(divergence);
+    }
+
+    for step in 1..=step_count {
+        let pc = reference.pc();
+        let written_address = reference
+            .current_instruction()
+            .filter(|instruction| {
+                instruction.instruction_type() == InstructionType::C && instruction.dst_has_m()
+            })
+            .map(|_| reference.a())
+            .filter(|&address| is_address(address as i64));
+
+        reference.step();
+        candidate.step();
+
+        if let Some(divergence) = compare_registers(step, pc, reference, candidate) {
+            return Some(divergence);
+        }
+
+        if let Some(divergence) = written_address.and_then(|address| {
+            compare(
+                step,
+                pc,
+                ChipOutput::RAM(address),
+                reference.get_ram_value(address),
+                candidate.get_ram_value(address),
+            )
+        }) {
+            return Some(divergence);
+        }
+    }
+
+    compare_memory(step_count, reference.pc(), reference, candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{Hardware, Instruction};
+
+    const PROGRAM: [u16; 14] = [
+        15, 60040, 14, 64528, 15, 58114, 13, 64528, 15, 61576, 14, 64648, 2, 60039,
+    ];
+
+    fn load(hardware: &mut Hardware, program: &[u16]) {
+        hardware.load_program(program.iter().copied().map(Instruction::from_legacy));
+        hardware.set_ram_value(13, 34);
+        hardware.set_ram_value(14, 12);
+    }
+
+    #[test]
+    fn test_identical_programs() {
+        let mut reference = Hardware::default();
+        let mut candidate = Hardware::default();
+        load(&mut reference, &PROGRAM);
+        load(&mut candidate, &PROGRAM);
+
+        assert_eq!(run_lockstep(&mut reference, &mut candidate, 200), None);
+        assert_eq!(candidate.get_ram_value(15), 34 * 12);
+    }
+
+    #[test]
+    fn test_faulty_register() {
+        let mut faulty_program = PROGRAM;
+        // D=M+1 instead of D=M
+        faulty_program[3] = 64976;
+
+        let mut reference = Hardware::default();
+        let mut candidate = Hardware::default();
+        load(&mut reference, &PROGRAM);
+        load(&mut candidate, &faulty_program);
+
+        assert_eq!(
+            run_lockstep(&mut reference, &mut candidate, 200),
+            Some(Divergence {
+                step: 4,
+                pc: 3,
+                output: ChipOutput::D,
+                expected: 12,
+                actual: 13,
+            })
+        );
+    }
+
+    #[test]
+    fn test_negative_a() {
+        // @32767, A=!A, D=A: A is negative when D=A runs, which doesn't write M.
+        let program = [32767, 60512, 60432];
+        let mut reference = Hardware::default();
+        let mut candidate = Hardware::default();
+        load(&mut reference, &program);
+        load(&mut candidate, &program);
+
+        assert_eq!(run_lockstep(&mut reference, &mut candidate, 3), None);
+    }
+
+    #[test]
+    fn test_faulty_memory() {
+        let mut reference = Hardware::default();
+        let mut candidate = Hardware::default();
+        load(&mut reference, &PROGRAM);
+        load(&mut candidate, &PROGRAM);
+        candidate.set_ram_value(1000, 1);
+
+        assert_eq!(
+            run_lockstep(&mut reference, &mut candidate, 200),
+            Some(Divergence {
+                step: 0,
+                pc: 0,
+                output: ChipOutput::RAM(1000),
+                expected: 0,
+                actual: 1,
+            })
+        );
+    }
+}
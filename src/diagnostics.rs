@@ -0,0 +1,384 @@
+use hashbrown::{HashMap, HashSet};
+
+use crate::expression::is_address;
+use crate::hardware::{Address, Hardware, Word, RAM};
+use crate::vm::{RunState, VMCommand, VM};
+
+pub(crate) const STACK_START: Word = Address::STACK_START.word();
+pub(crate) const STACK_END: Word = Address::HEAP_START.word() - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DiagnosticCategory {
+    UninitializedRead,
+    StackPointer,
+    MemoryMappedIO,
+    CallReturn,
+}
+
+impl DiagnosticCategory {
+    pub const ALL: [DiagnosticCategory; 4] = [
+        DiagnosticCategory::UninitializedRead,
+        DiagnosticCategory::StackPointer,
+        DiagnosticCategory::MemoryMappedIO,
+        DiagnosticCategory::CallReturn,
+    ];
+}
+
+impl std::fmt::Display for DiagnosticCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticCategory::UninitializedRead => write!(f, "Uninitialized reads"),
+            DiagnosticCategory::StackPointer => write!(f, "Stack pointer anomalies"),
+            DiagnosticCategory::MemoryMappedIO => write!(f, "Suspicious memory mapped I/O"),
+            DiagnosticCategory::CallReturn => write!(f, "Call/return mismatches"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "Warning"),
+            Severity::Error => write!(f, "Error"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub category: DiagnosticCategory,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+// Categories without a severity are disabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiagnosticsConfig {
+    severities: HashMap<DiagnosticCategory, Severity>,
+}
+
+impl DiagnosticsConfig {
+    pub fn severity(&self, category: DiagnosticCategory) -> Option<Severity> {
+        self.severities.get(&category).copied()
+    }
+
+    pub fn set_severity(&mut self, category: DiagnosticCategory, severity: Option<Severity>) {
+        match severity {
+            Some(severity) => self.severities.insert(category, severity),
+            None => self.severities.remove(&category),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.severities.is_empty()
+    }
+}
+
+const SAVED_FRAME_NAMES: [&str; 5] = ["return address", "LCL", "ARG", "THIS", "THAT"];
+
+// What a call pushed, as the matching return should find it.
+#[derive(Clone)]
+struct SavedFrame {
+    function_name: String,
+    // Where LCL points after the call: just past the saved values.
+    address: Word,
+    saved: [Word; 5],
+}
+
+// Every problem is reported once per address until reset, so that hot loops don't flood the
+// log and a run stopped by an error can be resumed.
+#[derive(Clone, Default)]
+pub struct Diagnostics {
+    pub config: DiagnosticsConfig,
+    written: HashSet<Word>,
+    reported: HashSet<(DiagnosticCategory, Word)>,
+    frames: Vec<SavedFrame>,
+    // A call to a VM function only gets a frame once the call stack grows, native OS calls don't.
+    pending_call: Option<(usize, SavedFrame)>,
+}
+
+impl Diagnostics {
+    pub fn new(config: DiagnosticsConfig) -> Self {
+        Diagnostics {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.written.clear();
+        self.reported.clear();
+        self.frames.clear();
+        self.pending_call = None;
+    }
+
+    fn report(
+        &mut self,
+        category: DiagnosticCategory,
+        address: Word,
+        message: impl FnOnce() -> String,
+    ) -> Option<Diagnostic> {
+        let severity = self.config.severity(category)?;
+        self.reported
+            .insert((category, address))
+            .then(|| Diagnostic {
+                category,
+                severity,
+                message: message(),
+            })
+    }
+
+    fn check_stack_pointer(&mut self, ram: &RAM) -> Option<Diagnostic> {
+        let sp = ram[0];
+        // Programs that don't use the stack leave SP at 0.
+        if sp == 0 || (STACK_START..=STACK_END + 1).contains(&sp) {
+            return None;
+        }
+        self.report(DiagnosticCategory::StackPointer, 0, || {
+            format!(
+                "SP is {}, outside the stack at {}-{}",
+                sp, STACK_START, STACK_END
+            )
+        })
+    }
+
+    // Checks the instruction that is about to execute.
+    pub fn check_hardware(&mut self, hardware: &Hardware) -> Vec<Diagnostic> {
+        let pc = hardware.pc;
+        let a = hardware.a;
+        let instruction = hardware.rom[pc as usize];
+        let mut diagnostics = vec![];
+
+        if instruction.reads_m() && (16..RAM::SCREEN).contains(&a) && !self.written.contains(&a) {
+            diagnostics.extend(self.report(DiagnosticCategory::UninitializedRead, a, || {
+                format!(
+                    "ROM[{}] reads RAM[{}] before anything was written to it",
+                    pc, a
+                )
+            }));
+        }
+        if instruction.dst_has_m() && is_address(a as i64) {
+            self.written.insert(a);
+            if a == RAM::KBD {
+                diagnostics.extend(self.report(DiagnosticCategory::MemoryMappedIO, a, || {
+                    format!(
+                        "ROM[{}] writes to the keyboard register, which is read only",
+                        pc
+                    )
+                }));
+            } else if a > RAM::KBD {
+                diagnostics.extend(self.report(DiagnosticCategory::MemoryMappedIO, a, || {
+                    format!(
+                        "ROM[{}] writes to RAM[{}], past the memory mapped devices",
+                        pc, a
+                    )
+                }));
+            }
+        }
+        diagnostics.extend(self.check_stack_pointer(&hardware.ram));
+
+        diagnostics
+    }
+
+    // Checks the command that is about to execute.
+    pub fn check_vm_command(&mut self, vm: &VM) -> Vec<Diagnostic> {
+        if self
+            .config
+            .severity(DiagnosticCategory::CallReturn)
+            .is_none()
+            || vm.halted()
+        {
+            return vec![];
+        }
+        let run_state = &vm.run_state;
+        let ram = &run_state.ram;
+        let index = run_state.current_command_index;
+        match &vm.program.all_commands[index] {
+            VMCommand::Call { function_name, .. } => {
+                let sp = ram[0];
+                self.pending_call = Some((
+                    run_state.call_stack.len(),
+                    SavedFrame {
+                        function_name: function_name.clone(),
+                        address: sp + 5,
+                        saved: [(index + 1) as Word, ram[1], ram[2], ram[3], ram[4]],
+                    },
+                ));
+                vec![]
+            }
+            VMCommand::Return => {
+                let Some(frame) = self.frames.pop() else {
+                    // Without a tracked frame the call might have happened before tracking began,
+                    // and Sys.init returns to the bootstrap.
+                    if run_state.call_stack.len() > 1 || vm.program.bootstrap_function().is_some() {
+                        return vec![];
+                    }
+                    let function_name = run_state
+                        .call_stack
+                        .last()
+                        .and_then(|frame| vm.program.function_name(frame.function_index))
+                        .unwrap_or("the top level")
+                        .to_owned();
+                    return self
+                        .report(DiagnosticCategory::CallReturn, index as Word, || {
+                            format!(
+                                "command {} returns from {}, which wasn't called by anything",
+                                index, function_name
+                            )
+                        })
+                        .into_iter()
+                        .collect();
+                };
+                let lcl = ram[1];
+                if lcl != frame.address {
+                    return self
+                        .report(DiagnosticCategory::CallReturn, index as Word, || {
+                            format!(
+                                "{} returns with LCL = {}, but its frame ends at {}",
+                                frame.function_name, lcl, frame.address
+                            )
+                        })
+                        .into_iter()
+                        .collect();
+                }
+                let corrupted =
+                    (0..5).find(|&i| ram[frame.address - 5 + i as Word] != frame.saved[i]);
+                corrupted
+                    .and_then(|i| {
+                        let address = frame.address - 5 + i as Word;
+                        self.report(DiagnosticCategory::CallReturn, index as Word, || {
+                            format!(
+                                "{} returns through a corrupted frame: the saved {} at RAM[{}] is {}, but the call saved {}",
+                                frame.function_name,
+                                SAVED_FRAME_NAMES[i],
+                                address,
+                                ram[address],
+                                frame.saved[i]
+                            )
+                        })
+                    })
+                    .into_iter()
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn check_vm(&mut self, run_state: &RunState) -> Vec<Diagnostic> {
+        if let Some((depth, frame)) = self.pending_call.take() {
+            if run_state.call_stack.len() > depth {
+                self.frames.push(frame);
+            }
+        }
+        self.check_stack_pointer(&run_state.ram)
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hardware_diagnostics() {
+        let mut config = DiagnosticsConfig::default();
+        config.set_severity(
+            DiagnosticCategory::UninitializedRead,
+            Some(Severity::Warning),
+        );
+        config.set_severity(DiagnosticCategory::MemoryMappedIO, Some(Severity::Error));
+        let mut diagnostics = Diagnostics::new(config);
+        let mut hardware =
+            Hardware::from_file_contents("@i\nD=M\n@j\nM=1\nD=M\n@KBD\nM=D\n@i\nD=M\n");
+
+        let mut reported = vec![];
+        while !hardware.halted() {
+            reported.extend(diagnostics.check_hardware(&hardware));
+            hardware.step_with_events();
+        }
+
+        assert_eq!(
+            reported,
+            vec![
+                Diagnostic {
+                    category: DiagnosticCategory::UninitializedRead,
+                    severity: Severity::Warning,
+                    message: "ROM[1] reads RAM[16] before anything was written to it".to_owned(),
+                },
+                Diagnostic {
+                    category: DiagnosticCategory::MemoryMappedIO,
+                    severity: Severity::Error,
+                    message: "ROM[6] writes to the keyboard register, which is read only"
+                        .to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_pointer_diagnostics() {
+        let mut config = DiagnosticsConfig::default();
+        config.set_severity(DiagnosticCategory::StackPointer, Some(Severity::Warning));
+        let mut diagnostics = Diagnostics::new(config);
+        let mut hardware = Hardware::default();
+
+        assert!(diagnostics.check_hardware(&hardware).is_empty());
+        hardware.ram[0] = 100;
+        assert_eq!(diagnostics.check_hardware(&hardware).len(), 1);
+        assert!(diagnostics.check_hardware(&hardware).is_empty());
+        diagnostics.reset();
+        hardware.ram[0] = 256;
+        assert!(diagnostics.check_hardware(&hardware).is_empty());
+    }
+
+    #[test]
+    fn test_call_return_diagnostics() {
+        let mut config = DiagnosticsConfig::default();
+        config.set_severity(DiagnosticCategory::CallReturn, Some(Severity::Error));
+        let mut diagnostics = Diagnostics::new(config);
+        let mut vm = VM::from_file_contents(vec![(
+            "Test.vm".to_owned(),
+            "function Test.run 0\ncall Test.good 0\ncall Test.bad 0\npush constant 0\nreturn\n\
+             function Test.good 0\npush constant 0\nreturn\n\
+             function Test.bad 0\npush constant 258\npop pointer 1\npush constant 7\n\
+             pop that 0\npush constant 0\nreturn\n"
+                .to_owned(),
+        )]);
+
+        let mut reported = vec![];
+        while reported.len() < 2 {
+            reported.extend(diagnostics.check_vm_command(&vm));
+            if reported.len() == 2 {
+                break;
+            }
+            vm.step();
+            reported.extend(diagnostics.check_vm(&vm.run_state));
+        }
+
+        assert_eq!(
+            reported
+                .into_iter()
+                .map(|diagnostic| diagnostic.message)
+                .collect::<Vec<_>>(),
+            vec![
+                "Test.bad returns through a corrupted frame: the saved LCL at RAM[258] is 7, but \
+                 the call saved 0"
+                    .to_owned(),
+                "command 4 returns from Test.run, which wasn't called by anything".to_owned(),
+            ]
+        );
+    }
+}
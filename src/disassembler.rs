@@ -0,0 +1,114 @@
+use hashbrown::HashSet;
+
+use crate::hardware::{Instruction, InstructionType, JumpCondition, RAM};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    // Set for the jump targets.
+    pub label: Option<String>,
+    pub code: String,
+}
+
+fn label_name(address: usize) -> String {
+    format!("L{}", address)
+}
+
+fn loads_jump_target(program: &[Instruction], index: usize) -> bool {
+    program.get(index + 1).is_some_and(|next| {
+        next.instruction_type() == InstructionType::C
+            && next.jump_condition() != JumpCondition::NoJump
+    })
+}
+
+// Addresses loaded right before a jump, only those inside the program or just past its end can
+// get a label.
+fn jump_targets(program: &[Instruction]) -> HashSet<usize> {
+    program
+        .iter()
+        .enumerate()
+        .filter(|&(index, instruction)| {
+            instruction.instruction_type() == InstructionType::A
+                && loads_jump_target(program, index)
+        })
+        .map(|(_, instruction)| instruction.loaded_value() as usize)
+        .filter(|&target| target <= program.len())
+        .collect()
+}
+
+fn symbol(program: &[Instruction], index: usize, targets: &HashSet<usize>) -> Option<String> {
+    let value = program[index].loaded_value();
+    if loads_jump_target(program, index) && targets.contains(&(value as usize)) {
+        return Some(label_name(value as usize));
+    }
+    let addresses_memory = program.get(index + 1).is_some_and(|next| {
+        next.instruction_type() == InstructionType::C && (next.reads_m() || next.dst_has_m())
+    });
+    match value {
+        RAM::SCREEN => Some("SCREEN".to_owned()),
+        RAM::KBD => Some("KBD".to_owned()),
+        0..=4 if addresses_memory => {
+            Some(["SP", "LCL", "ARG", "THIS", "THAT"][value as usize].to_owned())
+        }
+        5..=15 if addresses_memory => Some(format!("R{}", value)),
+        _ => None,
+    }
+}
+
+// Symbols are guessed from how each value is used, assembling the result gives back the same
+// instructions.
+pub fn disassemble(program: &[Instruction]) -> Vec<DisassembledInstruction> {
+    let targets = jump_targets(program);
+    program
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            let code = match instruction.instruction_type() {
+                InstructionType::A => symbol(program, index, &targets)
+                    .map_or_else(|| instruction.to_string(), |symbol| format!("@{}", symbol)),
+                InstructionType::C => instruction.to_string(),
+            };
+            DisassembledInstruction {
+                label: targets.contains(&index).then(|| label_name(index)),
+                code,
+            }
+        })
+        .collect()
+}
+
+pub fn disassembly_listing(program: &[Instruction]) -> String {
+    let mut listing = String::new();
+    for instruction in disassemble(program) {
+        if let Some(label) = instruction.label {
+            listing.push_str(&format!("({})\n", label));
+        }
+        listing.push_str(&format!("{}\n", instruction.code));
+    }
+    if jump_targets(program).contains(&program.len()) {
+        listing.push_str(&format!("({})\n", label_name(program.len())));
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware_parse::{assemble, parse_instructions};
+
+    #[test]
+    fn test_disassemble() {
+        let source = "@SCREEN\nD=A\n@R13\nM=D\n(LOOP)\n@KBD\nD=M\n@LOOP\nD;JEQ\n@SP\nAM=M+1\n\
+                      @7\nD=A\n@END\n0;JMP\n(END)\n";
+        let program = assemble(&parse_instructions(source).unwrap().1).unwrap();
+        let listing = disassembly_listing(&program);
+
+        assert_eq!(
+            listing,
+            "@SCREEN\nD=A\n@R13\nM=D\n(L4)\n@KBD\nD=M\n@L4\nD;JEQ\n@SP\nAM=M+1\n@7\nD=A\n@L14\n\
+             0;JMP\n(L14)\n"
+        );
+        assert_eq!(
+            assemble(&parse_instructions(&listing).unwrap().1).unwrap(),
+            program
+        );
+    }
+}
@@ -1,15 +1,47 @@
 use eframe::egui::DroppedFile;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
 
 use super::instant::Instant;
 
 use super::common_state::{
-    Action, AppState, CommonAction, CommonState, PerformanceData, SharedState,
+    Action, AppState, BackgroundBehavior, BreakpointAction, CheckpointAction, CommonAction,
+    CommonState, CountersAction, DiffAction, ExamUnlock, FindAction, FindQuery, FindState,
+    FunctionStep, InvariantAction, InvariantsState, KeyboardAction, KeyboardState, LoadErrors,
+    LoadedFile, MemoryAction, PerformanceData, ProfilerAction, RecordedData, SharedState,
+    SourceEditorAction, SpeedMarker, StepRunnable, StopReason, TestResult, TestStatus, TestsAction,
+    TestsState, TraceViewAction, TutorialAction,
 };
+use super::editor::open_in_editor;
+use super::examples::EXAMPLES;
 use super::hardware_reducer::reduce_breakpoint_hardware;
 use super::hardware_state::HardwareState;
-use super::vm_reducer::reduce_vm_file_selected;
-use super::vm_state::VMState;
+use super::projects::{
+    file_name, find_projects_root, find_test_scripts, read_program_files, read_vm_os_classes,
+    scan_projects, write_projects_root,
+};
+use super::recovery::remove_recovery_file;
+use super::snapshot::{Checkpoint, DiffState, SnapshotDiff};
+use super::test_runner::run_tests;
+use super::tutorial::TutorialStep;
+use super::vm_reducer::{
+    reduce_breakpoint_vm, reduce_counters, reduce_os_class_source_changed, reduce_segment_init,
+    reduce_vm_file_enabled_changed, reduce_vm_file_selected, reduce_watches,
+};
+use super::vm_state::{file_stem, OSClassSource, VMState};
 use super::EmulatorApp;
+use crate::annotation::AnnotationScript;
+use crate::exam_archive::{is_exam_archive, unpack_exam, EXTENSION};
+use crate::expression::{parse_expression, Invariant};
+use crate::hardware_parse::{expand_includes, IncludeExpansion, ProgramError};
+use crate::metadata::ProgramMetadata;
+use crate::project::parse_project;
+use crate::recording::RecordingBudget;
+use crate::session::{next_bookmark, Bookmark};
 
 #[cfg(not(target_arch = "wasm32"))]
 pub fn get_contents(dropped_file: &DroppedFile) -> String {
@@ -23,98 +55,1019 @@ pub fn get_contents(dropped_file: &DroppedFile) -> String {
     String::from_utf8(bytes).unwrap()
 }
 
-pub fn reduce(app: &mut EmulatorApp, action: &Action) {
+#[cfg(not(target_arch = "wasm32"))]
+fn get_bytes(dropped_file: &DroppedFile) -> Vec<u8> {
+    std::fs::read(dropped_file.path.as_ref().unwrap()).unwrap()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn get_bytes(dropped_file: &DroppedFile) -> Vec<u8> {
+    dropped_file.bytes.as_ref().unwrap().to_vec()
+}
+
+fn is_exam_file(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(EXTENSION))
+}
+
+fn load_state(app: &mut EmulatorApp, mut state: AppState) {
+    let invariants = std::mem::take(&mut app.shared_state.invariants.invariants);
+    let reloaded = !state.file_names().is_empty() && state.file_names() == app.state.file_names();
+    if let (true, AppState::VM(previous), AppState::VM(vm_state)) =
+        (reloaded, &mut app.state, &mut state)
+    {
+        vm_state.select_file(Some(previous.selected_file.clone()));
+        vm_state.watches = std::mem::take(&mut previous.watches);
+    }
+    let scroll_offsets = std::mem::take(&mut app.shared_state.scroll_offsets);
+    let bookmarks = std::mem::take(&mut app.shared_state.bookmarks);
+    app.shared_state = SharedState {
+        screen_detached: app.shared_state.screen_detached,
+        annotations: app.shared_state.annotations.take(),
+        tests: std::mem::take(&mut app.shared_state.tests),
+        source_editor: std::mem::take(&mut app.shared_state.source_editor),
+        find: std::mem::take(&mut app.shared_state.find),
+        from_exam: reloaded && app.shared_state.from_exam,
+        ..SharedState::from_metadata(state.metadata())
+    };
+    if reloaded {
+        restore_view(&mut app.shared_state, scroll_offsets, bookmarks);
+        app.performance_data.mark(SpeedMarker::Reloaded);
+    }
+    app.shared_state.invariants.invariants = invariants;
+    app.state = state;
+    app.state.set_diagnostics_config(&app.settings.diagnostics);
+    refresh_find(app);
+}
+
+// Snapshots of the previous link don't match the new program.
+fn reset_after_relink(shared_state: &mut SharedState) {
+    shared_state.checkpoints = Default::default();
+    shared_state.undo_points.clear();
+    shared_state.diff = DiffState {
+        open: shared_state.diff.open,
+        ..Default::default()
+    };
+    shared_state.stop_reason = None;
+    shared_state.run_started = false;
+    shared_state.scroll_once = true;
+}
+
+fn restore_view(
+    shared_state: &mut SharedState,
+    scroll_offsets: BTreeMap<String, u32>,
+    bookmarks: BTreeSet<Bookmark>,
+) {
+    shared_state.scroll_offsets = scroll_offsets;
+    shared_state.bookmarks = bookmarks;
+    shared_state.restore_scroll = true;
+    shared_state.scroll_once = false;
+    shared_state.follow_pc = false;
+}
+
+// The current program stays loaded, the errors go to its log.
+fn log_load_errors(app: &mut EmulatorApp, messages: impl Iterator<Item = String>) {
+    match &mut app.state {
+        AppState::Hardware(state) => state.log.extend(messages),
+        AppState::VM(state) => state.log.extend(messages),
+        AppState::Start => messages.for_each(|message| eprintln!("{}", message)),
+    }
+    app.shared_state.log_open = true;
+}
+
+fn load_hack_file(app: &mut EmulatorApp, file: &LoadedFile) {
+    let lowercase_name = file.name.to_lowercase();
+    let state = if lowercase_name.ends_with(".hack") {
+        HardwareState::try_from_hack_file_contents(&file.contents)
+    } else if lowercase_name.ends_with(".asm") {
+        app.shared_state.source_editor.file = Some(file.clone());
+        app.shared_state.source_editor.modified = false;
+        match app.settings.assembler_mode.source(&file.contents) {
+            Ok(_)
+                if ProgramMetadata::from_file_contents(&file.contents)
+                    .origin
+                    .is_some() =>
+            {
+                load_fragments(app, std::slice::from_ref(file));
+                return;
+            }
+            Ok(_) => {
+                let directory = file.path.as_deref().and_then(Path::parent);
+                expand_includes(&file.name, &file.contents, &mut |name| {
+                    read_include(directory, name)
+                })
+                .map_err(|error| vec![error])
+                .and_then(|expansion| assemble_expansion(app, &expansion))
+            }
+            Err(errors) => Err(errors),
+        }
+    } else {
+        app.warning = Some(format!(
+            "{} can't be loaded, only .hack, .asm, .vm and .n2r files can",
+            file.name
+        ));
+        return;
+    };
+    let state = match state {
+        Ok(state) => state,
+        Err(errors) => {
+            app.shared_state.load_errors = Some(LoadErrors {
+                file: file.clone(),
+                errors,
+            });
+            return;
+        }
+    };
+    load_state(
+        app,
+        AppState::Hardware(HardwareState {
+            source_path: file.path.clone(),
+            ..state
+        }),
+    );
+}
+
+// Included files are looked for next to the file that includes them.
+fn read_include(directory: Option<&Path>, name: &str) -> Result<String, String> {
+    let Some(directory) = directory else {
+        return Err(format!(
+            "{} can't be found, the including file wasn't opened from a folder",
+            name
+        ));
+    };
+    std::fs::read_to_string(directory.join(name))
+        .map_err(|error| format!("Failed to read {}: {}", name, error))
+}
+
+// The ROM maps back to the lines of the top file, included instructions to their #include.
+fn assemble_expansion(
+    app: &EmulatorApp,
+    expansion: &IncludeExpansion,
+) -> Result<HardwareState, Vec<ProgramError>> {
+    let top_errors = |errors: Vec<ProgramError>| -> Vec<ProgramError> {
+        errors
+            .into_iter()
+            .map(|error| expansion.top_error(error))
+            .collect()
+    };
+    let contents = app
+        .settings
+        .assembler_mode
+        .source(&expansion.text)
+        .map_err(top_errors)?;
+    let state = HardwareState::try_from_file_contents(&contents).map_err(top_errors)?;
+    Ok(HardwareState {
+        source_lines: state
+            .source_lines
+            .iter()
+            .map(|&line| expansion.top_lines[line - 1])
+            .collect(),
+        ..state
+    })
+}
+
+// Several .asm files without an origin are one program, as if a file included them in order.
+fn load_asm_files(app: &mut EmulatorApp, files: &[LoadedFile]) {
+    let program = LoadedFile {
+        name: files
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>()
+            .join(" + "),
+        contents: files
+            .iter()
+            .map(|file| format!("#include \"{}\"\n", file.name))
+            .collect(),
+        path: None,
+    };
+    let directory = files.iter().find_map(|file| file.path.as_deref()?.parent());
+    let state = expand_includes(&program.name, &program.contents, &mut |name| match files
+        .iter()
+        .find(|file| file.name == name)
+    {
+        Some(file) => Ok(file.contents.clone()),
+        None => read_include(directory, name),
+    })
+    .map_err(|error| vec![error])
+    .and_then(|expansion| assemble_expansion(app, &expansion));
+    match state {
+        Ok(state) => load_state(app, AppState::Hardware(state)),
+        Err(errors) => {
+            app.shared_state.load_errors = Some(LoadErrors {
+                file: program,
+                errors,
+            })
+        }
+    }
+}
+
+fn load_fragments(app: &mut EmulatorApp, files: &[LoadedFile]) {
+    let files: Vec<_> = files
+        .iter()
+        .map(|file| (file.name.clone(), file.contents.clone()))
+        .collect();
+    match HardwareState::try_from_fragment_files(&files) {
+        Ok(state) => load_state(app, AppState::Hardware(state)),
+        Err(errors) => log_load_errors(
+            app,
+            errors
+                .into_iter()
+                .map(|error| format!("Can't load {}", error)),
+        ),
+    }
+}
+
+fn load_vm_files(app: &mut EmulatorApp, files: &[LoadedFile]) {
+    let mut state = VMState::from_file_contents(
+        files
+            .iter()
+            .map(|file| (file.name.clone(), file.contents.clone()))
+            .collect(),
+    );
+    state.source_paths = files
+        .iter()
+        .filter_map(|file| Some((file_stem(&file.name).to_owned(), file.path.clone()?)))
+        .collect();
+    if let Some(directory) = state.program_directory() {
+        for class_name in read_vm_os_classes(directory) {
+            state.set_os_class_source(&class_name, OSClassSource::LoadedVM);
+        }
+    }
+    load_state(app, AppState::VM(state));
+}
+
+fn read_loaded_file(name: &str, path: &Path) -> Result<LoadedFile, String> {
+    std::fs::read_to_string(path)
+        .map(|contents| LoadedFile {
+            name: name.to_owned(),
+            contents,
+            path: Some(path.to_owned()),
+        })
+        .map_err(|error| format!("Failed to read {}: {}", path.display(), error))
+}
+
+// Loads the files of the program again from disk, the breakpoints stay.
+fn reload_sources(app: &mut EmulatorApp) {
+    app.shared_state.sources_changed = false;
+    let breakpoints = app.state.breakpoints();
+    let files = match &app.state {
+        AppState::Hardware(state) => state
+            .source_path
+            .iter()
+            .map(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                read_loaded_file(&name, path)
+            })
+            .collect(),
+        AppState::VM(state) => state
+            .files
+            .iter()
+            .map(
+                |(name, contents)| match state.source_paths.get(file_stem(name)) {
+                    Some(path) => read_loaded_file(name, path),
+                    None => Ok(LoadedFile {
+                        name: name.clone(),
+                        contents: contents.clone(),
+                        path: None,
+                    }),
+                },
+            )
+            .collect(),
+        AppState::Start => Ok(vec![]),
+    };
+    match files {
+        Ok(files) if !files.is_empty() => {
+            load_files(app, &files);
+            app.state.add_breakpoints(&breakpoints);
+        }
+        Ok(_) => {}
+        Err(error) => app.warning = Some(error),
+    }
+}
+
+fn load_files(app: &mut EmulatorApp, files: &[LoadedFile]) {
+    if files.len() == 1 && files[0].name.to_lowercase().ends_with(".n2r") {
+        load_project_file(app, &files[0]);
+    } else if files.len() == 1 && !files[0].name.to_lowercase().ends_with(".vm") {
+        load_hack_file(app, &files[0]);
+    } else if files
+        .iter()
+        .all(|file| file.name.to_lowercase().ends_with(".vm"))
+    {
+        load_vm_files(app, files);
+    } else if files.iter().all(|file| {
+        file.name.to_lowercase().ends_with(".asm")
+            && ProgramMetadata::from_file_contents(&file.contents)
+                .origin
+                .is_none()
+    }) {
+        load_asm_files(app, files);
+    } else if files.iter().all(|file| {
+        let name = file.name.to_lowercase();
+        name.ends_with(".asm") || name.ends_with(".hack")
+    }) {
+        load_fragments(app, files);
+    } else {
+        let names: Vec<_> = files.iter().map(|file| file.name.as_str()).collect();
+        app.warning = Some(format!(
+            "{} can't be loaded together, pick .vm files or .asm and .hack files",
+            names.join(", ")
+        ));
+    }
+}
+
+// The sources load like any others, the rest of the project is applied on top.
+fn load_project_file(app: &mut EmulatorApp, file: &LoadedFile) {
+    let project = match parse_project(&file.contents) {
+        Ok(project) => project,
+        Err(error) => {
+            app.warning = Some(format!("{}: {}", file.name, error));
+            return;
+        }
+    };
+    let Some(directory) = file.path.as_deref().and_then(Path::parent) else {
+        app.warning = Some(format!(
+            "{} can't load its files without knowing where it is",
+            file.name
+        ));
+        return;
+    };
+    let files = match read_program_files(&project.source_paths(directory)) {
+        Ok(files) => files,
+        Err(error) => {
+            app.warning = Some(error);
+            return;
+        }
+    };
+    if let Some(assembler_mode) = project.assembler_mode {
+        app.settings.assembler_mode = assembler_mode;
+    }
+    load_files(app, &files);
+    if let Some(speed) = project.speed {
+        app.shared_state.desired_steps_per_second = speed;
+    }
+
+    match &mut app.state {
+        AppState::Hardware(state) => {
+            if let Some(entry) = project.entry_address() {
+                state.hardware.pc = entry;
+            }
+            for breakpoint in project.hardware_breakpoints {
+                state.selected_breakpoint = breakpoint;
+                reduce_breakpoint_hardware(state, &BreakpointAction::AddClicked);
+            }
+            for (address, value) in project.ram {
+                state.ram_mut()[address] = value;
+            }
+        }
+        AppState::VM(state) => {
+            for class_name in &project.vm_os_classes {
+                state.set_os_class_source(class_name, OSClassSource::LoadedVM);
+            }
+            if project.entry.is_some() {
+                state.set_entry_function(project.entry);
+            }
+            for breakpoint in project.vm_breakpoints {
+                state.selected_breakpoint = breakpoint;
+                reduce_breakpoint_vm(state, &BreakpointAction::AddClicked);
+            }
+            for (address, value) in project.ram {
+                state.ram_mut()[address] = value;
+            }
+        }
+        AppState::Start => {}
+    }
+}
+
+fn open_exam(app: &mut EmulatorApp, name: &str, bytes: Vec<u8>) {
+    if !is_exam_archive(&bytes) {
+        app.warning = Some(format!("{} isn't an exam archive", name));
+        return;
+    }
+    app.shared_state.exam_unlock = Some(ExamUnlock {
+        name: name.to_owned(),
+        bytes,
+        password: String::new(),
+        error: None,
+    });
+}
+
+// The unpacked files have no paths, so nothing leads back to their sources.
+fn unlock_exam(app: &mut EmulatorApp) {
+    let Some(exam_unlock) = &mut app.shared_state.exam_unlock else {
+        return;
+    };
+    match unpack_exam(&exam_unlock.bytes, &exam_unlock.password) {
+        Ok(files) => {
+            let files: Vec<_> = files
+                .into_iter()
+                .map(|(name, contents)| LoadedFile {
+                    name,
+                    contents,
+                    path: None,
+                })
+                .collect();
+            app.shared_state.exam_unlock = None;
+            load_files(app, &files);
+            app.shared_state.from_exam = true;
+        }
+        Err(error) => exam_unlock.error = Some(error),
+    }
+}
+
+fn reduce_tutorial(app: &mut EmulatorApp, action: TutorialAction) {
+    app.tutorial = match action {
+        TutorialAction::Started => {
+            if matches!(app.state, AppState::Start) {
+                load_state(app, AppState::Hardware(HardwareState::default()));
+            }
+            Some(TutorialStep::FIRST)
+        }
+        TutorialAction::NextClicked => app.tutorial.and_then(TutorialStep::next),
+        TutorialAction::Closed => None,
+    };
+}
+
+// Every action goes through the middleware, which sees the state before and after, can drop the
+// action altogether or dispatch others ahead of it.
+pub fn dispatch(app: &mut EmulatorApp, action: &Action) {
+    let mut middleware = std::mem::take(&mut app.middleware);
+    let mut ahead = vec![];
+    let passed = middleware
+        .iter_mut()
+        .all(|middleware| middleware.before(&app.state, action, &mut ahead));
+    app.middleware = middleware;
+    for ahead_action in &ahead {
+        dispatch(app, ahead_action);
+    }
+    if passed {
+        reduce(app, action);
+        let mut middleware = std::mem::take(&mut app.middleware);
+        for middleware in &mut middleware {
+            middleware.after(&app.state, action);
+        }
+        app.middleware = middleware;
+    }
+}
+
+fn reduce_steps_run(app: &mut EmulatorApp, step_count: u64) {
+    let schedule = app.shared_state.keyboard.schedule(step_count);
+    let (stop_reason, steps_ran) = match &mut app.state {
+        AppState::Hardware(state) => {
+            let (stop_reason, steps_ran) = state.run_steps(&schedule);
+            if let Some(warning) = state.label_write_warning.take() {
+                app.warning = Some(warning);
+            }
+            check_invariants(state, &mut app.shared_state);
+            (stop_reason, steps_ran)
+        }
+        AppState::VM(state) => {
+            let result = state.run_steps(&schedule);
+            check_invariants(state, &mut app.shared_state);
+            result
+        }
+        AppState::Start => return,
+    };
+    if stop_reason != StopReason::StepLimit {
+        app.performance_data
+            .credit_unrun_steps(step_count, steps_ran);
+        app.performance_data.mark(match stop_reason {
+            StopReason::BreakpointHit(_) => SpeedMarker::BreakpointHit,
+            _ => SpeedMarker::Paused,
+        });
+    }
+    app.performance_data.sampled_steps += steps_ran;
+    if stop_reason != StopReason::StepLimit {
+        app.state.cancel_function_step();
+    }
+    app.state.trim_recording(&app.settings.recording_budget);
+    stop(&mut app.shared_state, stop_reason);
+    app.shared_state.keyboard.steps_ran(steps_ran);
+    app.shared_state.scroll_once |= step_count > 0;
+}
+
+fn reduce(app: &mut EmulatorApp, action: &Action) {
     match action {
-        Action::Common(common_action) => match &mut app.state {
-            AppState::Hardware(hardware_state) => {
-                reduce_common(hardware_state, &mut app.shared_state, common_action)
+        Action::FrameStarted {
+            last_frame_time,
+            host_key,
+            step_clicked,
+        } => {
+            app.performance_data.frame_steps = steps_to_run(
+                app.settings.background.steps_per_second(
+                    app.shared_state.desired_steps_per_second,
+                    app.shared_state.window_focused,
+                ),
+                *last_frame_time,
+                &mut app.performance_data,
+                app.shared_state.run_started,
+                *step_clicked,
+            );
+            app.performance_data.sample_speed(Instant::now());
+            app.shared_state.keyboard.host_value = *host_key;
+        }
+        Action::StepsRun { step_count } => reduce_steps_run(app, *step_count),
+        Action::FrameDrawn => app.shared_state.scroll_once = false,
+        Action::ScrollRestored => app.shared_state.restore_scroll = false,
+        Action::RowJumped => app.shared_state.jump_to_row = None,
+        Action::UndoPointSaved => {
+            let snapshot = match &app.state {
+                AppState::Hardware(hardware_state) => hardware_state.snapshot(),
+                AppState::VM(vm_state) => vm_state.snapshot(),
+                AppState::Start => return,
+            };
+            app.shared_state.undo_points.push(snapshot);
+            app.settings
+                .recording_budget
+                .trim_undo_points(&mut app.shared_state.undo_points);
+        }
+        Action::UndoClicked => {
+            let Some(snapshot) = app.shared_state.undo_points.pop() else {
+                return;
+            };
+            match &mut app.state {
+                AppState::Hardware(hardware_state) => hardware_state.travel_to(&snapshot),
+                AppState::VM(vm_state) => vm_state.travel_to(&snapshot),
+                AppState::Start => return,
+            }
+            app.state.cancel_function_step();
+            app.shared_state.dirty = true;
+            app.shared_state.run_started = false;
+            app.shared_state.stop_reason = None;
+            app.shared_state.scroll_once = true;
+        }
+        Action::Common(common_action) => {
+            if *common_action == CommonAction::PauseClicked {
+                app.performance_data.mark(SpeedMarker::Paused);
+            }
+            match &mut app.state {
+                AppState::Hardware(hardware_state) => {
+                    reduce_common(hardware_state, &mut app.shared_state, common_action)
+                }
+                AppState::VM(vm_state) => {
+                    reduce_common(vm_state, &mut app.shared_state, common_action)
+                }
+                AppState::Start => panic!(
+                    "Received common action {:?} when in state AppState::Start",
+                    common_action
+                ),
+            }
+            if app
+                .tutorial
+                .is_some_and(|step| step.completed_by(common_action))
+            {
+                app.tutorial = app.tutorial.and_then(TutorialStep::next);
+            }
+        }
+        Action::Breakpoint(breakpoint_action) => {
+            match &mut app.state {
+                AppState::Hardware(hardware_state) => {
+                    reduce_breakpoint_hardware(hardware_state, breakpoint_action)
+                }
+                AppState::VM(vm_state) => reduce_breakpoint_vm(vm_state, breakpoint_action),
+                AppState::Start => todo!(),
             }
-            AppState::VM(vm_state) => reduce_common(vm_state, &mut app.shared_state, common_action),
-            AppState::Start => panic!(
-                "Received common action {:?} when in state AppState::Start",
-                common_action
+            app.shared_state.dirty = true;
+        }
+        Action::Checkpoint(checkpoint_action) => match &mut app.state {
+            AppState::Hardware(hardware_state) => reduce_checkpoint(
+                hardware_state,
+                &mut app.shared_state,
+                &app.settings.recording_budget,
+                checkpoint_action,
+            ),
+            AppState::VM(vm_state) => reduce_checkpoint(
+                vm_state,
+                &mut app.shared_state,
+                &app.settings.recording_budget,
+                checkpoint_action,
             ),
+            AppState::Start => {}
         },
-        Action::Breakpoint(breakpoint_action) => match &mut app.state {
+        Action::Diff(diff_action) => match &mut app.state {
             AppState::Hardware(hardware_state) => {
-                reduce_breakpoint_hardware(hardware_state, breakpoint_action)
+                reduce_diff(hardware_state, &mut app.shared_state, diff_action)
             }
-            AppState::VM(_) => todo!(),
-            AppState::Start => todo!(),
+            AppState::VM(vm_state) => reduce_diff(vm_state, &mut app.shared_state, diff_action),
+            AppState::Start => {}
         },
-        Action::FilesPicked(file_contents) => {
-            app.state = AppState::VM(VMState::from_file_contents(file_contents.clone()));
-            app.shared_state = Default::default();
-        }
-        Action::FilePicked { name, contents } => {
-            let lowercase_name = name.to_lowercase();
-            if lowercase_name.ends_with(".hack") {
-                app.state = AppState::Hardware(HardwareState::from_hack_file_contents(contents));
-                app.shared_state = Default::default();
-            } else if lowercase_name.ends_with(".asm") {
-                app.state = AppState::Hardware(HardwareState::from_file_contents(contents));
-                app.shared_state = Default::default();
-            } else {
-                println!("{:?}", name);
+        Action::Invariant(invariant_action) => {
+            reduce_invariant(&mut app.shared_state.invariants, invariant_action)
+        }
+        Action::Keyboard(keyboard_action) => {
+            reduce_keyboard(&mut app.shared_state.keyboard, keyboard_action)
+        }
+        Action::Memory(memory_action) => match &mut app.state {
+            AppState::Hardware(hardware_state) => {
+                reduce_memory(hardware_state, &mut app.shared_state, *memory_action)
+            }
+            AppState::VM(vm_state) => {
+                reduce_memory(vm_state, &mut app.shared_state, *memory_action)
             }
+            AppState::Start => {}
+        },
+        Action::SourceEditor(source_editor_action) => {
+            reduce_source_editor(app, source_editor_action)
+        }
+        Action::Find(find_action) => reduce_find(app, find_action),
+        Action::Tutorial(tutorial_action) => reduce_tutorial(app, *tutorial_action),
+        Action::Profiler(profiler_action) => reduce_profiler(app, profiler_action),
+        Action::Tests(tests_action) => reduce_tests(app, tests_action),
+        Action::TraceView(trace_view_action) => match &mut app.state {
+            AppState::Hardware(hardware_state) => {
+                reduce_trace_view(hardware_state, &mut app.shared_state, trace_view_action)
+            }
+            AppState::VM(vm_state) => {
+                reduce_trace_view(vm_state, &mut app.shared_state, trace_view_action)
+            }
+            AppState::Start => {}
+        },
+        Action::FilesPicked(files) => {
+            load_vm_files(app, files);
+        }
+        Action::FilePicked(file) => {
+            load_hack_file(app, file);
+        }
+        Action::ProjectFilePicked(file) => load_project_file(app, file),
+        Action::ExamPicked(name, bytes) => open_exam(app, name, bytes.clone()),
+        Action::ExamPasswordChanged(password) => {
+            if let Some(exam_unlock) = &mut app.shared_state.exam_unlock {
+                exam_unlock.password.clone_from(password);
+            }
+        }
+        Action::ExamUnlockClicked => unlock_exam(app),
+        Action::ExamUnlockClosed => {
+            app.shared_state.exam_unlock = None;
         }
         Action::FilesDropped(dropped_files) => {
-            let first_file_lowercase = dropped_files[0].name.to_lowercase();
-            if dropped_files.len() == 1 && first_file_lowercase.ends_with(".asm") {
-                let file_contents = get_contents(&dropped_files[0]);
-                app.state = AppState::Hardware(HardwareState::from_file_contents(&file_contents));
-                app.shared_state = Default::default();
-            } else if dropped_files.len() == 1 && first_file_lowercase.ends_with(".hack") {
-                let file_contents = get_contents(&dropped_files[0]);
-                app.state =
-                    AppState::Hardware(HardwareState::from_hack_file_contents(&file_contents));
-                app.shared_state = Default::default();
-            } else if dropped_files
+            if let Some(exam) = dropped_files
                 .iter()
-                .all(|d| d.name.to_lowercase().ends_with(".vm"))
+                .find(|dropped_file| is_exam_file(&dropped_file.name))
             {
-                let file_contents = dropped_files
-                    .iter()
-                    .map(|dropped_file| (dropped_file.name.clone(), get_contents(dropped_file)))
-                    .collect();
-
-                app.state = AppState::VM(VMState::from_file_contents(file_contents));
-                app.shared_state = Default::default();
+                open_exam(app, &exam.name, get_bytes(exam));
+                return;
+            }
+            let files: Vec<_> = dropped_files
+                .iter()
+                .map(|dropped_file| LoadedFile {
+                    name: dropped_file.name.clone(),
+                    contents: get_contents(dropped_file),
+                    path: dropped_file.path.clone(),
+                })
+                .collect();
+            load_files(app, &files);
+        }
+        Action::AnnotationsPicked(file) => match AnnotationScript::new(&file.contents) {
+            Ok(script) => app.shared_state.annotations = Some(script),
+            Err(error) => app.warning = Some(format!("Failed to load {}: {}", file.name, error)),
+        },
+        Action::AnnotationsClosed => {
+            app.shared_state.annotations = None;
+        }
+        Action::ExampleSelected(index) => {
+            load_state(app, EXAMPLES[*index].load());
+        }
+        Action::SessionExported { then_quit } => {
+            app.shared_state.dirty = false;
+            app.shared_state.quit_dialog_open = false;
+            app.quitting |= then_quit;
+        }
+        Action::Quit => {
+            if app.shared_state.dirty || app.shared_state.source_editor.modified {
+                app.shared_state.quit_dialog_open = true;
             } else {
-                println!("{:?}", dropped_files);
+                app.quitting = true;
+            }
+        }
+        Action::QuitConfirmed => {
+            app.quitting = true;
+        }
+        Action::QuitCancelled => {
+            app.shared_state.quit_dialog_open = false;
+        }
+        Action::RecoveryAccepted => {
+            remove_recovery_file();
+            if let Some(session) = app.recovered_session.take() {
+                let view = session.view().clone();
+                load_state(app, AppState::from_session(session));
+                restore_view(&mut app.shared_state, view.scroll_offsets, view.bookmarks);
+                app.shared_state.dirty = true;
             }
         }
-        Action::Quit => todo!(),
+        Action::RecoveryDiscarded => {
+            remove_recovery_file();
+            app.recovered_session = None;
+        }
+        Action::WarningDismissed => {
+            app.warning = None;
+        }
+        Action::ScreenDetachClicked => {
+            app.shared_state.screen_detached = true;
+        }
+        Action::ScreenAttachClicked => {
+            app.shared_state.screen_detached = false;
+        }
         Action::VMFileSelected(file) => match &mut app.state {
             AppState::Hardware(_) => {
                 panic!("Received action {:?} when in state AppState::Start", action)
             }
-            AppState::VM(vm_state) => reduce_vm_file_selected(vm_state, file),
+            AppState::VM(vm_state) => {
+                // The grid switches files by itself when it follows the PC into another one.
+                let program = &vm_state.vm.program;
+                if *file != program.files[vm_state.vm.run_state.current_file_index].name {
+                    app.shared_state.follow_pc = false;
+                }
+                reduce_vm_file_selected(vm_state, file);
+                app.shared_state.restore_scroll = true;
+                refresh_find(app);
+            }
             AppState::Start => todo!(),
         },
+        Action::VMSplitFileChanged(split_file) => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                vm_state.split_file.clone_from(split_file);
+            }
+        }
+        Action::OSClassSourceChanged(class_name, source) => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                reduce_os_class_source_changed(vm_state, class_name, *source);
+                app.shared_state.dirty = true;
+            }
+        }
+        Action::SegmentInit(segment_init_action) => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                reduce_segment_init(vm_state, &mut app.shared_state, segment_init_action);
+                app.shared_state.scroll_once = true;
+            }
+        }
+        Action::LinkConflictsClicked => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                vm_state.link_conflicts_open = !vm_state.link_conflicts_open;
+            }
+        }
+        Action::LinkConflictsClosed => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                vm_state.link_conflicts_open = false;
+            }
+        }
+        Action::StackDepthClicked => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                vm_state.stack_depth_open = !vm_state.stack_depth_open;
+            }
+        }
+        Action::StackDepthClosed => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                vm_state.stack_depth_open = false;
+            }
+        }
+        Action::Counters(counters_action) => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                reduce_counters(vm_state, counters_action);
+                if let CountersAction::InstrumentedChanged(_) = counters_action {
+                    reset_after_relink(&mut app.shared_state);
+                }
+            }
+        }
+        Action::Watches(watches_action) => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                reduce_watches(vm_state, &mut app.shared_state, watches_action);
+            }
+        }
+        Action::RomDisplayChanged(rom_display) => {
+            if let AppState::Hardware(hardware_state) = &mut app.state {
+                hardware_state.rom_display = *rom_display;
+            }
+            refresh_find(app);
+        }
+        Action::RomSymbolsChanged(show_symbols) => {
+            if let AppState::Hardware(hardware_state) = &mut app.state {
+                hardware_state.show_symbols = *show_symbols;
+            }
+            refresh_find(app);
+        }
+        Action::FunctionFileChosen {
+            function_name,
+            file_name,
+        } => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                vm_state.choose_function_file(function_name, file_name);
+            }
+        }
+        Action::VMFileEnabledChanged(file, enabled) => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                reduce_vm_file_enabled_changed(vm_state, file, *enabled);
+                reset_after_relink(&mut app.shared_state);
+            }
+        }
+        Action::OpenInEditor(row) => {
+            let location = match &app.state {
+                AppState::Hardware(hardware_state) => hardware_state.source_location(*row),
+                AppState::VM(vm_state) => vm_state.source_location(*row),
+                AppState::Start => None,
+            };
+            if let Some((path, line)) = location {
+                if let Err(error) = open_in_editor(&app.settings.editor_command, path, line) {
+                    app.warning = Some(error);
+                }
+            }
+        }
+        Action::EditorCommandChanged(editor_command) => {
+            app.settings.editor_command.clone_from(editor_command);
+        }
+        Action::DiagnosticSeverityChanged(category, severity) => {
+            app.settings.diagnostics.set_severity(*category, *severity);
+            app.state.set_diagnostics_config(&app.settings.diagnostics);
+        }
+        Action::BackgroundBehaviorChanged(background) => {
+            app.settings.background = *background;
+        }
+        Action::WindowFocusChanged(focused) => {
+            app.shared_state.window_focused = *focused;
+            if !focused && app.settings.background == BackgroundBehavior::Pause {
+                app.shared_state.run_started = false;
+            }
+        }
+        Action::SourcesChanged => {
+            if app.settings.auto_reload {
+                reload_sources(app);
+            } else {
+                app.shared_state.sources_changed = true;
+            }
+        }
+        Action::ReloadClicked => reload_sources(app),
+        Action::ReloadDismissed => {
+            app.shared_state.sources_changed = false;
+        }
+        Action::AssemblerModeChanged(assembler_mode) => {
+            app.settings.assembler_mode = *assembler_mode;
+        }
+        Action::RecordingBudgetChanged(budget) => {
+            app.settings.recording_budget = *budget;
+            app.state.trim_recording(budget);
+            trim_checkpoints(&mut app.shared_state, budget);
+            budget.trim_undo_points(&mut app.shared_state.undo_points);
+        }
+        // Programs run on the UI thread, which is the only one that's tuned. A thread that was
+        // never tuned has nothing to undo.
+        Action::ThreadTuningChanged(tuning) => {
+            let previous = std::mem::replace(&mut app.settings.thread_tuning, *tuning);
+            if *tuning != previous {
+                if let Err(error) = tuning.apply() {
+                    app.warning = Some(error);
+                }
+            }
+        }
+        Action::AutoReloadChanged(auto_reload) => {
+            app.settings.auto_reload = *auto_reload;
+        }
+        Action::FollowPCChanged(follow_pc) => {
+            app.shared_state.follow_pc = *follow_pc;
+            app.shared_state.scroll_once |= *follow_pc;
+        }
+        Action::BookmarkToggled(bookmark) => {
+            if !app.shared_state.bookmarks.remove(bookmark) {
+                app.shared_state.bookmarks.insert(bookmark.clone());
+            }
+            app.shared_state.dirty = true;
+        }
+        Action::BookmarkNavigated { forward } => reduce_bookmark_navigated(app, *forward),
+        Action::GridsScrolled(scroll_offsets) => {
+            app.shared_state.scroll_offsets.extend(
+                scroll_offsets
+                    .iter()
+                    .map(|(grid, &offset)| (grid.clone(), offset)),
+            );
+        }
+        Action::ProjectsClicked => {
+            app.shared_state.projects_open = !app.shared_state.projects_open;
+        }
+        Action::ProjectsClosed => {
+            app.shared_state.projects_open = false;
+        }
+        Action::ProjectsRootPicked(path) => match find_projects_root(path) {
+            Some(root) => {
+                write_projects_root(&root);
+                app.projects = scan_projects(&root);
+                app.settings.projects_root = Some(root);
+            }
+            None => {
+                app.warning = Some(format!(
+                    "No nand2tetris projects were found in {}",
+                    path.display()
+                ));
+            }
+        },
+        Action::ProjectProgramSelected(paths)
+            if paths.len() == 1 && is_exam_file(&paths[0].to_string_lossy()) =>
+        {
+            match std::fs::read(&paths[0]) {
+                Ok(bytes) => open_exam(app, &file_name(&paths[0]), bytes),
+                Err(error) => {
+                    app.warning = Some(format!("Failed to read {}: {}", paths[0].display(), error))
+                }
+            }
+        }
+        Action::ProjectProgramSelected(paths) => match read_program_files(paths) {
+            Ok(files) => load_files(app, &files),
+            Err(error) => app.warning = Some(error),
+        },
         Action::CloseFile => {
-            app.state = Default::default();
-            app.shared_state = Default::default();
+            load_state(app, Default::default());
+        }
+        Action::LoadErrorsRetryClicked => {
+            let Some(load_errors) = app.shared_state.load_errors.take() else {
+                return;
+            };
+            let mut file = load_errors.file;
+            let editor = &app.shared_state.source_editor;
+            if let Some(edited) = editor.file.as_ref().filter(|edited| {
+                editor.modified && edited.path == file.path && edited.name == file.name
+            }) {
+                // Fixed in the editor rather than on disk.
+                file.contents.clone_from(&edited.contents);
+            } else if let Some(path) = &file.path {
+                match std::fs::read_to_string(path) {
+                    Ok(contents) => file.contents = contents,
+                    Err(error) => {
+                        app.warning = Some(format!("Failed to read {}: {}", path.display(), error))
+                    }
+                }
+            }
+            load_hack_file(app, &file);
+        }
+        Action::LoadErrorsClosed => {
+            app.shared_state.load_errors = None;
         }
     }
 }
 
+fn reduce_bookmark_navigated(app: &mut EmulatorApp, forward: bool) {
+    let shared_state = &mut app.shared_state;
+    let Some(from) = shared_state
+        .bookmark_cursor
+        .clone()
+        .or_else(|| app.state.pc_row())
+    else {
+        return;
+    };
+    let Some(bookmark) = next_bookmark(&shared_state.bookmarks, &from, forward).cloned() else {
+        return;
+    };
+    let grid = match (&mut app.state, &bookmark.file) {
+        (AppState::VM(vm_state), Some(file)) => {
+            reduce_vm_file_selected(vm_state, file);
+            format!("VM {}", file)
+        }
+        _ => "ROM".to_owned(),
+    };
+    shared_state.jump_to_row = Some((grid, bookmark.row));
+    shared_state.bookmark_cursor = Some(bookmark);
+    shared_state.follow_pc = false;
+}
+
 pub fn reduce_common(
     state: &mut impl CommonState,
     shared_state: &mut SharedState,
     action: &CommonAction,
 ) {
     match action {
-        CommonAction::StepClicked => {}
+        CommonAction::StepClicked => {
+            state.cancel_function_step();
+            shared_state.stop_reason = None;
+        }
+        CommonAction::StepOverClicked | CommonAction::StepOutClicked => {
+            let step = if *action == CommonAction::StepOverClicked {
+                FunctionStep::Over
+            } else {
+                FunctionStep::Out
+            };
+            shared_state.run_started = state.start_function_step(step);
+            shared_state.stop_reason = None;
+        }
+        CommonAction::StepFrameClicked => {
+            state.cancel_function_step();
+            shared_state.run_started = state.run_frame(shared_state.frame_sync);
+            shared_state.stop_reason = None;
+            shared_state.scroll_once = true;
+            check_invariants(state, shared_state);
+        }
+        CommonAction::FrameSyncChanged(frame_sync) => {
+            shared_state.frame_sync = *frame_sync;
+        }
         CommonAction::RunClicked => {
+            state.cancel_function_step();
             shared_state.run_started = true;
+            shared_state.stop_reason = None;
         }
         CommonAction::PauseClicked => {
+            state.cancel_function_step();
             shared_state.run_started = false;
         }
         CommonAction::ResetClicked => {
             state.reset();
             shared_state.run_started = false;
+            shared_state.stop_reason = None;
             shared_state.scroll_once = true;
         }
         CommonAction::BreakpointsClicked => {
@@ -123,18 +1076,499 @@ pub fn reduce_common(
         CommonAction::BreakpointsClosed => {
             shared_state.breakpoints_open = false;
         }
+        CommonAction::InvariantsClicked => {
+            shared_state.invariants_open = !shared_state.invariants_open;
+        }
+        CommonAction::InvariantsClosed => {
+            shared_state.invariants_open = false;
+        }
+        CommonAction::LogClicked => {
+            shared_state.log_open = !shared_state.log_open;
+        }
+        CommonAction::LogClosed => {
+            shared_state.log_open = false;
+        }
+        CommonAction::LogCleared => {
+            state.log_mut().clear();
+        }
+        CommonAction::RecordingChanged(recording) => {
+            state.set_recording(*recording);
+        }
+        CommonAction::WriteTrackingChanged(tracking) => {
+            state.set_write_tracking(*tracking);
+        }
         CommonAction::SpeedSliderMoved(new_value) => {
             shared_state.desired_steps_per_second = *new_value;
         }
     }
 }
 
+fn reduce_source_editor(app: &mut EmulatorApp, action: &SourceEditorAction) {
+    let editor = &mut app.shared_state.source_editor;
+    match action {
+        SourceEditorAction::Clicked => editor.open = !editor.open,
+        SourceEditorAction::Closed => editor.open = false,
+        SourceEditorAction::Edited(contents) => {
+            if let Some(file) = &mut editor.file {
+                file.contents.clone_from(contents);
+                editor.modified = true;
+            }
+        }
+        // The edits aren't saved, assembling only replaces the loaded program.
+        SourceEditorAction::AssembleClicked => {
+            if let Some(file) = editor.file.clone() {
+                let modified = editor.modified;
+                load_hack_file(app, &file);
+                app.shared_state.source_editor.modified = modified;
+            }
+        }
+        SourceEditorAction::SaveClicked => {
+            let Some(file) = &editor.file else {
+                return;
+            };
+            let Some(path) = &file.path else {
+                return;
+            };
+            match std::fs::write(path, &file.contents) {
+                Ok(()) => {
+                    editor.modified = false;
+                    // The program already has the saved contents or they're about to be
+                    // assembled, the save isn't a change from outside.
+                    app.file_watch = Default::default();
+                }
+                Err(error) => {
+                    app.warning = Some(format!("Failed to save {}: {}", path.display(), error))
+                }
+            }
+        }
+    }
+}
+
+fn refresh_find(app: &mut EmulatorApp) {
+    let find = &mut app.shared_state.find;
+    if !find.open {
+        return;
+    }
+    find.error = None;
+    let query = match FindQuery::new(&find.query, find.regex) {
+        Ok(query) => query,
+        Err(error) => {
+            find.error = Some(error);
+            None
+        }
+    };
+    let (file, matches) = query
+        .as_ref()
+        .map_or((None, vec![]), |query| app.state.find(query));
+    if file != find.file || !find.current.is_some_and(|row| matches.contains(&row)) {
+        find.current = None;
+    }
+    find.file = file;
+    find.matches = matches;
+    find.results = match &query {
+        Some(query) if find.all_files => app.state.find_in_files(query),
+        _ => vec![],
+    };
+}
+
+fn reduce_find(app: &mut EmulatorApp, action: &FindAction) {
+    let find = &mut app.shared_state.find;
+    match action {
+        FindAction::Opened => find.open = true,
+        FindAction::Closed => {
+            *find = FindState {
+                query: std::mem::take(&mut find.query),
+                regex: find.regex,
+                all_files: find.all_files,
+                ..Default::default()
+            };
+            return;
+        }
+        FindAction::QueryChanged(query) => query.clone_into(&mut find.query),
+        FindAction::RegexChanged(regex) => find.regex = *regex,
+        FindAction::AllFilesChanged(all_files) => find.all_files = *all_files,
+        FindAction::ResultClicked { file, row } => {
+            if let AppState::VM(vm_state) = &mut app.state {
+                reduce_vm_file_selected(vm_state, file);
+            }
+            refresh_find(app);
+            app.shared_state.find.current = Some(*row);
+            app.shared_state.jump_to_row = Some((format!("VM {}", file), *row));
+            app.shared_state.follow_pc = false;
+            return;
+        }
+        FindAction::Navigated { forward } => {
+            refresh_find(app);
+            let pc_row = app.state.pc_row().map(|pc_row| pc_row.row);
+            let find = &mut app.shared_state.find;
+            let from = find.current.or(pc_row).unwrap_or(0);
+            let row = if *forward {
+                let next = find.matches.iter().find(|&&row| row > from);
+                next.or(find.matches.first())
+            } else {
+                let previous = find.matches.iter().rev().find(|&&row| row < from);
+                previous.or(find.matches.last())
+            };
+            let Some(&row) = row else {
+                return;
+            };
+            let grid = match &find.file {
+                Some(file) => format!("VM {}", file),
+                None => "ROM".to_owned(),
+            };
+            find.current = Some(row);
+            app.shared_state.jump_to_row = Some((grid, row));
+            app.shared_state.follow_pc = false;
+            return;
+        }
+    }
+    refresh_find(app);
+}
+
+fn reduce_keyboard(keyboard_state: &mut KeyboardState, action: &KeyboardAction) {
+    match action {
+        KeyboardAction::Clicked => keyboard_state.open = !keyboard_state.open,
+        KeyboardAction::Closed => keyboard_state.open = false,
+        KeyboardAction::OverrideValueChanged(value) => keyboard_state.override_value = *value,
+        KeyboardAction::OverrideStepsChanged(steps) => keyboard_state.override_steps = *steps,
+        KeyboardAction::HoldClicked => {
+            keyboard_state.remaining_override_steps = keyboard_state.override_steps;
+        }
+        KeyboardAction::ReleaseClicked => keyboard_state.remaining_override_steps = 0,
+    }
+}
+
+fn reduce_profiler(app: &mut EmulatorApp, action: &ProfilerAction) {
+    let profiler_state = &mut app.shared_state.profiler;
+    match action {
+        ProfilerAction::Clicked => profiler_state.open = !profiler_state.open,
+        ProfilerAction::Closed => profiler_state.open = false,
+        ProfilerAction::SamplingChanged(sampling) => {
+            let interval = Duration::from_micros(profiler_state.interval_micros);
+            app.state.set_sampling(sampling.then_some(interval));
+        }
+        ProfilerAction::IntervalChanged(interval_micros) => {
+            profiler_state.interval_micros = *interval_micros;
+            // A running sampler restarts with the new interval.
+            if app.state.sampler().is_some() {
+                app.state
+                    .set_sampling(Some(Duration::from_micros(*interval_micros)));
+            }
+        }
+    }
+}
+
+// Results of scripts that are still there are kept when the directory is scanned again.
+fn refresh_tests(tests_state: &mut TestsState, directory: Option<&Path>) {
+    let Some(directory) = directory else {
+        return;
+    };
+    if tests_state.directory.as_deref() != Some(directory) {
+        tests_state.results.clear();
+        tests_state.selected = None;
+    }
+    let mut previous = std::mem::take(&mut tests_state.results);
+    tests_state.directory = Some(directory.to_path_buf());
+    tests_state.results = find_test_scripts(directory)
+        .into_iter()
+        .map(
+            |path| match previous.iter().position(|result| result.path == path) {
+                Some(index) => previous.swap_remove(index),
+                None => TestResult {
+                    name: file_stem(&path.file_name().unwrap_or_default().to_string_lossy())
+                        .to_owned(),
+                    path,
+                    status: TestStatus::NotRun,
+                    output: vec![],
+                },
+            },
+        )
+        .collect();
+    tests_state.selected = tests_state
+        .selected
+        .filter(|&selected| selected < tests_state.results.len());
+}
+
+fn start_tests(
+    tests_state: &mut TestsState,
+    sender: &Sender<Action>,
+    filter: impl Fn(&TestResult) -> bool,
+) {
+    tests_state.run += 1;
+    tests_state.cancelled.store(true, Ordering::Relaxed);
+    tests_state.cancelled = Arc::default();
+    let mut scripts = vec![];
+    for (index, result) in tests_state.results.iter_mut().enumerate() {
+        if filter(result) {
+            result.status = TestStatus::Running;
+            result.output.clear();
+            scripts.push((index, result.path.clone()));
+        } else if matches!(result.status, TestStatus::Running) {
+            // The previous run won't finish it anymore.
+            result.status = TestStatus::NotRun;
+        }
+    }
+    run_tests(
+        tests_state.run,
+        scripts,
+        sender,
+        tests_state.cancelled.clone(),
+    );
+}
+
+fn reduce_tests(app: &mut EmulatorApp, action: &TestsAction) {
+    let tests_state = &mut app.shared_state.tests;
+    match action {
+        TestsAction::Clicked => {
+            tests_state.open = !tests_state.open;
+            if tests_state.open && !tests_state.running() {
+                refresh_tests(tests_state, app.state.program_directory());
+            }
+        }
+        TestsAction::Closed => tests_state.open = false,
+        TestsAction::RefreshClicked => refresh_tests(tests_state, app.state.program_directory()),
+        TestsAction::RunAllClicked => {
+            refresh_tests(tests_state, app.state.program_directory());
+            start_tests(tests_state, &app.async_actions.0, |_| true);
+        }
+        TestsAction::RunFailedClicked => start_tests(tests_state, &app.async_actions.0, |result| {
+            result.status.failed()
+        }),
+        TestsAction::FilterChanged(filter) => tests_state.filter.clone_from(filter),
+        TestsAction::Selected(index) => tests_state.selected = Some(*index),
+        TestsAction::ComparisonClosed => tests_state.selected = None,
+        TestsAction::OutputProduced { run, index, line } => {
+            if *run != tests_state.run {
+                return;
+            }
+            if let Some(result) = tests_state.results.get_mut(*index) {
+                result.output.push(line.clone());
+            }
+        }
+        TestsAction::Finished {
+            run,
+            index,
+            outcome,
+        } => {
+            if *run != tests_state.run {
+                return;
+            }
+            if let Some(result) = tests_state.results.get_mut(*index) {
+                result.status = TestStatus::Finished(outcome.clone());
+            }
+        }
+    }
+}
+
+fn reduce_trace_view(
+    state: &mut impl CommonState,
+    shared_state: &mut SharedState,
+    action: &TraceViewAction,
+) {
+    let trace_view_state = &mut shared_state.trace_view;
+    match action {
+        TraceViewAction::Clicked => trace_view_state.open = !trace_view_state.open,
+        TraceViewAction::Closed => trace_view_state.open = false,
+        TraceViewAction::StepChanged(step) => trace_view_state.step = *step,
+        TraceViewAction::QueryChanged(query) => trace_view_state.query.clone_from(query),
+        TraceViewAction::FindLastClicked => {
+            let result = parse_expression(&trace_view_state.query).and_then(|condition| {
+                let snapshot = state.find_last(&condition).ok_or_else(|| {
+                    format!("{} wasn't true since the recording started", condition)
+                })?;
+                state.travel_to(&snapshot);
+                Ok(format!(
+                    "Travelled back to step {}, the last one where {}",
+                    snapshot.ticks(),
+                    condition
+                ))
+            });
+            if result.is_ok() {
+                shared_state.run_started = false;
+                shared_state.scroll_once = true;
+            }
+            shared_state.trace_view.query_result = Some(result);
+        }
+    }
+}
+
+fn reduce_memory(
+    state: &mut impl CommonState,
+    shared_state: &mut SharedState,
+    action: MemoryAction,
+) {
+    match action {
+        MemoryAction::Clicked => shared_state.memory_open = !shared_state.memory_open,
+        MemoryAction::Closed => shared_state.memory_open = false,
+        // Starting the recording over drops its keyframes too.
+        MemoryAction::Cleared(RecordedData::Trace) => {
+            if state.recording().is_some() {
+                state.set_recording(true);
+            }
+        }
+        MemoryAction::Cleared(RecordedData::Keyframes) => state.history_mut().clear(),
+        MemoryAction::Cleared(RecordedData::Checkpoints) => {
+            shared_state.checkpoints.checkpoints.clear();
+            shared_state.diff = DiffState {
+                open: shared_state.diff.open,
+                ..Default::default()
+            };
+        }
+        MemoryAction::Cleared(RecordedData::UndoPoints) => shared_state.undo_points.clear(),
+        MemoryAction::Cleared(RecordedData::Log) => state.log_mut().clear(),
+    }
+}
+
+fn reduce_invariant(invariants_state: &mut InvariantsState, action: &InvariantAction) {
+    match action {
+        InvariantAction::SourceChanged(source) => {
+            invariants_state.new_source = source.clone();
+            invariants_state.error = None;
+        }
+        InvariantAction::AddClicked => match Invariant::new(&invariants_state.new_source) {
+            Ok(invariant) => {
+                invariants_state.invariants.push(invariant);
+                invariants_state.new_source.clear();
+            }
+            Err(error) => invariants_state.error = Some(error),
+        },
+        InvariantAction::RemoveClicked(index) => {
+            invariants_state.invariants.remove(*index);
+            invariants_state.violation = None;
+        }
+    }
+}
+
+fn trim_checkpoints(shared_state: &mut SharedState, budget: &RecordingBudget) {
+    let checkpoints = &mut shared_state.checkpoints.checkpoints;
+    let count = checkpoints.len();
+    budget.trim_snapshots(checkpoints, |checkpoint| checkpoint.last_used);
+    // Checkpoint indices shift when the list changes.
+    if checkpoints.len() != count {
+        shared_state.diff = DiffState {
+            open: shared_state.diff.open,
+            ..Default::default()
+        };
+    }
+}
+
+fn reduce_checkpoint(
+    state: &mut impl CommonState,
+    shared_state: &mut SharedState,
+    budget: &RecordingBudget,
+    action: &CheckpointAction,
+) {
+    let checkpoints_state = &mut shared_state.checkpoints;
+    match action {
+        CheckpointAction::NameChanged(name) => {
+            checkpoints_state.new_name = name.clone();
+        }
+        CheckpointAction::AddClicked => {
+            let ticks = state.ticks();
+            let name = match std::mem::take(&mut checkpoints_state.new_name) {
+                name if name.trim().is_empty() => format!("Step {}", ticks),
+                name => name,
+            };
+            let index = checkpoints_state
+                .checkpoints
+                .partition_point(|checkpoint| checkpoint.ticks <= ticks);
+            checkpoints_state.checkpoints.insert(
+                index,
+                Checkpoint {
+                    name,
+                    ticks,
+                    snapshot: state.snapshot(),
+                    last_used: checkpoints_state.uses,
+                },
+            );
+            checkpoints_state.uses += 1;
+        }
+        CheckpointAction::Selected(index) => {
+            let checkpoint = &mut checkpoints_state.checkpoints[*index];
+            checkpoint.last_used = checkpoints_state.uses;
+            checkpoints_state.uses += 1;
+            state.restore(&checkpoint.snapshot);
+            shared_state.dirty = true;
+            shared_state.run_started = false;
+            shared_state.scroll_once = true;
+        }
+        CheckpointAction::RemoveClicked(index) => {
+            checkpoints_state.checkpoints.remove(*index);
+        }
+    }
+
+    if *action == CheckpointAction::AddClicked {
+        trim_checkpoints(shared_state, budget);
+    }
+
+    // Checkpoint indices shift when the list changes.
+    if matches!(
+        action,
+        CheckpointAction::AddClicked | CheckpointAction::RemoveClicked(_)
+    ) {
+        shared_state.diff = DiffState {
+            open: shared_state.diff.open,
+            ..Default::default()
+        };
+    }
+}
+
+fn reduce_diff(state: &impl CommonState, shared_state: &mut SharedState, action: &DiffAction) {
+    let diff_state = &mut shared_state.diff;
+    match action {
+        DiffAction::Clicked => {
+            diff_state.open = !diff_state.open;
+        }
+        DiffAction::Closed => {
+            diff_state.open = false;
+        }
+        DiffAction::FromSelected(from) => {
+            diff_state.from = *from;
+        }
+        DiffAction::ToSelected(to) => {
+            diff_state.to = *to;
+        }
+        DiffAction::CompareClicked => {
+            let current = state.snapshot();
+            let checkpoints = &shared_state.checkpoints.checkpoints;
+            let snapshot = |selection: Option<usize>| {
+                selection.map_or(&current, |index| &checkpoints[index].snapshot)
+            };
+            diff_state.diff = Some(SnapshotDiff::new(
+                snapshot(diff_state.from),
+                snapshot(diff_state.to),
+            ));
+        }
+    }
+}
+
+pub fn check_invariants(state: &impl CommonState, shared_state: &mut SharedState) {
+    let violation = state.check_invariants(&shared_state.invariants.invariants);
+    if violation.is_some() {
+        shared_state.run_started = false;
+        shared_state.invariants_open = true;
+        shared_state.invariants.violation = violation;
+    }
+}
+
+// Faults are explained in the log.
+pub fn stop(shared_state: &mut SharedState, stop_reason: StopReason) {
+    if stop_reason == StopReason::StepLimit {
+        return;
+    }
+    shared_state.run_started = false;
+    if let StopReason::Fault(_) = stop_reason {
+        shared_state.log_open = true;
+    }
+    shared_state.stop_reason = Some(stop_reason);
+}
+
 pub fn steps_to_run(
     desired_steps_per_second: u64,
     last_frame_time: f32,
     performance_data: &mut PerformanceData,
     run_started: bool,
-    action: &Option<Action>,
+    step_clicked: bool,
 ) -> u64 {
     if !run_started
         || performance_data.previous_desired_steps_per_second != desired_steps_per_second
@@ -146,7 +1580,7 @@ pub fn steps_to_run(
     }
 
     if !run_started {
-        return matches!(action, Some(Action::Common(CommonAction::StepClicked))) as u64;
+        return step_clicked as u64;
     }
 
     let run_start = performance_data.run_start.get_or_insert(Instant::now());
@@ -1,11 +1,31 @@
 use super::hardware_state::HardwareState;
+use super::history::History;
 use super::instant::Instant;
-use super::vm_state::VMState;
+use super::sampler::Sampler;
+use super::snapshot::{CheckpointsState, DiffState, Snapshot};
+use super::vm_state::{OSClassSource, VMState};
+pub use crate::machine::Breakpoint;
 use crate::{
-    hardware::{self, Word, RAM},
-    vm,
+    annotation::AnnotationScript,
+    diagnostics::{DiagnosticCategory, DiagnosticsConfig, Severity},
+    expression::{Expression, Invariant},
+    hardware::{HackKey, Instruction, UWord, Word, RAM},
+    hardware_parse::{AssemblerMode, ProgramError},
+    metadata::ProgramMetadata,
+    recording::{Recording, RecordingBudget},
+    script::{MessageTemplate, Script},
+    session::{Bookmark, Session},
+    test_script::TestOutcome,
+    thread_tuning::ThreadTuning,
+    vm::SegmentInit,
 };
 use eframe::egui::{DroppedFile, Key, Modifiers};
+use regex::{Regex, RegexBuilder};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Default)]
@@ -16,52 +36,969 @@ pub enum AppState {
     Start,
 }
 
+impl AppState {
+    pub fn from_session(session: Session) -> Self {
+        match session {
+            Session::Hardware { hardware, .. } => {
+                AppState::Hardware(HardwareState::from_hardware(hardware, Default::default()))
+            }
+            Session::VM {
+                files,
+                disabled_files,
+                vm_os_classes,
+                segment_init,
+                entry_function,
+                breakpoints,
+                run_state,
+                view,
+            } => {
+                let mut vm_state = VMState::from_file_contents(files);
+                vm_state.disabled_files.extend(disabled_files);
+                vm_state.vm_os_classes.extend(vm_os_classes);
+                vm_state.vm.set_segment_init(segment_init);
+                // Re-links with the files, classes and segments above.
+                vm_state.set_entry_function(entry_function);
+                for breakpoint in &breakpoints {
+                    vm_state.vm.add_breakpoint(breakpoint);
+                }
+                if let Some(run_state) =
+                    run_state.filter(|run_state| vm_state.vm.program.fits(run_state))
+                {
+                    vm_state.restore(&Snapshot::VM(run_state));
+                }
+                vm_state.select_file(view.selected_file);
+                AppState::VM(vm_state)
+            }
+        }
+    }
+
+    pub fn source_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<_> = match self {
+            AppState::Hardware(state) => state.source_path.as_deref().into_iter().collect(),
+            AppState::VM(state) => state.source_paths.values().map(PathBuf::as_path).collect(),
+            AppState::Start => vec![],
+        };
+        paths.sort();
+        paths
+    }
+
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        match self {
+            AppState::Hardware(state) => state
+                .hardware
+                .get_breakpoints()
+                .iter()
+                .cloned()
+                .map(Breakpoint::Hardware)
+                .collect(),
+            AppState::VM(state) => state
+                .vm
+                .get_breakpoints()
+                .iter()
+                .cloned()
+                .map(Breakpoint::VM)
+                .collect(),
+            AppState::Start => vec![],
+        }
+    }
+
+    // Breakpoints for the other kind of program and ones that are already set are skipped.
+    pub fn add_breakpoints(&mut self, breakpoints: &[Breakpoint]) {
+        let existing = self.breakpoints();
+        for breakpoint in breakpoints
+            .iter()
+            .filter(|breakpoint| !existing.contains(breakpoint))
+        {
+            match (&mut *self, breakpoint) {
+                (AppState::Hardware(state), Breakpoint::Hardware(breakpoint)) => {
+                    state.hardware.add_breakpoint(breakpoint)
+                }
+                (AppState::VM(state), Breakpoint::VM(breakpoint)) => {
+                    state.vm.add_breakpoint(breakpoint)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Reloading the same files keeps the view where it was.
+    pub fn file_names(&self) -> Vec<&str> {
+        match self {
+            AppState::Hardware(state) => state
+                .source_path
+                .iter()
+                .filter_map(|path| path.file_name()?.to_str())
+                .collect(),
+            AppState::VM(state) => state.files.iter().map(|(name, _)| name.as_str()).collect(),
+            AppState::Start => vec![],
+        }
+    }
+
+    pub fn metadata(&self) -> Option<&ProgramMetadata> {
+        match self {
+            AppState::Hardware(state) => Some(&state.metadata),
+            AppState::VM(state) => Some(&state.metadata),
+            AppState::Start => None,
+        }
+    }
+
+    pub fn program_directory(&self) -> Option<&Path> {
+        match self {
+            AppState::Hardware(state) => state.source_path.as_deref()?.parent(),
+            AppState::VM(state) => state.program_directory(),
+            AppState::Start => None,
+        }
+    }
+
+    pub fn ram(&self) -> Option<&RAM> {
+        match self {
+            AppState::Hardware(state) => Some(&state.hardware.ram),
+            AppState::VM(state) => Some(&state.vm.run_state.ram),
+            AppState::Start => None,
+        }
+    }
+
+    pub fn ticks(&self) -> Option<u64> {
+        match self {
+            AppState::Hardware(state) => Some(state.ticks()),
+            AppState::VM(state) => Some(state.ticks()),
+            AppState::Start => None,
+        }
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        match self {
+            AppState::Hardware(state) => state.recording(),
+            AppState::VM(state) => state.recording(),
+            AppState::Start => None,
+        }
+    }
+
+    pub fn sampler(&self) -> Option<&Sampler> {
+        match self {
+            AppState::Hardware(state) => state.sampler(),
+            AppState::VM(state) => state.sampler(),
+            AppState::Start => None,
+        }
+    }
+
+    pub fn set_sampling(&mut self, interval: Option<Duration>) {
+        match self {
+            AppState::Hardware(state) => state.set_sampling(interval),
+            AppState::VM(state) => state.set_sampling(interval),
+            AppState::Start => {}
+        }
+    }
+
+    pub fn profile_regions(&self) -> Vec<(String, usize)> {
+        match self {
+            AppState::Hardware(state) => state.profile_regions(),
+            AppState::VM(state) => state.profile_regions(),
+            AppState::Start => vec![],
+        }
+    }
+
+    pub fn write_tracking(&self) -> bool {
+        match self {
+            AppState::Hardware(state) => state.write_tracking(),
+            AppState::VM(state) => state.write_tracking(),
+            AppState::Start => false,
+        }
+    }
+
+    // Bytes held by each kind of recorded data, checkpoints, undo points and the log included.
+    pub fn memory_usage(&self, shared_state: &SharedState) -> Vec<(RecordedData, usize)> {
+        let (recording, history, log) = match self {
+            AppState::Hardware(state) => (state.recording(), state.history(), &state.log),
+            AppState::VM(state) => (state.recording(), state.history(), &state.log),
+            AppState::Start => return vec![],
+        };
+        vec![
+            (
+                RecordedData::Trace,
+                recording.map_or(0, Recording::memory_size),
+            ),
+            (RecordedData::Keyframes, history.memory_size()),
+            (
+                RecordedData::Checkpoints,
+                shared_state
+                    .checkpoints
+                    .checkpoints
+                    .iter()
+                    .map(|checkpoint| checkpoint.snapshot.memory_size())
+                    .sum(),
+            ),
+            (
+                RecordedData::UndoPoints,
+                shared_state
+                    .undo_points
+                    .iter()
+                    .map(Snapshot::memory_size)
+                    .sum(),
+            ),
+            (
+                RecordedData::Log,
+                log.lines.iter().map(String::capacity).sum(),
+            ),
+        ]
+    }
+
+    pub fn trim_recording(&mut self, budget: &RecordingBudget) {
+        match self {
+            AppState::Hardware(state) => state.trim_recording(budget),
+            AppState::VM(state) => state.trim_recording(budget),
+            AppState::Start => {}
+        }
+    }
+
+    pub fn cancel_function_step(&mut self) {
+        match self {
+            AppState::Hardware(state) => state.cancel_function_step(),
+            AppState::VM(state) => state.cancel_function_step(),
+            AppState::Start => {}
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording().is_some()
+    }
+
+    pub fn location_text(&self, location: usize) -> String {
+        match self {
+            AppState::Hardware(state) => state.location_text(location),
+            AppState::VM(state) => state.location_text(location),
+            AppState::Start => String::new(),
+        }
+    }
+
+    pub fn report(&self) -> Option<String> {
+        match self {
+            AppState::Hardware(state) => state.report(),
+            AppState::VM(state) => state.report(),
+            AppState::Start => None,
+        }
+    }
+
+    pub fn set_diagnostics_config(&mut self, config: &DiagnosticsConfig) {
+        match self {
+            AppState::Hardware(state) => state.diagnostics.config.clone_from(config),
+            AppState::VM(state) => state.diagnostics.config.clone_from(config),
+            AppState::Start => {}
+        }
+    }
+
+    pub fn log(&self) -> Option<&Log> {
+        match self {
+            AppState::Hardware(state) => Some(&state.log),
+            AppState::VM(state) => Some(&state.log),
+            AppState::Start => None,
+        }
+    }
+
+    // The row the PC is on, as a bookmark would point at it.
+    pub fn pc_row(&self) -> Option<Bookmark> {
+        match self {
+            AppState::Hardware(state) => Some(Bookmark {
+                file: None,
+                row: state.hardware.pc as usize,
+            }),
+            AppState::VM(state) => {
+                let run_state = &state.vm.run_state;
+                let file = &state.vm.program.files[run_state.current_file_index];
+                Some(Bookmark {
+                    file: Some(file.name.clone()),
+                    row: run_state.current_command_index - file.starting_command_index,
+                })
+            }
+            AppState::Start => None,
+        }
+    }
+
+    // The rows of the shown grid whose instruction or command matches.
+    pub fn find(&self, query: &FindQuery) -> (Option<String>, Vec<usize>) {
+        match self {
+            AppState::Hardware(state) => {
+                let rows = (0..state.hardware.length)
+                    .filter(|&row| {
+                        query.matches(&if state.show_symbols
+                            && state.rom_display == RomDisplay::Assembly
+                        {
+                            state.disassembly[row].code.clone()
+                        } else {
+                            state.rom_display.format(state.hardware.rom[row])
+                        })
+                    })
+                    .collect();
+                (None, rows)
+            }
+            AppState::VM(state) => {
+                let rows = state
+                    .find_in_file(&state.selected_file, query)
+                    .map(|(row, _)| row)
+                    .collect();
+                (Some(state.selected_file.clone()), rows)
+            }
+            AppState::Start => (None, vec![]),
+        }
+    }
+
+    // Matches in every VM file, in the order of the files.
+    pub fn find_in_files(&self, query: &FindQuery) -> Vec<FindResult> {
+        let AppState::VM(state) = self else {
+            return vec![];
+        };
+        state
+            .vm
+            .program
+            .files
+            .iter()
+            .flat_map(|file| {
+                state
+                    .find_in_file(&file.name, query)
+                    .map(|(row, text)| FindResult {
+                        file: file.name.clone(),
+                        row,
+                        text,
+                    })
+            })
+            .collect()
+    }
+
+    pub fn session(&self, shared_state: &SharedState) -> Option<Session> {
+        if shared_state.from_exam {
+            return None;
+        }
+        let mut session = match self {
+            AppState::Hardware(state) => state.session(),
+            AppState::VM(state) => state.session(),
+            AppState::Start => return None,
+        };
+        let view = session.view_mut();
+        view.scroll_offsets.clone_from(&shared_state.scroll_offsets);
+        view.bookmarks.clone_from(&shared_state.bookmarks);
+        Some(session)
+    }
+}
+
+// How the ROM grid shows each instruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RomDisplay {
+    #[default]
+    Assembly,
+    Binary,
+    Hex,
+}
+
+impl RomDisplay {
+    pub const ALL: [RomDisplay; 3] = [RomDisplay::Assembly, RomDisplay::Binary, RomDisplay::Hex];
+
+    pub fn format(self, instruction: Instruction) -> String {
+        match self {
+            RomDisplay::Assembly => instruction.to_string(),
+            RomDisplay::Binary => format!(
+                "{:0width$b}",
+                instruction.raw(),
+                width = UWord::BITS as usize
+            ),
+            RomDisplay::Hex => format!(
+                "{:0width$X}",
+                instruction.raw(),
+                width = UWord::BITS as usize / 4
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for RomDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomDisplay::Assembly => write!(f, "Instruction"),
+            RomDisplay::Binary => write!(f, "Binary"),
+            RomDisplay::Hex => write!(f, "Hex"),
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum UIStyle {
     Hardware,
     VM,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeFault {
+    AssertionFailed(String),
+    Diagnostic(String),
+    StackCollision(String),
+    OSCall(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    BreakpointHit(usize),
+    Fault(RuntimeFault),
+    StepLimit,
+    Halted,
+    StepFinished,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FunctionStep {
+    // Runs a call to completion, other commands are a single step.
+    Over,
+    // Runs until the current function returns.
+    Out,
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::BreakpointHit(index) => write!(f, "Breakpoint {} hit", index + 1),
+            StopReason::Fault(
+                RuntimeFault::AssertionFailed(message)
+                | RuntimeFault::Diagnostic(message)
+                | RuntimeFault::StackCollision(message)
+                | RuntimeFault::OSCall(message),
+            ) => write!(f, "{}", message),
+            StopReason::StepLimit => write!(f, "Step limit reached"),
+            StopReason::Halted => write!(f, "Program ended"),
+            StopReason::StepFinished => write!(f, "Step finished"),
+        }
+    }
+}
+
 pub trait CommonState {
-    fn run(&mut self, step_count: u64) -> bool;
+    fn run(&mut self, step_count: u64) -> StopReason;
     fn ram_mut(&mut self) -> &mut RAM;
     fn reset(&mut self);
+    fn session(&self) -> Session;
+    // Leaves the frame to the run loop, which stops with StepFinished when it's done. Returns false
+    // if there's nothing to run.
+    fn run_frame(&mut self, frame_sync: FrameSync) -> bool;
+    fn check_invariants(&self, invariants: &[Invariant]) -> Option<String>;
+    fn ticks(&self) -> u64;
+    fn log_mut(&mut self) -> &mut Log;
+    fn set_recording(&mut self, recording: bool);
+    fn recording(&self) -> Option<&Recording>;
+    // The keyframes that time travel replays from.
+    fn history_mut(&mut self) -> &mut History;
+    // Evicts whatever of the trace and keyframes doesn't fit the budget.
+    fn trim_recording(&mut self, budget: &RecordingBudget);
+    fn history(&self) -> &History;
+    fn set_sampling(&mut self, interval: Option<Duration>);
+    fn sampler(&self) -> Option<&Sampler>;
+    fn profile_regions(&self) -> Vec<(String, usize)>;
+    fn location_text(&self, location: usize) -> String;
+    fn report(&self) -> Option<String>;
+    fn snapshot(&self) -> Snapshot;
+    fn restore(&mut self, snapshot: &Snapshot);
+    // The latest recorded step before the current one where `condition` holds.
+    fn find_last(&self, condition: &Expression) -> Option<Snapshot>;
+    fn set_write_tracking(&mut self, tracking: bool);
+    fn write_tracking(&self) -> bool;
+    // Where the last write to `address` came from, while writes are tracked.
+    fn write_origin(&self, address: Word) -> Option<String>;
+    // Restores a recorded step, the recording continues from there.
+    fn travel_to(&mut self, snapshot: &Snapshot);
+    // Starts a step that runs until the call depth is back where it ends, returns whether there
+    // was anything to step through.
+    fn start_function_step(&mut self, step: FunctionStep) -> bool;
+    fn cancel_function_step(&mut self);
+}
+
+pub const MAX_FRAME_STEPS: u64 = 10_000_000;
+
+const MAX_LOG_LINES: usize = 10_000;
+
+#[derive(Default)]
+pub struct Log {
+    pub lines: VecDeque<String>,
+}
+
+impl Log {
+    pub fn extend(&mut self, lines: impl IntoIterator<Item = String>) {
+        self.lines.extend(lines);
+        let excess = self.lines.len().saturating_sub(MAX_LOG_LINES);
+        self.lines.drain(..excess);
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HookKind {
+    #[default]
+    Script,
+    Logpoint,
+}
+
+pub enum CompiledHook {
+    Script(Script),
+    Logpoint(MessageTemplate),
+}
+
+#[derive(Default)]
+pub struct BreakpointHook {
+    pub kind: HookKind,
+    pub source: String,
+    pub compiled: Option<CompiledHook>,
+    pub error: Option<String>,
+}
+
+impl BreakpointHook {
+    pub fn new(kind: HookKind, source: &str) -> Self {
+        let compiled = match kind {
+            _ if source.trim().is_empty() => Ok(None),
+            HookKind::Script => {
+                Script::new(source).map(|script| Some(CompiledHook::Script(script)))
+            }
+            HookKind::Logpoint => {
+                MessageTemplate::new(source).map(|template| Some(CompiledHook::Logpoint(template)))
+            }
+        };
+        let (compiled, error) = match compiled {
+            Ok(compiled) => (compiled, None),
+            Err(error) => (None, Some(error)),
+        };
+        BreakpointHook {
+            kind,
+            source: source.to_owned(),
+            compiled,
+            error,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameSync {
+    Address(Word),
+    Steps(u64),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum CommonAction {
     StepClicked,
+    StepOverClicked,
+    StepOutClicked,
+    StepFrameClicked,
+    FrameSyncChanged(FrameSync),
     RunClicked,
     PauseClicked,
     ResetClicked,
     BreakpointsClicked,
     BreakpointsClosed,
+    InvariantsClicked,
+    InvariantsClosed,
+    LogClicked,
+    LogClosed,
+    LogCleared,
+    RecordingChanged(bool),
+    WriteTrackingChanged(bool),
     SpeedSliderMoved(u64),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Breakpoint {
-    Hardware(hardware::Breakpoint),
-    VM(vm::Breakpoint),
+pub enum BreakpointAction {
+    AddClicked,
+    BreakpointChanged(Breakpoint),
+    RemoveClicked(usize),
+    HookChanged(usize, HookKind, String),
+    // A VM file and one of its rows.
+    LineToggled(String, usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TutorialAction {
+    Started,
+    NextClicked,
+    Closed,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub enum BreakpointAction {
+pub enum TraceViewAction {
+    Clicked,
+    Closed,
+    StepChanged(u64),
+    QueryChanged(String),
+    FindLastClicked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfilerAction {
+    Clicked,
+    Closed,
+    SamplingChanged(bool),
+    IntervalChanged(u64),
+}
+
+pub struct ProfilerState {
+    pub open: bool,
+    pub interval_micros: u64,
+}
+
+impl Default for ProfilerState {
+    fn default() -> Self {
+        Self {
+            open: false,
+            interval_micros: 1000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TestsAction {
+    Clicked,
+    Closed,
+    RefreshClicked,
+    RunAllClicked,
+    RunFailedClicked,
+    FilterChanged(String),
+    Selected(usize),
+    ComparisonClosed,
+    OutputProduced {
+        run: u64,
+        index: usize,
+        line: String,
+    },
+    Finished {
+        run: u64,
+        index: usize,
+        outcome: TestOutcome,
+    },
+}
+
+pub enum TestStatus {
+    NotRun,
+    Running,
+    Finished(TestOutcome),
+}
+
+impl TestStatus {
+    pub fn failed(&self) -> bool {
+        matches!(self, TestStatus::Finished(outcome) if !outcome.passed())
+    }
+}
+
+// `output` holds the rows of a running test as they are produced.
+pub struct TestResult {
+    pub name: String,
+    pub path: PathBuf,
+    pub status: TestStatus,
+    pub output: Vec<String>,
+}
+
+// `run` tells results of the latest run apart from those of a run that was started over.
+#[derive(Default)]
+pub struct TestsState {
+    pub open: bool,
+    pub run: u64,
+    pub directory: Option<PathBuf>,
+    pub results: Vec<TestResult>,
+    pub filter: String,
+    pub selected: Option<usize>,
+    // Shared with the threads of the latest run, which stop once it's set.
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl TestsState {
+    pub fn running(&self) -> bool {
+        self.results
+            .iter()
+            .any(|result| matches!(result.status, TestStatus::Running))
+    }
+}
+
+#[derive(Default)]
+pub struct TraceViewState {
+    pub open: bool,
+    pub step: u64,
+    pub query: String,
+    pub query_result: Option<Result<String, String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckpointAction {
+    NameChanged(String),
+    AddClicked,
+    Selected(usize),
+    RemoveClicked(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffAction {
+    Clicked,
+    Closed,
+    FromSelected(Option<usize>),
+    ToSelected(Option<usize>),
+    CompareClicked,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SegmentInitAction {
+    Clicked,
+    Closed,
+    Changed(SegmentInit),
+    ScriptChanged(String),
+    ScriptApplied,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordedData {
+    Trace,
+    Keyframes,
+    Checkpoints,
+    UndoPoints,
+    Log,
+}
+
+impl std::fmt::Display for RecordedData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordedData::Trace => write!(f, "Trace"),
+            RecordedData::Keyframes => write!(f, "Time travel keyframes"),
+            RecordedData::Checkpoints => write!(f, "Checkpoints"),
+            RecordedData::UndoPoints => write!(f, "Undo points"),
+            RecordedData::Log => write!(f, "Log"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchesAction {
+    Clicked,
+    Closed,
+    SourceChanged(String),
+    AddClicked,
+    RemoveClicked(usize),
+    QueryWatchChanged(usize),
+    QueryValueChanged(i64),
+    FindLastClicked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CountersAction {
+    Clicked,
+    Closed,
+    InstrumentedChanged(bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAction {
+    Clicked,
+    Closed,
+    Cleared(RecordedData),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyboardAction {
+    Clicked,
+    Closed,
+    OverrideValueChanged(Word),
+    OverrideStepsChanged(u64),
+    HoldClicked,
+    ReleaseClicked,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceEditorAction {
+    Clicked,
+    Closed,
+    Edited(String),
+    AssembleClicked,
+    SaveClicked,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FindAction {
+    Opened,
+    Closed,
+    QueryChanged(String),
+    RegexChanged(bool),
+    AllFilesChanged(bool),
+    Navigated { forward: bool },
+    ResultClicked { file: String, row: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvariantAction {
+    SourceChanged(String),
     AddClicked,
-    BreakpointChanged(Breakpoint),
     RemoveClicked(usize),
 }
 
 #[derive(Debug)]
 pub enum Action {
-    FilesPicked(Vec<(String, String)>),
-    FilePicked { name: String, contents: String },
+    // Works out the frame's steps before it's drawn. `step_clicked` is whether the toolbar asked
+    // for a single step.
+    FrameStarted {
+        last_frame_time: f32,
+        host_key: Word,
+        step_clicked: bool,
+    },
+    // The steps the running program gets this frame.
+    StepsRun {
+        step_count: u64,
+    },
+    FrameDrawn,
+    ScrollRestored,
+    RowJumped,
+    // Saved by the undo middleware before actions that move the program.
+    UndoPointSaved,
+    UndoClicked,
+    FilesPicked(Vec<LoadedFile>),
+    FilePicked(LoadedFile),
+    ProjectFilePicked(LoadedFile),
+    ExamPicked(String, Vec<u8>),
+    ExamPasswordChanged(String),
+    ExamUnlockClicked,
+    ExamUnlockClosed,
     FilesDropped(Vec<DroppedFile>),
+    AnnotationsPicked(LoadedFile),
+    AnnotationsClosed,
+    ExampleSelected(usize),
     Breakpoint(BreakpointAction),
+    Invariant(InvariantAction),
+    Keyboard(KeyboardAction),
+    Memory(MemoryAction),
+    SourceEditor(SourceEditorAction),
+    Find(FindAction),
+    Tutorial(TutorialAction),
+    TraceView(TraceViewAction),
+    Profiler(ProfilerAction),
+    Tests(TestsAction),
+    Checkpoint(CheckpointAction),
+    Diff(DiffAction),
     Common(CommonAction),
     VMFileSelected(String),
+    VMSplitFileChanged(Option<String>),
+    VMFileEnabledChanged(String, bool),
+    OSClassSourceChanged(String, OSClassSource),
+    SegmentInit(SegmentInitAction),
+    LinkConflictsClicked,
+    LinkConflictsClosed,
+    StackDepthClicked,
+    StackDepthClosed,
+    Counters(CountersAction),
+    Watches(WatchesAction),
+    RomSymbolsChanged(bool),
+    RomDisplayChanged(RomDisplay),
+    FunctionFileChosen {
+        function_name: String,
+        file_name: String,
+    },
     CloseFile,
+    SessionExported {
+        then_quit: bool,
+    },
     Quit,
+    QuitConfirmed,
+    QuitCancelled,
+    RecoveryAccepted,
+    RecoveryDiscarded,
+    WarningDismissed,
+    ScreenDetachClicked,
+    ScreenAttachClicked,
+    OpenInEditor(usize),
+    EditorCommandChanged(String),
+    DiagnosticSeverityChanged(DiagnosticCategory, Option<Severity>),
+    BackgroundBehaviorChanged(BackgroundBehavior),
+    WindowFocusChanged(bool),
+    GridsScrolled(BTreeMap<String, u32>),
+    FollowPCChanged(bool),
+    BookmarkToggled(Bookmark),
+    BookmarkNavigated {
+        forward: bool,
+    },
+    SourcesChanged,
+    ReloadClicked,
+    ReloadDismissed,
+    AutoReloadChanged(bool),
+    AssemblerModeChanged(AssemblerMode),
+    RecordingBudgetChanged(RecordingBudget),
+    ThreadTuningChanged(ThreadTuning),
+    ProjectsClicked,
+    ProjectsClosed,
+    ProjectsRootPicked(PathBuf),
+    ProjectProgramSelected(Vec<PathBuf>),
+    LoadErrorsRetryClicked,
+    LoadErrorsClosed,
+}
+
+#[derive(Clone, Debug)]
+pub struct LoadedFile {
+    pub name: String,
+    pub contents: String,
+    pub path: Option<PathBuf>,
+}
+
+// What a run does while the window is unfocused or the tab is hidden.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackgroundBehavior {
+    #[default]
+    KeepRunning,
+    Pause,
+    Throttle,
+}
+
+impl BackgroundBehavior {
+    pub const ALL: [BackgroundBehavior; 3] = [
+        BackgroundBehavior::KeepRunning,
+        BackgroundBehavior::Pause,
+        BackgroundBehavior::Throttle,
+    ];
+    const THROTTLED_STEPS_PER_SECOND: u64 = 1000;
+
+    pub fn steps_per_second(self, desired_steps_per_second: u64, focused: bool) -> u64 {
+        match self {
+            BackgroundBehavior::Throttle if !focused => {
+                desired_steps_per_second.min(Self::THROTTLED_STEPS_PER_SECOND)
+            }
+            _ => desired_steps_per_second,
+        }
+    }
+}
+
+impl std::fmt::Display for BackgroundBehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackgroundBehavior::KeepRunning => write!(f, "Keep running"),
+            BackgroundBehavior::Pause => write!(f, "Pause"),
+            BackgroundBehavior::Throttle => write!(
+                f,
+                "Slow down to {} steps/s",
+                Self::THROTTLED_STEPS_PER_SECOND
+            ),
+        }
+    }
+}
+
+pub struct Settings {
+    pub editor_command: String,
+    pub projects_root: Option<PathBuf>,
+    pub diagnostics: DiagnosticsConfig,
+    pub background: BackgroundBehavior,
+    // Changed files are reloaded without asking first.
+    pub auto_reload: bool,
+    pub assembler_mode: AssemblerMode,
+    pub recording_budget: RecordingBudget,
+    pub thread_tuning: ThreadTuning,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            editor_command: "code --goto {file}:{line}".to_owned(),
+            projects_root: None,
+            diagnostics: Default::default(),
+            background: Default::default(),
+            auto_reload: false,
+            assembler_mode: Default::default(),
+            recording_budget: Default::default(),
+            thread_tuning: Default::default(),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -70,6 +1007,120 @@ pub struct PerformanceData {
     pub total_steps: u64,
     pub run_start: Option<Instant>,
     pub previous_desired_steps_per_second: u64,
+    // Steps that actually ran since the last speed sample.
+    pub sampled_steps: u64,
+    pub last_sample: Option<Instant>,
+    // Steps per second over the last SPEED_HISTORY_SECONDS, oldest first.
+    pub speed_history: VecDeque<f64>,
+    pub samples_taken: u64,
+    // Each marker goes before the sample with its index.
+    pub speed_markers: VecDeque<(u64, SpeedMarker)>,
+    // Set when the frame starts.
+    pub frame_steps: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeedMarker {
+    BreakpointHit,
+    Paused,
+    Reloaded,
+}
+
+impl std::fmt::Display for SpeedMarker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpeedMarker::BreakpointHit => write!(f, "Breakpoint hit"),
+            SpeedMarker::Paused => write!(f, "Paused"),
+            SpeedMarker::Reloaded => write!(f, "Reloaded"),
+        }
+    }
+}
+
+pub const SPEED_SAMPLES_PER_SECOND: usize = 4;
+pub const SPEED_HISTORY_SECONDS: usize = 30;
+
+impl PerformanceData {
+    pub fn sample_speed(&mut self, now: Instant) {
+        let Some(last_sample) = self.last_sample else {
+            self.last_sample = Some(now);
+            return;
+        };
+        let elapsed = (now - last_sample).as_secs_f64();
+        if elapsed < 1.0 / SPEED_SAMPLES_PER_SECOND as f64 {
+            return;
+        }
+        if self.speed_history.len() == SPEED_SAMPLES_PER_SECOND * SPEED_HISTORY_SECONDS {
+            self.speed_history.pop_front();
+        }
+        self.speed_history
+            .push_back(self.sampled_steps as f64 / elapsed);
+        self.sampled_steps = 0;
+        self.last_sample = Some(now);
+        self.samples_taken += 1;
+        let oldest = self.samples_taken - self.speed_history.len() as u64;
+        while self
+            .speed_markers
+            .front()
+            .is_some_and(|&(sample, _)| sample < oldest)
+        {
+            self.speed_markers.pop_front();
+        }
+    }
+
+    pub fn mark(&mut self, marker: SpeedMarker) {
+        self.speed_markers.push_back((self.samples_taken, marker));
+    }
+
+    // Steps a stopped batch didn't get to aren't owed to the next frame.
+    pub fn credit_unrun_steps(&mut self, steps_to_run: u64, steps_ran: u64) {
+        let unrun_steps = steps_to_run.saturating_sub(steps_ran);
+        self.total_steps = self.total_steps.saturating_sub(unrun_steps);
+        self.steps_during_last_frame = self.steps_during_last_frame.min(steps_ran);
+    }
+}
+
+#[derive(Default)]
+pub struct InvariantsState {
+    pub invariants: Vec<Invariant>,
+    pub new_source: String,
+    pub error: Option<String>,
+    pub violation: Option<String>,
+}
+
+pub struct KeyboardState {
+    pub open: bool,
+    pub host_value: Word,
+    pub override_value: Word,
+    pub override_steps: u64,
+    pub remaining_override_steps: u64,
+}
+
+impl Default for KeyboardState {
+    fn default() -> Self {
+        Self {
+            open: false,
+            host_value: 0,
+            override_value: 0,
+            override_steps: 1000,
+            remaining_override_steps: 0,
+        }
+    }
+}
+
+impl KeyboardState {
+    // The keyboard value for each part of a run, a held override replaces the host key until the
+    // hold ends partway through.
+    pub fn schedule(&self, steps_to_run: u64) -> [(Word, u64); 2] {
+        let override_steps = steps_to_run.min(self.remaining_override_steps);
+        [
+            (self.override_value, override_steps),
+            (self.host_value, steps_to_run - override_steps),
+        ]
+    }
+
+    pub fn steps_ran(&mut self, steps: u64) {
+        self.remaining_override_steps = self.remaining_override_steps.saturating_sub(steps);
+    }
 }
 
 pub struct SharedState {
@@ -77,6 +1128,124 @@ pub struct SharedState {
     pub run_started: bool,
     pub scroll_once: bool,
     pub breakpoints_open: bool,
+    pub invariants_open: bool,
+    pub invariants: InvariantsState,
+    pub log_open: bool,
+    pub memory_open: bool,
+    pub annotations: Option<AnnotationScript>,
+    pub keyboard: KeyboardState,
+    pub trace_view: TraceViewState,
+    pub profiler: ProfilerState,
+    pub tests: TestsState,
+    pub stop_reason: Option<StopReason>,
+    pub checkpoints: CheckpointsState,
+    pub diff: DiffState,
+    pub projects_open: bool,
+    pub dirty: bool,
+    pub quit_dialog_open: bool,
+    pub screen_detached: bool,
+    pub frame_sync: FrameSync,
+    pub load_errors: Option<LoadErrors>,
+    pub exam_unlock: Option<ExamUnlock>,
+    // The program came out of an exam archive, so nothing may write its sources back out.
+    pub from_exam: bool,
+    pub window_focused: bool,
+    pub scroll_offsets: BTreeMap<String, u32>,
+    // Set when the grids should jump back to the saved offsets instead of following the PC.
+    pub restore_scroll: bool,
+    pub source_editor: SourceEditorState,
+    // Keeps the current instruction in view, scrolling the grids by hand turns it off.
+    pub follow_pc: bool,
+    pub sources_changed: bool,
+    pub bookmarks: BTreeSet<Bookmark>,
+    // The bookmark navigated to last, the next one is looked for from here.
+    pub bookmark_cursor: Option<Bookmark>,
+    // A grid and the row it should centre on the next time it's drawn.
+    pub jump_to_row: Option<(String, usize)>,
+    // Where Undo goes back to, the latest last.
+    pub undo_points: Vec<Snapshot>,
+    pub find: FindState,
+}
+
+// The matches are rows of the grid that was shown when they were looked for.
+#[derive(Default)]
+pub struct FindState {
+    pub open: bool,
+    pub query: String,
+    // The VM file the matches are in, None for the ROM.
+    pub file: Option<String>,
+    pub matches: Vec<usize>,
+    pub current: Option<usize>,
+    pub regex: bool,
+    // Lists the matches in every VM file, not just the shown one.
+    pub all_files: bool,
+    pub results: Vec<FindResult>,
+    // Why the query isn't a valid regular expression.
+    pub error: Option<String>,
+}
+
+// Both kinds ignore case.
+pub enum FindQuery {
+    Text(String),
+    Regex(Regex),
+}
+
+impl FindQuery {
+    // None for an empty query, which matches nothing.
+    pub fn new(query: &str, regex: bool) -> Result<Option<FindQuery>, String> {
+        if query.is_empty() {
+            return Ok(None);
+        }
+        if !regex {
+            return Ok(Some(FindQuery::Text(query.to_lowercase())));
+        }
+        RegexBuilder::new(query)
+            .case_insensitive(true)
+            .build()
+            .map(|regex| Some(FindQuery::Regex(regex)))
+            .map_err(|error| error.to_string())
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            FindQuery::Text(query) => text.to_lowercase().contains(query),
+            FindQuery::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+pub struct FindResult {
+    pub file: String,
+    pub row: usize,
+    pub text: String,
+}
+
+impl FindState {
+    pub fn is_match(&self, row: usize) -> bool {
+        self.matches.binary_search(&row).is_ok()
+    }
+}
+
+// The .asm file loaded last, edited here until it's assembled again.
+#[derive(Default)]
+pub struct SourceEditorState {
+    pub open: bool,
+    pub file: Option<LoadedFile>,
+    pub modified: bool,
+}
+
+// A file that didn't assemble, kept so that it can be loaded again once it's fixed.
+pub struct LoadErrors {
+    pub file: LoadedFile,
+    pub errors: Vec<ProgramError>,
+}
+
+// An exam archive waiting for its password.
+pub struct ExamUnlock {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    pub password: String,
+    pub error: Option<String>,
 }
 
 impl Default for SharedState {
@@ -86,63 +1255,107 @@ impl Default for SharedState {
             run_started: false,
             scroll_once: true,
             breakpoints_open: false,
+            invariants_open: false,
+            invariants: Default::default(),
+            log_open: false,
+            memory_open: false,
+            annotations: None,
+            keyboard: Default::default(),
+            trace_view: Default::default(),
+            profiler: Default::default(),
+            tests: Default::default(),
+            stop_reason: None,
+            checkpoints: Default::default(),
+            diff: Default::default(),
+            projects_open: false,
+            dirty: false,
+            quit_dialog_open: false,
+            screen_detached: false,
+            frame_sync: FrameSync::Steps(100000),
+            load_errors: None,
+            exam_unlock: None,
+            from_exam: false,
+            window_focused: true,
+            scroll_offsets: BTreeMap::new(),
+            restore_scroll: false,
+            source_editor: Default::default(),
+            follow_pc: true,
+            sources_changed: false,
+            bookmarks: BTreeSet::new(),
+            bookmark_cursor: None,
+            jump_to_row: None,
+            undo_points: vec![],
+            find: Default::default(),
+        }
+    }
+}
+
+impl SharedState {
+    pub fn from_metadata(metadata: Option<&ProgramMetadata>) -> Self {
+        let default = Self::default();
+        Self {
+            desired_steps_per_second: metadata
+                .and_then(|metadata| metadata.speed_hint)
+                .unwrap_or(default.desired_steps_per_second),
+            ..default
         }
     }
 }
 
 pub trait StepRunnable {
-    fn run_steps(&mut self, steps_to_run: u64, key_down: Option<Key>, modifiers: Modifiers)
-        -> bool;
+    // Runs each part of the schedule with its keyboard value, returning why the run stopped and
+    // the number of steps it got through.
+    fn run_steps(&mut self, schedule: &[(Word, u64)]) -> (StopReason, u64);
 }
 
 impl<T: CommonState> StepRunnable for T {
-    fn run_steps(
-        &mut self,
-        steps_to_run: u64,
-        key_down: Option<Key>,
-        modifiers: Modifiers,
-    ) -> bool {
-        if steps_to_run > 0 {
-            let keyboard_value = keyboard_value_from_key(key_down, modifiers);
+    fn run_steps(&mut self, schedule: &[(Word, u64)]) -> (StopReason, u64) {
+        let mut steps_ran = 0;
+        for &(keyboard_value, steps_to_run) in schedule {
+            if steps_to_run == 0 {
+                continue;
+            }
             self.ram_mut().set_keyboard(keyboard_value);
-
-            if self.run(steps_to_run) {
-                return false;
+            let ticks_before = self.ticks();
+            let stop_reason = self.run(steps_to_run);
+            steps_ran += self.ticks().saturating_sub(ticks_before);
+            if stop_reason != StopReason::StepLimit {
+                return (stop_reason, steps_ran);
             }
         }
-        true
+
+        (StopReason::StepLimit, steps_ran)
     }
 }
 
-fn keyboard_value_from_key(key: Option<Key>, modifiers: Modifiers) -> Word {
+pub fn keyboard_value_from_key(key: Option<Key>, modifiers: Modifiers) -> Word {
     let mut value = match key {
-        Some(Key::ArrowDown) => 133,
-        Some(Key::ArrowLeft) => 130,
-        Some(Key::ArrowRight) => 132,
-        Some(Key::ArrowUp) => 131,
-        Some(Key::Escape) => 140,
-        Some(Key::Tab) => todo!(),
-        Some(Key::Backspace) => 129,
-        Some(Key::Enter) => 128,
+        Some(Key::ArrowDown) => HackKey::Down.code(),
+        Some(Key::ArrowLeft) => HackKey::Left.code(),
+        Some(Key::ArrowRight) => HackKey::Right.code(),
+        Some(Key::ArrowUp) => HackKey::Up.code(),
+        Some(Key::Escape) => HackKey::Esc.code(),
+        Some(Key::Backspace) => HackKey::Backspace.code(),
+        Some(Key::Enter) => HackKey::NewLine.code(),
         Some(Key::Space) => 32,
-        Some(Key::Insert) => 138,
-        Some(Key::Delete) => 139,
-        Some(Key::Home) => 134,
-        Some(Key::End) => 135,
-        Some(Key::PageUp) => 136,
-        Some(Key::PageDown) => 137,
-        Some(Key::F1) => 141,
-        Some(Key::F2) => 142,
-        Some(Key::F3) => 143,
-        Some(Key::F4) => 144,
-        Some(Key::F5) => 145,
-        Some(Key::F6) => 146,
-        Some(Key::F7) => 147,
-        Some(Key::F8) => 148,
-        Some(Key::F9) => 149,
-        Some(Key::F10) => 150,
-        Some(Key::F11) => 151,
-        Some(Key::F12) => 152,
+        Some(Key::Insert) => HackKey::Insert.code(),
+        Some(Key::Delete) => HackKey::Delete.code(),
+        Some(Key::Home) => HackKey::Home.code(),
+        Some(Key::End) => HackKey::End.code(),
+        Some(Key::PageUp) => HackKey::PageUp.code(),
+        Some(Key::PageDown) => HackKey::PageDown.code(),
+        Some(Key::F1) => HackKey::F1.code(),
+        Some(Key::F2) => HackKey::F2.code(),
+        Some(Key::F3) => HackKey::F3.code(),
+        Some(Key::F4) => HackKey::F4.code(),
+        Some(Key::F5) => HackKey::F5.code(),
+        Some(Key::F6) => HackKey::F6.code(),
+        Some(Key::F7) => HackKey::F7.code(),
+        Some(Key::F8) => HackKey::F8.code(),
+        Some(Key::F9) => HackKey::F9.code(),
+        Some(Key::F10) => HackKey::F10.code(),
+        Some(Key::F11) => HackKey::F11.code(),
+        Some(Key::F12) => HackKey::F12.code(),
         Some(Key::Num0) => 48,
         Some(Key::Num1) => 49,
         Some(Key::Num2) => 50,
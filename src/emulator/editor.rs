@@ -0,0 +1,30 @@
+use std::path::Path;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn editor_args(command: &str, path: &Path, line: usize) -> Vec<String> {
+    let file = path.to_string_lossy();
+    let line = line.to_string();
+    command
+        .split_whitespace()
+        .map(|arg| arg.replace("{file}", &file).replace("{line}", &line))
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_in_editor(command: &str, path: &Path, line: usize) -> Result<(), String> {
+    let args = editor_args(command, path, line);
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| "No editor command is configured".to_owned())?;
+
+    std::process::Command::new(program)
+        .args(args)
+        .spawn()
+        .map(|_| ())
+        .map_err(|error| format!("Failed to run editor command \"{}\": {}", program, error))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn open_in_editor(_command: &str, _path: &Path, _line: usize) -> Result<(), String> {
+    Err("Opening files in an editor isn't supported in the browser".to_owned())
+}
@@ -0,0 +1,98 @@
+use include_dir::{include_dir, Dir};
+
+use super::common_state::AppState;
+use super::hardware_state::HardwareState;
+use super::vm_state::VMState;
+
+pub enum ExampleSource {
+    Asm(&'static str),
+    Hack(&'static str),
+    VM(&'static Dir<'static>),
+}
+
+pub struct Example {
+    pub name: &'static str,
+    pub source: ExampleSource,
+}
+
+static RAYTRACER: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/Raytracer");
+static HACKENSTEIN: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/hackenstein3DVM");
+static DINO: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/Dino");
+static GAME_2048: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/2048");
+static RAYMARCHER: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/Raymarcher");
+
+pub const FILL_ASM: &str = include_str!("../../HackExamples/Fill.asm");
+
+pub static EXAMPLES: &[Example] = &[
+    Example {
+        name: "VM Example 1: Ray Tracer",
+        source: ExampleSource::VM(&RAYTRACER),
+    },
+    Example {
+        name: "VM Example 2: Hackenstein",
+        source: ExampleSource::VM(&HACKENSTEIN),
+    },
+    Example {
+        name: "VM Example 3: Dino",
+        source: ExampleSource::VM(&DINO),
+    },
+    Example {
+        name: "VM Example 4: 2048",
+        source: ExampleSource::VM(&GAME_2048),
+    },
+    Example {
+        name: "VM Example 5: Ray Marcher",
+        source: ExampleSource::VM(&RAYMARCHER),
+    },
+    Example {
+        name: "Hack Example: Ray Marcher",
+        source: ExampleSource::Hack(include_str!("../../r_soj.hack")),
+    },
+    Example {
+        name: "Assembly Example: Pong",
+        source: ExampleSource::Asm(include_str!("../../HackExamples/Pong.asm")),
+    },
+    Example {
+        name: "Assembly Example: Fill",
+        source: ExampleSource::Asm(FILL_ASM),
+    },
+    Example {
+        name: "Assembly Example: Stripes",
+        source: ExampleSource::Asm(include_str!("../../HackExamples/Stripes.asm")),
+    },
+    Example {
+        name: "Assembly Example: Fibonacci",
+        source: ExampleSource::Asm(include_str!("../../HackExamples/Fibonacci.asm")),
+    },
+    Example {
+        name: "Assembly Example: Sort",
+        source: ExampleSource::Asm(include_str!("../../HackExamples/Sort.asm")),
+    },
+];
+
+fn file_contents_from_dir(dir: &Dir) -> Vec<(String, String)> {
+    dir.files()
+        .map(|f| {
+            (
+                f.path().file_name().unwrap().to_str().unwrap().to_owned(),
+                f.contents_utf8().unwrap().to_owned(),
+            )
+        })
+        .collect()
+}
+
+impl Example {
+    pub fn load(&self) -> AppState {
+        match self.source {
+            ExampleSource::Asm(contents) => {
+                AppState::Hardware(HardwareState::from_file_contents(contents))
+            }
+            ExampleSource::Hack(contents) => {
+                AppState::Hardware(HardwareState::from_hack_file_contents(contents))
+            }
+            ExampleSource::VM(dir) => {
+                AppState::VM(VMState::from_file_contents(file_contents_from_dir(dir)))
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::Path;
+
+// The emulator becomes the default program for these.
+#[cfg(any(windows, all(unix, not(target_os = "macos"))))]
+const EXTENSIONS: [(&str, &str, &str); 2] = [
+    ("hack", "text/x-hack", "Hack machine code"),
+    ("vm", "text/x-nand2tetris-vm", "nand2tetris VM code"),
+];
+
+// .asm is x86 and every other kind of assembly too, so the emulator is only added to its Open
+// With list, under the type the system already gives it.
+#[cfg(any(windows, all(unix, not(target_os = "macos"))))]
+const ASM_EXTENSION: (&str, &str, &str) = ("asm", "text/x-asm", "Hack assembly");
+
+#[cfg(any(windows, all(unix, not(target_os = "macos"))))]
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|error| format!("Failed to run {}: {}", program, error))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed with {}", program, status))
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create {}: {}", parent.display(), error))?;
+    }
+    std::fs::write(path, contents)
+        .map_err(|error| format!("Failed to write {}: {}", path.display(), error))
+}
+
+// A desktop entry and a shared MIME package under the user's data directory, the databases are
+// refreshed when the tools for that are installed. The desktop entry also lists .asm's type, which
+// puts the emulator in its Open With menu without making it the default.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn register(executable: &Path) -> Result<Vec<String>, String> {
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::PathBuf::from(home).join(".local/share"))
+        })
+        .ok_or_else(|| "Neither XDG_DATA_HOME nor HOME is set".to_owned())?;
+    let mime_types: Vec<_> = EXTENSIONS
+        .iter()
+        .map(|(_, mime_type, _)| *mime_type)
+        .collect();
+    let (_, asm_mime_type, _) = ASM_EXTENSION;
+
+    let desktop_path = data_dir.join("applications/nand2rust.desktop");
+    write_file(
+        &desktop_path,
+        &format!(
+            "[Desktop Entry]\nType=Application\nName=nand2rust\nComment=nand2tetris emulator\n\
+             Exec=\"{}\" %F\nTerminal=false\nCategories=Development;Education;\nMimeType={};\n",
+            executable.display(),
+            [mime_types.as_slice(), &[asm_mime_type]].concat().join(";")
+        ),
+    )?;
+    let mime_path = data_dir.join("mime/packages/nand2rust.xml");
+    let mime_entries: String = EXTENSIONS
+        .iter()
+        .map(|(extension, mime_type, comment)| {
+            format!(
+                "  <mime-type type=\"{}\">\n    <comment>{}</comment>\n    \
+                 <sub-class-of type=\"text/plain\"/>\n    <glob pattern=\"*.{}\"/>\n  </mime-type>\n",
+                mime_type, comment, extension
+            )
+        })
+        .collect();
+    write_file(
+        &mime_path,
+        &format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n{}</mime-info>\n",
+            mime_entries
+        ),
+    )?;
+
+    let mut notes = vec![
+        format!("Wrote {}", desktop_path.display()),
+        format!("Wrote {}", mime_path.display()),
+    ];
+    let mime_dir = data_dir.join("mime");
+    let refreshes = [
+        run("update-mime-database", &[&mime_dir.to_string_lossy()]),
+        run(
+            "update-desktop-database",
+            &[&data_dir.join("applications").to_string_lossy()],
+        ),
+        run(
+            "xdg-mime",
+            &[&["default", "nand2rust.desktop"], mime_types.as_slice()].concat(),
+        ),
+    ];
+    notes.extend(refreshes.into_iter().filter_map(Result::err).map(|error| {
+        format!(
+            "{}, the association may only apply after logging in again",
+            error
+        )
+    }));
+    Ok(notes)
+}
+
+#[cfg(windows)]
+const CLASSES: &str = r"HKCU\Software\Classes";
+
+// A class opening files with the executable, returns its name.
+#[cfg(windows)]
+fn add_class(extension: &str, comment: &str, executable: &Path) -> Result<String, String> {
+    let class = format!("nand2rust.{}", extension);
+    let class_key = format!(r"{}\{}", CLASSES, class);
+    let command = format!("\"{}\" \"%1\"", executable.display());
+    run("reg", &["add", &class_key, "/ve", "/d", comment, "/f"])?;
+    run(
+        "reg",
+        &[
+            "add",
+            &format!(r"{}\shell\open\command", class_key),
+            "/ve",
+            "/d",
+            &command,
+            "/f",
+        ],
+    )?;
+    Ok(class)
+}
+
+// Per-user classes in the registry, which don't need administrator rights.
+#[cfg(windows)]
+fn register(executable: &Path) -> Result<Vec<String>, String> {
+    let mut notes = vec![];
+    for (extension, _, comment) in EXTENSIONS {
+        let class = add_class(extension, comment, executable)?;
+        let extension_key = format!(r"{}\.{}", CLASSES, extension);
+        run("reg", &["add", &extension_key, "/ve", "/d", &class, "/f"])?;
+        notes.push(format!(
+            ".{} files open with {}",
+            extension,
+            executable.display()
+        ));
+    }
+
+    let (extension, _, comment) = ASM_EXTENSION;
+    let class = add_class(extension, comment, executable)?;
+    run(
+        "reg",
+        &[
+            "add",
+            &format!(r"{}\.{}\OpenWithProgids", CLASSES, extension),
+            "/v",
+            &class,
+            "/t",
+            "REG_NONE",
+            "/f",
+        ],
+    )?;
+    notes.push(format!(
+        ".{} files can be opened with {} from Open With",
+        extension,
+        executable.display()
+    ));
+    Ok(notes)
+}
+
+#[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+fn register(_executable: &Path) -> Result<Vec<String>, String> {
+    Err("Registering file types isn't supported on this platform, \
+         choose the emulator in the file's Open With menu instead"
+        .to_owned())
+}
+
+// Makes double clicking .hack and .vm files open them in this executable and offers it for .asm
+// files, returns what was done.
+pub fn register_file_associations() -> Result<Vec<String>, String> {
+    let executable = std::env::current_exe()
+        .map_err(|error| format!("Failed to find the running executable: {}", error))?;
+    register(&executable)
+}
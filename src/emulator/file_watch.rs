@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+
+// Polls the modification times of the loaded files, there's no watcher API in the browser and
+// polling once a second is cheap for a handful of files.
+#[derive(Default)]
+pub struct FileWatch {
+    files: Vec<(PathBuf, Option<std::time::SystemTime>)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn modified(_path: &Path) -> Option<std::time::SystemTime> {
+    None
+}
+
+impl FileWatch {
+    // Other files than the watched ones start a new watch instead of counting as a change.
+    pub fn changed(&mut self, paths: &[&Path]) -> bool {
+        let watched = self.files.len() == paths.len()
+            && self
+                .files
+                .iter()
+                .zip(paths)
+                .all(|((watched, _), path)| watched == path);
+        if !watched {
+            self.files = paths
+                .iter()
+                .map(|path| (path.to_path_buf(), modified(path)))
+                .collect();
+            return false;
+        }
+        let mut changed = false;
+        for (path, last_modified) in &mut self.files {
+            let modified = modified(path);
+            changed |= modified.is_some() && modified != *last_modified;
+            *last_modified = modified;
+        }
+        changed
+    }
+}
@@ -0,0 +1,186 @@
+use eframe::{egui, epaint::Rect, glow};
+use std::sync::Arc;
+
+use crate::hardware::RAM;
+
+use super::screen::{screen_bytes, ScreenRenderer};
+
+#[derive(Clone, Copy)]
+pub struct GlowScreen {
+    program: glow::Program,
+    vertex_array: glow::VertexArray,
+    texture: glow::Texture,
+}
+
+impl GlowScreen {
+    pub fn new(gl: &glow::Context) -> Result<Self, String> {
+        use glow::HasContext as _;
+
+        let shader_version = if cfg!(target_arch = "wasm32") {
+            "#version 300 es"
+        } else {
+            "#version 410"
+        };
+
+        unsafe {
+            let program = gl.create_program()?;
+
+            let (vertex_shader_source, fragment_shader_source) = (
+                r#"
+                    const vec2 verts[4] = vec2[4](
+                        vec2(1.0, 1.0),
+                        vec2(-1.0, 1.0),
+                        vec2(1.0, -1.0),
+                        vec2(-1.0, -1.0)
+                    );
+
+                    out vec2 v_pos;
+                    void main() {
+                        gl_Position = vec4(verts[gl_VertexID], 0.0, 1.0);
+                        v_pos = gl_Position.xy * vec2(1.0, -1.0);
+                    }
+                "#,
+                r#"
+                    precision mediump float;
+                    uniform lowp usampler2D u_screen;
+                    in vec2 v_pos;
+                    out vec4 out_color;
+                    void main() {
+                        ivec2 coord = ivec2((v_pos + 1.0) * vec2(256.0, 128.0));
+                        uint i_color = uint(1) - ((texelFetch(u_screen, coord / ivec2(8, 1) ,0).r >> (coord.x % 8)) & uint(1));
+                        out_color = vec4(vec3(i_color), 1.0);
+                    }
+                "#,
+            );
+
+            let shader_sources = [
+                (glow::VERTEX_SHADER, vertex_shader_source),
+                (glow::FRAGMENT_SHADER, fragment_shader_source),
+            ];
+
+            let mut shaders = vec![];
+            for (shader_type, shader_source) in shader_sources {
+                let shader = gl.create_shader(shader_type)?;
+                shaders.push(shader);
+                gl.shader_source(shader, &format!("{}\n{}", shader_version, shader_source));
+                gl.compile_shader(shader);
+                if !gl.get_shader_compile_status(shader) {
+                    let error = gl.get_shader_info_log(shader);
+                    for shader in shaders {
+                        gl.delete_shader(shader);
+                    }
+                    gl.delete_program(program);
+                    return Err(error);
+                }
+                gl.attach_shader(program, shader);
+            }
+
+            gl.link_program(program);
+            let link_error =
+                (!gl.get_program_link_status(program)).then(|| gl.get_program_info_log(program));
+
+            for shader in shaders {
+                gl.detach_shader(program, shader);
+                gl.delete_shader(shader);
+            }
+
+            if let Some(error) = link_error {
+                gl.delete_program(program);
+                return Err(error);
+            }
+
+            let vertex_array = gl.create_vertex_array()?;
+
+            let texture = gl.create_texture()?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::REPEAT as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::REPEAT as i32);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            Ok(Self {
+                program,
+                vertex_array,
+                texture,
+            })
+        }
+    }
+
+    fn delete(&self, gl: &glow::Context) {
+        use glow::HasContext as _;
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_vertex_array(self.vertex_array);
+            gl.delete_texture(self.texture);
+        }
+    }
+
+    fn paint(&self, gl: &glow::Context) {
+        use glow::HasContext as _;
+        unsafe {
+            gl.use_program(Some(self.program));
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            gl.uniform_1_i32(
+                gl.get_uniform_location(self.program, "u_screen").as_ref(),
+                0,
+            );
+            gl.bind_vertex_array(Some(self.vertex_array));
+            gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+        }
+    }
+}
+
+impl ScreenRenderer for GlowScreen {
+    fn draw(&mut self, ui: &mut egui::Ui, rect: Rect, ram: &RAM, frame: &eframe::Frame) {
+        unsafe {
+            use glow::HasContext as _;
+            let context = frame.gl().unwrap();
+
+            context.active_texture(glow::TEXTURE0);
+            context.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            context.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::R8UI as i32,
+                64,
+                256,
+                0,
+                glow::RED_INTEGER,
+                glow::UNSIGNED_BYTE,
+                Some(screen_bytes(ram)),
+            );
+            context.bind_texture(glow::TEXTURE_2D, None);
+        }
+
+        // Copy the handles so we can move them into the paint callback:
+        let screen = *self;
+
+        let cb = eframe::egui_glow::CallbackFn::new(move |_info, painter| {
+            screen.paint(painter.gl());
+        });
+
+        let callback = egui::PaintCallback {
+            rect,
+            callback: Arc::new(cb),
+        };
+        ui.painter().add(callback);
+    }
+
+    fn destroy(&mut self, gl: Option<&glow::Context>) {
+        if let Some(gl) = gl {
+            self.delete(gl);
+        }
+    }
+}
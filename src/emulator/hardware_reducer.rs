@@ -1,5 +1,5 @@
 use super::{
-    common_state::{Breakpoint, BreakpointAction},
+    common_state::{Breakpoint, BreakpointAction, BreakpointHook},
     hardware_state::HardwareState,
 };
 
@@ -9,14 +9,20 @@ pub fn reduce_breakpoint_hardware(hardware_state: &mut HardwareState, action: &B
             hardware_state
                 .hardware
                 .add_breakpoint(&hardware_state.selected_breakpoint);
+            hardware_state.breakpoint_hooks.push(Default::default());
         }
         BreakpointAction::RemoveClicked(row_index) => {
             hardware_state.hardware.remove_breakpoint(*row_index);
+            hardware_state.breakpoint_hooks.remove(*row_index);
+        }
+        BreakpointAction::HookChanged(row_index, kind, source) => {
+            hardware_state.breakpoint_hooks[*row_index] = BreakpointHook::new(*kind, source);
         }
         BreakpointAction::BreakpointChanged(Breakpoint::Hardware(new_breakpoint)) => {
             hardware_state.selected_breakpoint = new_breakpoint.clone();
         }
-        BreakpointAction::BreakpointChanged(Breakpoint::VM(_)) => {
+        BreakpointAction::BreakpointChanged(Breakpoint::VM(_))
+        | BreakpointAction::LineToggled(..) => {
             panic!("Invalid action {action:?} in hardware state");
         }
     }
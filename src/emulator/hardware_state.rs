@@ -1,63 +1,351 @@
-use crate::hardware::{
-    Breakpoint, BreakpointVar, Emulator as _, Hardware, Instruction, UWord, RAM,
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::assertion::{parse_assertions, Assertion};
+use crate::diagnostics::{Diagnostics, Severity};
+use crate::disassembler::{disassemble, DisassembledInstruction};
+use crate::expression::{check_invariants, Expression, Invariant};
+use crate::hardware::{Breakpoint, BreakpointVar, Emulator as _, Hardware, StepGoal, Word, RAM};
+use crate::hardware_parse::{
+    expand_macros, instruction_labels, instruction_line_numbers, is_rom_line, label_writes,
+    parse_instructions, LabelWrite, ProgramError, RomFragment,
+};
+use crate::lint::lint_asm;
+use crate::metadata::ProgramMetadata;
+use crate::provenance::Provenance;
+use crate::recording::{Recording, RecordingBudget};
+use crate::report::{html_report, ReportSource};
+use crate::session::Session;
+
+use super::common_state::{
+    BreakpointHook, CommonState, CompiledHook, FrameSync, FunctionStep, Log, RomDisplay,
+    RuntimeFault, StopReason,
 };
+use super::examples::FILL_ASM;
+use super::history::History;
+use super::sampler::{Sampler, SAMPLE_CHECK_STEPS};
+use super::snapshot::Snapshot;
 
-use super::common_state::CommonState;
+const MAX_LOGPOINT_MESSAGES_PER_RUN: u64 = 20;
 
 pub struct HardwareState {
     pub selected_breakpoint: Breakpoint,
     pub hardware: Hardware,
+    pub metadata: ProgramMetadata,
+    pub source_path: Option<PathBuf>,
+    pub source_lines: Vec<usize>,
+    pub breakpoint_hooks: Vec<BreakpointHook>,
+    pub assertions: Vec<Assertion>,
+    pub assertion_stop: Option<u64>,
+    pub label_writes: Vec<LabelWrite>,
+    // Shown once per program, the first time one of the label writes executes.
+    pub label_write_warning: Option<String>,
+    pub label_write_warned: bool,
+    pub diagnostics: Diagnostics,
+    pub recording: Option<Recording>,
+    pub history: History,
+    pub provenance: Option<Provenance>,
+    pub sampler: Option<Sampler>,
+    pub labels: Vec<(String, usize)>,
+    // The ROM ranges of programs loaded as separate fragments.
+    pub fragments: Vec<(String, Range<usize>)>,
+    pub disassembly: Vec<DisassembledInstruction>,
+    // Whether the ROM shows the disassembly with guessed symbols instead of raw instructions.
+    pub show_symbols: bool,
+    pub rom_display: RomDisplay,
+    pub log: Log,
+    pub step_goal: Option<StepGoal>,
 }
 
 impl Default for HardwareState {
     fn default() -> Self {
-        let mut hardware = Hardware::default();
-        let program: [UWord; 29] = [
-            16384, 60432, 16, 58248, 17, 60040, 24576, 64528, 12, 58114, 17, 61064, 17, 64528, 16,
-            65000, 58120, 24576, 60560, 16, 62672, 4, 58115, 16384, 60432, 16, 58248, 4, 60039,
-        ];
-        hardware.load_program(
-            program
-                .iter()
-                .map(|raw| Instruction::new(*raw))
-                .collect::<Vec<_>>(),
-        );
+        HardwareState::from_file_contents(FILL_ASM)
+    }
+}
 
+impl HardwareState {
+    pub fn from_hardware(hardware: Hardware, metadata: ProgramMetadata) -> Self {
         HardwareState {
+            breakpoint_hooks: hardware
+                .breakpoints
+                .iter()
+                .map(|_| Default::default())
+                .collect(),
+            assertions: Vec::new(),
+            assertion_stop: None,
+            label_writes: Vec::new(),
+            label_write_warning: None,
+            label_write_warned: false,
+            diagnostics: Default::default(),
+            recording: None,
+            history: Default::default(),
+            provenance: None,
+            sampler: None,
+            labels: vec![],
+            fragments: vec![],
+            disassembly: disassemble(&hardware.rom[..hardware.length]),
+            show_symbols: false,
+            rom_display: Default::default(),
+            log: Default::default(),
+            step_goal: None,
             selected_breakpoint: Breakpoint {
                 var: BreakpointVar::A,
                 value: 0,
             },
             hardware,
+            metadata,
+            source_path: None,
+            source_lines: Vec::new(),
         }
     }
-}
 
-impl HardwareState {
     pub fn from_file_contents(contents: &str) -> Self {
-        HardwareState {
-            selected_breakpoint: Breakpoint {
-                var: BreakpointVar::A,
-                value: 0,
-            },
-            hardware: Hardware::from_file_contents(contents),
+        Self::try_from_file_contents(contents).unwrap()
+    }
+
+    pub fn try_from_file_contents(contents: &str) -> Result<Self, Vec<ProgramError>> {
+        let mut state = Self {
+            source_lines: instruction_line_numbers(contents),
+            ..Self::from_hardware(
+                Hardware::try_from_file_contents(contents)?,
+                ProgramMetadata::from_file_contents(contents),
+            )
+        };
+        // The hardware loaded, so the macros expand.
+        let expansion = expand_macros(contents).unwrap();
+        match parse_assertions(&expansion.text, is_rom_line) {
+            Ok(assertions) => {
+                state.assertions = assertions
+                    .into_iter()
+                    .map(|assertion| Assertion {
+                        line: expansion.source_line(assertion.line),
+                        ..assertion
+                    })
+                    .collect()
+            }
+            Err(error) => state.log.extend([error]),
+        }
+        if let Ok((_, instructions)) = parse_instructions(&expansion.text) {
+            state.label_writes = label_writes(&instructions);
+            state.labels = instruction_labels(&instructions);
         }
+        state.log.extend(
+            lint_asm(contents)
+                .iter()
+                .map(|finding| format!("Lint: {}", finding)),
+        );
+        Ok(state)
+    }
+
+    // Diagnostics are logged and the first error stops the run.
+    fn check_diagnostics(&mut self) -> Option<RuntimeFault> {
+        if self.diagnostics.config.is_empty() {
+            return None;
+        }
+        let diagnostics = self.diagnostics.check_hardware(&self.hardware);
+        self.log
+            .extend(diagnostics.iter().map(|diagnostic| diagnostic.to_string()));
+        diagnostics
+            .into_iter()
+            .find(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| RuntimeFault::Diagnostic(diagnostic.message))
+    }
+
+    fn check_label_write(&mut self) {
+        let pc = self.hardware.pc as usize;
+        let Some(label_write) = self
+            .label_writes
+            .iter()
+            .find(|label_write| label_write.address == pc)
+        else {
+            return;
+        };
+        self.label_write_warned = true;
+        self.label_write_warning = Some(format!(
+            "ROM[{}] writes to RAM[{}] because A was loaded with @{}, which is the label of \
+             ROM[{}]. Labels name instructions in ROM and programs can't write to ROM, use a \
+             variable like @{} for data instead.",
+            pc,
+            label_write.label_address,
+            label_write.label,
+            label_write.label_address,
+            label_write.label.to_lowercase(),
+        ));
+    }
+
+    // The assertion the run stopped on is skipped, so that it can be resumed.
+    fn failed_assertion(&self) -> Option<String> {
+        if self.assertion_stop == Some(self.hardware.ticks) {
+            return None;
+        }
+        self.assertions
+            .iter()
+            .filter(|assertion| assertion.index == self.hardware.pc as usize)
+            .find_map(|assertion| assertion.check(&self.hardware))
     }
 
     pub fn from_hack_file_contents(contents: &str) -> Self {
-        HardwareState {
-            selected_breakpoint: Breakpoint {
-                var: BreakpointVar::A,
-                value: 0,
-            },
-            hardware: Hardware::from_hack_file_contents(contents),
+        Self::try_from_hack_file_contents(contents).unwrap()
+    }
+
+    pub fn try_from_hack_file_contents(contents: &str) -> Result<Self, Vec<ProgramError>> {
+        Ok(Self {
+            source_lines: instruction_line_numbers(contents),
+            ..Self::from_hardware(Hardware::from_hack_binary(contents)?, Default::default())
+        })
+    }
+
+    // Each fragment starts where the previous one ended unless it has an origin. There's no single
+    // source to open in the editor.
+    pub fn try_from_fragment_files(files: &[(String, String)]) -> Result<Self, Vec<String>> {
+        let mut fragments: Vec<RomFragment> = vec![];
+        let mut metadata = ProgramMetadata::default();
+        for (name, contents) in files {
+            let default_origin = fragments.last().map_or(0, RomFragment::end);
+            let fragment =
+                RomFragment::from_file(name, contents, default_origin).map_err(|errors| {
+                    errors
+                        .iter()
+                        .map(|error| format!("{}, {}", name, error))
+                        .collect::<Vec<_>>()
+                })?;
+            fragments.push(fragment);
+            metadata.merge(ProgramMetadata::from_file_contents(contents));
         }
+        let hardware = Hardware::from_fragments(&fragments).map_err(|error| vec![error])?;
+
+        Ok(Self {
+            fragments: fragments
+                .into_iter()
+                .map(|fragment| (fragment.name.clone(), fragment.origin..fragment.end()))
+                .collect(),
+            ..Self::from_hardware(hardware, metadata)
+        })
+    }
+
+    pub fn source_location(&self, address: usize) -> Option<(&Path, usize)> {
+        Some((
+            self.source_path.as_deref()?,
+            *self.source_lines.get(address)?,
+        ))
     }
 }
 
 impl CommonState for HardwareState {
-    fn run(&mut self, step_count: u64) -> bool {
-        self.hardware.run(step_count)
+    // Breakpoints with a script that continues and logpoints don't stop the run. Each logpoint
+    // logs a limited number of hits per run, hot loops would drown the log otherwise.
+    fn run(&mut self, step_count: u64) -> StopReason {
+        let mut end = self.hardware.ticks + step_count;
+        if let Some(StepGoal::Ticks(ticks)) = self.step_goal {
+            end = end.min(ticks);
+        }
+        let mut logpoint_hits = vec![0; self.breakpoint_hooks.len()];
+        let mut stop_reason = StopReason::StepLimit;
+        while self.hardware.ticks < end {
+            if let Some(failure) = self.failed_assertion() {
+                self.log.extend([failure.clone()]);
+                self.assertion_stop = Some(self.hardware.ticks);
+                stop_reason = StopReason::Fault(RuntimeFault::AssertionFailed(failure));
+                break;
+            }
+            if self.hardware.halted() {
+                stop_reason = StopReason::Halted;
+                break;
+            }
+            if let Some(fault) = self.check_diagnostics() {
+                stop_reason = StopReason::Fault(fault);
+                break;
+            }
+            let checks_label_writes = !self.label_write_warned && !self.label_writes.is_empty();
+            if checks_label_writes {
+                self.check_label_write();
+            }
+            if let Some(provenance) = &mut self.provenance {
+                if let Some(address) = self.hardware.next_write_address() {
+                    provenance.record_write(address, self.hardware.pc as usize);
+                }
+            }
+            if let Some(recording) = &mut self.recording {
+                recording.record(self.hardware.ticks, self.hardware.pc as usize);
+                self.history.record(self.hardware.ticks, || {
+                    Snapshot::Hardware(Box::new(self.hardware.clone()))
+                });
+            }
+            let writes_goal = match self.step_goal {
+                Some(StepGoal::Write(address)) => {
+                    self.hardware.next_write_address() == Some(address)
+                }
+                _ => false,
+            };
+            let steps = if self.assertions.is_empty()
+                && !checks_label_writes
+                && self.diagnostics.config.is_empty()
+                && self.recording.is_none()
+                && self.provenance.is_none()
+                && !matches!(self.step_goal, Some(StepGoal::Write(_)))
+            {
+                let steps = end - self.hardware.ticks;
+                match self.sampler {
+                    Some(_) => steps.min(SAMPLE_CHECK_STEPS),
+                    None => steps,
+                }
+            } else {
+                1
+            };
+            let event = self.hardware.run(steps);
+            if let Some(sampler) = &mut self.sampler {
+                sampler.poll(self.hardware.pc as usize);
+            }
+            if let Some(event) = event {
+                let index = event.breakpoint;
+                let resume = match &self.breakpoint_hooks[index].compiled {
+                    Some(CompiledHook::Script(script)) => {
+                        let outcome = script.run(&mut self.hardware);
+                        self.log.extend(outcome.log);
+                        outcome.resume
+                    }
+                    Some(CompiledHook::Logpoint(template)) => {
+                        logpoint_hits[index] += 1;
+                        if logpoint_hits[index] <= MAX_LOGPOINT_MESSAGES_PER_RUN {
+                            self.log.extend([template.render(&self.hardware)]);
+                        }
+                        true
+                    }
+                    None => false,
+                };
+                if !resume {
+                    stop_reason = StopReason::BreakpointHit(index);
+                    break;
+                }
+            }
+            if writes_goal {
+                stop_reason = StopReason::StepFinished;
+                break;
+            }
+        }
+        if let (StopReason::StepLimit, Some(StepGoal::Ticks(ticks))) =
+            (&stop_reason, self.step_goal)
+        {
+            if self.hardware.ticks >= ticks {
+                stop_reason = StopReason::StepFinished;
+            }
+        }
+
+        self.log.extend(
+            logpoint_hits
+                .iter()
+                .enumerate()
+                .filter(|(_, &hits)| hits > MAX_LOGPOINT_MESSAGES_PER_RUN)
+                .map(|(index, hits)| {
+                    format!(
+                        "Logpoint {} hit {} more times",
+                        index + 1,
+                        hits - MAX_LOGPOINT_MESSAGES_PER_RUN
+                    )
+                }),
+        );
+        stop_reason
     }
 
     fn ram_mut(&mut self) -> &mut RAM {
@@ -66,5 +354,166 @@ impl CommonState for HardwareState {
 
     fn reset(&mut self) {
         self.hardware.reset();
+        self.assertion_stop = None;
+        self.step_goal = None;
+        self.diagnostics.reset();
+        if self.provenance.is_some() {
+            self.set_write_tracking(true);
+        }
+        if self.recording.is_some() {
+            self.set_recording(true);
+        }
+        if let Some(sampler) = &self.sampler {
+            self.set_sampling(Some(sampler.interval));
+        }
+    }
+
+    fn session(&self) -> Session {
+        Session::Hardware {
+            hardware: self.hardware.clone(),
+            view: Default::default(),
+        }
+    }
+
+    // Left to the run loop like the VM's, so breakpoints, hooks and recording work as in a run.
+    fn run_frame(&mut self, frame_sync: FrameSync) -> bool {
+        self.step_goal = Some(match frame_sync {
+            FrameSync::Address(address) => StepGoal::Write(address),
+            FrameSync::Steps(step_count) => StepGoal::Ticks(self.hardware.ticks + step_count),
+        });
+        !self.hardware.halted()
+    }
+
+    fn check_invariants(&self, invariants: &[Invariant]) -> Option<String> {
+        check_invariants(invariants, &self.hardware)
+    }
+
+    fn ticks(&self) -> u64 {
+        self.hardware.ticks
+    }
+
+    fn log_mut(&mut self) -> &mut Log {
+        &mut self.log
+    }
+
+    fn set_recording(&mut self, recording: bool) {
+        self.recording = recording.then(|| Recording::new(self.hardware.length));
+        self.history = History::default();
+    }
+
+    fn set_write_tracking(&mut self, tracking: bool) {
+        self.provenance = tracking.then(Provenance::default);
+    }
+
+    fn write_tracking(&self) -> bool {
+        self.provenance.is_some()
+    }
+
+    fn write_origin(&self, address: Word) -> Option<String> {
+        let location = self.provenance.as_ref()?.last_write(address)?;
+        Some(format!("ROM {}", location))
+    }
+
+    fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    fn trim_recording(&mut self, budget: &RecordingBudget) {
+        if let Some(recording) = &mut self.recording {
+            budget.trim_trace(&mut recording.full_trace);
+        }
+        self.history.trim(budget);
+    }
+
+    fn history(&self) -> &History {
+        &self.history
+    }
+
+    fn set_sampling(&mut self, interval: Option<Duration>) {
+        self.sampler = interval.map(|interval| Sampler::new(interval, self.hardware.length));
+    }
+
+    fn sampler(&self) -> Option<&Sampler> {
+        self.sampler.as_ref()
+    }
+
+    fn profile_regions(&self) -> Vec<(String, usize)> {
+        self.labels.clone()
+    }
+
+    fn location_text(&self, location: usize) -> String {
+        self.hardware.rom[location].to_string()
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(html_report(&ReportSource {
+            title: self.metadata.name.as_deref().unwrap_or("Hardware run"),
+            recording: self.recording.as_ref()?,
+            regions: &self.profile_regions(),
+            location_text: &|location| self.location_text(location),
+        }))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot::Hardware(Box::new(self.hardware.clone()))
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        if let Snapshot::Hardware(hardware) = snapshot {
+            let breakpoints = std::mem::take(&mut self.hardware.breakpoints);
+            self.hardware.clone_from(hardware);
+            self.hardware.breakpoints = breakpoints;
+        }
+    }
+    fn find_last(&self, condition: &Expression) -> Option<Snapshot> {
+        let holds =
+            |hardware: &Hardware| condition.evaluate(hardware).is_some_and(|value| value != 0);
+        self.history
+            .find_last(self.hardware.ticks, |keyframe, end| {
+                let Snapshot::Hardware(keyframe) = keyframe else {
+                    return None;
+                };
+                let mut hardware = keyframe.clone();
+                hardware.breakpoints.clear();
+                let mut found = None;
+                while hardware.ticks < end {
+                    if holds(&hardware) {
+                        found = Some(hardware.ticks);
+                    }
+                    if hardware.halted() {
+                        break;
+                    }
+                    hardware.step_with_events();
+                }
+                let found = found?;
+                let mut hardware = keyframe.clone();
+                hardware.breakpoints.clear();
+                hardware.run(found - hardware.ticks);
+                Some(Snapshot::Hardware(hardware))
+            })
+    }
+
+    fn travel_to(&mut self, snapshot: &Snapshot) {
+        self.restore(snapshot);
+        self.history.truncate(self.hardware.ticks);
+        if self.provenance.is_some() {
+            self.set_write_tracking(true);
+        }
+        if self.recording.is_some() {
+            self.recording = Some(Recording::new(self.hardware.length));
+        }
+    }
+
+    // The CPU has no calls to step over.
+    fn start_function_step(&mut self, _step: FunctionStep) -> bool {
+        false
+    }
+
+    fn cancel_function_step(&mut self) {
+        self.step_goal = None;
     }
 }
@@ -1,17 +1,15 @@
-use std::sync::Arc;
-
 use crate::hardware::{self, BreakpointVar, Word, MEM_SIZE};
-use eframe::{
-    egui,
-    epaint::{mutex::Mutex, Vec2},
-};
+use crate::session::Bookmark;
+use eframe::{egui, epaint::Vec2};
 use egui_extras::{Column, Size, StripBuilder, TableBuilder};
 
 use super::common_state::{
-    Action, Breakpoint, BreakpointAction, CommonAction, SharedState, UIStyle,
+    Action, Breakpoint, BreakpointAction, CommonAction, CommonState, HookKind, SharedState, UIStyle,
 };
 use super::hardware_state::HardwareState;
+use super::screen::{draw_screen, Screen};
 use super::shared_ui::*;
+use super::tutorial::{help_button, mark_tutorial_target, TutorialTarget};
 
 impl HardwareState {
     pub fn draw(
@@ -19,7 +17,7 @@ impl HardwareState {
         ctx: &egui::Context,
         action: &mut Option<Action>,
         shared_state: &SharedState,
-        screen: &Arc<Mutex<Screen>>,
+        screen: &mut Screen,
         frame: &eframe::Frame,
     ) {
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -44,13 +42,67 @@ impl HardwareState {
                                             .size(Size::exact(20.0))
                                             .vertical(|mut strip| {
                                                 strip.cell(|ui| {
-                                                    ui.rom_grid(
+                                                    let mut show_symbols = self.show_symbols;
+                                                    if ui
+                                                        .checkbox(&mut show_symbols, "Symbols")
+                                                        .on_hover_text(
+                                                            "Show the ROM with labels and \
+                                                             symbols guessed from their use",
+                                                        )
+                                                        .changed()
+                                                    {
+                                                        *action = Some(Action::RomSymbolsChanged(
+                                                            show_symbols,
+                                                        ));
+                                                    }
+                                                    if shared_state.follow_pc
+                                                        && scrolled_by_hand(ui)
+                                                    {
+                                                        *action =
+                                                            Some(Action::FollowPCChanged(false));
+                                                    }
+                                                    let mut rom_display = self.rom_display;
+                                                    let row_action = ui.rom_grid(
                                                         "ROM",
                                                         &self.hardware.rom,
                                                         &(0..=((MEM_SIZE - 1) as Word)),
                                                         self.hardware.pc,
-                                                        shared_state.scroll_once,
+                                                        shared_state.follow_pc,
+                                                        self.source_path.is_some(),
+                                                        &shared_state.bookmarks,
+                                                        &shared_state.find,
+                                                        &self.fragments,
+                                                        &mut rom_display,
+                                                        self.show_symbols
+                                                            .then_some(&self.disassembly[..]),
                                                     );
+                                                    match row_action {
+                                                        Some(RowAction::OpenInEditor(address)) => {
+                                                            *action =
+                                                                Some(Action::OpenInEditor(address));
+                                                        }
+                                                        Some(RowAction::ToggleBookmark(row)) => {
+                                                            *action =
+                                                                Some(Action::BookmarkToggled(
+                                                                    Bookmark { file: None, row },
+                                                                ));
+                                                        }
+                                                        Some(RowAction::NavigateBookmark {
+                                                            forward,
+                                                        }) => {
+                                                            *action =
+                                                                Some(Action::BookmarkNavigated {
+                                                                    forward,
+                                                                });
+                                                        }
+                                                        Some(RowAction::ToggleBreakpoint(_))
+                                                        | None => {}
+                                                    }
+                                                    if rom_display != self.rom_display {
+                                                        *action = Some(Action::RomDisplayChanged(
+                                                            rom_display,
+                                                        ));
+                                                    }
                                                 });
 
                                                 strip.empty();
@@ -89,6 +141,11 @@ impl HardwareState {
                                             .size(Size::exact(20.0))
                                             .vertical(|mut strip| {
                                                 strip.cell(|ui| {
+                                                    mark_tutorial_target(
+                                                        ui.ctx(),
+                                                        TutorialTarget::RAMGrid,
+                                                        ui.max_rect(),
+                                                    );
                                                     ui.ram_grid(
                                                         "RAM",
                                                         &self.hardware.ram,
@@ -96,6 +153,7 @@ impl HardwareState {
                                                         UIStyle::Hardware,
                                                         Some(self.hardware.a),
                                                         shared_state.scroll_once,
+                                                        &|address| self.write_origin(address),
                                                     );
                                                 });
 
@@ -136,7 +194,11 @@ impl HardwareState {
                             let screen_height = (available_width / 2.0).min(256.0);
                             let screen_width = available_width.min(512.0);
                             ui.allocate_ui(Vec2::new(screen_width, screen_height), |ui| {
-                                draw_screen(ui, screen, &self.hardware.ram, frame);
+                                if shared_state.screen_detached {
+                                    ui.label("The screen is shown in a separate window.");
+                                } else {
+                                    draw_screen(ui, screen, &self.hardware.ram, frame);
+                                }
                             });
                             ui.add_space(screen_height + 20.0);
                             ui.horizontal(|ui| {
@@ -169,6 +231,11 @@ impl HardwareState {
             .show(ctx, |ui| {
                 let breakpoints = self.hardware.get_breakpoints();
                 ui.horizontal(|ui| {
+                    help_button(
+                        ui,
+                        "A breakpoint pauses the run when a register or RAM word equals its value. \
+                         A script runs when it is hit and a logpoint only writes to the log.",
+                    );
                     let breakpoint_address =
                         if let BreakpointVar::RAM(address) = self.selected_breakpoint.var {
                             address
@@ -263,6 +330,7 @@ impl HardwareState {
                     .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
                     .column(Column::exact(100.0))
                     .column(Column::exact(100.0))
+                    .column(Column::exact(330.0))
                     .column(Column::exact(70.0))
                     .header(header_height, |mut header| {
                         header.col(|ui| {
@@ -271,6 +339,9 @@ impl HardwareState {
                         header.col(|ui| {
                             ui.label("Value");
                         });
+                        header.col(|ui| {
+                            ui.label("On Hit");
+                        });
                         header.col(|_| {});
                     })
                     .body(|body| {
@@ -291,6 +362,43 @@ impl HardwareState {
                                         .unwrap_or("".to_string()),
                                 );
                             });
+                            row.col(|ui| {
+                                let Some(breakpoint_hook) = self.breakpoint_hooks.get(row_index)
+                                else {
+                                    return;
+                                };
+                                let mut kind = breakpoint_hook.kind;
+                                egui::ComboBox::from_id_source(("hook kind", row_index))
+                                    .selected_text(match kind {
+                                        HookKind::Script => "Script",
+                                        HookKind::Logpoint => "Log",
+                                    })
+                                    .width(60.0)
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(&mut kind, HookKind::Script, "Script");
+                                        ui.selectable_value(&mut kind, HookKind::Logpoint, "Log");
+                                    });
+                                let mut source = breakpoint_hook.source.clone();
+                                let mut text_edit = egui::TextEdit::singleline(&mut source)
+                                    .hint_text(match kind {
+                                        HookKind::Script => "log D; continue",
+                                        HookKind::Logpoint => "D = {D}, M = {M}",
+                                    })
+                                    .desired_width(240.0);
+                                if breakpoint_hook.error.is_some() {
+                                    text_edit = text_edit.text_color(ui.visuals().error_fg_color);
+                                }
+                                let response = ui.add(text_edit);
+                                if let Some(error) = &breakpoint_hook.error {
+                                    response.on_hover_text(error);
+                                }
+                                if kind != breakpoint_hook.kind || source != breakpoint_hook.source
+                                {
+                                    *action = Some(Action::Breakpoint(
+                                        BreakpointAction::HookChanged(row_index, kind, source),
+                                    ));
+                                }
+                            });
                             row.col(|ui| {
                                 if breakpoint.is_some() && ui.button("Remove").clicked() {
                                     *action = Some(Action::Breakpoint(
@@ -0,0 +1,64 @@
+use crate::recording::RecordingBudget;
+
+use super::snapshot::Snapshot;
+
+pub const KEYFRAME_STEPS: u64 = 1 << 16;
+
+// Snapshots taken every KEYFRAME_STEPS steps of a recording. Steps in between are found by
+// replaying from the closest snapshot before them, so keys pressed during the run aren't
+// replayed.
+#[derive(Default)]
+pub struct History {
+    keyframes: Vec<Snapshot>,
+}
+
+impl History {
+    pub fn record(&mut self, ticks: u64, snapshot: impl FnOnce() -> Snapshot) {
+        if self
+            .keyframes
+            .last()
+            .is_none_or(|keyframe| ticks >= keyframe.ticks() + KEYFRAME_STEPS)
+        {
+            self.keyframes.push(snapshot());
+        }
+    }
+
+    // Keyframes after a step that was travelled back to describe a future that won't happen.
+    pub fn truncate(&mut self, ticks: u64) {
+        self.keyframes.retain(|keyframe| keyframe.ticks() <= ticks);
+    }
+
+    // Time travel doesn't go further back than the oldest keyframe the budget keeps.
+    pub fn trim(&mut self, budget: &RecordingBudget) {
+        budget.trim_keyframes(&mut self.keyframes);
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.keyframes.iter().map(Snapshot::memory_size).sum()
+    }
+
+    // `search` replays from a keyframe up to the given step, keyframes are searched from the
+    // latest one before `ticks` backwards.
+    pub fn find_last(
+        &self,
+        ticks: u64,
+        mut search: impl FnMut(&Snapshot, u64) -> Option<Snapshot>,
+    ) -> Option<Snapshot> {
+        let mut end = ticks;
+        for keyframe in self.keyframes.iter().rev() {
+            if keyframe.ticks() >= end {
+                continue;
+            }
+            if let Some(found) = search(keyframe, end) {
+                return Some(found);
+            }
+            end = keyframe.ticks();
+        }
+
+        None
+    }
+}
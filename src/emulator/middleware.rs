@@ -0,0 +1,68 @@
+use super::common_state::{Action, AppState, CommonAction, TraceViewAction, WatchesAction};
+
+pub trait Middleware {
+    // Returning false drops the action before it reaches the reducer. Actions pushed to `ahead`
+    // are dispatched first.
+    fn before(&mut self, _state: &AppState, _action: &Action, _ahead: &mut Vec<Action>) -> bool {
+        true
+    }
+
+    fn after(&mut self, _state: &AppState, _action: &Action) {}
+}
+
+// Prints every action but the per frame ones to stderr.
+pub struct ActionLog;
+
+impl Middleware for ActionLog {
+    fn before(&mut self, _state: &AppState, action: &Action, _ahead: &mut Vec<Action>) -> bool {
+        if !matches!(
+            action,
+            Action::FrameStarted { .. }
+                | Action::StepsRun { .. }
+                | Action::FrameDrawn
+                | Action::ScrollRestored
+                | Action::RowJumped
+        ) {
+            eprintln!("{:?}", action);
+        }
+        true
+    }
+}
+
+// Saves the state before every action that moves the program, so Undo can go back to it. A single
+// step runs with the frame, before its click is dispatched.
+pub struct UndoPoints;
+
+fn moves_program(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::FrameStarted {
+            step_clicked: true,
+            ..
+        } | Action::Common(
+            CommonAction::StepOverClicked
+                | CommonAction::StepOutClicked
+                | CommonAction::StepFrameClicked
+                | CommonAction::RunClicked
+                | CommonAction::ResetClicked
+        ) | Action::TraceView(TraceViewAction::FindLastClicked)
+            | Action::Watches(WatchesAction::FindLastClicked)
+    )
+}
+
+impl Middleware for UndoPoints {
+    fn before(&mut self, state: &AppState, action: &Action, ahead: &mut Vec<Action>) -> bool {
+        if !matches!(state, AppState::Start) && moves_program(action) {
+            ahead.push(Action::UndoPointSaved);
+        }
+        true
+    }
+}
+
+pub fn enabled_middleware() -> Vec<Box<dyn Middleware>> {
+    let mut middleware: Vec<Box<dyn Middleware>> = vec![Box::new(UndoPoints)];
+    if std::env::var_os("NAND2TETRIS_LOG_ACTIONS").is_some() {
+        middleware.push(Box::new(ActionLog));
+    }
+    middleware
+}
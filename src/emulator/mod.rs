@@ -1,51 +1,125 @@
 mod common_reducer;
 mod common_state;
+mod editor;
+mod examples;
+#[cfg(not(target_arch = "wasm32"))]
+mod file_association;
+mod file_watch;
+mod glow_screen;
 mod hardware_reducer;
 mod hardware_state;
 mod hardware_ui;
+mod history;
 mod instant;
+mod middleware;
+mod projects;
+mod recovery;
+mod sampler;
+mod screen;
 mod shared_ui;
+mod snapshot;
+mod test_runner;
+mod tutorial;
 mod vm_reducer;
 mod vm_state;
 mod vm_ui;
+#[cfg(feature = "wgpu")]
+mod wgpu_screen;
 
 use common_state::SharedState;
 use eframe::egui;
 
-use egui::mutex::Mutex;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
-use std::sync::Arc;
 
-use common_reducer::reduce;
-use common_reducer::steps_to_run;
-use common_state::{Action, AppState, PerformanceData, StepRunnable};
-use shared_ui::{draw_shared, Screen};
+use common_reducer::dispatch;
+use common_state::{
+    keyboard_value_from_key, Action, AppState, CommonAction, FindAction, PerformanceData, Settings,
+    TutorialAction,
+};
+use file_watch::FileWatch;
+use instant::Instant;
+use middleware::{enabled_middleware, Middleware};
+use projects::{expand_program_paths, read_projects_root, scan_projects, ProjectEntry};
+use recovery::{install_panic_hook, read_recovered_session, update_recovery_session};
+use screen::{draw_detached_screen, Screen};
+use shared_ui::{
+    draw_exam_unlock_dialog, draw_find_bar, draw_projects_window, draw_recovery_dialog,
+    draw_reload_banner, draw_shared, draw_warning_banner, focus_find_bar, jump_to_row,
+    restore_scroll_offsets, take_scroll_offsets, window_title,
+};
+use tutorial::{draw_tutorial, TutorialStep};
 use vm_ui::draw_vm;
 
-use crate::emulator::hardware_state::HardwareState;
-
-use self::vm_state::VMState;
+use crate::session::Session;
+use examples::EXAMPLES;
+#[cfg(not(target_arch = "wasm32"))]
+pub use file_association::register_file_associations;
 
 pub struct EmulatorApp {
     performance_data: PerformanceData,
     shared_state: SharedState,
+    settings: Settings,
+    projects: Vec<ProjectEntry>,
     state: AppState,
-    screen: Arc<Mutex<Screen>>,
+    screen: Screen,
     async_actions: (Sender<Action>, Receiver<Action>),
+    title: String,
+    quitting: bool,
+    recovered_session: Option<Session>,
+    last_recovery_update: Instant,
+    warning: Option<String>,
+    detached_screen_key: Option<egui::Key>,
+    tutorial: Option<TutorialStep>,
+    middleware: Vec<Box<dyn Middleware>>,
+    file_watch: FileWatch,
 }
 
 impl EmulatorApp {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        install_panic_hook();
+
+        let (screen, warning) = Screen::new(cc);
+        let projects_root = read_projects_root();
+
         Self {
             performance_data: Default::default(),
             shared_state: Default::default(),
+            projects: projects_root
+                .as_deref()
+                .map(scan_projects)
+                .unwrap_or_default(),
+            settings: Settings {
+                projects_root,
+                ..Default::default()
+            },
             state: Default::default(),
-            screen: Arc::new(Mutex::new(Screen::new(cc.gl.as_ref().unwrap()))),
+            screen,
             async_actions: channel(),
+            title: window_title(None),
+            quitting: false,
+            recovered_session: read_recovered_session(),
+            last_recovery_update: Instant::now(),
+            warning,
+            detached_screen_key: None,
+            tutorial: None,
+            middleware: enabled_middleware(),
+            file_watch: Default::default(),
         }
     }
+
+    // Files the emulator was launched with, e.g. by double clicking one, load on the first frame.
+    pub fn with_paths(self, paths: &[std::path::PathBuf]) -> Self {
+        let paths = expand_program_paths(paths);
+        if !paths.is_empty() {
+            let _ = self
+                .async_actions
+                .0
+                .send(Action::ProjectProgramSelected(paths));
+        }
+        self
+    }
 }
 
 impl eframe::App for EmulatorApp {
@@ -54,145 +128,225 @@ impl eframe::App for EmulatorApp {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         ctx.input(|i| {
             if !i.raw.dropped_files.is_empty() {
-                reduce(self, &Action::FilesDropped(i.raw.dropped_files.clone()));
+                dispatch(self, &Action::FilesDropped(i.raw.dropped_files.clone()));
             }
         });
 
         while let Ok(action) = self.async_actions.1.try_recv() {
-            reduce(self, &action);
+            dispatch(self, &action);
+        }
+
+        if ctx.input(|i| i.viewport().close_requested()) && !self.quitting {
+            dispatch(self, &Action::Quit);
+            if !self.quitting {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            }
+        }
+
+        if (Instant::now() - self.last_recovery_update).as_secs() >= 1 {
+            if self.file_watch.changed(&self.state.source_paths()) {
+                dispatch(self, &Action::SourcesChanged);
+            }
+            update_recovery_session(self.state.session(&self.shared_state));
+            self.last_recovery_update = Instant::now();
+        }
+
+        let title = window_title(self.state.metadata());
+        if title != self.title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.title = title;
+        }
+
+        if self.shared_state.restore_scroll {
+            restore_scroll_offsets(ctx, self.shared_state.scroll_offsets.clone());
+            dispatch(self, &Action::ScrollRestored);
+        }
+
+        if let Some((grid, row)) = self.shared_state.jump_to_row.clone() {
+            jump_to_row(ctx, grid, row);
+            dispatch(self, &Action::RowJumped);
+        }
+
+        if ctx.memory(|m| m.focus().is_none())
+            && ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::F))
+        {
+            dispatch(self, &Action::Find(FindAction::Opened));
+            focus_find_bar(ctx);
+        }
+
+        if ctx.memory(|m| m.focus().is_none())
+            && ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z))
+            && !self.shared_state.undo_points.is_empty()
+        {
+            dispatch(self, &Action::UndoClicked);
+        }
+
+        if ctx.memory(|m| m.focus().is_none()) {
+            let navigated = ctx.input_mut(|i| {
+                if i.consume_key(
+                    egui::Modifiers::CTRL | egui::Modifiers::SHIFT,
+                    egui::Key::F2,
+                ) {
+                    Some(false)
+                } else if i.consume_key(egui::Modifiers::CTRL, egui::Key::F2) {
+                    Some(true)
+                } else {
+                    None
+                }
+            });
+            if let Some(forward) = navigated {
+                dispatch(self, &Action::BookmarkNavigated { forward });
+            }
         }
 
         let mut action = None;
 
         draw_shared(
             &self.shared_state,
+            &self.state,
+            &self.settings,
             ctx,
             &self.performance_data,
-            !matches!(self.state, AppState::Start),
             &mut action,
             &self.async_actions.0,
         );
 
-        let last_frame_time = frame.info().cpu_usage.unwrap_or(1.0 / 60.0);
-        let steps_to_run = steps_to_run(
-            self.shared_state.desired_steps_per_second,
-            last_frame_time,
-            &mut self.performance_data,
-            self.shared_state.run_started,
-            &action,
-        );
+        if let Some(warning) = &self.warning {
+            draw_warning_banner(ctx, warning, &mut action);
+        }
+
+        if self.shared_state.sources_changed {
+            draw_reload_banner(ctx, &mut action);
+        }
+
+        if self.shared_state.find.open {
+            draw_find_bar(
+                ctx,
+                &self.shared_state.find,
+                matches!(self.state, AppState::VM(_)),
+                &mut action,
+            );
+        }
+
+        if self.shared_state.projects_open {
+            draw_projects_window(
+                ctx,
+                &self.projects,
+                self.settings.projects_root.as_deref(),
+                &mut action,
+                &self.async_actions.0,
+            );
+        }
+
+        if let Some(exam_unlock) = &self.shared_state.exam_unlock {
+            draw_exam_unlock_dialog(ctx, exam_unlock, &mut action);
+        }
+
+        if self.recovered_session.is_some() {
+            draw_recovery_dialog(ctx, &mut action);
+        }
+
+        let focused = ctx.input(|i| i.focused);
+        if focused != self.shared_state.window_focused {
+            dispatch(self, &Action::WindowFocusChanged(focused));
+        }
 
         let key_down = if ctx.memory(|m| m.focus().is_none()) {
             ctx.input(|i| i.keys_down.iter().cloned().next())
         } else {
             None
-        };
-
-        match &mut self.state {
-            AppState::Hardware(state) => {
-                self.shared_state.run_started &=
-                    state.run_steps(steps_to_run, key_down, ctx.input(|i| i.modifiers));
-            }
-            AppState::VM(state) => {
-                self.shared_state.run_started &=
-                    state.run_steps(steps_to_run, key_down, ctx.input(|i| i.modifiers));
-            }
-            _ => {}
         }
+        .or(self.detached_screen_key);
 
+        dispatch(
+            self,
+            &Action::FrameStarted {
+                last_frame_time: frame.info().cpu_usage.unwrap_or(1.0 / 60.0),
+                host_key: keyboard_value_from_key(key_down, ctx.input(|i| i.modifiers)),
+                step_clicked: matches!(action, Some(Action::Common(CommonAction::StepClicked))),
+            },
+        );
+        let steps_to_run = self.performance_data.frame_steps;
         if steps_to_run > 0 {
+            dispatch(
+                self,
+                &Action::StepsRun {
+                    step_count: steps_to_run,
+                },
+            );
+        }
+
+        if steps_to_run > 0 || self.shared_state.tests.running() {
             ctx.request_repaint();
         }
-        self.shared_state.scroll_once |= steps_to_run > 0;
 
         match &self.state {
             AppState::Hardware(state) => {
-                state.draw(ctx, &mut action, &self.shared_state, &self.screen, frame);
+                state.draw(
+                    ctx,
+                    &mut action,
+                    &self.shared_state,
+                    &mut self.screen,
+                    frame,
+                );
             }
             AppState::VM(state) => draw_vm(
                 state,
                 ctx,
                 &mut action,
                 &self.shared_state,
-                &self.screen,
+                &mut self.screen,
                 frame,
             ),
             AppState::Start => {
                 egui::CentralPanel::default().show(ctx, |ui| {
                     ui.vertical(|ui| {
-                        fn file_contents_from_dir(dir: &include_dir::Dir) -> Vec<(String, String)> {
-                            dir.files()
-                                .map(|f| {
-                                    (
-                                        f.path().file_name().unwrap().to_str().unwrap().to_owned(),
-                                        f.contents_utf8().unwrap().to_owned(),
-                                    )
-                                })
-                                .collect()
-                        }
-                        if ui.button("VM Example 1: Ray Tracer").clicked() {
-                            let file_contents = file_contents_from_dir(&include_dir::include_dir!(
-                                "$CARGO_MANIFEST_DIR/Raytracer"
-                            ));
-                            self.state = AppState::VM(VMState::from_file_contents(file_contents));
-                            self.shared_state = Default::default();
-                        }
-                        if ui.button("VM Example 2: Hackenstein").clicked() {
-                            let file_contents = file_contents_from_dir(&include_dir::include_dir!(
-                                "$CARGO_MANIFEST_DIR/hackenstein3DVM"
-                            ));
-                            self.state = AppState::VM(VMState::from_file_contents(file_contents));
-                            self.shared_state = Default::default();
-                        }
-                        if ui.button("VM Example 3: Dino").clicked() {
-                            let file_contents = file_contents_from_dir(&include_dir::include_dir!(
-                                "$CARGO_MANIFEST_DIR/Dino"
-                            ));
-                            self.state = AppState::VM(VMState::from_file_contents(file_contents));
-                            self.shared_state = Default::default();
+                        if ui.button("Take the Guided Tour").clicked() {
+                            action = Some(Action::Tutorial(TutorialAction::Started));
                         }
-                        if ui.button("VM Example 4: 2048").clicked() {
-                            let file_contents = file_contents_from_dir(&include_dir::include_dir!(
-                                "$CARGO_MANIFEST_DIR/2048"
-                            ));
-                            self.state = AppState::VM(VMState::from_file_contents(file_contents));
-                            self.shared_state = Default::default();
-                        }
-                        if ui.button("VM Example 5: Ray Marcher").clicked() {
-                            let file_contents = file_contents_from_dir(&include_dir::include_dir!(
-                                "$CARGO_MANIFEST_DIR/Raymarcher"
-                            ));
-                            self.state = AppState::VM(VMState::from_file_contents(file_contents));
-                            self.shared_state = Default::default();
-                        }
-                        if ui.button("Hack Example: Ray Marcher").clicked() {
-                            let file_contents = include_str!("../../r_soj.hack");
-                            self.state = AppState::Hardware(
-                                HardwareState::from_hack_file_contents(file_contents),
-                            );
-                            self.shared_state = Default::default();
+                        ui.separator();
+                        for (index, example) in EXAMPLES.iter().enumerate() {
+                            if ui.button(example.name).clicked() {
+                                action = Some(Action::ExampleSelected(index));
+                            }
                         }
                     });
                 });
             }
         };
 
-        self.shared_state.scroll_once = false;
+        self.detached_screen_key = match self.state.ram() {
+            Some(ram) if self.shared_state.screen_detached => {
+                draw_detached_screen(ctx, &mut self.screen, ram, frame, &mut action)
+            }
+            _ => None,
+        };
+
+        if let Some(step) = self.tutorial {
+            draw_tutorial(ctx, step, &mut action);
+        }
+
+        dispatch(self, &Action::FrameDrawn);
 
-        if matches!(action, Some(Action::Quit)) {
-            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-            return;
+        let scroll_offsets = take_scroll_offsets(ctx);
+        if scroll_offsets
+            .iter()
+            .any(|(grid, offset)| self.shared_state.scroll_offsets.get(grid) != Some(offset))
+        {
+            dispatch(self, &Action::GridsScrolled(scroll_offsets));
         }
 
         if let Some(action) = action {
-            reduce(self, &action);
+            dispatch(self, &action);
             ctx.request_repaint();
         }
+
+        if self.quitting {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
     }
 
     fn on_exit(&mut self, gl: Option<&eframe::glow::Context>) {
-        if let Some(context) = gl {
-            self.screen.lock().destroy(context);
-        }
+        self.screen.destroy(gl);
     }
 }
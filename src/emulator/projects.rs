@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+
+use super::common_state::LoadedFile;
+
+pub struct ProjectEntry {
+    pub name: String,
+    pub kind: ProjectEntryKind,
+}
+
+pub enum ProjectEntryKind {
+    Directory(Vec<ProjectEntry>),
+    Program(Vec<PathBuf>),
+}
+
+fn is_project_number(name: &str) -> bool {
+    name.len() == 2 && name.chars().all(|c| c.is_ascii_digit())
+}
+
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| extensions.contains(&extension.as_str()))
+}
+
+fn sorted_dir_entries(path: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<_> = std::fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    entries.sort();
+    entries
+}
+
+fn is_projects_root(path: &Path) -> bool {
+    sorted_dir_entries(path)
+        .iter()
+        .any(|entry| entry.is_dir() && is_project_number(&file_name(entry)))
+}
+
+// Accepts the projects directory itself, the course directory containing it or a single
+// project directory inside it.
+pub fn find_projects_root(path: &Path) -> Option<PathBuf> {
+    let mut candidates = vec![path.to_path_buf(), path.join("projects")];
+    if is_project_number(&file_name(path)) {
+        candidates.extend(path.parent().map(Path::to_path_buf));
+    }
+
+    candidates
+        .into_iter()
+        .find(|candidate| is_projects_root(candidate))
+}
+
+// A directory with .vm files in it is a single program, .asm and .hack files are programs
+// on their own.
+fn scan_directory(path: &Path) -> Vec<ProjectEntry> {
+    let entries = sorted_dir_entries(path);
+    let vm_files: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.is_file() && has_extension(entry, &["vm"]))
+        .cloned()
+        .collect();
+    if !vm_files.is_empty() {
+        return vec![ProjectEntry {
+            name: file_name(path),
+            kind: ProjectEntryKind::Program(vm_files),
+        }];
+    }
+
+    entries
+        .iter()
+        .flat_map(|entry| {
+            if entry.is_dir() {
+                let children = scan_directory(entry);
+                match children.as_slice() {
+                    [] => vec![],
+                    [ProjectEntry {
+                        kind: ProjectEntryKind::Program(_),
+                        ..
+                    }] => children,
+                    _ => vec![ProjectEntry {
+                        name: file_name(entry),
+                        kind: ProjectEntryKind::Directory(children),
+                    }],
+                }
+            } else if has_extension(entry, &["asm", "hack"]) {
+                vec![ProjectEntry {
+                    name: file_name(entry),
+                    kind: ProjectEntryKind::Program(vec![entry.clone()]),
+                }]
+            } else {
+                vec![]
+            }
+        })
+        .collect()
+}
+
+pub fn scan_projects(root: &Path) -> Vec<ProjectEntry> {
+    sorted_dir_entries(root)
+        .iter()
+        .filter(|entry| entry.is_dir() && is_project_number(&file_name(entry)))
+        .filter_map(|entry| {
+            let children = scan_directory(entry);
+            (!children.is_empty()).then(|| ProjectEntry {
+                name: format!("Project {}", file_name(entry)),
+                kind: ProjectEntryKind::Directory(children),
+            })
+        })
+        .collect()
+}
+
+pub fn find_test_scripts(directory: &Path) -> Vec<PathBuf> {
+    sorted_dir_entries(directory)
+        .into_iter()
+        .filter(|entry| entry.is_file() && has_extension(entry, &["tst"]))
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn config_file_path(name: &str) -> Option<PathBuf> {
+    let config_dir = std::env::var_os("APPDATA")
+        .or_else(|| std::env::var_os("XDG_CONFIG_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("nand2rust").join(name))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_config_file(name: &str, contents: &str) {
+    if let Some(path) = config_file_path(name) {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _ = std::fs::write(path, contents);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_projects_root() -> Option<PathBuf> {
+    let contents = std::fs::read_to_string(config_file_path("projects-root")?).ok()?;
+    Some(PathBuf::from(contents.trim_end_matches('\n')))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read_projects_root() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_projects_root(root: &Path) {
+    write_config_file("projects-root", &root.to_string_lossy());
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write_projects_root(_root: &Path) {}
+
+#[cfg(not(target_arch = "wasm32"))]
+const VM_OS_CLASSES_FILE: &str = "vm-os-classes";
+
+// One line per program directory: the directory, a tab and the OS classes that run from the
+// loaded VM files separated by spaces.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_all_vm_os_classes() -> Vec<(PathBuf, Vec<String>)> {
+    let Some(contents) =
+        config_file_path(VM_OS_CLASSES_FILE).and_then(|path| std::fs::read_to_string(path).ok())
+    else {
+        return vec![];
+    };
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (directory, classes) = line.split_once('\t')?;
+            Some((
+                PathBuf::from(directory),
+                classes.split_whitespace().map(str::to_owned).collect(),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_vm_os_classes(directory: &Path) -> Vec<String> {
+    read_all_vm_os_classes()
+        .into_iter()
+        .find(|(entry_directory, _)| entry_directory == directory)
+        .map(|(_, classes)| classes)
+        .unwrap_or_default()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read_vm_os_classes(_directory: &Path) -> Vec<String> {
+    vec![]
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_vm_os_classes(directory: &Path, classes: &[String]) {
+    let mut entries = read_all_vm_os_classes();
+    entries.retain(|(entry_directory, _)| entry_directory != directory);
+    if !classes.is_empty() {
+        entries.push((directory.to_path_buf(), classes.to_vec()));
+    }
+    let contents: String = entries
+        .iter()
+        .map(|(directory, classes)| format!("{}\t{}\n", directory.display(), classes.join(" ")))
+        .collect();
+    write_config_file(VM_OS_CLASSES_FILE, &contents);
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write_vm_os_classes(_directory: &Path, _classes: &[String]) {}
+
+pub fn read_program_files(paths: &[PathBuf]) -> Result<Vec<LoadedFile>, String> {
+    paths
+        .iter()
+        .map(|path| {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+            Ok(LoadedFile {
+                name: file_name(path),
+                contents,
+                path: Some(path.clone()),
+            })
+        })
+        .collect()
+}
+
+// A directory given on the command line is the program made of its .vm files.
+pub fn expand_program_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            if path.is_dir() {
+                sorted_dir_entries(path)
+                    .into_iter()
+                    .filter(|entry| entry.is_file() && has_extension(entry, &["vm"]))
+                    .collect()
+            } else {
+                vec![path.clone()]
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,66 @@
+use std::sync::Mutex;
+
+use crate::session::{parse_session, Session};
+
+static RECOVERY_SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+#[cfg(not(target_arch = "wasm32"))]
+fn recovery_file_path() -> std::path::PathBuf {
+    std::env::temp_dir().join("nand2rust-recovery.n2s")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_recovery_file(contents: &str) {
+    let _ = std::fs::write(recovery_file_path(), contents);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_recovery_file(_contents: &str) {}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_recovery_file() -> Option<String> {
+    std::fs::read_to_string(recovery_file_path()).ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_recovery_file() -> Option<String> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn remove_recovery_file() {
+    let _ = std::fs::remove_file(recovery_file_path());
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn remove_recovery_file() {}
+
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // The panic may have happened while the session was being updated.
+        if let Ok(session) = RECOVERY_SESSION.try_lock() {
+            if let Some(session) = session.as_ref() {
+                write_recovery_file(&session.to_string());
+            }
+        }
+        default_hook(info);
+    }));
+}
+
+pub fn update_recovery_session(session: Option<Session>) {
+    if let Ok(mut recovery_session) = RECOVERY_SESSION.lock() {
+        *recovery_session = session;
+    }
+}
+
+pub fn read_recovered_session() -> Option<Session> {
+    let contents = read_recovery_file()?;
+    match parse_session(&contents) {
+        Ok((_, session)) => Some(session),
+        Err(_) => {
+            remove_recovery_file();
+            None
+        }
+    }
+}
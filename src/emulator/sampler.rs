@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::instant::Instant;
+use crate::recording::{profile, ProfileEntry};
+
+// Runs are split into chunks of this many steps, the clock is only read between chunks.
+pub const SAMPLE_CHECK_STEPS: u64 = 1024;
+
+pub struct Sampler {
+    pub interval: Duration,
+    last_sample: Instant,
+    pub samples: Vec<u64>,
+    pub sample_count: u64,
+    // Samples of the call stack, for programs that have one.
+    pub stacks: BTreeMap<Vec<usize>, u64>,
+}
+
+impl Sampler {
+    pub fn new(interval: Duration, location_count: usize) -> Self {
+        Sampler {
+            interval,
+            last_sample: Instant::now(),
+            samples: vec![0; location_count],
+            sample_count: 0,
+            stacks: BTreeMap::new(),
+        }
+    }
+
+    // Whether a sample was taken, the stack is only worth collecting when one was.
+    pub fn poll(&mut self, location: usize) -> bool {
+        let now = Instant::now();
+        if now - self.last_sample < self.interval {
+            return false;
+        }
+        self.last_sample = now;
+        let Some(count) = self.samples.get_mut(location) else {
+            return false;
+        };
+        *count += 1;
+        self.sample_count += 1;
+        true
+    }
+
+    pub fn poll_stack(&mut self, stack: Vec<usize>) {
+        *self.stacks.entry(stack).or_default() += 1;
+    }
+
+    pub fn profile(&self, regions: &[(String, usize)]) -> Vec<ProfileEntry> {
+        profile(&self.samples, regions)
+    }
+}
@@ -0,0 +1,165 @@
+use eframe::{
+    egui::{self, Key},
+    epaint::Rect,
+    glow,
+};
+
+use crate::hardware::RAM;
+
+use super::common_state::Action;
+
+use super::glow_screen::GlowScreen;
+#[cfg(feature = "wgpu")]
+use super::wgpu_screen::WgpuScreen;
+
+pub trait ScreenRenderer {
+    fn draw(&mut self, ui: &mut egui::Ui, rect: Rect, ram: &RAM, frame: &eframe::Frame);
+
+    fn destroy(&mut self, _gl: Option<&glow::Context>) {}
+}
+
+pub struct Screen {
+    renderer: Box<dyn ScreenRenderer>,
+}
+
+impl Screen {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> (Self, Option<String>) {
+        #[cfg(feature = "wgpu")]
+        if let Some(render_state) = cc.wgpu_render_state.as_ref() {
+            return (Self::from_renderer(WgpuScreen::new(render_state)), None);
+        }
+
+        match cc.gl.as_deref().map(GlowScreen::new) {
+            Some(Ok(screen)) => (Self::from_renderer(screen), None),
+            Some(Err(error)) => (
+                Self::from_renderer(SoftwareScreen::default()),
+                Some(format!(
+                    "Falling back to software screen rendering, shader setup failed: {}",
+                    error.trim()
+                )),
+            ),
+            None => (Self::from_renderer(SoftwareScreen::default()), None),
+        }
+    }
+
+    fn from_renderer(renderer: impl ScreenRenderer + 'static) -> Self {
+        Screen {
+            renderer: Box::new(renderer),
+        }
+    }
+
+    pub fn destroy(&mut self, gl: Option<&glow::Context>) {
+        self.renderer.destroy(gl);
+    }
+}
+
+pub fn screen_bytes(ram: &RAM) -> &[u8] {
+    let screen_buffer = ram.slice(RAM::SCREEN..RAM::KBD);
+
+    unsafe { screen_buffer.align_to::<u8>().1 }
+}
+
+#[derive(Default)]
+pub struct SoftwareScreen {
+    texture: Option<egui::TextureHandle>,
+}
+
+fn screen_image(ram: &RAM) -> egui::ColorImage {
+    let pixels = ram
+        .screen_as_bitvec()
+        .into_iter()
+        .map(|on| {
+            if on {
+                egui::Color32::BLACK
+            } else {
+                egui::Color32::WHITE
+            }
+        })
+        .collect();
+
+    egui::ColorImage {
+        size: [512, 256],
+        pixels,
+    }
+}
+
+impl ScreenRenderer for SoftwareScreen {
+    fn draw(&mut self, ui: &mut egui::Ui, rect: Rect, ram: &RAM, _frame: &eframe::Frame) {
+        let image = screen_image(ram);
+        let texture = match &mut self.texture {
+            Some(texture) => {
+                texture.set(image, egui::TextureOptions::NEAREST);
+                texture
+            }
+            None => self.texture.insert(ui.ctx().load_texture(
+                "screen",
+                image,
+                egui::TextureOptions::NEAREST,
+            )),
+        };
+
+        ui.painter().image(
+            texture.id(),
+            rect,
+            Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            egui::Color32::WHITE,
+        );
+    }
+}
+
+pub fn draw_screen(ui: &mut egui::Ui, screen: &mut Screen, ram: &RAM, frame: &eframe::Frame) {
+    let rect = Rect::from_min_size(
+        ui.cursor().min,
+        egui::Vec2::new(ui.available_width(), ui.available_height()),
+    );
+
+    screen.renderer.draw(ui, rect, ram, frame);
+}
+
+// Returns the key held down while the detached window has focus, since the main viewport
+// doesn't get its input.
+pub fn draw_detached_screen(
+    ctx: &egui::Context,
+    screen: &mut Screen,
+    ram: &RAM,
+    frame: &eframe::Frame,
+    action: &mut Option<Action>,
+) -> Option<Key> {
+    ctx.show_viewport_immediate(
+        egui::ViewportId::from_hash_of("screen"),
+        egui::ViewportBuilder::default()
+            .with_title("Screen")
+            .with_inner_size([512.0, 256.0]),
+        |ctx, class| {
+            if class == egui::ViewportClass::Embedded {
+                let mut open = true;
+                egui::Window::new("Screen")
+                    .open(&mut open)
+                    .default_size([512.0, 256.0])
+                    .show(ctx, |ui| {
+                        ui.allocate_ui(ui.available_size(), |ui| {
+                            draw_screen(ui, screen, ram, frame)
+                        });
+                    });
+                if !open {
+                    *action = Some(Action::ScreenAttachClicked);
+                }
+                return None;
+            }
+
+            egui::CentralPanel::default()
+                .frame(egui::Frame::none())
+                .show(ctx, |ui| draw_screen(ui, screen, ram, frame));
+
+            if ctx.input(|i| i.key_pressed(Key::F11)) {
+                let fullscreen = ctx.input(|i| i.viewport().fullscreen.unwrap_or(false));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(!fullscreen));
+            }
+            if ctx.input(|i| i.viewport().close_requested()) {
+                *action = Some(Action::ScreenAttachClicked);
+            }
+
+            ctx.input(|i| i.keys_down.iter().cloned().next())
+        },
+    )
+}
@@ -1,333 +1,2161 @@
 use super::instant::Instant;
 use crate::{
-    hardware::{Instruction, Word, RAM},
+    annotation::AnnotationScript,
+    diagnostics::{DiagnosticCategory, Severity},
+    disassembler::{disassembly_listing, DisassembledInstruction},
+    exam_archive::EXTENSION,
+    hardware::{Address, Instruction, Word, MEM_SIZE, RAM},
+    hardware_parse::{hack_binary, AssemblerMode},
+    highlight::{highlight_asm, AsmToken},
+    metadata::ProgramMetadata,
+    recording::Recording,
+    session::{Bookmark, Session},
+    test_script::{TestFailure, TestOutcome},
+    thread_tuning::{core_count, ThreadTuning},
+    translator::translate,
     vm::{Program, RunState},
 };
-use eframe::{
-    egui::{self, Slider},
-    epaint::Rect,
-    glow,
-};
-use egui::mutex::Mutex;
+use eframe::egui::{self, Slider};
 use egui_extras::{Column, TableBuilder};
 use futures::future::join_all;
+use hashbrown::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
 use std::{future::Future, sync::mpsc::Sender};
-use std::{ops::RangeInclusive, sync::Arc};
 
-use super::common_state::{Action, CommonAction, PerformanceData, SharedState, UIStyle};
+use super::common_state::{
+    Action, AppState, BackgroundBehavior, CheckpointAction, CommonAction, DiffAction, ExamUnlock,
+    FindAction, FindState, FrameSync, InvariantAction, KeyboardAction, KeyboardState, LoadErrors,
+    LoadedFile, Log, MemoryAction, PerformanceData, ProfilerAction, ProfilerState, RecordedData,
+    RomDisplay, Settings, SharedState, SourceEditorAction, SpeedMarker, TestResult, TestStatus,
+    TestsAction, TestsState, TraceViewAction, TraceViewState, TutorialAction, UIStyle,
+    MAX_FRAME_STEPS, SPEED_HISTORY_SECONDS, SPEED_SAMPLES_PER_SECOND,
+};
+use super::examples::EXAMPLES;
+use super::history::KEYFRAME_STEPS;
+use super::projects::{ProjectEntry, ProjectEntryKind};
+use super::snapshot::{CheckpointsState, DiffState};
+use super::tutorial::{help_button, mark_tutorial_target, TutorialTarget};
+
+#[cfg(not(target_arch = "wasm32"))]
+fn file_handle_path(file: &rfd::FileHandle) -> Option<std::path::PathBuf> {
+    Some(file.path().to_path_buf())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn file_handle_path(_file: &rfd::FileHandle) -> Option<std::path::PathBuf> {
+    None
+}
+
+pub fn draw_warning_banner(ctx: &egui::Context, warning: &str, action: &mut Option<Action>) {
+    egui::TopBottomPanel::top("warning_banner").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.colored_label(ui.visuals().warn_fg_color, warning);
+            if ui.button("Dismiss").clicked() {
+                *action = Some(Action::WarningDismissed);
+            }
+        });
+    });
+}
+
+pub fn draw_reload_banner(ctx: &egui::Context, action: &mut Option<Action>) {
+    egui::TopBottomPanel::top("reload_banner").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("The loaded files changed on disk.");
+            if ui.button("Reload").clicked() {
+                *action = Some(Action::ReloadClicked);
+            }
+            if ui.button("Dismiss").clicked() {
+                *action = Some(Action::ReloadDismissed);
+            }
+        });
+    });
+}
+
+// A line of the achieved speed, scaled to the fastest sample so that dips stand out, with what
+// happened at the time marked across it.
+fn speed_graph(ui: &mut egui::Ui, performance_data: &PerformanceData) {
+    let speed_history = &performance_data.speed_history;
+    let size = egui::vec2(120.0, ui.text_style_height(&egui::TextStyle::Body));
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let max = speed_history.iter().copied().fold(0.0, f64::max);
+    let capacity = SPEED_SAMPLES_PER_SECOND * SPEED_HISTORY_SECONDS;
+    let oldest = performance_data.samples_taken - speed_history.len() as u64;
+    let x = |sample: u64| {
+        let position = (capacity - speed_history.len()) as u64 + sample - oldest;
+        rect.left() + rect.width() * (position as f32 / (capacity - 1) as f32).min(1.0)
+    };
+    let points: Vec<egui::Pos2> = speed_history
+        .iter()
+        .enumerate()
+        .map(|(index, &speed)| {
+            let y = if max > 0.0 { speed / max } else { 0.0 } as f32;
+            egui::pos2(x(oldest + index as u64), rect.bottom() - y * rect.height())
+        })
+        .collect();
+    let painter = ui.painter_at(rect);
+    painter.rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+    for &(sample, marker) in &performance_data.speed_markers {
+        let color = match marker {
+            SpeedMarker::BreakpointHit => ui.visuals().error_fg_color,
+            SpeedMarker::Paused => ui.visuals().warn_fg_color,
+            SpeedMarker::Reloaded => ui.visuals().hyperlink_color,
+        };
+        painter.vline(x(sample), rect.y_range(), egui::Stroke::new(1.0, color));
+    }
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.0, ui.visuals().text_color()),
+    ));
+    response.on_hover_ui(|ui| {
+        ui.label(format!(
+            "Steps per second over the last {} seconds, at most {}",
+            SPEED_HISTORY_SECONDS,
+            max.round() as u64
+        ));
+        for &(sample, marker) in performance_data.speed_markers.iter().rev() {
+            let seconds_ago =
+                (performance_data.samples_taken - sample) as usize / SPEED_SAMPLES_PER_SECOND;
+            ui.label(format!("{} {} s ago", marker, seconds_ago));
+        }
+    });
+}
+
+fn severity_text(severity: Option<Severity>) -> &'static str {
+    match severity {
+        None => "Off",
+        Some(Severity::Warning) => "Warning",
+        Some(Severity::Error) => "Error",
+    }
+}
+
+// The emulator steps on the UI thread, so that's the one these pin, along with the test runner's.
+fn draw_thread_tuning(ui: &mut egui::Ui, tuning: ThreadTuning, action: &mut Option<Action>) {
+    let mut new_tuning = tuning;
+    let core_text = |core: Option<usize>| match core {
+        Some(core) => format!("Core {}", core),
+        None => "Any".to_owned(),
+    };
+    ui.horizontal(|ui| {
+        ui.label("Run programs on");
+        egui::ComboBox::from_id_source("thread core")
+            .selected_text(core_text(new_tuning.core))
+            .show_ui(ui, |ui| {
+                for core in std::iter::once(None).chain((0..core_count()).map(Some)) {
+                    ui.selectable_value(&mut new_tuning.core, core, core_text(core));
+                }
+            });
+    })
+    .response
+    .on_hover_text("Pinning to one core steadies benchmark numbers");
+    ui.checkbox(&mut new_tuning.high_priority, "High priority")
+        .on_hover_text("Usually needs administrator rights outside Windows");
+    if new_tuning != tuning {
+        *action = Some(Action::ThreadTuningChanged(new_tuning));
+    }
+}
+
+pub fn draw_shared(
+    state: &SharedState,
+    app_state: &AppState,
+    settings: &Settings,
+    ctx: &egui::Context,
+    performance_data: &PerformanceData,
+    action: &mut Option<Action>,
+    async_actions_sender: &Sender<Action>,
+) {
+    let is_top_bar_enabled = !matches!(app_state, AppState::Start);
+    let metadata = app_state.metadata();
+
+    egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+        // The top panel is often a good place for a menu bar:
+        // #[cfg(not(target_arch = "wasm32"))]
+        {
+            egui::menu::bar(ui, |ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.button("Load VM Files").clicked() {
+                        ui.close_menu();
+                        let mut dialog = rfd::AsyncFileDialog::new();
+                        if let Ok(current_dir) = std::env::current_dir() {
+                            dialog = dialog.set_directory(current_dir);
+                        }
+                        let task = dialog.add_filter("VM", &[&"vm"]).pick_files();
+                        let ctx = ctx.clone();
+                        let async_actions_sender = async_actions_sender.clone();
+                        execute(async move {
+                            if let Some(files) = task.await {
+                                let file_contents_futures: Vec<_> = files
+                                    .iter()
+                                    .map(|f| async {
+                                        LoadedFile {
+                                            name: f.file_name(),
+                                            contents: String::from_utf8(f.read().await).unwrap(),
+                                            path: file_handle_path(f),
+                                        }
+                                    })
+                                    .collect();
+                                let file_contents = join_all(file_contents_futures).await;
+                                let _ =
+                                    async_actions_sender.send(Action::FilesPicked(file_contents));
+                                ctx.request_repaint();
+                            }
+                        });
+                    }
+                    // Assembled programs load without their source, so they get an entry of
+                    // their own, as do project files naming the sources to load.
+                    for (label, filter, extension) in [
+                        ("Load .asm File", "Assembly", "asm"),
+                        ("Load .hack File", "Hack binary", "hack"),
+                        ("Load .n2r Project", "Project", "n2r"),
+                    ] {
+                        if !ui.button(label).clicked() {
+                            continue;
+                        }
+                        ui.close_menu();
+                        let mut dialog = rfd::AsyncFileDialog::new();
+                        if let Ok(current_dir) = std::env::current_dir() {
+                            dialog = dialog.set_directory(current_dir);
+                        }
+                        let task = dialog.add_filter(filter, &[extension]).pick_file();
+                        let ctx = ctx.clone();
+                        let async_actions_sender = async_actions_sender.clone();
+                        execute(async move {
+                            if let Some(file) = task.await {
+                                let loaded_file = LoadedFile {
+                                    name: file.file_name(),
+                                    contents: String::from_utf8_lossy(&file.read().await)
+                                        .into_owned(),
+                                    path: file_handle_path(&file),
+                                };
+                                let _ = async_actions_sender.send(if extension == "n2r" {
+                                    Action::ProjectFilePicked(loaded_file)
+                                } else {
+                                    Action::FilePicked(loaded_file)
+                                });
+                                ctx.request_repaint();
+                            }
+                        });
+                    }
+                    if ui.button("Load Exam").clicked() {
+                        ui.close_menu();
+                        let task = rfd::AsyncFileDialog::new()
+                            .add_filter("Exam", &[EXTENSION])
+                            .pick_file();
+                        let ctx = ctx.clone();
+                        let async_actions_sender = async_actions_sender.clone();
+                        execute(async move {
+                            if let Some(file) = task.await {
+                                let _ = async_actions_sender
+                                    .send(Action::ExamPicked(file.file_name(), file.read().await));
+                                ctx.request_repaint();
+                            }
+                        });
+                    }
+                    if ui.button("Open Project").clicked() {
+                        ui.close_menu();
+                        *action = Some(Action::ProjectsClicked);
+                    }
+                    ui.menu_button("Examples", |ui| {
+                        for (index, example) in EXAMPLES.iter().enumerate() {
+                            if ui.button(example.name).clicked() {
+                                ui.close_menu();
+                                *action = Some(Action::ExampleSelected(index));
+                            }
+                        }
+                    });
+                    if ui.button("Load Annotations").clicked() {
+                        ui.close_menu();
+                        let task = rfd::AsyncFileDialog::new()
+                            .add_filter("Annotations", &[&"md", &"txt"])
+                            .pick_file();
+                        let ctx = ctx.clone();
+                        let async_actions_sender = async_actions_sender.clone();
+                        execute(async move {
+                            if let Some(file) = task.await {
+                                let loaded_file = LoadedFile {
+                                    name: file.file_name(),
+                                    contents: String::from_utf8_lossy(&file.read().await)
+                                        .into_owned(),
+                                    path: file_handle_path(&file),
+                                };
+                                let _ = async_actions_sender
+                                    .send(Action::AnnotationsPicked(loaded_file));
+                                ctx.request_repaint();
+                            }
+                        });
+                    }
+                    if ui
+                        .add_enabled(
+                            is_top_bar_enabled && !state.from_exam,
+                            egui::Button::new("Export Session"),
+                        )
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let Some(session) = app_state.session(state) {
+                            export_session(ctx, async_actions_sender, session, false);
+                        }
+                    }
+                    if ui
+                        .add_enabled(app_state.is_recording(), egui::Button::new("Export Trace"))
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let Some(recording) = app_state.recording() {
+                            let mut contents = vec![];
+                            if recording.full_trace.write(&mut contents).is_ok() {
+                                export_bytes("trace.n2rtrace", "Trace", "n2rtrace", contents);
+                            }
+                        }
+                    }
+                    if ui
+                        .add_enabled(
+                            matches!(app_state, AppState::VM(_)) && app_state.is_recording(),
+                            egui::Button::new("Export Call Trace…"),
+                        )
+                        .on_hover_text("Save the recorded calls and returns to view in Perfetto")
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let AppState::VM(vm_state) = app_state {
+                            if let Some(trace) = vm_state.call_trace() {
+                                export_bytes("calls.json", "JSON", "json", trace.into_bytes());
+                            }
+                        }
+                    }
+                    if ui
+                        .add_enabled(app_state.is_recording(), egui::Button::new("Export Report"))
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let Some(report) = app_state.report() {
+                            export_bytes("report.html", "HTML", "html", report.into_bytes());
+                        }
+                    }
+                    if ui
+                        .add_enabled(
+                            matches!(app_state, AppState::Hardware(_)) && !state.from_exam,
+                            egui::Button::new("Export .hack…"),
+                        )
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let AppState::Hardware(hardware_state) = app_state {
+                            let hardware = &hardware_state.hardware;
+                            let file_name = hardware_state
+                                .source_path
+                                .as_deref()
+                                .and_then(|path| path.file_stem())
+                                .map_or("program".to_owned(), |stem| {
+                                    stem.to_string_lossy().into_owned()
+                                });
+                            export_bytes(
+                                &format!("{}.hack", file_name),
+                                "Hack binary",
+                                "hack",
+                                hack_binary(&hardware.rom[..hardware.length]).into_bytes(),
+                            );
+                        }
+                    }
+                    if ui
+                        .add_enabled(
+                            matches!(app_state, AppState::VM(_)) && !state.from_exam,
+                            egui::Button::new("Export .asm…"),
+                        )
+                        .on_hover_text("Translate the VM files to Hack assembly")
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let AppState::VM(vm_state) = app_state {
+                            let translation = translate(&vm_state.vm.program);
+                            let mut asm: String = translation
+                                .warnings
+                                .iter()
+                                .map(|warning| format!("// Warning: {}\n", warning))
+                                .collect();
+                            asm.push_str(&translation.asm);
+                            let file_name = vm_state
+                                .program_directory()
+                                .and_then(|directory| directory.file_name())
+                                .map_or("program".to_owned(), |name| {
+                                    name.to_string_lossy().into_owned()
+                                });
+                            export_bytes(
+                                &format!("{}.asm", file_name),
+                                "Assembly",
+                                "asm",
+                                asm.into_bytes(),
+                            );
+                        }
+                    }
+                    if ui
+                        .add_enabled(
+                            matches!(app_state, AppState::Hardware(_)) && !state.from_exam,
+                            egui::Button::new("Save Disassembly…"),
+                        )
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let AppState::Hardware(hardware_state) = app_state {
+                            let hardware = &hardware_state.hardware;
+                            let listing = disassembly_listing(&hardware.rom[..hardware.length]);
+                            export_bytes(
+                                "disassembly.asm",
+                                "Assembly",
+                                "asm",
+                                listing.into_bytes(),
+                            );
+                        }
+                    }
+                    if ui.button("Close File(s)").clicked() {
+                        ui.close_menu();
+                        *action = Some(Action::CloseFile)
+                    }
+                    if ui.button("Quit").clicked() {
+                        *action = Some(Action::Quit);
+                    }
+                });
+                ui.menu_button("Settings", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Editor command");
+                        let mut editor_command = settings.editor_command.clone();
+                        ui.text_edit_singleline(&mut editor_command).on_hover_text(
+                            "{file} and {line} are replaced with the source location",
+                        );
+                        if editor_command != settings.editor_command {
+                            *action = Some(Action::EditorCommandChanged(editor_command));
+                        }
+                    });
+                    let mut auto_reload = settings.auto_reload;
+                    if ui
+                        .checkbox(&mut auto_reload, "Reload changed files automatically")
+                        .changed()
+                    {
+                        *action = Some(Action::AutoReloadChanged(auto_reload));
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Assembler");
+                        for mode in AssemblerMode::ALL {
+                            if ui
+                                .radio(settings.assembler_mode == mode, mode.to_string())
+                                .clicked()
+                            {
+                                *action = Some(Action::AssemblerModeChanged(mode));
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Strict only takes what the official assembler does, for the same .hack",
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("In the background");
+                        let mut background = settings.background;
+                        egui::ComboBox::from_id_source("background behavior")
+                            .selected_text(background.to_string())
+                            .show_ui(ui, |ui| {
+                                for option in BackgroundBehavior::ALL {
+                                    ui.selectable_value(
+                                        &mut background,
+                                        option,
+                                        option.to_string(),
+                                    );
+                                }
+                            });
+                        if background != settings.background {
+                            *action = Some(Action::BackgroundBehaviorChanged(background));
+                        }
+                    });
+                    ui.separator();
+                    ui.label("Recording limits");
+                    let mut budget = settings.recording_budget;
+                    egui::Grid::new("recording budget grid").show(ui, |ui| {
+                        ui.label("Trace (MiB)");
+                        let mut trace_mib = budget.max_trace_bytes >> 20;
+                        ui.add(egui::DragValue::new(&mut trace_mib).clamp_range(1..=4096));
+                        budget.max_trace_bytes = trace_mib << 20;
+                        ui.end_row();
+                        ui.label("Checkpoints");
+                        ui.add(
+                            egui::DragValue::new(&mut budget.max_snapshots).clamp_range(1..=1000),
+                        );
+                        ui.end_row();
+                        ui.label("Time travel keyframes").on_hover_text(format!(
+                            "One every {} steps, time travel goes back as far as the oldest",
+                            KEYFRAME_STEPS
+                        ));
+                        ui.add(
+                            egui::DragValue::new(&mut budget.max_keyframes).clamp_range(1..=10000),
+                        );
+                        ui.end_row();
+                        ui.label("Undo points");
+                        ui.add(
+                            egui::DragValue::new(&mut budget.max_undo_points).clamp_range(1..=1000),
+                        );
+                        ui.end_row();
+                    });
+                    if budget != settings.recording_budget {
+                        *action = Some(Action::RecordingBudgetChanged(budget));
+                    }
+                    ui.separator();
+                    ui.label("Diagnostics");
+                    egui::Grid::new("diagnostics grid").show(ui, |ui| {
+                        for category in DiagnosticCategory::ALL {
+                            let mut severity = settings.diagnostics.severity(category);
+                            ui.label(category.to_string());
+                            egui::ComboBox::from_id_source(("diagnostic severity", category))
+                                .selected_text(severity_text(severity))
+                                .show_ui(ui, |ui| {
+                                    for option in
+                                        [None, Some(Severity::Warning), Some(Severity::Error)]
+                                    {
+                                        ui.selectable_value(
+                                            &mut severity,
+                                            option,
+                                            severity_text(option),
+                                        );
+                                    }
+                                });
+                            if severity != settings.diagnostics.severity(category) {
+                                *action =
+                                    Some(Action::DiagnosticSeverityChanged(category, severity));
+                            }
+                            ui.end_row();
+                        }
+                    });
+                    if ThreadTuning::is_supported() {
+                        ui.separator();
+                        ui.collapsing("Advanced", |ui| {
+                            draw_thread_tuning(ui, settings.thread_tuning, action);
+                        });
+                    }
+                });
+                ui.menu_button("Help", |ui| {
+                    if ui.button("Guided Tour").clicked() {
+                        ui.close_menu();
+                        *action = Some(Action::Tutorial(TutorialAction::Started));
+                    }
+                });
+            });
+        }
+        ui.separator();
+        ui.add_enabled_ui(is_top_bar_enabled, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                let is_vm = matches!(app_state, AppState::VM(_));
+                let step_button = ui.button(if is_vm { "Step Into" } else { "Step" });
+                mark_tutorial_target(ctx, TutorialTarget::StepButton, step_button.rect);
+                if step_button.clicked() {
+                    *action = Some(Action::Common(CommonAction::StepClicked));
+                }
+                if is_vm {
+                    if ui
+                        .button("Step Over")
+                        .on_hover_text("Runs a call until it returns")
+                        .clicked()
+                    {
+                        *action = Some(Action::Common(CommonAction::StepOverClicked));
+                    }
+                    if ui
+                        .button("Step Out")
+                        .on_hover_text("Runs until the current function returns")
+                        .clicked()
+                    {
+                        *action = Some(Action::Common(CommonAction::StepOutClicked));
+                    }
+                }
+                if ui.button("Step Frame").clicked() {
+                    *action = Some(Action::Common(CommonAction::StepFrameClicked));
+                }
+                if ui
+                    .add_enabled(!state.undo_points.is_empty(), egui::Button::new("Undo"))
+                    .on_hover_text("Goes back to before the last step or run (Ctrl+Z)")
+                    .clicked()
+                {
+                    *action = Some(Action::UndoClicked);
+                }
+                ui.menu_button("Frame Sync", |ui| {
+                    let mut frame_sync = state.frame_sync;
+                    let (mut address, mut step_count) = match frame_sync {
+                        FrameSync::Address(address) => (address, 100000),
+                        FrameSync::Steps(step_count) => (RAM::SCREEN, step_count),
+                    };
+                    ui.horizontal(|ui| {
+                        if ui
+                            .radio(matches!(frame_sync, FrameSync::Address(_)), "Write to")
+                            .clicked()
+                        {
+                            frame_sync = FrameSync::Address(address);
+                        }
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut address)
+                                    .clamp_range(0..=(MEM_SIZE - 1) as Word),
+                            )
+                            .changed()
+                        {
+                            frame_sync = FrameSync::Address(address);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        if ui
+                            .radio(matches!(frame_sync, FrameSync::Steps(_)), "Steps")
+                            .clicked()
+                        {
+                            frame_sync = FrameSync::Steps(step_count);
+                        }
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut step_count)
+                                    .clamp_range(1..=MAX_FRAME_STEPS),
+                            )
+                            .changed()
+                        {
+                            frame_sync = FrameSync::Steps(step_count);
+                        }
+                    });
+                    if frame_sync != state.frame_sync {
+                        *action = Some(Action::Common(CommonAction::FrameSyncChanged(frame_sync)));
+                    }
+                });
+                let run_button = ui.button("Run");
+                mark_tutorial_target(ctx, TutorialTarget::RunButton, run_button.rect);
+                if run_button.clicked() {
+                    *action = Some(Action::Common(CommonAction::RunClicked));
+                }
+                if ui.button("Pause").clicked() {
+                    *action = Some(Action::Common(CommonAction::PauseClicked));
+                }
+                if ui.button("Reset").clicked() {
+                    *action = Some(Action::Common(CommonAction::ResetClicked));
+                }
+                let breakpoints_button = ui.button("Breakpoints");
+                mark_tutorial_target(
+                    ctx,
+                    TutorialTarget::BreakpointsButton,
+                    breakpoints_button.rect,
+                );
+                if breakpoints_button.clicked() {
+                    *action = Some(Action::Common(CommonAction::BreakpointsClicked));
+                }
+                if ui.button("Invariants").clicked() {
+                    *action = Some(Action::Common(CommonAction::InvariantsClicked));
+                }
+                if ui.button("Log").clicked() {
+                    *action = Some(Action::Common(CommonAction::LogClicked));
+                }
+                if ui.button("Memory").clicked() {
+                    *action = Some(Action::Memory(MemoryAction::Clicked));
+                }
+                if ui.button("Keyboard").clicked() {
+                    *action = Some(Action::Keyboard(KeyboardAction::Clicked));
+                }
+                let mut follow_pc = state.follow_pc;
+                if ui
+                    .checkbox(&mut follow_pc, "Follow PC")
+                    .on_hover_text(
+                        "Scroll to the current instruction whenever it moves out of view",
+                    )
+                    .changed()
+                {
+                    *action = Some(Action::FollowPCChanged(follow_pc));
+                }
+                if ui
+                    .add_enabled(
+                        state.source_editor.file.is_some(),
+                        egui::Button::new("Source"),
+                    )
+                    .on_hover_text("Edit the loaded .asm file and assemble it again")
+                    .clicked()
+                {
+                    *action = Some(Action::SourceEditor(SourceEditorAction::Clicked));
+                }
+                let mut recording = app_state.is_recording();
+                if ui
+                    .checkbox(&mut recording, "Record")
+                    .on_hover_text("Record a trace, coverage and a profile for Export Report")
+                    .changed()
+                {
+                    *action = Some(Action::Common(CommonAction::RecordingChanged(recording)));
+                }
+                let mut write_tracking = app_state.write_tracking();
+                if ui
+                    .checkbox(&mut write_tracking, "Track Writes")
+                    .on_hover_text("Show which instruction last wrote each RAM address")
+                    .changed()
+                {
+                    *action = Some(Action::Common(CommonAction::WriteTrackingChanged(
+                        write_tracking,
+                    )));
+                }
+                if ui.button("Profiler").clicked() {
+                    *action = Some(Action::Profiler(ProfilerAction::Clicked));
+                }
+                if ui.button("Tests").clicked() {
+                    *action = Some(Action::Tests(TestsAction::Clicked));
+                }
+                if ui
+                    .add_enabled(recording, egui::Button::new("Trace"))
+                    .clicked()
+                {
+                    *action = Some(Action::TraceView(TraceViewAction::Clicked));
+                }
+                if state.screen_detached {
+                    if ui.button("Attach Screen").clicked() {
+                        *action = Some(Action::ScreenAttachClicked);
+                    }
+                } else if ui.button("Detach Screen").clicked() {
+                    *action = Some(Action::ScreenDetachClicked);
+                }
+
+                let mut new_steps_per_second = state.desired_steps_per_second;
+                let height = ui.text_style_height(&egui::TextStyle::Body);
+                ui.allocate_ui_with_layout(
+                    [320.0, height].into(),
+                    egui::Layout::left_to_right(egui::Align::Center),
+                    |ui| {
+                        ui.label("Steps per second:");
+                        ui.scope(|ui| {
+                            ui.spacing_mut().interact_size.x = 100.0;
+                            ui.add_sized(
+                                [200.0, height],
+                                Slider::new(&mut new_steps_per_second, 0..=1000000000)
+                                    .logarithmic(true),
+                            );
+                        })
+                    },
+                );
+
+                if is_top_bar_enabled && new_steps_per_second != state.desired_steps_per_second {
+                    *action = Some(Action::Common(CommonAction::SpeedSliderMoved(
+                        new_steps_per_second,
+                    )))
+                }
+                if let Some(run_start) = performance_data.run_start {
+                    let run_time = (Instant::now() - run_start).as_secs_f64();
+                    let steps_per_second = performance_data.total_steps as f64 / run_time;
+                    ui.label("Actual:");
+                    ui.label((steps_per_second.round() as u64).to_string());
+                    speed_graph(ui, performance_data);
+                }
+                if let Some(stop_reason) = state.stop_reason.as_ref().filter(|_| !state.run_started)
+                {
+                    ui.label(format!("Paused: {}", stop_reason));
+                }
+            });
+        });
+    });
+
+    if let (Some(script), Some(ticks)) = (&state.annotations, app_state.ticks()) {
+        draw_annotations_panel(script, ticks, ctx, action);
+    }
+
+    if is_top_bar_enabled {
+        draw_invariants_window(state, ctx, action);
+    }
+
+    if let Some(log) = app_state.log().filter(|_| state.log_open) {
+        draw_log_window(log, ctx, action);
+    }
+
+    if state.memory_open {
+        draw_memory_window(&app_state.memory_usage(state), ctx, action);
+    }
+
+    if let Some(load_errors) = &state.load_errors {
+        draw_load_errors_window(load_errors, ctx, action);
+    }
+
+    if let Some(file) = state
+        .source_editor
+        .file
+        .as_ref()
+        .filter(|_| state.source_editor.open)
+    {
+        draw_source_editor_window(file, state.source_editor.modified, ctx, action);
+    }
+
+    if is_top_bar_enabled && state.profiler.open {
+        draw_profiler_window(&state.profiler, app_state, ctx, action);
+    }
+
+    if state.tests.open {
+        draw_test_explorer(&state.tests, app_state, ctx, action);
+    }
+
+    let selected_test = state
+        .tests
+        .selected
+        .and_then(|selected| state.tests.results.get(selected));
+    if let Some(result) = selected_test {
+        match &result.status {
+            TestStatus::Finished(outcome) => {
+                draw_test_comparison_window(result, outcome, ctx, action)
+            }
+            TestStatus::Running => draw_test_output_window(result, ctx, action),
+            TestStatus::NotRun => {}
+        }
+    }
+
+    if let Some(recording) = app_state.recording().filter(|_| state.trace_view.open) {
+        draw_trace_window(&state.trace_view, recording, app_state, ctx, action);
+    }
+
+    if let Some(ram) = app_state.ram().filter(|_| state.keyboard.open) {
+        draw_keyboard_window(&state.keyboard, ram, ctx, action);
+    }
+
+    if state.quit_dialog_open {
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                if state.dirty {
+                    ui.label("The session has changes that were not exported.");
+                }
+                if state.source_editor.modified {
+                    ui.label("The source editor has changes that were not saved.");
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            state.dirty && !state.from_exam,
+                            egui::Button::new("Export and Quit"),
+                        )
+                        .clicked()
+                    {
+                        if let Some(session) = app_state.session(state) {
+                            export_session(ctx, async_actions_sender, session, true);
+                        }
+                    }
+                    if ui.button("Quit Without Exporting").clicked() {
+                        *action = Some(Action::QuitConfirmed);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        *action = Some(Action::QuitCancelled);
+                    }
+                });
+            });
+    }
+
+    if let Some(metadata) = metadata.filter(|metadata| !metadata.is_empty()) {
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(metadata.to_string());
+                if let Some(speed_hint) = metadata.speed_hint {
+                    ui.separator();
+                    ui.label(format!("Speed hint: {} steps per second", speed_hint));
+                }
+            });
+        });
+    }
+
+    if let Some(ticks) = app_state.ticks() {
+        draw_timeline(&state.checkpoints, ticks, ctx, action);
+        if state.diff.open {
+            draw_diff_window(&state.diff, &state.checkpoints, ctx, action);
+        }
+    }
+}
+
+fn draw_timeline(
+    checkpoints_state: &CheckpointsState,
+    ticks: u64,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    egui::TopBottomPanel::bottom("timeline").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.label(format!("Step {}", ticks));
+            let mut new_name = checkpoints_state.new_name.clone();
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut new_name)
+                    .hint_text("Checkpoint name")
+                    .desired_width(150.0),
+            );
+            if new_name != checkpoints_state.new_name {
+                *action = Some(Action::Checkpoint(CheckpointAction::NameChanged(new_name)));
+            }
+            if ui.button("Add Checkpoint").clicked()
+                || (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)))
+            {
+                *action = Some(Action::Checkpoint(CheckpointAction::AddClicked));
+            }
+            if ui.button("Compare").clicked() {
+                *action = Some(Action::Diff(DiffAction::Clicked));
+            }
+            ui.separator();
+            egui::ScrollArea::horizontal().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for (index, checkpoint) in checkpoints_state.checkpoints.iter().enumerate() {
+                        let response = ui.selectable_label(
+                            checkpoint.ticks == ticks,
+                            format!("{} @ {}", checkpoint.name, checkpoint.ticks),
+                        );
+                        if response.clicked() {
+                            *action = Some(Action::Checkpoint(CheckpointAction::Selected(index)));
+                        }
+                        response.context_menu(|ui| {
+                            if ui.button("Remove").clicked() {
+                                ui.close_menu();
+                                *action = Some(Action::Checkpoint(
+                                    CheckpointAction::RemoveClicked(index),
+                                ));
+                            }
+                        });
+                    }
+                });
+            });
+        });
+    });
+}
+
+fn snapshot_name(checkpoints_state: &CheckpointsState, selection: Option<usize>) -> &str {
+    selection.map_or("Current", |index| {
+        &checkpoints_state.checkpoints[index].name
+    })
+}
+
+fn snapshot_combo(
+    ui: &mut egui::Ui,
+    id: &str,
+    checkpoints_state: &CheckpointsState,
+    selection: &mut Option<usize>,
+) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(snapshot_name(checkpoints_state, *selection))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(selection, None, "Current");
+            for (index, checkpoint) in checkpoints_state.checkpoints.iter().enumerate() {
+                ui.selectable_value(selection, Some(index), &checkpoint.name);
+            }
+        });
+}
+
+fn draw_diff_window(
+    diff_state: &DiffState,
+    checkpoints_state: &CheckpointsState,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    egui::Window::new("Compare Snapshots")
+        .open(&mut open)
+        .default_height(400.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                help_button(
+                    ui,
+                    "Shows the registers and memory words that differ between two checkpoints.",
+                );
+                ui.label("From");
+                let mut from = diff_state.from;
+                snapshot_combo(ui, "diff_from", checkpoints_state, &mut from);
+                if from != diff_state.from {
+                    *action = Some(Action::Diff(DiffAction::FromSelected(from)));
+                }
+                ui.label("to");
+                let mut to = diff_state.to;
+                snapshot_combo(ui, "diff_to", checkpoints_state, &mut to);
+                if to != diff_state.to {
+                    *action = Some(Action::Diff(DiffAction::ToSelected(to)));
+                }
+                if ui.button("Compare").clicked() {
+                    *action = Some(Action::Diff(DiffAction::CompareClicked));
+                }
+            });
+            let Some(diff) = &diff_state.diff else {
+                return;
+            };
+            ui.separator();
+            if diff.registers.is_empty() && diff.regions.is_empty() {
+                ui.label("No differences");
+            }
+            egui::Grid::new("register_changes").show(ui, |ui| {
+                for change in &diff.registers {
+                    ui.label(change.name);
+                    ui.monospace(change.old.to_string());
+                    ui.monospace(change.new.to_string());
+                    ui.end_row();
+                }
+            });
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (region, changes) in &diff.regions {
+                    egui::CollapsingHeader::new(format!("{} ({} changed)", region, changes.len()))
+                        .show(ui, |ui| {
+                            egui::ScrollArea::vertical()
+                                .id_source(region)
+                                .max_height(200.0)
+                                .show_rows(ui, row_height, changes.len(), |ui, rows| {
+                                    for change in &changes[rows] {
+                                        ui.monospace(format!(
+                                            "{:>5}: {} -> {}",
+                                            change.address, change.old, change.new
+                                        ));
+                                    }
+                                });
+                        });
+                }
+            });
+        });
+    if !open {
+        *action = Some(Action::Diff(DiffAction::Closed));
+    }
+}
+
+fn draw_keyboard_window(
+    keyboard_state: &KeyboardState,
+    ram: &RAM,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = keyboard_state.open;
+    egui::Window::new("Keyboard")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            help_button(
+                ui,
+                "The program reads the pressed key from KBD. Hold a key code to press it for a \
+                 fixed number of steps regardless of the host keyboard.",
+            );
+            egui::Grid::new("keyboard grid").show(ui, |ui| {
+                ui.label("KBD");
+                ui.monospace(ram[RAM::KBD].to_string());
+                ui.end_row();
+                ui.label("Host key");
+                ui.monospace(keyboard_state.host_value.to_string());
+                ui.end_row();
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let mut value = keyboard_state.override_value;
+                let mut steps = keyboard_state.override_steps;
+                ui.label("Hold");
+                ui.add(egui::DragValue::new(&mut value));
+                ui.label("for");
+                ui.add(egui::DragValue::new(&mut steps).clamp_range(1..=MAX_FRAME_STEPS));
+                ui.label("steps");
+                if value != keyboard_state.override_value {
+                    *action = Some(Action::Keyboard(KeyboardAction::OverrideValueChanged(
+                        value,
+                    )));
+                }
+                if steps != keyboard_state.override_steps {
+                    *action = Some(Action::Keyboard(KeyboardAction::OverrideStepsChanged(
+                        steps,
+                    )));
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Hold").clicked() {
+                    *action = Some(Action::Keyboard(KeyboardAction::HoldClicked));
+                }
+                if keyboard_state.remaining_override_steps > 0 {
+                    if ui.button("Release").clicked() {
+                        *action = Some(Action::Keyboard(KeyboardAction::ReleaseClicked));
+                    }
+                    ui.label(format!(
+                        "{} steps left",
+                        keyboard_state.remaining_override_steps
+                    ));
+                }
+            });
+        });
+
+    if keyboard_state.open != open {
+        *action = Some(Action::Keyboard(KeyboardAction::Closed));
+    }
+}
+
+// Covers the subset of markdown that lecture notes need: headings, bullets and code blocks.
+fn draw_markdown(ui: &mut egui::Ui, text: &str) {
+    let mut in_code_block = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        } else if in_code_block {
+            ui.monospace(line);
+        } else if line.trim().is_empty() {
+            ui.add_space(ui.spacing().item_spacing.y);
+        } else if let Some(heading) = line.strip_prefix('#') {
+            let level = heading.chars().take_while(|c| *c == '#').count();
+            let text = egui::RichText::new(heading.trim_start_matches('#').trim()).strong();
+            ui.label(if level == 0 { text.heading() } else { text });
+        } else if let Some(item) = line
+            .trim_start()
+            .strip_prefix("- ")
+            .or_else(|| line.trim_start().strip_prefix("* "))
+        {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("•");
+                ui.label(item);
+            });
+        } else {
+            ui.label(line);
+        }
+    }
+}
+
+fn draw_annotations_panel(
+    script: &AnnotationScript,
+    ticks: u64,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    egui::SidePanel::right("annotations_panel")
+        .resizable(true)
+        .default_width(300.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Notes");
+                if ui.button("Close").clicked() {
+                    *action = Some(Action::AnnotationsClosed);
+                }
+            });
+            ui.separator();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                let mut active = script.active(ticks).peekable();
+                if active.peek().is_none() {
+                    ui.weak(format!("No notes for step {}", ticks));
+                }
+                for annotation in active {
+                    draw_markdown(ui, &annotation.note);
+                    ui.separator();
+                }
+            });
+        });
+}
+
+const PROFILER_ROWS: usize = 30;
 
-pub struct Screen {
-    program: glow::Program,
-    vertex_array: glow::VertexArray,
-    texture: glow::Texture,
+fn draw_profiler_window(
+    profiler_state: &ProfilerState,
+    app_state: &AppState,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    egui::Window::new("Profiler")
+        .open(&mut open)
+        .default_width(320.0)
+        .show(ctx, |ui| {
+            let sampler = app_state.sampler();
+            ui.horizontal(|ui| {
+                let mut sampling = sampler.is_some();
+                if ui.checkbox(&mut sampling, "Sample").changed() {
+                    *action = Some(Action::Profiler(ProfilerAction::SamplingChanged(sampling)));
+                }
+                ui.label("every");
+                let mut interval_micros = profiler_state.interval_micros;
+                ui.add(
+                    egui::DragValue::new(&mut interval_micros)
+                        .clamp_range(1..=1_000_000)
+                        .suffix(" µs"),
+                );
+                if interval_micros != profiler_state.interval_micros {
+                    *action = Some(Action::Profiler(ProfilerAction::IntervalChanged(
+                        interval_micros,
+                    )));
+                }
+                help_button(
+                    ui,
+                    "Samples the running instruction or VM command at a fixed interval. The \
+                     counts are approximate, but sampling barely slows down the run.",
+                );
+            });
+            let Some(sampler) = sampler else {
+                return;
+            };
+            ui.horizontal(|ui| {
+                ui.label(format!("{} samples", sampler.sample_count));
+                if let AppState::VM(vm_state) = app_state {
+                    if ui
+                        .add_enabled(
+                            !sampler.stacks.is_empty(),
+                            egui::Button::new("Export for speedscope…"),
+                        )
+                        .on_hover_text("Save the sampled call stacks to open as a flame graph")
+                        .clicked()
+                    {
+                        if let Some(profile) = vm_state.speedscope_profile() {
+                            export_bytes(
+                                "profile.speedscope.json",
+                                "JSON",
+                                "json",
+                                profile.into_bytes(),
+                            );
+                        }
+                    }
+                }
+            });
+            let total = sampler.sample_count.max(1);
+            egui::Grid::new("profiler grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    for entry in sampler
+                        .profile(&app_state.profile_regions())
+                        .iter()
+                        .take(PROFILER_ROWS)
+                    {
+                        ui.label(&entry.name);
+                        ui.monospace(entry.count.to_string());
+                        ui.monospace(format!("{:.1}%", entry.count as f64 / total as f64 * 100.0));
+                        ui.end_row();
+                    }
+                });
+        });
+    if !open {
+        *action = Some(Action::Profiler(ProfilerAction::Closed));
+    }
+}
+
+fn test_status_icon(ui: &mut egui::Ui, status: &TestStatus) {
+    match status {
+        TestStatus::NotRun => ui.weak("○"),
+        TestStatus::Running => ui.spinner(),
+        TestStatus::Finished(outcome) if outcome.passed() => {
+            ui.colored_label(egui::Color32::GREEN, "✔")
+        }
+        TestStatus::Finished(_) => ui.colored_label(egui::Color32::RED, "✖"),
+    };
+}
+
+fn draw_test_explorer(
+    tests_state: &TestsState,
+    app_state: &AppState,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    egui::SidePanel::left("test_explorer")
+        .resizable(true)
+        .default_width(260.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Tests");
+                if ui.button("Close").clicked() {
+                    *action = Some(Action::Tests(TestsAction::Closed));
+                }
+                help_button(
+                    ui,
+                    "Lists the .tst scripts in the program's directory. Scripts run at the same \
+                     time, each on its own emulator, and their output is compared with the .cmp \
+                     files. Click a failed test to see where its output differs.",
+                );
+            });
+            let running = tests_state.running();
+            ui.horizontal(|ui| {
+                let can_run = app_state.program_directory().is_some() && !running;
+                if ui
+                    .add_enabled(can_run, egui::Button::new("Run all tests"))
+                    .clicked()
+                {
+                    *action = Some(Action::Tests(TestsAction::RunAllClicked));
+                }
+                let any_failed = tests_state
+                    .results
+                    .iter()
+                    .any(|result| result.status.failed());
+                if ui
+                    .add_enabled(can_run && any_failed, egui::Button::new("Run failed"))
+                    .clicked()
+                {
+                    *action = Some(Action::Tests(TestsAction::RunFailedClicked));
+                }
+                if ui
+                    .add_enabled(can_run, egui::Button::new("Refresh"))
+                    .clicked()
+                {
+                    *action = Some(Action::Tests(TestsAction::RefreshClicked));
+                }
+            });
+            let mut filter = tests_state.filter.clone();
+            ui.add(egui::TextEdit::singleline(&mut filter).hint_text("Filter"));
+            if filter != tests_state.filter {
+                *action = Some(Action::Tests(TestsAction::FilterChanged(filter)));
+            }
+            ui.separator();
+
+            let Some(directory) = &tests_state.directory else {
+                ui.weak("Load a program from a directory with .tst files");
+                return;
+            };
+            if tests_state.results.is_empty() {
+                ui.weak(format!(
+                    "There are no .tst files in {}",
+                    directory.display()
+                ));
+                return;
+            }
+            let passed = tests_state
+                .results
+                .iter()
+                .filter(|result| {
+                    matches!(&result.status, TestStatus::Finished(outcome) if outcome.passed())
+                })
+                .count();
+            ui.label(format!(
+                "{} of {} tests passed",
+                passed,
+                tests_state.results.len()
+            ));
+            let filter = tests_state.filter.to_lowercase();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("tests grid").striped(true).show(ui, |ui| {
+                    for (index, result) in tests_state.results.iter().enumerate() {
+                        if !result.name.to_lowercase().contains(&filter) {
+                            continue;
+                        }
+                        test_status_icon(ui, &result.status);
+                        let selected = tests_state.selected == Some(index);
+                        let label = ui.selectable_label(selected, &result.name);
+                        if let TestStatus::Finished(outcome) = &result.status {
+                            if label.clicked() {
+                                *action = Some(Action::Tests(TestsAction::Selected(index)));
+                            }
+                            ui.monospace(outcome.steps.to_string());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+}
+
+fn output_cells(line: &str) -> impl Iterator<Item = &str> {
+    line.trim().trim_matches('|').split('|')
+}
+
+// The first row is the output-list header, the rest are the values output so far.
+fn draw_test_output_window(result: &TestResult, ctx: &egui::Context, action: &mut Option<Action>) {
+    let mut open = true;
+    egui::Window::new(format!("{} Output", result.name))
+        .id(egui::Id::new("test comparison"))
+        .open(&mut open)
+        .default_width(640.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("{} rows", result.output.len().saturating_sub(1)));
+            });
+            let Some((header, rows)) = result.output.split_first() else {
+                return;
+            };
+            egui::ScrollArea::both()
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    egui::Grid::new("test output grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for name in output_cells(header) {
+                                ui.strong(name.trim());
+                            }
+                            ui.end_row();
+                            for row in rows {
+                                for cell in output_cells(row) {
+                                    ui.monospace(cell);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+    if !open {
+        *action = Some(Action::Tests(TestsAction::ComparisonClosed));
+    }
+}
+
+fn draw_test_comparison_window(
+    result: &TestResult,
+    outcome: &TestOutcome,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    egui::Window::new(format!("{} Comparison", result.name))
+        .id(egui::Id::new("test comparison"))
+        .open(&mut open)
+        .default_width(640.0)
+        .show(ctx, |ui| {
+            match &outcome.result {
+                Ok(()) => ui.colored_label(egui::Color32::GREEN, "Passed"),
+                Err(failure) => ui.colored_label(egui::Color32::RED, failure.to_string()),
+            };
+            let failed_line = match &outcome.result {
+                Err(TestFailure::Comparison { line, .. }) => Some(*line),
+                _ => None,
+            };
+            egui::ScrollArea::both().show(ui, |ui| {
+                egui::Grid::new("test comparison grid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Line");
+                        ui.strong("Expected");
+                        ui.strong("Output");
+                        ui.end_row();
+                        for index in 0..outcome.expected.len().max(outcome.output.len()) {
+                            let color = if failed_line == Some(index + 1) {
+                                egui::Color32::RED
+                            } else {
+                                ui.visuals().text_color()
+                            };
+                            let line = |lines: &[String]| {
+                                egui::RichText::new(lines.get(index).map_or("", String::as_str))
+                                    .monospace()
+                                    .color(color)
+                            };
+                            ui.monospace((index + 1).to_string());
+                            ui.label(line(&outcome.expected));
+                            ui.label(line(&outcome.output));
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+    if !open {
+        *action = Some(Action::Tests(TestsAction::ComparisonClosed));
+    }
+}
+
+const TRACE_VIEW_ROWS: u64 = 20;
+
+fn draw_trace_window(
+    trace_view_state: &TraceViewState,
+    recording: &Recording,
+    app_state: &AppState,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    egui::Window::new("Trace")
+        .open(&mut open)
+        .default_width(360.0)
+        .show(ctx, |ui| {
+            let trace = &recording.full_trace;
+            ui.label(format!(
+                "{} steps in {} KiB",
+                trace.len(),
+                trace.compressed_size().div_ceil(1024)
+            ));
+            ui.horizontal(|ui| {
+                ui.label("From step");
+                let mut step = trace_view_state.step;
+                ui.add(egui::DragValue::new(&mut step));
+                if step != trace_view_state.step {
+                    *action = Some(Action::TraceView(TraceViewAction::StepChanged(step)));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Last step where");
+                let mut query = trace_view_state.query.clone();
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut query)
+                        .hint_text("RAM[300] == 0")
+                        .desired_width(150.0),
+                );
+                if query != trace_view_state.query {
+                    *action = Some(Action::TraceView(TraceViewAction::QueryChanged(query)));
+                }
+                if ui.button("Find and Jump").clicked()
+                    || (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)))
+                {
+                    *action = Some(Action::TraceView(TraceViewAction::FindLastClicked));
+                }
+                help_button(
+                    ui,
+                    "Replays the recording to find the last step before the current one where \
+                     the condition held, then goes back to it. Use it to find out when a \
+                     variable still had its old value.",
+                );
+            });
+            match &trace_view_state.query_result {
+                Some(Ok(message)) => {
+                    ui.label(message);
+                }
+                Some(Err(error)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                None => {}
+            }
+            ui.separator();
+            match trace.range(
+                trace_view_state.step,
+                trace_view_state.step + TRACE_VIEW_ROWS,
+            ) {
+                Ok(entries) if entries.is_empty() => {
+                    ui.weak("No steps were recorded in this range");
+                }
+                Ok(entries) => {
+                    egui::Grid::new("trace grid").striped(true).show(ui, |ui| {
+                        for entry in entries {
+                            ui.monospace(entry.step.to_string());
+                            ui.monospace(entry.location.to_string());
+                            ui.monospace(app_state.location_text(entry.location));
+                            ui.end_row();
+                        }
+                    });
+                }
+                Err(error) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            }
+        });
+    if !open {
+        *action = Some(Action::TraceView(TraceViewAction::Closed));
+    }
+}
+
+fn draw_log_window(log: &Log, ctx: &egui::Context, action: &mut Option<Action>) {
+    let mut open = true;
+    egui::Window::new("Log")
+        .open(&mut open)
+        .default_height(300.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Clear").clicked() {
+                    *action = Some(Action::Common(CommonAction::LogCleared));
+                }
+                help_button(
+                    ui,
+                    "Logpoints, breakpoint scripts, assertions and diagnostics write here.",
+                );
+            });
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::vertical()
+                .auto_shrink(false)
+                .stick_to_bottom(true)
+                .show_rows(ui, row_height, log.lines.len(), |ui, rows| {
+                    for line in log.lines.range(rows) {
+                        ui.monospace(line);
+                    }
+                });
+        });
+    if !open {
+        *action = Some(Action::Common(CommonAction::LogClosed));
+    }
+}
+
+fn draw_memory_window(
+    usage: &[(RecordedData, usize)],
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    egui::Window::new("Memory").open(&mut open).show(ctx, |ui| {
+        egui::Grid::new("memory_usage")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                for &(data, size) in usage {
+                    ui.label(data.to_string());
+                    ui.label(format!("{} KiB", size.div_ceil(1024)));
+                    if ui
+                        .add_enabled(size > 0, egui::Button::new("Clear"))
+                        .clicked()
+                    {
+                        *action = Some(Action::Memory(MemoryAction::Cleared(data)));
+                    }
+                    ui.end_row();
+                }
+                let total: usize = usage.iter().map(|(_, size)| size).sum();
+                ui.strong("Total");
+                ui.strong(format!("{} KiB", total.div_ceil(1024)));
+                ui.end_row();
+            });
+        help_button(
+            ui,
+            "Memory held by recorded data, which keeps growing while recording. Clearing the \
+            trace starts the recording over.",
+        );
+    });
+    if !open {
+        *action = Some(Action::Memory(MemoryAction::Closed));
+    }
+}
+
+fn highlighted_asm(ui: &egui::Ui, text: &str) -> egui::text::LayoutJob {
+    let visuals = ui.visuals();
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+    let format = |color| egui::TextFormat::simple(font_id.clone(), color);
+    let mut job = egui::text::LayoutJob::default();
+    let mut end = 0;
+    for (range, token) in highlight_asm(text) {
+        job.append(&text[end..range.start], 0.0, format(visuals.text_color()));
+        let color = match token {
+            AsmToken::Comment => visuals.weak_text_color(),
+            AsmToken::Label => visuals.warn_fg_color,
+            AsmToken::Directive => visuals.hyperlink_color,
+            AsmToken::Symbol => visuals.strong_text_color(),
+            AsmToken::Number => egui::Color32::from_rgb(0x4c, 0xaf, 0x50),
+            AsmToken::Destination => egui::Color32::from_rgb(0x29, 0x96, 0xd8),
+            AsmToken::Computation => visuals.text_color(),
+            AsmToken::Jump => egui::Color32::from_rgb(0xc0, 0x6e, 0xd4),
+        };
+        end = range.end;
+        job.append(&text[range], 0.0, format(color));
+    }
+    job.append(&text[end..], 0.0, format(visuals.text_color()));
+    job
+}
+
+fn draw_source_editor_window(
+    file: &LoadedFile,
+    modified: bool,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    let title = if modified {
+        format!("{} (modified)", file.name)
+    } else {
+        file.name.clone()
+    };
+    egui::Window::new(title)
+        .id(egui::Id::new("source editor"))
+        .open(&mut open)
+        .default_size([400.0, 500.0])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Assemble & Reload").clicked() {
+                    *action = Some(Action::SourceEditor(SourceEditorAction::AssembleClicked));
+                }
+                if ui
+                    .add_enabled(modified && file.path.is_some(), egui::Button::new("Save"))
+                    .clicked()
+                {
+                    *action = Some(Action::SourceEditor(SourceEditorAction::SaveClicked));
+                }
+            });
+            let mut contents = file.contents.clone();
+            let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                let mut job = highlighted_asm(ui, text);
+                job.wrap.max_width = wrap_width;
+                ui.fonts(|fonts| fonts.layout_job(job))
+            };
+            egui::ScrollArea::vertical()
+                .auto_shrink(false)
+                .show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut contents)
+                            .code_editor()
+                            .desired_width(f32::INFINITY)
+                            .layouter(&mut layouter),
+                    );
+                });
+            if contents != file.contents {
+                *action = Some(Action::SourceEditor(SourceEditorAction::Edited(contents)));
+            }
+        });
+    if !open {
+        *action = Some(Action::SourceEditor(SourceEditorAction::Closed));
+    }
 }
 
-impl Screen {
-    pub fn new(gl: &glow::Context) -> Self {
-        use glow::HasContext as _;
-
-        let shader_version = if cfg!(target_arch = "wasm32") {
-            "#version 300 es"
-        } else {
-            "#version 410"
-        };
+fn draw_load_errors_window(
+    load_errors: &LoadErrors,
+    ctx: &egui::Context,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    egui::Window::new(format!("Errors in {}", load_errors.file.name))
+        .open(&mut open)
+        .default_height(300.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(load_errors.file.path.is_some(), egui::Button::new("Retry"))
+                    .on_hover_text("Load the file again after fixing it")
+                    .clicked()
+                {
+                    *action = Some(Action::LoadErrorsRetryClicked);
+                }
+                ui.label(format!(
+                    "{} errors, nothing was loaded",
+                    load_errors.errors.len()
+                ));
+            });
+            egui::ScrollArea::vertical()
+                .auto_shrink(false)
+                .show(ui, |ui| {
+                    egui::Grid::new("load_errors").striped(true).show(ui, |ui| {
+                        for error in &load_errors.errors {
+                            ui.monospace(format!("line {}", error.line));
+                            ui.monospace(&error.text);
+                            ui.label(&error.message);
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+    if !open {
+        *action = Some(Action::LoadErrorsClosed);
+    }
+}
 
-        unsafe {
-            let program = gl.create_program().expect("Cannot create program");
+fn draw_invariants_window(state: &SharedState, ctx: &egui::Context, action: &mut Option<Action>) {
+    let mut invariants_open = state.invariants_open;
 
-            let (vertex_shader_source, fragment_shader_source) = (
-                r#"
-                    const vec2 verts[4] = vec2[4](
-                        vec2(1.0, 1.0),
-                        vec2(-1.0, 1.0),
-                        vec2(1.0, -1.0),
-                        vec2(-1.0, -1.0)
-                    );
+    egui::Window::new("Invariants")
+        .open(&mut invariants_open)
+        .resizable(true)
+        .show(ctx, |ui| {
+            let invariants_state = &state.invariants;
+            if let Some(violation) = &invariants_state.violation {
+                ui.colored_label(ui.visuals().error_fg_color, violation);
+            }
+            ui.horizontal(|ui| {
+                help_button(
+                    ui,
+                    "Invariants are expressions checked after every frame, e.g. RAM[0] >= 256. \
+                     The run pauses when one stops holding.",
+                );
+                let mut new_source = invariants_state.new_source.clone();
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut new_source)
+                        .hint_text("RAM[0] >= 256")
+                        .desired_width(200.0),
+                );
+                if new_source != invariants_state.new_source {
+                    *action = Some(Action::Invariant(InvariantAction::SourceChanged(
+                        new_source,
+                    )));
+                }
+                if ui.button("Add").clicked()
+                    || (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)))
+                {
+                    *action = Some(Action::Invariant(InvariantAction::AddClicked));
+                }
+            });
+            if let Some(error) = &invariants_state.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            for (index, invariant) in invariants_state.invariants.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.monospace(&invariant.source);
+                    if ui.button("Remove").clicked() {
+                        *action = Some(Action::Invariant(InvariantAction::RemoveClicked(index)));
+                    }
+                });
+            }
+        });
 
-                    out vec2 v_pos;
-                    void main() {
-                        gl_Position = vec4(verts[gl_VertexID], 0.0, 1.0);
-                        v_pos = gl_Position.xy * vec2(1.0, -1.0);
-                    }
-                "#,
-                r#"
-                    precision mediump float;
-                    uniform lowp usampler2D u_screen;
-                    in vec2 v_pos;
-                    out vec4 out_color;
-                    void main() {
-                        ivec2 coord = ivec2((v_pos + 1.0) * vec2(256.0, 128.0));
-                        uint i_color = uint(1) - ((texelFetch(u_screen, coord / ivec2(8, 1) ,0).r >> (coord.x % 8)) & uint(1));
-                        out_color = vec4(vec3(i_color), 1.0);
-                    }
-                "#,
-            );
+    if state.invariants_open != invariants_open {
+        *action = Some(Action::Common(CommonAction::InvariantsClosed));
+    }
+}
 
-            let shader_sources = [
-                (glow::VERTEX_SHADER, vertex_shader_source),
-                (glow::FRAGMENT_SHADER, fragment_shader_source),
-            ];
+pub fn draw_exam_unlock_dialog(
+    ctx: &egui::Context,
+    exam_unlock: &ExamUnlock,
+    action: &mut Option<Action>,
+) {
+    let mut open = true;
+    egui::Window::new(format!("Unlock {}", exam_unlock.name))
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Password");
+                let mut password = exam_unlock.password.clone();
+                let response = ui.add(egui::TextEdit::singleline(&mut password).password(true));
+                if password != exam_unlock.password {
+                    *action = Some(Action::ExamPasswordChanged(password));
+                }
+                let entered =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui.button("Unlock").clicked() || entered {
+                    *action = Some(Action::ExamUnlockClicked);
+                }
+            });
+            if let Some(error) = &exam_unlock.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+    if !open {
+        *action = Some(Action::ExamUnlockClosed);
+    }
+}
 
-            let shaders: Vec<_> = shader_sources
-                .iter()
-                .map(|(shader_type, shader_source)| {
-                    let shader = gl
-                        .create_shader(*shader_type)
-                        .expect("Cannot create shader");
-                    gl.shader_source(shader, &format!("{}\n{}", shader_version, shader_source));
-                    gl.compile_shader(shader);
-                    if !gl.get_shader_compile_status(shader) {
-                        panic!("{}", gl.get_shader_info_log(shader));
-                    }
-                    gl.attach_shader(program, shader);
-                    shader
-                })
-                .collect();
+pub fn draw_recovery_dialog(ctx: &egui::Context, action: &mut Option<Action>) {
+    egui::Window::new("Recover Session")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label("The emulator crashed during the previous run.");
+            ui.horizontal(|ui| {
+                if ui.button("Restore Session").clicked() {
+                    *action = Some(Action::RecoveryAccepted);
+                }
+                if ui.button("Discard").clicked() {
+                    *action = Some(Action::RecoveryDiscarded);
+                }
+            });
+        });
+}
 
-            gl.link_program(program);
-            if !gl.get_program_link_status(program) {
-                panic!("{}", gl.get_program_info_log(program));
+fn draw_project_entries(ui: &mut egui::Ui, entries: &[ProjectEntry], action: &mut Option<Action>) {
+    for entry in entries {
+        match &entry.kind {
+            ProjectEntryKind::Directory(children) => {
+                egui::CollapsingHeader::new(&entry.name).show(ui, |ui| {
+                    draw_project_entries(ui, children, action);
+                });
             }
-
-            for shader in shaders {
-                gl.detach_shader(program, shader);
-                gl.delete_shader(shader);
+            ProjectEntryKind::Program(paths) => {
+                if ui.button(&entry.name).clicked() {
+                    *action = Some(Action::ProjectProgramSelected(paths.clone()));
+                }
             }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pick_projects_root(ctx: &egui::Context, async_actions_sender: &Sender<Action>) {
+    let task = rfd::AsyncFileDialog::new().pick_folder();
+    let ctx = ctx.clone();
+    let async_actions_sender = async_actions_sender.clone();
+    execute(async move {
+        if let Some(folder) = task.await {
+            let _ =
+                async_actions_sender.send(Action::ProjectsRootPicked(folder.path().to_path_buf()));
+            ctx.request_repaint();
+        }
+    });
+}
 
-            let vertex_array = gl
-                .create_vertex_array()
-                .expect("Cannot create vertex array");
+pub fn draw_projects_window(
+    ctx: &egui::Context,
+    projects: &[ProjectEntry],
+    projects_root: Option<&Path>,
+    action: &mut Option<Action>,
+    async_actions_sender: &Sender<Action>,
+) {
+    let mut open = true;
+    egui::Window::new("Projects")
+        .open(&mut open)
+        .default_height(400.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                match projects_root {
+                    Some(root) => ui.label(root.display().to_string()),
+                    None => ui.label("No projects directory chosen"),
+                };
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui.button("Choose...").clicked() {
+                    pick_projects_root(ctx, async_actions_sender);
+                }
+                #[cfg(target_arch = "wasm32")]
+                let _ = async_actions_sender;
+            });
+            ui.separator();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                draw_project_entries(ui, projects, action);
+            });
+        });
+    if !open {
+        *action = Some(Action::ProjectsClosed);
+    }
+}
 
-            let texture = gl.create_texture().unwrap();
-            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+pub fn window_title(metadata: Option<&ProgramMetadata>) -> String {
+    match metadata.filter(|metadata| metadata.name.is_some() || metadata.author.is_some()) {
+        Some(metadata) => format!("Emulator - {}", metadata),
+        None => "Emulator".to_owned(),
+    }
+}
 
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_MIN_FILTER,
-                glow::NEAREST as i32,
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_MAG_FILTER,
-                glow::NEAREST as i32,
-            );
-            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::REPEAT as i32);
-            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::REPEAT as i32);
-            gl.bind_texture(glow::TEXTURE_2D, None);
-
-            Self {
-                program,
-                vertex_array,
-                texture,
+fn export_session(
+    ctx: &egui::Context,
+    async_actions_sender: &Sender<Action>,
+    session: Session,
+    then_quit: bool,
+) {
+    let mut dialog = rfd::AsyncFileDialog::new().set_file_name("session.n2s");
+    if let Ok(current_dir) = std::env::current_dir() {
+        dialog = dialog.set_directory(current_dir);
+    }
+    let task = dialog.add_filter("Session", &[&"n2s"]).save_file();
+    let contents = session.to_string();
+    let ctx = ctx.clone();
+    let async_actions_sender = async_actions_sender.clone();
+    execute(async move {
+        if let Some(file) = task.await {
+            if file.write(contents.as_bytes()).await.is_ok() {
+                let _ = async_actions_sender.send(Action::SessionExported { then_quit });
+                ctx.request_repaint();
             }
         }
-    }
+    });
+}
 
-    pub fn destroy(&self, gl: &glow::Context) {
-        use glow::HasContext as _;
-        unsafe {
-            gl.delete_program(self.program);
-            gl.delete_vertex_array(self.vertex_array);
-            gl.delete_texture(self.texture);
-        }
+fn export_bytes(file_name: &str, filter_name: &str, extension: &str, contents: Vec<u8>) {
+    let mut dialog = rfd::AsyncFileDialog::new().set_file_name(file_name);
+    if let Ok(current_dir) = std::env::current_dir() {
+        dialog = dialog.set_directory(current_dir);
     }
+    let task = dialog.add_filter(filter_name, &[extension]).save_file();
+    execute(async move {
+        if let Some(file) = task.await {
+            let _ = file.write(&contents).await;
+        }
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn execute<F: Future<Output = ()> + Send + 'static>(f: F) {
+    std::thread::spawn(move || futures::executor::block_on(f));
+}
+
+#[cfg(target_arch = "wasm32")]
+fn execute<F: Future<Output = ()> + 'static>(f: F) {
+    wasm_bindgen_futures::spawn_local(f);
+}
 
-    pub fn paint(&self, gl: &glow::Context) {
-        use glow::HasContext as _;
-        unsafe {
-            gl.use_program(Some(self.program));
-            gl.active_texture(glow::TEXTURE0);
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
-            gl.uniform_1_i32(
-                gl.get_uniform_location(self.program, "u_screen").as_ref(),
-                0,
+// What the context menu of a row in the ROM and VM grids was used for.
+pub enum RowAction {
+    OpenInEditor(usize),
+    ToggleBookmark(usize),
+    ToggleBreakpoint(usize),
+    NavigateBookmark { forward: bool },
+}
+
+fn row_menu(
+    response: &egui::Response,
+    row_index: usize,
+    can_open_in_editor: bool,
+    can_toggle_breakpoint: bool,
+    row_action: &mut Option<RowAction>,
+) {
+    if can_toggle_breakpoint && response.double_clicked() {
+        *row_action = Some(RowAction::ToggleBreakpoint(row_index));
+    }
+    response.context_menu(|ui| {
+        let mut clicked = |ui: &mut egui::Ui, text, action| {
+            if ui.button(text).clicked() {
+                ui.close_menu();
+                *row_action = Some(action);
+            }
+        };
+        clicked(ui, "Toggle Bookmark", RowAction::ToggleBookmark(row_index));
+        if can_toggle_breakpoint {
+            clicked(
+                ui,
+                "Toggle Breakpoint (Double Click)",
+                RowAction::ToggleBreakpoint(row_index),
             );
-            gl.bind_vertex_array(Some(self.vertex_array));
-            gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
-            gl.bind_texture(glow::TEXTURE_2D, None);
         }
+        clicked(
+            ui,
+            "Next Bookmark (Ctrl+F2)",
+            RowAction::NavigateBookmark { forward: true },
+        );
+        clicked(
+            ui,
+            "Previous Bookmark (Ctrl+Shift+F2)",
+            RowAction::NavigateBookmark { forward: false },
+        );
+        if can_open_in_editor {
+            clicked(ui, "Open in Editor", RowAction::OpenInEditor(row_index));
+        }
+    });
+}
+
+fn row_index_text(row_index: usize, bookmarked: bool, ui: &egui::Ui) -> egui::RichText {
+    let text = egui::RichText::new(row_index.to_string()).monospace();
+    if bookmarked {
+        text.color(ui.visuals().warn_fg_color)
+    } else {
+        text
     }
 }
 
-pub fn draw_screen(
-    ui: &mut egui::Ui,
-    screen: &Arc<Mutex<Screen>>,
-    ram: &RAM,
-    frame: &eframe::Frame,
-) {
-    let rect = Rect::from_min_size(
-        ui.cursor().min,
-        egui::Vec2::new(ui.available_width(), ui.available_height()),
-    );
+// Whether the mouse wheel scrolled what's under the pointer in this UI.
+pub fn scrolled_by_hand(ui: &egui::Ui) -> bool {
+    ui.rect_contains_pointer(ui.max_rect()) && ui.input(|i| i.raw_scroll_delta.y != 0.0)
+}
 
-    // Clone locals so we can move them into the paint callback:
-    let screen = screen.clone();
-    let screen_buffer =
-        &ram.contents[RAM::SCREEN as usize..(RAM::SCREEN + 256 * RAM::SCREEN_ROW_LENGTH) as usize];
-
-    unsafe {
-        use glow::HasContext as _;
-        let context = frame.gl().unwrap();
-
-        context.active_texture(glow::TEXTURE0);
-        let guard = screen.lock();
-        context.bind_texture(glow::TEXTURE_2D, Some(guard.texture));
-        context.tex_image_2d(
-            glow::TEXTURE_2D,
-            0,
-            glow::R8UI as i32,
-            64,
-            256,
-            0,
-            glow::RED_INTEGER,
-            glow::UNSIGNED_BYTE,
-            Some(screen_buffer.align_to::<u8>().1),
-        );
-        context.bind_texture(glow::TEXTURE_2D, None);
+fn restored_scroll_offsets_id() -> egui::Id {
+    egui::Id::new("restored scroll offsets")
+}
+
+fn scroll_offsets_id() -> egui::Id {
+    egui::Id::new("scroll offsets")
+}
+
+// The grids jump to these offsets the next time they're drawn.
+pub fn restore_scroll_offsets(ctx: &egui::Context, scroll_offsets: BTreeMap<String, u32>) {
+    ctx.data_mut(|data| data.insert_temp(restored_scroll_offsets_id(), scroll_offsets));
+}
+
+// The offsets of the grids drawn since the last call.
+pub fn take_scroll_offsets(ctx: &egui::Context) -> BTreeMap<String, u32> {
+    ctx.data_mut(|data| data.remove_temp(scroll_offsets_id()))
+        .unwrap_or_default()
+}
+
+fn command_text(text: String, found: bool, current: bool, ui: &egui::Ui) -> egui::RichText {
+    let text = egui::RichText::new(text).monospace();
+    match (found, current) {
+        (_, true) => text
+            .color(ui.visuals().strong_text_color())
+            .background_color(ui.visuals().selection.bg_fill),
+        (true, false) => text.background_color(ui.visuals().faint_bg_color),
+        (false, false) => text,
     }
+}
 
-    let cb = eframe::egui_glow::CallbackFn::new(move |_info, painter| {
-        screen.lock().paint(painter.gl());
-    });
+fn find_field_id() -> egui::Id {
+    egui::Id::new("find field")
+}
 
-    let callback = egui::PaintCallback {
-        rect,
-        callback: Arc::new(cb),
-    };
-    ui.painter().add(callback);
+pub fn focus_find_bar(ctx: &egui::Context) {
+    ctx.memory_mut(|memory| memory.request_focus(find_field_id()));
 }
 
-pub fn draw_shared(
-    state: &SharedState,
+// Enter goes to the next match, Shift+Enter to the previous one.
+pub fn draw_find_bar(
     ctx: &egui::Context,
-    performance_data: &PerformanceData,
-    is_top_bar_enabled: bool,
+    find: &FindState,
+    can_find_in_files: bool,
     action: &mut Option<Action>,
-    async_actions_sender: &Sender<Action>,
 ) {
-    egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
-        // The top panel is often a good place for a menu bar:
-        // #[cfg(not(target_arch = "wasm32"))]
-        {
-            egui::menu::bar(ui, |ui| {
-                ui.menu_button("File", |ui| {
-                    if ui.button("Load VM Files").clicked() {
-                        ui.close_menu();
-                        let mut dialog = rfd::AsyncFileDialog::new();
-                        if let Ok(current_dir) = std::env::current_dir() {
-                            dialog = dialog.set_directory(current_dir);
-                        }
-                        let task = dialog.add_filter("VM", &[&"vm"]).pick_files();
-                        let ctx = ctx.clone();
-                        let async_actions_sender = async_actions_sender.clone();
-                        execute(async move {
-                            if let Some(files) = task.await {
-                                let file_contents_futures: Vec<_> = files
-                                    .iter()
-                                    .map(|f| async {
-                                        (f.file_name(), String::from_utf8(f.read().await).unwrap())
-                                    })
-                                    .collect();
-                                let file_contents = join_all(file_contents_futures).await;
-                                let _ =
-                                    async_actions_sender.send(Action::FilesPicked(file_contents));
-                                ctx.request_repaint();
-                            }
-                        });
-                    }
-                    if ui.button("Load Hack File").clicked() {
-                        ui.close_menu();
-                        let mut dialog = rfd::AsyncFileDialog::new();
-                        if let Ok(current_dir) = std::env::current_dir() {
-                            dialog = dialog.set_directory(current_dir);
-                        }
-                        let task = dialog.add_filter("Hack", &[&"asm", &"hack"]).pick_file();
-                        let ctx = ctx.clone();
-                        let async_actions_sender = async_actions_sender.clone();
-                        execute(async move {
-                            if let Some(file) = task.await {
-                                let contents = String::from_utf8(file.read().await).unwrap();
-                                let name = file.file_name();
-                                let _ = async_actions_sender
-                                    .send(Action::FilePicked { name, contents });
-                                ctx.request_repaint();
-                            }
-                        });
-                    }
-                    if ui.button("Close File(s)").clicked() {
-                        ui.close_menu();
-                        *action = Some(Action::CloseFile)
-                    }
-                    if ui.button("Quit").clicked() {
-                        *action = Some(Action::Quit);
-                    }
+    egui::TopBottomPanel::bottom("find_bar").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Find");
+            let mut query = find.query.clone();
+            let response = ui.add(egui::TextEdit::singleline(&mut query).id(find_field_id()));
+            if query != find.query {
+                *action = Some(Action::Find(FindAction::QueryChanged(query)));
+            }
+            if response.lost_focus() {
+                let (enter, shift, escape) = ui.input(|i| {
+                    (
+                        i.key_pressed(egui::Key::Enter),
+                        i.modifiers.shift,
+                        i.key_pressed(egui::Key::Escape),
+                    )
                 });
-            });
-        }
-        ui.separator();
-        ui.add_enabled_ui(is_top_bar_enabled, |ui| {
-            ui.horizontal_wrapped(|ui| {
-                if ui.button("Step").clicked() {
-                    *action = Some(Action::Common(CommonAction::StepClicked));
-                }
-                if ui.button("Run").clicked() {
-                    *action = Some(Action::Common(CommonAction::RunClicked));
-                }
-                if ui.button("Pause").clicked() {
-                    *action = Some(Action::Common(CommonAction::PauseClicked));
+                if enter {
+                    response.request_focus();
+                    *action = Some(Action::Find(FindAction::Navigated { forward: !shift }));
+                } else if escape {
+                    *action = Some(Action::Find(FindAction::Closed));
                 }
-                if ui.button("Reset").clicked() {
-                    *action = Some(Action::Common(CommonAction::ResetClicked));
+            }
+            let mut regex = find.regex;
+            if ui.checkbox(&mut regex, "Regex").changed() {
+                *action = Some(Action::Find(FindAction::RegexChanged(regex)));
+            }
+            let mut all_files = find.all_files;
+            if can_find_in_files && ui.checkbox(&mut all_files, "All files").changed() {
+                *action = Some(Action::Find(FindAction::AllFilesChanged(all_files)));
+            }
+            if ui.button("Previous").clicked() {
+                *action = Some(Action::Find(FindAction::Navigated { forward: false }));
+            }
+            if ui.button("Next").clicked() {
+                *action = Some(Action::Find(FindAction::Navigated { forward: true }));
+            }
+            let position = find
+                .current
+                .and_then(|row| find.matches.iter().position(|&found| found == row));
+            match (position, find.matches.len()) {
+                _ if find.query.is_empty() => {}
+                _ if find.error.is_some() => {
+                    ui.colored_label(ui.visuals().error_fg_color, "Invalid regex")
+                        .on_hover_text(find.error.as_deref().unwrap_or_default());
                 }
-                if ui.button("Breakpoints").clicked() {
-                    *action = Some(Action::Common(CommonAction::BreakpointsClicked));
+                (_, 0) => {
+                    ui.label("No matches");
                 }
-
-                let mut new_steps_per_second = state.desired_steps_per_second;
-                let height = ui.text_style_height(&egui::TextStyle::Body);
-                ui.allocate_ui_with_layout(
-                    [320.0, height].into(),
-                    egui::Layout::left_to_right(egui::Align::Center),
-                    |ui| {
-                        ui.label("Steps per second:");
-                        ui.scope(|ui| {
-                            ui.spacing_mut().interact_size.x = 100.0;
-                            ui.add_sized(
-                                [200.0, height],
-                                Slider::new(&mut new_steps_per_second, 0..=1000000000)
-                                    .logarithmic(true),
-                            );
-                        })
-                    },
-                );
-
-                if is_top_bar_enabled && new_steps_per_second != state.desired_steps_per_second {
-                    *action = Some(Action::Common(CommonAction::SpeedSliderMoved(
-                        new_steps_per_second,
-                    )))
+                (Some(position), count) => {
+                    ui.label(format!("{} of {}", position + 1, count));
                 }
-                if let Some(run_start) = performance_data.run_start {
-                    let run_time = (Instant::now() - run_start).as_secs_f64();
-                    let steps_per_second = performance_data.total_steps as f64 / run_time;
-                    ui.label("Actual:");
-                    ui.label((steps_per_second.round() as u64).to_string());
+                (None, count) => {
+                    ui.label(format!("{} matches", count));
                 }
-            });
+            }
+            if ui.button("Close").clicked() {
+                *action = Some(Action::Find(FindAction::Closed));
+            }
         });
+        if !can_find_in_files || !find.all_files || find.results.is_empty() {
+            return;
+        }
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical().max_height(150.0).show_rows(
+            ui,
+            row_height,
+            find.results.len(),
+            |ui, rows| {
+                for result in &find.results[rows] {
+                    let current = find.file.as_ref() == Some(&result.file)
+                        && find.current == Some(result.row);
+                    let text = format!("{}:{}  {}", result.file, result.row, result.text);
+                    if ui
+                        .selectable_label(current, egui::RichText::new(text).monospace())
+                        .clicked()
+                    {
+                        *action = Some(Action::Find(FindAction::ResultClicked {
+                            file: result.file.clone(),
+                            row: result.row,
+                        }));
+                    }
+                }
+            },
+        );
     });
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn execute<F: Future<Output = ()> + Send + 'static>(f: F) {
-    std::thread::spawn(move || futures::executor::block_on(f));
+fn jump_to_row_id() -> egui::Id {
+    egui::Id::new("jump to row")
 }
 
-#[cfg(target_arch = "wasm32")]
-fn execute<F: Future<Output = ()> + 'static>(f: F) {
-    wasm_bindgen_futures::spawn_local(f);
+// The grid centres on the row the next time it's drawn.
+pub fn jump_to_row(ctx: &egui::Context, grid: String, row: usize) {
+    ctx.data_mut(|data| data.insert_temp(jump_to_row_id(), (grid, row)));
+}
+
+fn take_jump_row(ui: &egui::Ui, grid: &str) -> Option<usize> {
+    ui.data_mut(
+        |data| match data.get_temp::<(String, usize)>(jump_to_row_id()) {
+            Some((jump_grid, row)) if jump_grid == grid => {
+                data.remove_temp::<(String, usize)>(jump_to_row_id());
+                Some(row)
+            }
+            _ => None,
+        },
+    )
+}
+
+fn take_restored_scroll_offset(ui: &egui::Ui, grid: &str) -> Option<f32> {
+    ui.data_mut(|data| {
+        data.get_temp_mut_or_default::<BTreeMap<String, u32>>(restored_scroll_offsets_id())
+            .remove(grid)
+    })
+    .map(|offset| offset as f32)
+}
+
+// The body of a scrolled table starts above the visible part by the offset.
+fn report_scroll_offset(ui: &egui::Ui, grid: &str) {
+    let offset = (ui.clip_rect().top() - ui.max_rect().top())
+        .max(0.0)
+        .round() as u32;
+    ui.data_mut(|data| {
+        data.get_temp_mut_or_default::<BTreeMap<String, u32>>(scroll_offsets_id())
+            .insert(grid.to_owned(), offset)
+    });
 }
 
 pub trait EmulatorWidgets {
+    #[allow(clippy::too_many_arguments)]
     fn ram_grid(
         &mut self,
         caption: &str,
@@ -336,7 +2164,9 @@ pub trait EmulatorWidgets {
         style: UIStyle,
         highlight_address: Option<Word>,
         scroll_to_row: bool,
+        write_origin: &dyn Fn(Word) -> Option<String>,
     );
+    #[allow(clippy::too_many_arguments)]
     fn rom_grid(
         &mut self,
         caption: &str,
@@ -344,14 +2174,27 @@ pub trait EmulatorWidgets {
         range: &RangeInclusive<Word>,
         highlight_address: Word,
         scroll_to_row: bool,
-    );
+        can_open_in_editor: bool,
+        bookmarks: &BTreeSet<Bookmark>,
+        find: &FindState,
+        fragments: &[(String, Range<usize>)],
+        display: &mut RomDisplay,
+        disassembly: Option<&[DisassembledInstruction]>,
+    ) -> Option<RowAction>;
+    #[allow(clippy::too_many_arguments)]
     fn vm_grid(
         &mut self,
+        id: &str,
         program: &Program,
         run_state: &RunState,
         selected_file: &mut String,
         scroll_to_row: bool,
-    );
+        locked: bool,
+        can_open_in_editor: bool,
+        bookmarks: &BTreeSet<Bookmark>,
+        breakpoint_commands: &HashMap<usize, usize>,
+        find: &FindState,
+    ) -> Option<RowAction>;
 }
 
 impl EmulatorWidgets for egui::Ui {
@@ -363,6 +2206,7 @@ impl EmulatorWidgets for egui::Ui {
         style: UIStyle,
         highlight_address: Option<Word>,
         scroll_to_address: bool,
+        write_origin: &dyn Fn(Word) -> Option<String>,
     ) {
         self.push_id(caption, |ui| {
             ui.vertical(|ui| {
@@ -371,12 +2215,15 @@ impl EmulatorWidgets for egui::Ui {
                 let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
 
                 let available_height = ui.available_height();
+                let restored_offset = take_restored_scroll_offset(ui, caption);
                 let mut builder = TableBuilder::new(ui)
                     .auto_shrink(false)
                     .min_scrolled_height(header_height + row_height)
                     .max_scroll_height(available_height);
 
-                if scroll_to_address {
+                if let Some(offset) = restored_offset {
+                    builder = builder.vertical_scroll_offset(offset);
+                } else if scroll_to_address {
                     if let Some(address) = highlight_address {
                         builder = builder.scroll_to_row((address - range.start()) as usize, None);
                     }
@@ -397,23 +2244,32 @@ impl EmulatorWidgets for egui::Ui {
                             });
                         }
                     })
-                    .body(|body| {
+                    .body(|mut body| {
+                        report_scroll_offset(body.ui_mut(), caption);
                         body.rows(
                             row_height,
                             *range.end() as usize - *range.start() as usize,
                             |mut row| {
                                 let row_index = row.index();
+                                // Segment views can reach past the end of the RAM.
+                                let address = Address::new(*range.start())
+                                    .and_then(|start| start.offset(row_index));
                                 row.set_selected(
-                                    highlight_address.map(|addr| addr as usize)
-                                        == Some(row_index + *range.start() as usize),
+                                    address.is_some()
+                                        && address.map(Address::word) == highlight_address,
                                 );
                                 row.col(|ui| {
                                     ui.monospace(row_index.to_string());
                                 });
+                                let Some(address) = address else {
+                                    return;
+                                };
                                 row.col(|ui| {
-                                    ui.monospace(
-                                        ram[row_index as Word + range.start()].to_string(),
-                                    );
+                                    let response = ui.monospace(ram[address].to_string());
+                                    if let Some(origin) = write_origin(address.word()) {
+                                        response
+                                            .on_hover_text(format!("Last written by {}", origin));
+                                    }
                                 });
                             },
                         );
@@ -429,7 +2285,14 @@ impl EmulatorWidgets for egui::Ui {
         range: &RangeInclusive<Word>,
         highlight_address: Word,
         scroll_to_address: bool,
-    ) {
+        can_open_in_editor: bool,
+        bookmarks: &BTreeSet<Bookmark>,
+        find: &FindState,
+        fragments: &[(String, Range<usize>)],
+        display: &mut RomDisplay,
+        disassembly: Option<&[DisassembledInstruction]>,
+    ) -> Option<RowAction> {
+        let mut row_action = None;
         self.push_id(caption, |ui| {
             ui.vertical(|ui| {
                 ui.label(caption);
@@ -437,17 +2300,24 @@ impl EmulatorWidgets for egui::Ui {
                 let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
 
                 let available_height = ui.available_height();
+                let jump_row = take_jump_row(ui, caption);
+                let restored_offset = take_restored_scroll_offset(ui, caption);
                 let mut builder = TableBuilder::new(ui)
                     .min_scrolled_height(header_height + row_height)
                     .max_scroll_height(available_height);
 
-                if scroll_to_address {
+                if let Some(row) = jump_row {
+                    builder = builder.scroll_to_row(row, Some(egui::Align::Center));
+                } else if let Some(offset) = restored_offset {
+                    builder = builder.vertical_scroll_offset(offset);
+                } else if scroll_to_address {
                     builder = builder.scroll_to_row(highlight_address as usize, None);
                 }
 
                 builder
                     .auto_shrink(false)
                     .striped(true)
+                    .sense(egui::Sense::click())
                     .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
                     .column(Column::initial(45.0).at_least(45.0))
                     .column(Column::remainder().at_least(70.0))
@@ -456,10 +2326,17 @@ impl EmulatorWidgets for egui::Ui {
                             ui.label("Address");
                         });
                         header.col(|ui| {
-                            ui.label("Instruction");
+                            egui::ComboBox::from_id_source("ROM display")
+                                .selected_text(display.to_string())
+                                .show_ui(ui, |ui| {
+                                    for option in RomDisplay::ALL {
+                                        ui.selectable_value(display, option, option.to_string());
+                                    }
+                                });
                         });
                     })
-                    .body(|body| {
+                    .body(|mut body| {
+                        report_scroll_offset(body.ui_mut(), caption);
                         body.rows(
                             row_height,
                             *range.end() as usize - *range.start() as usize,
@@ -467,28 +2344,75 @@ impl EmulatorWidgets for egui::Ui {
                                 let row_index = row.index();
                                 row.set_selected(row_index == highlight_address as usize);
                                 row.col(|ui| {
-                                    ui.monospace(row_index.to_string());
+                                    let fragment = fragments
+                                        .iter()
+                                        .find(|(_, range)| range.start == row_index);
+                                    let bookmarked = bookmarks.contains(&Bookmark {
+                                        file: None,
+                                        row: row_index,
+                                    });
+                                    let text = row_index_text(row_index, bookmarked, ui);
+                                    match fragment {
+                                        Some((name, range)) => {
+                                            ui.label(text.strong()).on_hover_text(format!(
+                                                "{} starts here, ROM[{}..{}]",
+                                                name, range.start, range.end
+                                            ));
+                                        }
+                                        None => {
+                                            ui.label(text);
+                                        }
+                                    }
                                 });
                                 row.col(|ui| {
-                                    ui.monospace(rom[row_index].to_string());
+                                    let disassembled = disassembly
+                                        .filter(|_| *display == RomDisplay::Assembly)
+                                        .and_then(|lines| lines.get(row_index));
+                                    let text = match disassembled {
+                                        Some(DisassembledInstruction {
+                                            label: Some(label),
+                                            code,
+                                        }) => format!("({}) {}", label, code),
+                                        Some(DisassembledInstruction { code, .. }) => code.clone(),
+                                        None => display.format(rom[row_index]),
+                                    };
+                                    let found = find.file.is_none() && find.is_match(row_index);
+                                    let current = found && find.current == Some(row_index);
+                                    ui.label(command_text(text, found, current, ui));
                                 });
+                                row_menu(
+                                    &row.response(),
+                                    row_index,
+                                    can_open_in_editor,
+                                    false,
+                                    &mut row_action,
+                                );
                             },
                         );
                     });
             });
         });
+        row_action
     }
 
+    // A locked grid stays on its file instead of switching to the one the PC is in.
     fn vm_grid(
         &mut self,
+        id: &str,
         program: &Program,
         run_state: &RunState,
         selected_file: &mut String,
         scroll_to_row: bool,
-    ) {
-        self.push_id("VM", |ui| {
+        locked: bool,
+        can_open_in_editor: bool,
+        bookmarks: &BTreeSet<Bookmark>,
+        breakpoint_commands: &HashMap<usize, usize>,
+        find: &FindState,
+    ) -> Option<RowAction> {
+        let mut row_action = None;
+        self.push_id(id, |ui| {
             ui.vertical(|ui| {
-                if scroll_to_row {
+                if scroll_to_row && !locked {
                     selected_file.clone_from(&program.files[run_state.current_file_index].name);
                 }
                 egui::ComboBox::from_id_source("VM combo")
@@ -505,11 +2429,19 @@ impl EmulatorWidgets for egui::Ui {
                 let commands = file.commands(&program.all_commands);
 
                 let available_height = ui.available_height();
+                // Every file keeps its own place.
+                let grid = format!("{} {}", id, selected_file);
+                let jump_row = take_jump_row(ui, &grid);
+                let restored_offset = take_restored_scroll_offset(ui, &grid);
                 let mut builder = TableBuilder::new(ui)
                     .min_scrolled_height(header_height + row_height)
                     .max_scroll_height(available_height);
 
-                if scroll_to_row {
+                if let Some(row) = jump_row {
+                    builder = builder.scroll_to_row(row, Some(egui::Align::Center));
+                } else if let Some(offset) = restored_offset {
+                    builder = builder.vertical_scroll_offset(offset);
+                } else if scroll_to_row && file_index == run_state.current_file_index {
                     builder = builder.scroll_to_row(
                         run_state.current_command_index - file.starting_command_index,
                         None,
@@ -519,6 +2451,7 @@ impl EmulatorWidgets for egui::Ui {
                 builder
                     .auto_shrink(false)
                     .striped(true)
+                    .sense(egui::Sense::click())
                     .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
                     .column(Column::initial(45.0).at_least(45.0))
                     .column(Column::remainder().at_least(70.0))
@@ -530,7 +2463,8 @@ impl EmulatorWidgets for egui::Ui {
                             ui.label("Command");
                         });
                     })
-                    .body(|body| {
+                    .body(|mut body| {
+                        report_scroll_offset(body.ui_mut(), &grid);
                         body.rows(row_height, commands.len(), |mut row| {
                             let row_index = row.index();
                             let is_highlighted = file_index == run_state.current_file_index
@@ -538,15 +2472,39 @@ impl EmulatorWidgets for egui::Ui {
                                     == run_state.current_command_index
                                         - file.starting_command_index;
                             row.set_selected(is_highlighted);
+                            let bookmarked = bookmarks.contains(&Bookmark {
+                                file: Some(selected_file.clone()),
+                                row: row_index,
+                            });
                             row.col(|ui| {
-                                ui.monospace(row_index.to_string());
+                                let text = row_index_text(row_index, bookmarked, ui);
+                                if breakpoint_commands
+                                    .contains_key(&(file.starting_command_index + row_index))
+                                {
+                                    ui.label(
+                                        egui::RichText::new("●").color(ui.visuals().error_fg_color),
+                                    );
+                                }
+                                ui.label(text);
                             });
+                            let found = find.file.as_deref() == Some(selected_file.as_str())
+                                && find.is_match(row_index);
+                            let current = found && find.current == Some(row_index);
                             row.col(|ui| {
-                                ui.monospace(commands[row_index].to_string());
+                                let text = commands[row_index].to_string();
+                                ui.label(command_text(text, found, current, ui));
                             });
+                            row_menu(
+                                &row.response(),
+                                row_index,
+                                can_open_in_editor,
+                                true,
+                                &mut row_action,
+                            );
                         });
                     });
             });
         });
+        row_action
     }
 }
@@ -0,0 +1,112 @@
+use crate::hardware::{Hardware, MemoryChange, MemoryRegion, RAM};
+use crate::vm::{Frame, RunState};
+
+pub enum Snapshot {
+    Hardware(Box<Hardware>),
+    VM(Box<RunState>),
+}
+
+impl Snapshot {
+    pub fn ram(&self) -> &RAM {
+        match self {
+            Snapshot::Hardware(hardware) => &hardware.ram,
+            Snapshot::VM(run_state) => &run_state.ram,
+        }
+    }
+
+    pub fn ticks(&self) -> u64 {
+        match self {
+            Snapshot::Hardware(hardware) => hardware.ticks,
+            Snapshot::VM(run_state) => run_state.ticks,
+        }
+    }
+
+    // Bytes held by the snapshot, roughly.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Snapshot::Hardware(hardware) => {
+                std::mem::size_of::<Hardware>()
+                    + std::mem::size_of_val(&*hardware.rom)
+                    + std::mem::size_of_val(&*hardware.ram.contents)
+            }
+            Snapshot::VM(run_state) => {
+                std::mem::size_of::<RunState>()
+                    + std::mem::size_of_val(&*run_state.ram.contents)
+                    + run_state.call_stack.capacity() * std::mem::size_of::<Frame>()
+            }
+        }
+    }
+
+    fn registers(&self) -> Vec<(&'static str, i64)> {
+        match self {
+            Snapshot::Hardware(hardware) => vec![
+                ("Steps", hardware.ticks as i64),
+                ("A", hardware.a as i64),
+                ("D", hardware.d as i64),
+                ("PC", hardware.pc as i64),
+            ],
+            Snapshot::VM(run_state) => vec![
+                ("Steps", run_state.ticks as i64),
+                ("File", run_state.current_file_index as i64),
+                ("Command", run_state.current_command_index as i64),
+                ("Call depth", run_state.call_stack.len() as i64),
+            ],
+        }
+    }
+}
+
+pub struct Checkpoint {
+    pub name: String,
+    pub ticks: u64,
+    pub snapshot: Snapshot,
+    // When it was added or restored last, on the `uses` count.
+    pub last_used: u64,
+}
+
+#[derive(Default)]
+pub struct CheckpointsState {
+    pub checkpoints: Vec<Checkpoint>,
+    pub new_name: String,
+    pub uses: u64,
+}
+
+pub struct RegisterChange {
+    pub name: &'static str,
+    pub old: i64,
+    pub new: i64,
+}
+
+pub struct SnapshotDiff {
+    pub registers: Vec<RegisterChange>,
+    pub regions: Vec<(MemoryRegion, Vec<MemoryChange>)>,
+}
+
+impl SnapshotDiff {
+    pub fn new(from: &Snapshot, to: &Snapshot) -> Self {
+        let registers = from
+            .registers()
+            .into_iter()
+            .zip(to.registers())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| RegisterChange { name, old, new })
+            .collect();
+
+        // Regions are contiguous, so the address ordered changes can be split in one pass.
+        let regions = to
+            .ram()
+            .changes_from(from.ram())
+            .chunk_by(|a, b| MemoryRegion::of(a.address) == MemoryRegion::of(b.address))
+            .map(|changes| (MemoryRegion::of(changes[0].address), changes.to_vec()))
+            .collect();
+
+        SnapshotDiff { registers, regions }
+    }
+}
+
+#[derive(Default)]
+pub struct DiffState {
+    pub open: bool,
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+    pub diff: Option<SnapshotDiff>,
+}
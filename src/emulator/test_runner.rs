@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{atomic::Ordering, Mutex};
+use std::sync::{mpsc::Sender, Arc};
+
+use super::common_state::{Action, TestsAction};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::test_script::{run_test_file, TestEvent};
+
+// Scripts run on a thread per core, each with its own emulator, results arrive as actions. Once
+// `cancelled` is set the running scripts stop and the rest are skipped.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_tests(
+    run: u64,
+    scripts: Vec<(usize, PathBuf)>,
+    sender: &Sender<Action>,
+    cancelled: Arc<AtomicBool>,
+) {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |count| count.get())
+        .min(scripts.len());
+    let scripts = Arc::new(Mutex::new(scripts.into_iter()));
+    for _ in 0..threads {
+        let sender = sender.clone();
+        let scripts = scripts.clone();
+        let cancelled = cancelled.clone();
+        std::thread::spawn(move || loop {
+            let Some((index, script)) = scripts.lock().unwrap().next() else {
+                break;
+            };
+            if cancelled.load(Ordering::Relaxed) {
+                break;
+            }
+            let outcome = run_test_file(
+                &script,
+                &mut |event| {
+                    if let TestEvent::Output(line) = event {
+                        let _ = sender.send(Action::Tests(TestsAction::OutputProduced {
+                            run,
+                            index,
+                            line: line.clone(),
+                        }));
+                    }
+                },
+                &cancelled,
+            );
+            let _ = sender.send(Action::Tests(TestsAction::Finished {
+                run,
+                index,
+                outcome,
+            }));
+        });
+    }
+}
+
+// Browsers have neither threads nor a project directory to read the scripts from.
+#[cfg(target_arch = "wasm32")]
+pub fn run_tests(
+    run: u64,
+    scripts: Vec<(usize, PathBuf)>,
+    sender: &Sender<Action>,
+    _cancelled: Arc<AtomicBool>,
+) {
+    for (index, _) in scripts {
+        let _ = sender.send(Action::Tests(TestsAction::Finished {
+            run,
+            index,
+            outcome: crate::test_script::TestOutcome::error(
+                "Tests can't run in the browser".to_owned(),
+            ),
+        }));
+    }
+}
@@ -0,0 +1,136 @@
+use eframe::egui;
+
+use super::common_state::{Action, CommonAction, TutorialAction};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TutorialTarget {
+    StepButton,
+    RAMGrid,
+    BreakpointsButton,
+    RunButton,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TutorialStep {
+    Step,
+    Memory,
+    Breakpoints,
+    Run,
+}
+
+impl TutorialStep {
+    pub const FIRST: TutorialStep = TutorialStep::Step;
+
+    pub fn target(self) -> TutorialTarget {
+        match self {
+            TutorialStep::Step => TutorialTarget::StepButton,
+            TutorialStep::Memory => TutorialTarget::RAMGrid,
+            TutorialStep::Breakpoints => TutorialTarget::BreakpointsButton,
+            TutorialStep::Run => TutorialTarget::RunButton,
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            TutorialStep::Step => {
+                "Step executes a single instruction. Click it to run the first one."
+            }
+            TutorialStep::Memory => {
+                "The RAM grid shows every memory word and updates as the program writes to it."
+            }
+            TutorialStep::Breakpoints => {
+                "Breakpoints pause a run when a register or memory word reaches a value. Open \
+                 the breakpoints window to add one."
+            }
+            TutorialStep::Run => {
+                "Run executes the program until a breakpoint is hit or you press Pause. Click it \
+                 to finish the tour."
+            }
+        }
+    }
+
+    // Steps that wait for the user to use the highlighted control have no Next button.
+    fn completing_action(self) -> Option<CommonAction> {
+        match self {
+            TutorialStep::Step => Some(CommonAction::StepClicked),
+            TutorialStep::Memory => None,
+            TutorialStep::Breakpoints => Some(CommonAction::BreakpointsClicked),
+            TutorialStep::Run => Some(CommonAction::RunClicked),
+        }
+    }
+
+    pub fn next(self) -> Option<TutorialStep> {
+        match self {
+            TutorialStep::Step => Some(TutorialStep::Memory),
+            TutorialStep::Memory => Some(TutorialStep::Breakpoints),
+            TutorialStep::Breakpoints => Some(TutorialStep::Run),
+            TutorialStep::Run => None,
+        }
+    }
+
+    pub fn completed_by(self, action: &CommonAction) -> bool {
+        self.completing_action().as_ref() == Some(action)
+    }
+}
+
+fn target_id(target: TutorialTarget) -> egui::Id {
+    egui::Id::new(("tutorial target", target))
+}
+
+// Widgets the tour can point at record where they were drawn this frame.
+pub fn mark_tutorial_target(ctx: &egui::Context, target: TutorialTarget, rect: egui::Rect) {
+    ctx.data_mut(|data| data.insert_temp(target_id(target), rect));
+}
+
+pub fn draw_tutorial(ctx: &egui::Context, step: TutorialStep, action: &mut Option<Action>) {
+    let id = target_id(step.target());
+    let target_rect = ctx.data_mut(|data| {
+        let rect = data.get_temp::<egui::Rect>(id);
+        data.remove::<egui::Rect>(id);
+        rect
+    });
+
+    if let Some(rect) = target_rect {
+        ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Foreground,
+            egui::Id::new("tutorial highlight"),
+        ))
+        .rect_stroke(
+            rect.expand(3.0),
+            4.0,
+            egui::Stroke::new(3.0, ctx.style().visuals.warn_fg_color),
+        );
+    }
+
+    let window = egui::Window::new("Guided Tour")
+        .collapsible(false)
+        .resizable(false);
+    let window = match target_rect {
+        Some(rect) => window.current_pos(rect.left_bottom() + egui::vec2(0.0, 12.0)),
+        None => window.anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0]),
+    };
+    window.show(ctx, |ui| {
+        ui.set_max_width(300.0);
+        ui.label(step.text());
+        ui.horizontal(|ui| {
+            if step.completing_action().is_none() && ui.button("Next").clicked() {
+                *action = Some(Action::Tutorial(TutorialAction::NextClicked));
+            }
+            if ui.button("End Tour").clicked() {
+                *action = Some(Action::Tutorial(TutorialAction::Closed));
+            }
+        });
+    });
+}
+
+pub fn help_button(ui: &mut egui::Ui, text: &str) {
+    let response = ui.small_button("?");
+    let popup_id = response.id.with("help");
+    if response.clicked() {
+        ui.memory_mut(|memory| memory.toggle_popup(popup_id));
+    }
+    egui::popup_below_widget(ui, popup_id, &response, |ui| {
+        ui.set_min_width(250.0);
+        ui.label(text);
+    });
+}
@@ -1,13 +1,128 @@
-use super::vm_state::VMState;
+use super::common_state::{
+    Breakpoint, BreakpointAction, CommonState, CountersAction, SegmentInitAction, SharedState,
+    WatchesAction,
+};
+use super::projects::write_vm_os_classes;
+use super::vm_state::{OSClassSource, VMState};
+use crate::hardware::Word;
+use crate::vm;
+use crate::vm_parse::parse_segment_init;
+use crate::watch::Watch;
 
 pub fn reduce_vm_file_selected(vm_state: &mut VMState, selected_file: &str) {
     selected_file.clone_into(&mut vm_state.selected_file);
 }
 
-use super::{
-    common_state::{Breakpoint, BreakpointAction},
-    hardware_state::HardwareState,
-};
+pub fn reduce_vm_file_enabled_changed(vm_state: &mut VMState, file: &str, enabled: bool) {
+    vm_state.set_file_enabled(file, enabled);
+}
+
+pub fn reduce_os_class_source_changed(
+    vm_state: &mut VMState,
+    class_name: &str,
+    source: OSClassSource,
+) {
+    vm_state.set_os_class_source(class_name, source);
+    if let Some(directory) = vm_state.program_directory() {
+        let mut classes: Vec<_> = vm_state.vm_os_classes.iter().cloned().collect();
+        classes.sort();
+        write_vm_os_classes(directory, &classes);
+    }
+}
+
+pub fn reduce_segment_init(
+    vm_state: &mut VMState,
+    shared_state: &mut SharedState,
+    action: &SegmentInitAction,
+) {
+    let segment_init_state = &mut vm_state.segment_init;
+    match action {
+        SegmentInitAction::Clicked => segment_init_state.open = !segment_init_state.open,
+        SegmentInitAction::Closed => segment_init_state.open = false,
+        SegmentInitAction::Changed(segment_init) => {
+            vm_state.vm.set_segment_init(*segment_init);
+            shared_state.dirty = true;
+        }
+        SegmentInitAction::ScriptChanged(script) => {
+            script.clone_into(&mut segment_init_state.script);
+        }
+        SegmentInitAction::ScriptApplied => match parse_segment_init(&segment_init_state.script) {
+            Ok(segment_init) => {
+                segment_init_state.error = None;
+                vm_state.vm.set_segment_init(segment_init);
+                shared_state.dirty = true;
+            }
+            Err(error) => segment_init_state.error = Some(error),
+        },
+    }
+}
+
+pub fn reduce_watches(
+    vm_state: &mut VMState,
+    shared_state: &mut SharedState,
+    action: &WatchesAction,
+) {
+    let watches_state = &mut vm_state.watches;
+    match action {
+        WatchesAction::Clicked => watches_state.open = !watches_state.open,
+        WatchesAction::Closed => watches_state.open = false,
+        WatchesAction::SourceChanged(source) => source.clone_into(&mut watches_state.source),
+        WatchesAction::AddClicked => match Watch::new(&watches_state.source) {
+            Ok(watch) => {
+                watches_state.watches.push(watch);
+                watches_state.source.clear();
+                watches_state.error = None;
+            }
+            Err(error) => watches_state.error = Some(error),
+        },
+        WatchesAction::RemoveClicked(index) => {
+            watches_state.watches.remove(*index);
+            if *index < watches_state.query_watch {
+                watches_state.query_watch -= 1;
+            }
+        }
+        WatchesAction::QueryWatchChanged(index) => watches_state.query_watch = *index,
+        WatchesAction::QueryValueChanged(value) => watches_state.query_value = *value,
+        WatchesAction::FindLastClicked => {
+            let Some(watch) = watches_state
+                .watches
+                .get(watches_state.query_watch)
+                .cloned()
+            else {
+                return;
+            };
+            let value = watches_state.query_value;
+            let condition = format!("{} == {}", watch.source, value);
+            let result = match vm_state.find_last_where(|vm| watch.value(vm) == Some(value)) {
+                Some(snapshot) => {
+                    vm_state.travel_to(&snapshot);
+                    shared_state.run_started = false;
+                    shared_state.scroll_once = true;
+                    Ok(format!(
+                        "Travelled back to step {}, the last one where {}",
+                        snapshot.ticks(),
+                        condition
+                    ))
+                }
+                None => Err(format!(
+                    "{} wasn't true since the recording started",
+                    condition
+                )),
+            };
+            vm_state.watches.query_result = Some(result);
+        }
+    }
+}
+
+pub fn reduce_counters(vm_state: &mut VMState, action: &CountersAction) {
+    match action {
+        CountersAction::Clicked => vm_state.counters_open = !vm_state.counters_open,
+        CountersAction::Closed => vm_state.counters_open = false,
+        CountersAction::InstrumentedChanged(instrumented) => {
+            vm_state.set_instrumented(*instrumented)
+        }
+    }
+}
 
 pub fn reduce_breakpoint_vm(vm_state: &mut VMState, action: &BreakpointAction) {
     match action {
@@ -20,7 +135,23 @@ pub fn reduce_breakpoint_vm(vm_state: &mut VMState, action: &BreakpointAction) {
         BreakpointAction::BreakpointChanged(Breakpoint::VM(new_breakpoint)) => {
             vm_state.selected_breakpoint = new_breakpoint.clone();
         }
-        BreakpointAction::BreakpointChanged(Breakpoint::Hardware(_)) => {
+        BreakpointAction::LineToggled(file_name, row) => {
+            let breakpoint = vm::Breakpoint::Line {
+                file_name: file_name.clone(),
+                line_number: *row as Word,
+            };
+            match vm_state
+                .vm
+                .get_breakpoints()
+                .iter()
+                .position(|existing| *existing == breakpoint)
+            {
+                Some(index) => vm_state.vm.remove_breakpoint(index),
+                None => vm_state.vm.add_breakpoint(&breakpoint),
+            }
+        }
+        BreakpointAction::BreakpointChanged(Breakpoint::Hardware(_))
+        | BreakpointAction::HookChanged(..) => {
             panic!("Invalid action {action:?} in VM state");
         }
     }
@@ -1,33 +1,550 @@
-use crate::hardware::RAM;
-use crate::vm::{Breakpoint, VM};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use super::common_state::CommonState;
+use hashbrown::{HashMap, HashSet};
+
+use crate::assertion::{parse_assertions, Assertion};
+use crate::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::expression::{check_invariants, Expression, Invariant};
+use crate::hardware::{Word, RAM};
+use crate::instrument::{instrument, Counter};
+use crate::lint::lint_vm;
+use crate::metadata::ProgramMetadata;
+use crate::profile_export::{chrome_trace_json, speedscope_json};
+use crate::provenance::Provenance;
+use crate::recording::{Recording, RecordingBudget};
+use crate::report::{html_report, ReportSource};
+use crate::session::{Session, ViewState};
+use crate::stack_depth::{stack_depth_warning, StaticDepth};
+use crate::vm::{Breakpoint, LinkConflict, StepGoal, VM};
+use crate::vm_parse::command_line_numbers;
+use crate::watch::Watch;
+
+use super::common_state::{
+    CommonState, FindQuery, FrameSync, FunctionStep, Log, RuntimeFault, StopReason,
+};
+use super::history::History;
+use super::sampler::{Sampler, SAMPLE_CHECK_STEPS};
+use super::snapshot::Snapshot;
+
+pub const OS_CLASSES: [&str; 8] = [
+    "Array", "Keyboard", "Math", "Memory", "Output", "Screen", "String", "Sys",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OSClassSource {
+    LoadedVM,
+    Native,
+}
+
+#[derive(Default)]
+pub struct SegmentInitState {
+    pub open: bool,
+    pub script: String,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct WatchesState {
+    pub open: bool,
+    pub source: String,
+    pub error: Option<String>,
+    pub watches: Vec<Watch>,
+    // The watch and value the last step query looks for.
+    pub query_watch: usize,
+    pub query_value: i64,
+    pub query_result: Option<Result<String, String>>,
+}
 
 pub struct VMState {
     pub vm: VM,
+    pub files: Vec<(String, String)>,
+    pub disabled_files: HashSet<String>,
+    pub vm_os_classes: HashSet<String>,
+    pub link_conflicts: Vec<LinkConflict>,
+    pub link_conflicts_open: bool,
+    // The file whose definition is used for functions defined in several files.
+    pub function_choices: HashMap<String, String>,
+    pub segment_init: SegmentInitState,
+    pub watches: WatchesState,
+    pub static_depth: StaticDepth,
+    pub stack_depth_open: bool,
+    // Set while the program runs instrumented.
+    pub counters: Option<Vec<Counter>>,
+    pub counters_open: bool,
+    step_goal: Option<StepGoal>,
+    // Set by project files, kept when re-linking.
+    entry_function: Option<String>,
+    // Logged once per run.
+    pub stack_warning_logged: bool,
+    pub diagnostics: Diagnostics,
+    pub recording: Option<Recording>,
+    pub history: History,
+    pub provenance: Option<Provenance>,
+    pub sampler: Option<Sampler>,
     pub selected_file: String,
+    // The file shown next to the selected one, it doesn't follow the PC into other files.
+    pub split_file: Option<String>,
     pub selected_breakpoint: Breakpoint,
+    pub metadata: ProgramMetadata,
+    pub source_paths: HashMap<String, PathBuf>,
+    // File names alongside assertions indexed by their command in the whole program.
+    pub assertions: Vec<(String, Assertion)>,
+    pub assertion_stop: Option<u64>,
+    pub log: Log,
 }
 
 impl VMState {
+    // Like find_last, for conditions that need the program too, like watches on statics.
+    pub fn find_last_where(&self, condition: impl Fn(&VM) -> bool) -> Option<Snapshot> {
+        let mut vm = self.vm.clone();
+        self.history
+            .find_last(self.vm.run_state.ticks, |keyframe, end| {
+                let Snapshot::VM(keyframe) = keyframe else {
+                    return None;
+                };
+                vm.run_state.clone_from(keyframe);
+                vm.run_state.breakpoints.clear();
+                let mut found = None;
+                while vm.run_state.ticks < end {
+                    if condition(&vm) {
+                        found = Some(vm.run_state.ticks);
+                    }
+                    if vm.halted() {
+                        break;
+                    }
+                    vm.step();
+                }
+                let found = found?;
+                vm.run_state.clone_from(keyframe);
+                vm.run_state.breakpoints.clear();
+                vm.run(found - vm.run_state.ticks);
+                Some(Snapshot::VM(Box::new(vm.run_state.clone())))
+            })
+    }
+
+    // The sampled call stacks in speedscope's format, once there are any.
+    pub fn speedscope_profile(&self) -> Option<String> {
+        let sampler = self
+            .sampler
+            .as_ref()
+            .filter(|sampler| !sampler.stacks.is_empty())?;
+        let program = &self.vm.program;
+        let frame_names: Vec<_> = (0..program.function_metadata.len())
+            .map(|index| program.function_name(index).unwrap_or("?"))
+            .collect();
+        Some(speedscope_json(
+            self.metadata.name.as_deref().unwrap_or("VM program"),
+            &frame_names,
+            &sampler.stacks,
+        ))
+    }
+
+    // The calls and returns in the recorded trace as Chrome trace events.
+    pub fn call_trace(&self) -> Option<String> {
+        let trace = &self.recording.as_ref()?.full_trace;
+        let entries = trace.range(0, u64::MAX).ok()?;
+        Some(chrome_trace_json(&self.vm.program, &entries))
+    }
+
+    // The rows of a file whose command matches, with the command.
+    pub fn find_in_file<'a>(
+        &'a self,
+        file_name: &str,
+        query: &'a FindQuery,
+    ) -> impl Iterator<Item = (usize, String)> + 'a {
+        let program = &self.vm.program;
+        let file = &program.files[program.file_name_to_index[file_name]];
+        file.commands(&program.all_commands)
+            .iter()
+            .map(|command| command.to_string())
+            .enumerate()
+            .filter(|(_, text)| query.matches(text))
+    }
+
     pub fn from_file_contents(file_contents: Vec<(String, String)>) -> Self {
-        let vm = VM::from_file_contents(file_contents);
+        let mut metadata = ProgramMetadata::default();
+        for (_, contents) in file_contents
+            .iter()
+            .filter(|(name, _)| name == "Main.vm")
+            .chain(file_contents.iter().filter(|(name, _)| name != "Main.vm"))
+        {
+            metadata.merge(ProgramMetadata::from_file_contents(contents));
+        }
+        let mut log = Log::default();
+        for (name, contents) in &file_contents {
+            log.extend(
+                lint_vm(contents)
+                    .iter()
+                    .map(|finding| format!("Lint: {} {}", name, finding)),
+            );
+        }
+        let (vm, assertions) = link(&file_contents, &mut log);
         let selected_file = vm.program.files[vm.run_state.current_file_index]
             .name
             .clone();
-        let selected_breakpoint = Breakpoint::SP(0);
+        let selected_breakpoint = Breakpoint::CurrentFunction(String::new());
+        let link_conflicts = vm.program.link_conflicts();
+        let static_depth = StaticDepth::analyze(&vm.program, entry_function(&vm));
         VMState {
             vm,
+            files: file_contents,
+            disabled_files: HashSet::new(),
+            vm_os_classes: HashSet::new(),
+            link_conflicts_open: !link_conflicts.is_empty(),
+            link_conflicts,
+            function_choices: HashMap::new(),
+            segment_init: Default::default(),
+            watches: Default::default(),
+            static_depth,
+            stack_depth_open: false,
+            counters: None,
+            counters_open: false,
+            step_goal: None,
+            entry_function: None,
+            stack_warning_logged: false,
+            diagnostics: Default::default(),
+            recording: None,
+            history: Default::default(),
+            provenance: None,
+            sampler: None,
             selected_file,
+            split_file: None,
             selected_breakpoint,
+            metadata,
+            source_paths: HashMap::new(),
+            assertions,
+            assertion_stop: None,
+            log,
+        }
+    }
+
+    pub fn is_file_enabled(&self, name: &str) -> bool {
+        !self.disabled_files.contains(name)
+    }
+
+    pub fn set_file_enabled(&mut self, name: &str, enabled: bool) {
+        if enabled {
+            self.disabled_files.remove(name);
+        } else {
+            self.disabled_files.insert(name.to_owned());
+        }
+        self.relink();
+    }
+
+    // The program starts at the function instead of Sys.init.
+    pub fn set_entry_function(&mut self, entry_function: Option<String>) {
+        self.entry_function = entry_function;
+        self.relink();
+    }
+
+    // Instrumenting re-links the program with the counters added, like toggling a file.
+    pub fn set_instrumented(&mut self, instrumented: bool) {
+        self.counters = instrumented.then(Vec::new);
+        self.relink();
+    }
+
+    // Re-linking restarts the program, breakpoints are kept.
+    fn relink(&mut self) {
+        let mut enabled_files: Vec<_> = self
+            .files
+            .iter()
+            .filter(|(name, _)| self.is_file_enabled(name))
+            .cloned()
+            .collect();
+        if self.counters.is_some() {
+            let instrumentation = instrument(&enabled_files);
+            enabled_files = instrumentation.files;
+            self.counters = Some(instrumentation.counters);
+        }
+        let breakpoints = self.vm.get_breakpoints().clone();
+        let segment_init = self.vm.segment_init;
+        (self.vm, self.assertions) = link(&enabled_files, &mut self.log);
+        if self.entry_function.is_some() {
+            self.vm
+                .program
+                .entry_function
+                .clone_from(&self.entry_function);
+            self.vm.reset();
+        }
+        self.vm.set_segment_init(segment_init);
+        for breakpoint in &breakpoints {
+            self.vm.add_breakpoint(breakpoint);
+        }
+        self.assertion_stop = None;
+        self.stack_warning_logged = false;
+        if self.provenance.is_some() {
+            self.set_write_tracking(true);
+        }
+        if self.recording.is_some() {
+            self.set_recording(true);
+        }
+        if let Some(sampler) = &self.sampler {
+            self.set_sampling(Some(sampler.interval));
+        }
+        self.apply_vm_os_classes();
+        self.apply_function_choices();
+        self.link_conflicts = self.vm.program.link_conflicts();
+        self.update_static_depth();
+        if !self
+            .vm
+            .program
+            .file_name_to_index
+            .contains_key(&self.selected_file)
+        {
+            self.selected_file
+                .clone_from(&self.vm.program.files[self.vm.run_state.current_file_index].name);
+        }
+        let file_name_to_index = &self.vm.program.file_name_to_index;
+        self.split_file = self
+            .split_file
+            .take()
+            .filter(|split_file| file_name_to_index.contains_key(split_file));
+    }
+
+    // Files that aren't in the program anymore are ignored.
+    pub fn select_file(&mut self, file_name: Option<String>) {
+        if let Some(file_name) =
+            file_name.filter(|file_name| self.vm.program.file_name_to_index.contains_key(file_name))
+        {
+            self.selected_file = file_name;
+        }
+    }
+
+    pub fn os_class_source(&self, class_name: &str) -> OSClassSource {
+        if self.vm_os_classes.contains(class_name) {
+            OSClassSource::LoadedVM
+        } else {
+            OSClassSource::Native
+        }
+    }
+
+    pub fn set_os_class_source(&mut self, class_name: &str, source: OSClassSource) {
+        match source {
+            OSClassSource::LoadedVM => self.vm_os_classes.insert(class_name.to_owned()),
+            OSClassSource::Native => self.vm_os_classes.remove(class_name),
+        };
+        self.apply_vm_os_classes();
+        self.update_static_depth();
+    }
+
+    // Classes without a linked file can only run natively.
+    fn apply_vm_os_classes(&mut self) {
+        let program = &mut self.vm.program;
+        program.vm_os_classes = self
+            .vm_os_classes
+            .iter()
+            .filter(|class_name| program.file_name_to_index.contains_key(*class_name))
+            .cloned()
+            .collect();
+    }
+
+    pub fn choose_function_file(&mut self, function_name: &str, file_name: &str) {
+        self.function_choices
+            .insert(function_name.to_owned(), file_name.to_owned());
+        self.apply_function_choices();
+        self.update_static_depth();
+    }
+
+    // Choices for files that are no longer linked fall back to the last definition.
+    fn apply_function_choices(&mut self) {
+        for (function_name, file_name) in &self.function_choices {
+            self.vm.program.use_function_from(function_name, file_name);
+        }
+    }
+
+    fn update_static_depth(&mut self) {
+        self.static_depth = StaticDepth::analyze(&self.vm.program, entry_function(&self.vm));
+    }
+
+    pub fn stack_depth_warning(&self) -> Option<String> {
+        stack_depth_warning(
+            &self.static_depth,
+            self.vm.stack_start(),
+            self.vm.run_state.max_sp,
+        )
+    }
+
+    pub fn program_directory(&self) -> Option<&Path> {
+        self.source_paths.values().next()?.parent()
+    }
+
+    fn failed_assertion(&self) -> Option<String> {
+        let run_state = &self.vm.run_state;
+        if self.assertion_stop == Some(run_state.ticks) {
+            return None;
+        }
+        self.assertions
+            .iter()
+            .filter(|(_, assertion)| assertion.index == run_state.current_command_index)
+            .find_map(|(name, assertion)| {
+                assertion
+                    .check(run_state)
+                    .map(|failure| format!("{}: {}", name, failure))
+            })
+    }
+
+    // Returns the first error, which should stop the run.
+    fn log_diagnostics(&mut self, diagnostics: Vec<Diagnostic>) -> Option<RuntimeFault> {
+        self.log
+            .extend(diagnostics.iter().map(|diagnostic| diagnostic.to_string()));
+        diagnostics
+            .into_iter()
+            .find(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|error| RuntimeFault::Diagnostic(error.message))
+    }
+
+    pub fn source_location(&self, row: usize) -> Option<(&Path, usize)> {
+        let path = self.source_paths.get(&self.selected_file)?;
+        let (_, contents) = self
+            .files
+            .iter()
+            .find(|(name, _)| file_stem(name) == self.selected_file)?;
+        Some((path, *command_line_numbers(contents).get(row)?))
+    }
+}
+
+fn link(file_contents: &[(String, String)], log: &mut Log) -> (VM, Vec<(String, Assertion)>) {
+    let vm = VM::from_file_contents(file_contents.to_vec());
+    let mut assertions = vec![];
+    for (name, contents) in file_contents {
+        let file = &vm.program.files[vm.program.file_name_to_index[file_stem(name)]];
+        match parse_assertions(contents, |_| true) {
+            Ok(file_assertions) => {
+                assertions.extend(file_assertions.into_iter().map(|assertion| {
+                    (
+                        name.clone(),
+                        Assertion {
+                            index: file.starting_command_index + assertion.index,
+                            ..assertion
+                        },
+                    )
+                }))
+            }
+            Err(error) => log.extend([format!("{}: {}", name, error)]),
         }
     }
+
+    (vm, assertions)
+}
+
+fn entry_function(vm: &VM) -> usize {
+    vm.run_state
+        .call_stack
+        .first()
+        .map_or(0, |frame| frame.function_index)
+}
+
+pub fn file_stem(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
 }
 
 impl CommonState for VMState {
-    fn run(&mut self, step_count: u64) -> bool {
-        self.vm.run(step_count);
-        false
+    fn run(&mut self, step_count: u64) -> StopReason {
+        let collided = self.vm.run_state.stack_collision.is_some();
+        let breakpoints = self.vm.breakpoint_commands();
+        if self.assertions.is_empty()
+            && self.diagnostics.config.is_empty()
+            && self.recording.is_none()
+            && self.provenance.is_none()
+            && self.step_goal.is_none()
+            && breakpoints.is_empty()
+        {
+            match &mut self.sampler {
+                Some(sampler) => {
+                    let mut remaining = step_count;
+                    while remaining > 0
+                        && !self.vm.halted()
+                        && (collided || self.vm.run_state.stack_collision.is_none())
+                        && self.vm.run_state.os_fault.is_none()
+                    {
+                        let steps = remaining.min(SAMPLE_CHECK_STEPS);
+                        self.vm.run_commands(steps);
+                        if sampler.poll(self.vm.run_state.current_command_index) {
+                            sampler.poll_stack(
+                                self.vm
+                                    .run_state
+                                    .call_stack
+                                    .iter()
+                                    .map(|frame| frame.function_index)
+                                    .collect(),
+                            );
+                        }
+                        remaining -= steps;
+                    }
+                }
+                None => self.vm.run_commands(step_count),
+            }
+        } else {
+            for _ in 0..step_count {
+                if let Some(failure) = self.failed_assertion() {
+                    self.log.extend([failure.clone()]);
+                    self.assertion_stop = Some(self.vm.run_state.ticks);
+                    return StopReason::Fault(RuntimeFault::AssertionFailed(failure));
+                }
+                if let Some(recording) = &mut self.recording {
+                    let run_state = &self.vm.run_state;
+                    recording.record(run_state.ticks, run_state.current_command_index);
+                    self.history.record(run_state.ticks, || {
+                        Snapshot::VM(Box::new(run_state.clone()))
+                    });
+                }
+                let diagnostics = self.diagnostics.check_vm_command(&self.vm);
+                if let Some(fault) = self.log_diagnostics(diagnostics) {
+                    return StopReason::Fault(fault);
+                }
+                let location = self.vm.run_state.current_command_index;
+                self.vm.run_commands(1);
+                if let Some(provenance) = &mut self.provenance {
+                    provenance.record_changes(&self.vm.run_state.ram, location);
+                }
+                if (!collided && self.vm.run_state.stack_collision.is_some())
+                    || self.vm.run_state.os_fault.is_some()
+                {
+                    break;
+                }
+                if let Some(sampler) = &mut self.sampler {
+                    sampler.poll(self.vm.run_state.current_command_index);
+                }
+                let diagnostics = self.diagnostics.check_vm(&self.vm.run_state);
+                if let Some(fault) = self.log_diagnostics(diagnostics) {
+                    return StopReason::Fault(fault);
+                }
+                if let Some(goal) = &self.step_goal {
+                    if goal.reached(&self.vm.run_state) {
+                        return StopReason::StepFinished;
+                    }
+                }
+                // Checked after the step, so running on from a breakpoint doesn't stop right away.
+                if let Some(&index) = breakpoints.get(&self.vm.run_state.current_command_index) {
+                    return StopReason::BreakpointHit(index);
+                }
+            }
+        }
+
+        if let Some(message) = &self.vm.run_state.os_fault {
+            self.log.extend([message.clone()]);
+            return StopReason::Fault(RuntimeFault::OSCall(message.clone()));
+        }
+
+        if let (false, Some(collision)) = (collided, self.vm.run_state.stack_collision) {
+            let message = collision.to_string();
+            self.log.extend([message.clone()]);
+            return StopReason::Fault(RuntimeFault::StackCollision(message));
+        }
+
+        if !self.stack_warning_logged {
+            if let Some(warning) = self.stack_depth_warning() {
+                self.log.extend([warning]);
+                self.stack_warning_logged = true;
+            }
+        }
+
+        if self.vm.halted() {
+            StopReason::Halted
+        } else {
+            StopReason::StepLimit
+        }
     }
 
     fn ram_mut(&mut self) -> &mut RAM {
@@ -36,5 +553,200 @@ impl CommonState for VMState {
 
     fn reset(&mut self) {
         self.vm.reset();
+        self.assertion_stop = None;
+        self.step_goal = None;
+        self.stack_warning_logged = false;
+        self.diagnostics.reset();
+        if self.provenance.is_some() {
+            self.set_write_tracking(true);
+        }
+        if self.recording.is_some() {
+            self.set_recording(true);
+        }
+        if let Some(sampler) = &self.sampler {
+            self.set_sampling(Some(sampler.interval));
+        }
+    }
+
+    fn session(&self) -> Session {
+        let sorted = |names: &HashSet<String>| {
+            let mut names: Vec<_> = names.iter().cloned().collect();
+            names.sort();
+            names
+        };
+        let mut run_state = self.vm.run_state.clone();
+        run_state.breakpoints.clear();
+        Session::VM {
+            files: self.files.clone(),
+            disabled_files: sorted(&self.disabled_files),
+            vm_os_classes: sorted(&self.vm_os_classes),
+            segment_init: self.vm.segment_init,
+            entry_function: self.entry_function.clone(),
+            breakpoints: self.vm.get_breakpoints().clone(),
+            run_state: Some(Box::new(run_state)),
+            view: ViewState {
+                selected_file: Some(self.selected_file.clone()),
+                ..Default::default()
+            },
+        }
+    }
+
+    // Stepping to a write can take a long while, so it runs like a step out, a frame's worth of
+    // steps at a time and stopping at breakpoints.
+    fn run_frame(&mut self, frame_sync: FrameSync) -> bool {
+        let run_state = &self.vm.run_state;
+        self.step_goal = Some(match frame_sync {
+            FrameSync::Address(address) => StepGoal::write(run_state, address),
+            FrameSync::Steps(step_count) => StepGoal::steps(run_state, step_count),
+        });
+        !self.vm.halted()
+    }
+
+    fn check_invariants(&self, invariants: &[Invariant]) -> Option<String> {
+        check_invariants(invariants, &self.vm.run_state)
+    }
+
+    fn ticks(&self) -> u64 {
+        self.vm.run_state.ticks
+    }
+
+    fn log_mut(&mut self) -> &mut Log {
+        &mut self.log
+    }
+
+    fn set_recording(&mut self, recording: bool) {
+        self.recording = recording.then(|| Recording::new(self.vm.program.all_commands.len()));
+        self.history = History::default();
+    }
+
+    fn set_write_tracking(&mut self, tracking: bool) {
+        self.provenance = tracking.then(|| {
+            let mut provenance = Provenance::default();
+            provenance.record_changes(&self.vm.run_state.ram, 0);
+            provenance
+        });
+    }
+
+    fn write_tracking(&self) -> bool {
+        self.provenance.is_some()
+    }
+
+    fn write_origin(&self, address: Word) -> Option<String> {
+        let location = self.provenance.as_ref()?.last_write(address)?;
+        let file = self
+            .vm
+            .program
+            .files
+            .iter()
+            .rev()
+            .find(|file| file.starting_command_index <= location)?;
+        let line = self
+            .files
+            .iter()
+            .find(|(name, _)| file_stem(name) == file.name)
+            .and_then(|(_, contents)| {
+                command_line_numbers(contents)
+                    .get(location - file.starting_command_index)
+                    .copied()
+            });
+        Some(match line {
+            Some(line) => format!("{}.vm:{}", file.name, line),
+            None => format!("{}.vm", file.name),
+        })
+    }
+
+    fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    fn trim_recording(&mut self, budget: &RecordingBudget) {
+        if let Some(recording) = &mut self.recording {
+            budget.trim_trace(&mut recording.full_trace);
+        }
+        self.history.trim(budget);
+    }
+
+    fn history(&self) -> &History {
+        &self.history
+    }
+
+    fn set_sampling(&mut self, interval: Option<Duration>) {
+        self.sampler =
+            interval.map(|interval| Sampler::new(interval, self.vm.program.all_commands.len()));
+    }
+
+    fn sampler(&self) -> Option<&Sampler> {
+        self.sampler.as_ref()
+    }
+
+    fn profile_regions(&self) -> Vec<(String, usize)> {
+        self.vm.program.function_starts()
+    }
+
+    fn location_text(&self, location: usize) -> String {
+        let program = &self.vm.program;
+        let file_name = program
+            .files
+            .iter()
+            .rev()
+            .find(|file| file.starting_command_index <= location)
+            .map_or("", |file| file.name.as_str());
+        format!("{}: {}", file_name, program.all_commands[location])
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(html_report(&ReportSource {
+            title: self.metadata.name.as_deref().unwrap_or("VM run"),
+            recording: self.recording.as_ref()?,
+            regions: &self.profile_regions(),
+            location_text: &|location| self.location_text(location),
+        }))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot::VM(Box::new(self.vm.run_state.clone()))
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        if let Snapshot::VM(run_state) = snapshot {
+            let breakpoints = std::mem::take(&mut self.vm.run_state.breakpoints);
+            self.vm.run_state.clone_from(run_state);
+            self.vm.run_state.breakpoints = breakpoints;
+        }
+    }
+    fn find_last(&self, condition: &Expression) -> Option<Snapshot> {
+        self.find_last_where(|vm| {
+            condition
+                .evaluate(&vm.run_state)
+                .is_some_and(|value| value != 0)
+        })
+    }
+
+    fn travel_to(&mut self, snapshot: &Snapshot) {
+        self.restore(snapshot);
+        self.history.truncate(self.vm.run_state.ticks);
+        if self.provenance.is_some() {
+            self.set_write_tracking(true);
+        }
+        if self.recording.is_some() {
+            self.recording = Some(Recording::new(self.vm.program.all_commands.len()));
+        }
+    }
+
+    fn start_function_step(&mut self, step: FunctionStep) -> bool {
+        let run_state = &self.vm.run_state;
+        self.step_goal = match step {
+            FunctionStep::Over => Some(StepGoal::step_over(run_state)),
+            FunctionStep::Out => StepGoal::step_out(run_state),
+        };
+        self.step_goal.is_some() && !self.vm.halted()
+    }
+
+    fn cancel_function_step(&mut self) {
+        self.step_goal = None;
     }
 }
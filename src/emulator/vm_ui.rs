@@ -1,23 +1,32 @@
-use std::sync::Arc;
-
-use crate::emulator::common_state::CommonAction;
+use crate::emulator::common_state::{
+    Breakpoint, BreakpointAction, CommonAction, CountersAction, SegmentInitAction, WatchesAction,
+};
 use crate::hardware::{Word, MEM_SIZE};
-use crate::vm::{self, Register};
+use crate::session::Bookmark;
+use crate::vm::{self, LinkConflict, Register};
 use eframe::egui;
-use eframe::epaint::mutex::Mutex;
-use egui_extras::{Column, Size, StripBuilder, TableBuilder};
+use egui_extras::{Size, StripBuilder};
 
-use super::common_state::{Breakpoint, BreakpointAction, SharedState, UIStyle};
-use super::shared_ui::{draw_screen, EmulatorWidgets, Screen};
-use super::vm_state::VMState;
+use super::common_state::{CommonState, SharedState, UIStyle};
+use super::screen::{draw_screen, Screen};
+use super::shared_ui::{scrolled_by_hand, EmulatorWidgets, RowAction};
+use super::tutorial::{help_button, mark_tutorial_target, TutorialTarget};
+use super::vm_state::{file_stem, OSClassSource, VMState, OS_CLASSES};
 use super::Action;
 
+fn os_class_source_text(source: OSClassSource) -> &'static str {
+    match source {
+        OSClassSource::LoadedVM => "Loaded .vm",
+        OSClassSource::Native => "Built-in",
+    }
+}
+
 pub fn draw_vm(
     state: &VMState,
     ctx: &egui::Context,
     action: &mut Option<Action>,
     shared_state: &SharedState,
-    screen: &Arc<Mutex<Screen>>,
+    screen: &mut Screen,
     frame: &eframe::Frame,
 ) {
     egui::CentralPanel::default().show(ctx, |ui| {
@@ -40,15 +49,146 @@ pub fn draw_vm(
                 strip.empty();
             } else {
                 strip.cell(|ui| {
+                    if ui.button("Initial Segments").clicked() {
+                        *action = Some(Action::SegmentInit(SegmentInitAction::Clicked));
+                    }
+                    if ui.button("Stack Depth").clicked() {
+                        *action = Some(Action::StackDepthClicked);
+                    }
+                    if ui.button("Counters").clicked() {
+                        *action = Some(Action::Counters(CountersAction::Clicked));
+                    }
+                    if ui.button("Watches").clicked() {
+                        *action = Some(Action::Watches(WatchesAction::Clicked));
+                    }
+                    egui::CollapsingHeader::new("Files").show(ui, |ui| {
+                        let enabled_count = state
+                            .files
+                            .iter()
+                            .filter(|(name, _)| state.is_file_enabled(name))
+                            .count();
+                        for (name, _) in &state.files {
+                            let mut enabled = state.is_file_enabled(name);
+                            // The program needs at least one file to run.
+                            let can_toggle = !enabled || enabled_count > 1;
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_enabled(
+                                        can_toggle,
+                                        egui::Checkbox::new(&mut enabled, name),
+                                    )
+                                    .changed()
+                                {
+                                    *action =
+                                        Some(Action::VMFileEnabledChanged(name.clone(), enabled));
+                                }
+                                let class_name = file_stem(name);
+                                if enabled && OS_CLASSES.contains(&class_name) {
+                                    let mut source = state.os_class_source(class_name);
+                                    egui::ComboBox::from_id_source(("OS class source", class_name))
+                                        .selected_text(os_class_source_text(source))
+                                        .show_ui(ui, |ui| {
+                                            for option in
+                                                [OSClassSource::LoadedVM, OSClassSource::Native]
+                                            {
+                                                ui.selectable_value(
+                                                    &mut source,
+                                                    option,
+                                                    os_class_source_text(option),
+                                                );
+                                            }
+                                        });
+                                    if source != state.os_class_source(class_name) {
+                                        *action = Some(Action::OSClassSourceChanged(
+                                            class_name.to_owned(),
+                                            source,
+                                        ));
+                                    }
+                                }
+                            });
+                        }
+                        if !state.link_conflicts.is_empty()
+                            && ui
+                                .button(format!("Conflicts ({})", state.link_conflicts.len()))
+                                .clicked()
+                        {
+                            *action = Some(Action::LinkConflictsClicked);
+                        }
+                    });
+                    if shared_state.follow_pc && scrolled_by_hand(ui) {
+                        *action = Some(Action::FollowPCChanged(false));
+                    }
+                    let mut split = state.split_file.is_some();
+                    if ui
+                        .checkbox(&mut split, "Split")
+                        .on_hover_text("Show a second file next to this one, e.g. a callee")
+                        .changed()
+                    {
+                        *action = Some(Action::VMSplitFileChanged(
+                            split.then(|| state.selected_file.clone()),
+                        ));
+                    }
                     let mut selected_file = state.selected_file.clone();
-                    ui.vm_grid(
-                        &state.vm.program,
-                        &state.vm.run_state,
-                        &mut selected_file,
-                        shared_state.scroll_once,
-                    );
-                    if selected_file != state.selected_file {
+                    let mut split_file = state.split_file.clone();
+                    let mut row_action = None;
+                    let breakpoint_commands = state.vm.breakpoint_commands();
+                    StripBuilder::new(ui)
+                        .sizes(Size::remainder(), 1 + split_file.is_some() as usize)
+                        .horizontal(|mut strip| {
+                            strip.cell(|ui| {
+                                row_action = ui
+                                    .vm_grid(
+                                        "VM",
+                                        &state.vm.program,
+                                        &state.vm.run_state,
+                                        &mut selected_file,
+                                        shared_state.follow_pc,
+                                        split_file.is_some(),
+                                        state.source_paths.contains_key(&state.selected_file),
+                                        &shared_state.bookmarks,
+                                        &breakpoint_commands,
+                                        &shared_state.find,
+                                    )
+                                    .map(|row_action| (row_action, selected_file.clone()));
+                            });
+                            if let Some(split_file) = &mut split_file {
+                                strip.cell(|ui| {
+                                    let split_action = ui.vm_grid(
+                                        "VM split",
+                                        &state.vm.program,
+                                        &state.vm.run_state,
+                                        split_file,
+                                        shared_state.follow_pc,
+                                        true,
+                                        false,
+                                        &shared_state.bookmarks,
+                                        &breakpoint_commands,
+                                        &shared_state.find,
+                                    );
+                                    if let Some(split_action) = split_action {
+                                        row_action = Some((split_action, split_file.clone()));
+                                    }
+                                });
+                            }
+                        });
+                    if let Some((row_action, file)) = row_action {
+                        *action = Some(match row_action {
+                            RowAction::OpenInEditor(row) => Action::OpenInEditor(row),
+                            RowAction::ToggleBookmark(row) => Action::BookmarkToggled(Bookmark {
+                                file: Some(file),
+                                row,
+                            }),
+                            RowAction::NavigateBookmark { forward } => {
+                                Action::BookmarkNavigated { forward }
+                            }
+                            RowAction::ToggleBreakpoint(row) => {
+                                Action::Breakpoint(BreakpointAction::LineToggled(file, row))
+                            }
+                        });
+                    } else if selected_file != state.selected_file {
                         *action = Some(Action::VMFileSelected(selected_file));
+                    } else if split_file != state.split_file {
+                        *action = Some(Action::VMSplitFileChanged(split_file));
                     }
                 });
                 strip.cell(|ui| {
@@ -71,6 +211,7 @@ pub fn draw_vm(
                                 UIStyle::VM,
                                 None,
                                 shared_state.scroll_once,
+                                &|address| state.write_origin(address),
                             );
                         });
 
@@ -87,6 +228,7 @@ pub fn draw_vm(
                                 UIStyle::VM,
                                 None,
                                 shared_state.scroll_once,
+                                &|address| state.write_origin(address),
                             );
                         });
 
@@ -103,6 +245,7 @@ pub fn draw_vm(
                                 UIStyle::VM,
                                 None,
                                 shared_state.scroll_once,
+                                &|address| state.write_origin(address),
                             );
                         });
 
@@ -118,6 +261,7 @@ pub fn draw_vm(
                                 UIStyle::VM,
                                 None,
                                 shared_state.scroll_once,
+                                &|address| state.write_origin(address),
                             );
                         });
 
@@ -133,6 +277,7 @@ pub fn draw_vm(
                                 UIStyle::VM,
                                 None,
                                 shared_state.scroll_once,
+                                &|address| state.write_origin(address),
                             );
                         });
 
@@ -145,6 +290,7 @@ pub fn draw_vm(
                             UIStyle::VM,
                             None,
                             shared_state.scroll_once,
+                            &|address| state.write_origin(address),
                         );
                     });
                 });
@@ -157,7 +303,11 @@ pub fn draw_vm(
                     .size(Size::remainder())
                     .vertical(|mut strip| {
                         strip.cell(|ui| {
-                            draw_screen(ui, screen, &state.vm.run_state.ram, frame);
+                            if shared_state.screen_detached {
+                                ui.label("The screen is shown in a separate window.");
+                            } else {
+                                draw_screen(ui, screen, &state.vm.run_state.ram, frame);
+                            }
                         });
                         strip.strip(|builder| {
                             builder
@@ -172,9 +322,15 @@ pub fn draw_vm(
                                             UIStyle::VM,
                                             Some(state.vm.run_state.ram[Register::SP]),
                                             shared_state.scroll_once,
+                                            &|address| state.write_origin(address),
                                         );
                                     });
                                     strip.cell(|ui| {
+                                        mark_tutorial_target(
+                                            ui.ctx(),
+                                            TutorialTarget::RAMGrid,
+                                            ui.max_rect(),
+                                        );
                                         ui.ram_grid(
                                             "RAM",
                                             &state.vm.run_state.ram,
@@ -182,6 +338,7 @@ pub fn draw_vm(
                                             UIStyle::VM,
                                             None,
                                             shared_state.scroll_once,
+                                            &|address| state.write_origin(address),
                                         );
                                     });
                                 });
@@ -191,146 +348,366 @@ pub fn draw_vm(
         });
     });
 
+    draw_link_conflicts_window(state, ctx, action);
+    draw_stack_depth_window(state, ctx, action);
+    draw_counters_window(state, ctx, action);
+    draw_watches_window(state, ctx, action);
+    draw_segment_init_window(state, ctx, action);
+
     let mut breakpoints_open = shared_state.breakpoints_open;
 
     egui::Window::new("Breakpoints")
         .open(&mut breakpoints_open)
         .resizable(true)
-        .default_width(1000.0)
         .show(ctx, |ui| {
-            //         let breakpoints = state.vm.get_breakpoints();
-            //         ui.horizontal(|ui| {
-            //             let mut new_selected_breakpoint = state.selected_breakpoint;
-            //             let selected_text = state.selected_breakpoint.variable_name();
-            //             egui::ComboBox::from_id_source("Variable")
-            //                 .selected_text(selected_text)
-            //                 .width(50.0)
-            //                 .show_ui(ui, |ui| {
-            //                     for breakpoint_type in [
-            //                         vm::Breakpoint::SP(0),
-            //                         vm::Breakpoint::CurrentFunction("".to_owned()),
-            //                         vm::Breakpoint::Line {
-            //                             file_name: "".to_owned(),
-            //                             line_number: 0,
-            //                         },
-            //                         vm::Breakpoint::RAM {
-            //                             address: 0,
-            //                             value: 0,
-            //                         },
-            //                         vm::Breakpoint::LCL(0),
-            //                         vm::Breakpoint::Local {
-            //                             offset: 0,
-            //                             value: 0,
-            //                         },
-            //                         vm::Breakpoint::ARG(0),
-            //                         vm::Breakpoint::Argument {
-            //                             offset: 0,
-            //                             value: 0,
-            //                         },
-            //                         vm::Breakpoint::This(0),
-            //                         vm::Breakpoint::ThisPointer {
-            //                             offset: 0,
-            //                             value: 0,
-            //                         },
-            //                         vm::Breakpoint::That(0),
-            //                         vm::Breakpoint::ThatPointer {
-            //                             offset: 0,
-            //                             value: 0,
-            //                         },
-            //                         vm::Breakpoint::Temp {
-            //                             offset: 0,
-            //                             value: 0,
-            //                         },
-            //                     ] {
-            //                         ui.selectable_value(
-            //                             &mut new_selected_breakpoint,
-            //                             breakpoint_type,
-            //                             breakpoint_type.variable_name(),
-            //                         );
-            //                     }
-            //                 });
+            let breakpoints = state.vm.get_breakpoints();
+            ui.horizontal(|ui| {
+                help_button(
+                    ui,
+                    "A breakpoint pauses the run when it reaches a function or a row. Double \
+                     click a row, or use its context menu, to toggle a breakpoint on it.",
+                );
+                ui.label("Function");
+                let function_name = match &state.selected_breakpoint {
+                    vm::Breakpoint::CurrentFunction(function_name) => function_name.clone(),
+                    _ => String::new(),
+                };
+                let mut new_function_name = function_name.clone();
+                ui.add(
+                    egui::TextEdit::singleline(&mut new_function_name)
+                        .hint_text("Main.main")
+                        .desired_width(150.0),
+                );
+                if new_function_name != function_name {
+                    *action = Some(Action::Breakpoint(BreakpointAction::BreakpointChanged(
+                        Breakpoint::VM(vm::Breakpoint::CurrentFunction(new_function_name)),
+                    )));
+                }
+                let known = state.vm.program.function_index(&function_name).is_some();
+                if ui
+                    .add_enabled(known, egui::Button::new("Add"))
+                    .on_disabled_hover_text("No function has that name")
+                    .clicked()
+                {
+                    *action = Some(Action::Breakpoint(BreakpointAction::AddClicked));
+                }
+            });
+            ui.separator();
+            if breakpoints.is_empty() {
+                ui.label("No breakpoints");
+            }
+            egui::Grid::new("VM breakpoints")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (index, breakpoint) in breakpoints.iter().enumerate() {
+                        ui.monospace(match breakpoint {
+                            vm::Breakpoint::CurrentFunction(function_name) => {
+                                format!("function {}", function_name)
+                            }
+                            vm::Breakpoint::Line {
+                                file_name,
+                                line_number,
+                            } => format!("{} row {}", file_name, line_number),
+                            other => other.variable_name(),
+                        });
+                        if ui.button("Remove").clicked() {
+                            *action =
+                                Some(Action::Breakpoint(BreakpointAction::RemoveClicked(index)));
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+
+    if shared_state.breakpoints_open != breakpoints_open {
+        assert!(shared_state.breakpoints_open);
+        *action = Some(Action::Common(CommonAction::BreakpointsClosed));
+    }
+}
+
+fn draw_link_conflicts_window(state: &VMState, ctx: &egui::Context, action: &mut Option<Action>) {
+    let mut link_conflicts_open = state.link_conflicts_open;
+    egui::Window::new("Link Conflicts")
+        .open(&mut link_conflicts_open)
+        .resizable(true)
+        .show(ctx, |ui| {
+            for conflict in &state.link_conflicts {
+                ui.label(conflict.to_string());
+                if let LinkConflict::DuplicateFunction { name, file_names } = conflict {
+                    let used_file = state.vm.program.function_file_name(name);
+                    ui.horizontal(|ui| {
+                        ui.label("Use the definition in");
+                        for file_name in file_names {
+                            if ui
+                                .radio(used_file == Some(file_name.as_str()), file_name)
+                                .clicked()
+                            {
+                                *action = Some(Action::FunctionFileChosen {
+                                    function_name: name.clone(),
+                                    file_name: file_name.clone(),
+                                });
+                            }
+                        }
+                    });
+                }
+                ui.separator();
+            }
+            if state.link_conflicts.is_empty() {
+                ui.label("No conflicts.");
+            }
+        });
+
+    if state.link_conflicts_open != link_conflicts_open {
+        *action = Some(Action::LinkConflictsClosed);
+    }
+}
+
+fn draw_stack_depth_window(state: &VMState, ctx: &egui::Context, action: &mut Option<Action>) {
+    let mut open = state.stack_depth_open;
+    egui::Window::new("Stack Depth")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            help_button(
+                ui,
+                "The static depth follows every call in the program from its entry function, the \
+                 observed depth is the deepest the program got since the last reset.",
+            );
+            egui::Grid::new("stack depth grid").show(ui, |ui| {
+                ui.label("Static");
+                ui.label(state.static_depth.to_string());
+                ui.end_row();
+                let run_state = &state.vm.run_state;
+                ui.label("Observed");
+                ui.label(format!(
+                    "{} calls deep, SP up to {}",
+                    run_state.max_call_depth, run_state.max_sp
+                ));
+                ui.end_row();
+            });
+            if let Some(warning) = state.stack_depth_warning() {
+                ui.colored_label(ui.visuals().warn_fg_color, warning);
+            }
+        });
 
-            //             if let Some(address) = state.selected_breakpoint.address() {
-            //                 ui.label("[");
-            //                 let mut new_address_text = address.to_string();
-            //                 ui.add(
-            //                     egui::TextEdit::singleline(&mut new_address_text).desired_width(50.0),
-            //                 );
-            //                 if let Ok(new_address) = new_address_text.parse::<Word>() {
-            //                     new_selected_breakpoint.change_address(new_address);
-            //                 }
-            //                 ui.label("]");
-            //             }
+    if state.stack_depth_open != open {
+        *action = Some(Action::StackDepthClosed);
+    }
+}
 
-            //             ui.label("=");
+fn draw_counters_window(state: &VMState, ctx: &egui::Context, action: &mut Option<Action>) {
+    let mut open = state.counters_open;
+    egui::Window::new("Counters")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let mut instrumented = state.counters.is_some();
+                if ui.checkbox(&mut instrumented, "Instrument").changed() {
+                    *action = Some(Action::Counters(CountersAction::InstrumentedChanged(
+                        instrumented,
+                    )));
+                }
+                help_button(
+                    ui,
+                    "Adds code counting the entries to every function and the iterations of every \
+                     loop to the program, the counts are kept at the top of the heap. The counting \
+                     code stays in exported assembly, so other emulators count too.",
+                );
+            });
+            let Some(counters) = &state.counters else {
+                return;
+            };
+            let ram = &state.vm.run_state.ram;
+            let mut counts: Vec<_> = counters
+                .iter()
+                .map(|counter| (counter.name(), counter.address, counter.value(ram)))
+                .collect();
+            counts.sort_by_key(|&(_, _, count)| std::cmp::Reverse(count));
+            egui::ScrollArea::vertical()
+                .max_height(400.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("counters grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (name, address, count) in &counts {
+                                ui.label(name);
+                                ui.label(format!("RAM[{}]", address));
+                                ui.label(count.to_string());
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+
+    if state.counters_open != open {
+        *action = Some(Action::Counters(CountersAction::Closed));
+    }
+}
+
+fn draw_watches_window(state: &VMState, ctx: &egui::Context, action: &mut Option<Action>) {
+    let watches_state = &state.watches;
+    let mut open = watches_state.open;
+    egui::Window::new("Watches")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let mut source = watches_state.source.clone();
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut source)
+                        .code_editor()
+                        .hint_text("local[2]"),
+                );
+                if source != watches_state.source {
+                    *action = Some(Action::Watches(WatchesAction::SourceChanged(source)));
+                }
+                let entered =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui.button("Add").clicked() || entered {
+                    *action = Some(Action::Watches(WatchesAction::AddClicked));
+                }
+                help_button(
+                    ui,
+                    "Segment accesses like local[2], this 0, static 3 or static Foo.3, which is \
+                     Foo.vm's, or expressions like RAM[SP - 1]. Values update as the program runs.",
+                );
+            });
+            if let Some(error) = &watches_state.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            egui::Grid::new("watches grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (index, watch) in watches_state.watches.iter().enumerate() {
+                        ui.monospace(&watch.source);
+                        match watch.address(&state.vm) {
+                            Some(address) => ui.label(format!("RAM[{}]", address)),
+                            None => ui.label(""),
+                        };
+                        match watch.value(&state.vm) {
+                            Some(value) => ui.monospace(value.to_string()),
+                            None => ui.weak("undefined"),
+                        };
+                        if ui.button("Remove").clicked() {
+                            *action = Some(Action::Watches(WatchesAction::RemoveClicked(index)));
+                        }
+                        ui.end_row();
+                    }
+                });
+            let Some(query_watch) = watches_state.watches.get(watches_state.query_watch) else {
+                return;
+            };
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Last step where");
+                egui::ComboBox::from_id_source("watch query")
+                    .selected_text(&query_watch.source)
+                    .show_ui(ui, |ui| {
+                        for (index, watch) in watches_state.watches.iter().enumerate() {
+                            if ui
+                                .selectable_label(index == watches_state.query_watch, &watch.source)
+                                .clicked()
+                            {
+                                *action =
+                                    Some(Action::Watches(WatchesAction::QueryWatchChanged(index)));
+                            }
+                        }
+                    });
+                ui.label("==");
+                let mut value = watches_state.query_value;
+                ui.add(egui::DragValue::new(&mut value));
+                if value != watches_state.query_value {
+                    *action = Some(Action::Watches(WatchesAction::QueryValueChanged(value)));
+                }
+                if ui
+                    .add_enabled(
+                        state.recording.is_some(),
+                        egui::Button::new("Find and Jump"),
+                    )
+                    .clicked()
+                {
+                    *action = Some(Action::Watches(WatchesAction::FindLastClicked));
+                }
+                help_button(
+                    ui,
+                    "Replays the recording to find the last step before the current one where \
+                     the watch had the value, then goes back to it. Needs recording to be on.",
+                );
+            });
+            match &watches_state.query_result {
+                Some(Ok(message)) => {
+                    ui.label(message);
+                }
+                Some(Err(error)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                None => {}
+            }
+        });
 
-            //             let mut new_value_text = state.selected_breakpoint.value().to_string();
-            //             ui.add(egui::TextEdit::singleline(&mut new_value_text).desired_width(50.0));
-            //             if let Ok(new_value) = new_value_text.parse::<Word>() {
-            //                 // if new_value != state.selected_breakpoint.value() {
-            //                 //     new_selected_breakpoint.change_value(new_value);
-            //                 // }
-            //             }
+    if watches_state.open != open {
+        *action = Some(Action::Watches(WatchesAction::Closed));
+    }
+}
 
-            //             if new_selected_breakpoint != state.selected_breakpoint {
-            //                 *action = Some(Action::Breakpoint(BreakpointAction::BreakpointChanged(Breakpoint::VM(new_selected_breakpoint)
-            //                 )));
-            //             }
+fn draw_segment_init_window(state: &VMState, ctx: &egui::Context, action: &mut Option<Action>) {
+    let segment_init_state = &state.segment_init;
+    let mut open = segment_init_state.open;
+    egui::Window::new("Initial Segments")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            help_button(
+                ui,
+                "The segment pointers the program starts with. Changes apply right away before the \
+                 first step and otherwise on the next reset.",
+            );
+            let mut segment_init = state.vm.segment_init;
+            egui::Grid::new("segment init grid").show(ui, |ui| {
+                for (name, value) in [
+                    ("SP", &mut segment_init.sp),
+                    ("LCL", &mut segment_init.lcl),
+                    ("ARG", &mut segment_init.arg),
+                    ("THIS", &mut segment_init.this),
+                    ("THAT", &mut segment_init.that),
+                ] {
+                    ui.label(name);
+                    ui.add(egui::DragValue::new(value));
+                    ui.end_row();
+                }
+            });
+            if segment_init != state.vm.segment_init {
+                *action = Some(Action::SegmentInit(SegmentInitAction::Changed(
+                    segment_init,
+                )));
+            }
+            if state.vm.run_state.ticks > 0 {
+                ui.label("Changes take effect on reset.");
+            }
 
-            //             if ui.button("Add").clicked() {
-            //                 *action = Some(Action::Breakpoint(BreakpointAction::AddClicked));
-            //             }
-            //         });
-            //         ui.label("Breakpoints:");
-            //         let header_height = ui.text_style_height(&egui::TextStyle::Body);
-            //         let row_height = ui.text_style_height(&egui::TextStyle::Monospace)
-            //             + 2.0 * ui.spacing().button_padding.x;
-            //         TableBuilder::new(ui)
-            //             .striped(true)
-            //             .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-            //             .column(Column::exact(100.0))
-            //             .column(Column::exact(100.0))
-            //             .column(Column::exact(70.0))
-            //             .header(header_height, |mut header| {
-            //                 header.col(|ui| {
-            //                     ui.label("Variable");
-            //                 });
-            //                 header.col(|ui| {
-            //                     ui.label("Value");
-            //                 });
-            //                 header.col(|_| {});
-            //             })
-            //             .body(|body| {
-            //                 body.rows(row_height, usize::max(breakpoints.len(), 10), |mut row| {
-            //                     let row_index = row.index();
-            //                     let breakpoint = breakpoints.get(row_index);
-            //                     row.col(|ui| {
-            //                         ui.monospace(
-            //                             breakpoint
-            //                                 .map(|b| b.var.to_string())
-            //                                 .unwrap_or("".to_string()),
-            //                         );
-            //                     });
-            //                     row.col(|ui| {
-            //                         ui.monospace(
-            //                             breakpoint
-            //                                 .map(|b| b.value.to_string())
-            //                                 .unwrap_or("".to_string()),
-            //                         );
-            //                     });
-            //                     row.col(|ui| {
-            //                         if breakpoint.is_some() && ui.button("Remove").clicked() {
-            //                             *action = Some(Action::Breakpoint(
-            //                                 BreakpointAction::RemoveClicked(row_index),
-            //                             ));
-            //                         }
-            //                     });
-            //                 });
-            //             });
+            ui.separator();
+            ui.label("From a test script, e.g. set sp 256, set local 300;");
+            let mut script = segment_init_state.script.clone();
+            ui.add(
+                egui::TextEdit::multiline(&mut script)
+                    .code_editor()
+                    .desired_rows(3),
+            );
+            if script != segment_init_state.script {
+                *action = Some(Action::SegmentInit(SegmentInitAction::ScriptChanged(
+                    script,
+                )));
+            }
+            if ui.button("Apply").clicked() {
+                *action = Some(Action::SegmentInit(SegmentInitAction::ScriptApplied));
+            }
+            if let Some(error) = &segment_init_state.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
         });
 
-    if shared_state.breakpoints_open != breakpoints_open {
-        assert!(shared_state.breakpoints_open);
-        *action = Some(Action::Common(CommonAction::BreakpointsClosed));
+    if segment_init_state.open != open {
+        *action = Some(Action::SegmentInit(SegmentInitAction::Closed));
     }
 }
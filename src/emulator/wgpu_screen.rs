@@ -0,0 +1,194 @@
+use eframe::{
+    egui,
+    egui_wgpu::{self, wgpu},
+    epaint::Rect,
+};
+
+use crate::hardware::RAM;
+
+use super::screen::{screen_bytes, ScreenRenderer};
+
+const SHADER: &str = r#"
+    struct VertexOutput {
+        @builtin(position) position: vec4<f32>,
+        @location(0) pos: vec2<f32>,
+    };
+
+    @vertex
+    fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+        var verts = array<vec2<f32>, 4>(
+            vec2<f32>(1.0, 1.0),
+            vec2<f32>(-1.0, 1.0),
+            vec2<f32>(1.0, -1.0),
+            vec2<f32>(-1.0, -1.0),
+        );
+
+        var out: VertexOutput;
+        out.position = vec4<f32>(verts[index], 0.0, 1.0);
+        out.pos = verts[index] * vec2<f32>(1.0, -1.0);
+        return out;
+    }
+
+    @group(0) @binding(0) var u_screen: texture_2d<u32>;
+
+    @fragment
+    fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+        let coord = vec2<i32>((in.pos + 1.0) * vec2<f32>(256.0, 128.0));
+        let texel = textureLoad(u_screen, vec2<i32>(coord.x / 8, coord.y), 0).r;
+        let color = f32(1u - ((texel >> u32(coord.x % 8)) & 1u));
+        return vec4<f32>(vec3<f32>(color), 1.0);
+    }
+"#;
+
+const TEXTURE_SIZE: wgpu::Extent3d = wgpu::Extent3d {
+    width: 64,
+    height: 256,
+    depth_or_array_layers: 1,
+};
+
+struct WgpuScreenResources {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+}
+
+pub struct WgpuScreen;
+
+impl WgpuScreen {
+    pub fn new(render_state: &egui_wgpu::RenderState) -> Self {
+        let device = &render_state.device;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("screen"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("screen"),
+            size: TEXTURE_SIZE,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Uint,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("screen"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Uint,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("screen"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("screen"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("screen"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(render_state.target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        render_state
+            .renderer
+            .write()
+            .callback_resources
+            .insert(WgpuScreenResources {
+                pipeline,
+                bind_group,
+                texture,
+            });
+
+        WgpuScreen
+    }
+}
+
+struct WgpuScreenCallback {
+    screen_bytes: Vec<u8>,
+}
+
+impl egui_wgpu::CallbackTrait for WgpuScreenCallback {
+    fn prepare(
+        &self,
+        _device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _screen_descriptor: &egui_wgpu::ScreenDescriptor,
+        _egui_encoder: &mut wgpu::CommandEncoder,
+        callback_resources: &mut egui_wgpu::CallbackResources,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let resources: &WgpuScreenResources = callback_resources.get().unwrap();
+        queue.write_texture(
+            resources.texture.as_image_copy(),
+            &self.screen_bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(TEXTURE_SIZE.width),
+                rows_per_image: Some(TEXTURE_SIZE.height),
+            },
+            TEXTURE_SIZE,
+        );
+
+        Vec::new()
+    }
+
+    fn paint<'a>(
+        &'a self,
+        _info: egui::PaintCallbackInfo,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        callback_resources: &'a egui_wgpu::CallbackResources,
+    ) {
+        let resources: &WgpuScreenResources = callback_resources.get().unwrap();
+        render_pass.set_pipeline(&resources.pipeline);
+        render_pass.set_bind_group(0, &resources.bind_group, &[]);
+        render_pass.draw(0..4, 0..1);
+    }
+}
+
+impl ScreenRenderer for WgpuScreen {
+    fn draw(&mut self, ui: &mut egui::Ui, rect: Rect, ram: &RAM, _frame: &eframe::Frame) {
+        ui.painter().add(egui_wgpu::Callback::new_paint_callback(
+            rect,
+            WgpuScreenCallback {
+                screen_bytes: screen_bytes(ram).to_vec(),
+            },
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::{Read, Write};
+
+use argon2::Argon2;
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::compressed_trace::{read_varint, write_varint};
+
+// Programs handed out for exams, packed with a password so they open in the emulator but not in a
+// text editor. The key comes from the password with Argon2id and the files are sealed with
+// ChaCha20-Poly1305, whose tag tells a wrong password apart.
+pub const EXTENSION: &str = "n2x";
+pub const SALT_LENGTH: usize = 16;
+
+const MAGIC: &[u8; 8] = b"N2REXAM2";
+const TAG_LENGTH: usize = 16;
+// Every archive gets its own key from its salt, so the nonce can stay the same.
+const NONCE: [u8; 12] = [0; 12];
+
+fn cipher(password: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .expect("the salt and key lengths are valid");
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+fn compressed_payload(files: &[(String, String)]) -> Vec<u8> {
+    let mut payload = vec![];
+    write_varint(&mut payload, files.len() as u64);
+    for (name, contents) in files {
+        for text in [name, contents] {
+            write_varint(&mut payload, text.len() as u64);
+            payload.extend_from_slice(text.as_bytes());
+        }
+    }
+    let mut encoder = DeflateEncoder::new(vec![], Compression::best());
+    encoder
+        .write_all(&payload)
+        .expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+pub fn is_exam_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// The salt has to be random, archives packed with the same password and salt share a key.
+pub fn pack_exam(files: &[(String, String)], password: &str, salt: &[u8; SALT_LENGTH]) -> Vec<u8> {
+    let sealed = cipher(password, salt)
+        .encrypt(
+            Nonce::from_slice(&NONCE),
+            compressed_payload(files).as_slice(),
+        )
+        .expect("exam files are far below ChaCha20-Poly1305's limit");
+
+    let mut archive = MAGIC.to_vec();
+    archive.extend_from_slice(salt);
+    archive.extend_from_slice(&sealed);
+    archive
+}
+
+pub fn unpack_exam(bytes: &[u8], password: &str) -> Result<Vec<(String, String)>, String> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err("Not an exam archive".to_owned());
+    };
+    if rest.len() < SALT_LENGTH + TAG_LENGTH {
+        return Err("Truncated exam archive".to_owned());
+    }
+    let (salt, sealed) = rest.split_at(SALT_LENGTH);
+    let Ok(compressed) = cipher(password, salt).decrypt(Nonce::from_slice(&NONCE), sealed) else {
+        return Err("Wrong password".to_owned());
+    };
+
+    let corrupt = || "Corrupt exam archive".to_owned();
+    let mut payload = vec![];
+    DeflateDecoder::new(compressed.as_slice())
+        .read_to_end(&mut payload)
+        .map_err(|_| corrupt())?;
+    let mut payload = payload.into_iter();
+    let file_count = read_varint(&mut payload).ok_or_else(corrupt)?;
+    let mut read_text = || {
+        let length = read_varint(&mut payload).ok_or_else(corrupt)? as usize;
+        let text: Vec<_> = payload.by_ref().take(length).collect();
+        if text.len() != length {
+            return Err(corrupt());
+        }
+        String::from_utf8(text).map_err(|_| corrupt())
+    };
+    (0..file_count)
+        .map(|_| Ok((read_text()?, read_text()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exam_archive() {
+        let files = vec![
+            (
+                "Main.vm".to_owned(),
+                "function Main.main 0\npush constant 0\nreturn\n".to_owned(),
+            ),
+            ("Empty.vm".to_owned(), String::new()),
+        ];
+        let archive = pack_exam(&files, "hunter2", &[1; SALT_LENGTH]);
+        assert!(is_exam_archive(&archive));
+        assert!(!archive
+            .windows("Main.main".len())
+            .any(|window| window == b"Main.main"));
+        assert_ne!(archive, pack_exam(&files, "hunter2", &[2; SALT_LENGTH]));
+
+        assert_eq!(unpack_exam(&archive, "hunter2"), Ok(files));
+        assert_eq!(
+            unpack_exam(&archive, "hunter3"),
+            Err("Wrong password".to_owned())
+        );
+        let mut tampered = archive.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            unpack_exam(&tampered, "hunter2"),
+            Err("Wrong password".to_owned())
+        );
+        assert_eq!(
+            unpack_exam(&archive[..12], "hunter2"),
+            Err("Truncated exam archive".to_owned())
+        );
+        assert_eq!(
+            unpack_exam(b"function Main.main 0", "hunter2"),
+            Err("Not an exam archive".to_owned())
+        );
+    }
+
+    // A student who knows what one archive holds gets its keystream by XORing the files out of it.
+    // That must not open another archive with the same password.
+    #[test]
+    fn test_known_plaintext() {
+        let known = vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\npush constant 0\nreturn\n".to_owned(),
+        )];
+        let secret = vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\npush constant 1\nreturn\n".to_owned(),
+        )];
+        let sealed = |archive: &[u8]| archive[MAGIC.len() + SALT_LENGTH..].to_vec();
+        let keystream: Vec<u8> = sealed(&pack_exam(&known, "hunter2", &[1; SALT_LENGTH]))
+            .iter()
+            .zip(compressed_payload(&known))
+            .map(|(sealed, plain)| sealed ^ plain)
+            .collect();
+
+        let secret_payload = compressed_payload(&secret);
+        let guess: Vec<u8> = sealed(&pack_exam(&secret, "hunter2", &[2; SALT_LENGTH]))
+            .iter()
+            .zip(&keystream)
+            .map(|(sealed, key)| sealed ^ key)
+            .collect();
+        assert_ne!(guess, secret_payload[..guess.len()]);
+        assert!(
+            guess
+                .iter()
+                .zip(&secret_payload)
+                .filter(|(guess, secret)| guess == secret)
+                .count()
+                < guess.len() / 4
+        );
+    }
+}
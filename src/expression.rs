@@ -0,0 +1,426 @@
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{alpha1, alphanumeric0, char, i64, multispace0},
+    combinator::{all_consuming, map, map_opt, recognize, value},
+    error::convert_error,
+    multi::many0,
+    sequence::{delimited, pair, preceded},
+    Finish,
+};
+
+use crate::hardware::{Hardware, Word, MEM_SIZE};
+use crate::parse_utils::IResult;
+use crate::vm::RunState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variable {
+    A,
+    D,
+    M,
+    PC,
+    SP,
+    LCL,
+    ARG,
+    THIS,
+    THAT,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    Constant(i64),
+    Variable(Variable),
+    RAM(Box<Expression>),
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+pub trait ExpressionContext {
+    fn ram_value(&self, address: Word) -> Word;
+    fn variable_value(&self, variable: Variable) -> Option<Word>;
+}
+
+impl ExpressionContext for Hardware {
+    fn ram_value(&self, address: Word) -> Word {
+        self.ram[address]
+    }
+
+    fn variable_value(&self, variable: Variable) -> Option<Word> {
+        match variable {
+            Variable::A => Some(self.a),
+            Variable::D => Some(self.d),
+            Variable::M => is_address(self.a as i64).then(|| self.ram[self.a]),
+            Variable::PC => Some(self.pc),
+            Variable::SP => Some(self.ram[0]),
+            Variable::LCL => Some(self.ram[1]),
+            Variable::ARG => Some(self.ram[2]),
+            Variable::THIS => Some(self.ram[3]),
+            Variable::THAT => Some(self.ram[4]),
+        }
+    }
+}
+
+impl ExpressionContext for RunState {
+    fn ram_value(&self, address: Word) -> Word {
+        self.ram[address]
+    }
+
+    fn variable_value(&self, variable: Variable) -> Option<Word> {
+        match variable {
+            Variable::A | Variable::D | Variable::M | Variable::PC => None,
+            Variable::SP => Some(self.ram[0]),
+            Variable::LCL => Some(self.ram[1]),
+            Variable::ARG => Some(self.ram[2]),
+            Variable::THIS => Some(self.ram[3]),
+            Variable::THAT => Some(self.ram[4]),
+        }
+    }
+}
+
+pub(crate) fn is_address(address: i64) -> bool {
+    (0..MEM_SIZE as i64).contains(&address)
+}
+
+impl Expression {
+    pub fn evaluate(&self, context: &impl ExpressionContext) -> Option<i64> {
+        match self {
+            Expression::Constant(value) => Some(*value),
+            Expression::Variable(variable) => context.variable_value(*variable).map(i64::from),
+            Expression::RAM(address) => {
+                let address = address.evaluate(context)?;
+                is_address(address).then(|| context.ram_value(address as Word) as i64)
+            }
+            Expression::Unary(operator, operand) => {
+                let operand = operand.evaluate(context)?;
+                Some(match operator {
+                    UnaryOperator::Neg => operand.wrapping_neg(),
+                    UnaryOperator::Not => (operand == 0) as i64,
+                })
+            }
+            Expression::Binary(operator, lhs, rhs) => {
+                let lhs = lhs.evaluate(context)?;
+                let rhs = rhs.evaluate(context)?;
+                Some(match operator {
+                    BinaryOperator::Or => (lhs != 0 || rhs != 0) as i64,
+                    BinaryOperator::And => (lhs != 0 && rhs != 0) as i64,
+                    BinaryOperator::Equal => (lhs == rhs) as i64,
+                    BinaryOperator::NotEqual => (lhs != rhs) as i64,
+                    BinaryOperator::Less => (lhs < rhs) as i64,
+                    BinaryOperator::LessEqual => (lhs <= rhs) as i64,
+                    BinaryOperator::Greater => (lhs > rhs) as i64,
+                    BinaryOperator::GreaterEqual => (lhs >= rhs) as i64,
+                    BinaryOperator::Add => lhs.wrapping_add(rhs),
+                    BinaryOperator::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOperator::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOperator::Div => lhs.checked_div(rhs)?,
+                    BinaryOperator::Rem => lhs.checked_rem(rhs)?,
+                })
+            }
+        }
+    }
+
+    // The registers and memory cells an expression reads, for explaining its result.
+    pub fn operands(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Constant(_) => vec![],
+            Expression::Variable(_) | Expression::RAM(_) => vec![self],
+            Expression::Unary(_, operand) => operand.operands(),
+            Expression::Binary(_, lhs, rhs) => {
+                let mut operands = lhs.operands();
+                for operand in rhs.operands() {
+                    if !operands.contains(&operand) {
+                        operands.push(operand);
+                    }
+                }
+                operands
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Variable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Or => "||",
+            BinaryOperator::And => "&&",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Rem => "%",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Constant(value) => write!(f, "{value}"),
+            Expression::Variable(variable) => write!(f, "{variable}"),
+            Expression::RAM(address) => write!(f, "RAM[{address}]"),
+            Expression::Unary(UnaryOperator::Neg, operand) => write!(f, "-{operand}"),
+            Expression::Unary(UnaryOperator::Not, operand) => write!(f, "!{operand}"),
+            Expression::Binary(operator, lhs, rhs) => write!(f, "({lhs} {operator} {rhs})"),
+        }
+    }
+}
+
+fn token<'a, O>(
+    parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    delimited(multispace0, parser, multispace0)
+}
+
+fn variable(input: &str) -> IResult<&str, Variable> {
+    map_opt(recognize(pair(alpha1, alphanumeric0)), |name| match name {
+        "A" => Some(Variable::A),
+        "D" => Some(Variable::D),
+        "M" => Some(Variable::M),
+        "PC" => Some(Variable::PC),
+        "SP" => Some(Variable::SP),
+        "LCL" => Some(Variable::LCL),
+        "ARG" => Some(Variable::ARG),
+        "THIS" => Some(Variable::THIS),
+        "THAT" => Some(Variable::THAT),
+        _ => None,
+    })(input)
+}
+
+fn primary(input: &str) -> IResult<&str, Expression> {
+    token(alt((
+        map(i64, Expression::Constant),
+        map(
+            delimited(pair(tag("RAM"), token(char('['))), expression, char(']')),
+            |address| Expression::RAM(Box::new(address)),
+        ),
+        map(variable, Expression::Variable),
+        delimited(char('('), expression, char(')')),
+    )))(input)
+}
+
+fn unary(input: &str) -> IResult<&str, Expression> {
+    alt((
+        map(
+            pair(
+                token(alt((
+                    value(UnaryOperator::Neg, char('-')),
+                    value(UnaryOperator::Not, char('!')),
+                ))),
+                unary,
+            ),
+            |(operator, operand)| Expression::Unary(operator, Box::new(operand)),
+        ),
+        primary,
+    ))(input)
+}
+
+fn left_associative<'a>(
+    mut operand: impl FnMut(&'a str) -> IResult<&'a str, Expression>,
+    mut operator: impl FnMut(&'a str) -> IResult<&'a str, BinaryOperator>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Expression> {
+    move |input| {
+        let (input, first) = operand(input)?;
+        let (input, rest) = many0(pair(token(&mut operator), &mut operand))(input)?;
+
+        Ok((
+            input,
+            rest.into_iter().fold(first, |lhs, (operator, rhs)| {
+                Expression::Binary(operator, Box::new(lhs), Box::new(rhs))
+            }),
+        ))
+    }
+}
+
+fn product(input: &str) -> IResult<&str, Expression> {
+    left_associative(
+        unary,
+        alt((
+            value(BinaryOperator::Mul, char('*')),
+            value(BinaryOperator::Div, char('/')),
+            value(BinaryOperator::Rem, char('%')),
+        )),
+    )(input)
+}
+
+fn sum(input: &str) -> IResult<&str, Expression> {
+    left_associative(
+        product,
+        alt((
+            value(BinaryOperator::Add, char('+')),
+            value(BinaryOperator::Sub, char('-')),
+        )),
+    )(input)
+}
+
+fn comparison(input: &str) -> IResult<&str, Expression> {
+    left_associative(
+        sum,
+        alt((
+            value(BinaryOperator::Equal, tag("==")),
+            value(BinaryOperator::NotEqual, tag("!=")),
+            value(BinaryOperator::LessEqual, tag("<=")),
+            value(BinaryOperator::GreaterEqual, tag(">=")),
+            value(BinaryOperator::Less, char('<')),
+            value(BinaryOperator::Greater, char('>')),
+        )),
+    )(input)
+}
+
+fn conjunction(input: &str) -> IResult<&str, Expression> {
+    left_associative(comparison, value(BinaryOperator::And, tag("&&")))(input)
+}
+
+pub fn expression(input: &str) -> IResult<&str, Expression> {
+    left_associative(conjunction, value(BinaryOperator::Or, tag("||")))(input)
+}
+
+pub fn parse_expression(input: &str) -> Result<Expression, String> {
+    all_consuming(preceded(multispace0, expression))(input)
+        .finish()
+        .map(|(_, expression)| expression)
+        .map_err(|error| convert_error(input, error))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Invariant {
+    pub source: String,
+    pub expression: Expression,
+}
+
+impl Invariant {
+    pub fn new(source: &str) -> Result<Self, String> {
+        Ok(Invariant {
+            source: source.trim().to_owned(),
+            expression: parse_expression(source)?,
+        })
+    }
+
+    // Returns a report if the invariant doesn't hold.
+    pub fn check(&self, context: &impl ExpressionContext) -> Option<String> {
+        let result = self.expression.evaluate(context);
+        if result.is_some_and(|result| result != 0) {
+            return None;
+        }
+
+        let operands = self
+            .expression
+            .operands()
+            .into_iter()
+            .map(|operand| match operand.evaluate(context) {
+                Some(value) => format!("{operand} = {value}"),
+                None => format!("{operand} is undefined"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let verdict = if result.is_some() {
+            "violated"
+        } else {
+            "could not be evaluated"
+        };
+
+        Some(if operands.is_empty() {
+            format!("{} {verdict}", self.source)
+        } else {
+            format!("{} {verdict} ({operands})", self.source)
+        })
+    }
+}
+
+pub fn check_invariants(
+    invariants: &[Invariant],
+    context: &impl ExpressionContext,
+) -> Option<String> {
+    invariants
+        .iter()
+        .find_map(|invariant| invariant.check(context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        let expression = parse_expression("1 + 2 * -3 == -5 && !(4 < 3) || 0").unwrap();
+
+        assert_eq!(
+            expression.to_string(),
+            "((((1 + (2 * -3)) == -5) && !(4 < 3)) || 0)"
+        );
+        assert_eq!(expression.evaluate(&Hardware::default()), Some(1));
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut hardware = Hardware {
+            a: 300,
+            d: 7,
+            ..Default::default()
+        };
+        hardware.ram[0] = 300;
+        hardware.ram[300] = 42;
+
+        let evaluate = |source: &str| parse_expression(source).unwrap().evaluate(&hardware);
+
+        assert_eq!(evaluate("RAM[SP] + D"), Some(49));
+        assert_eq!(evaluate("M == RAM[RAM[0]]"), Some(1));
+        assert_eq!(evaluate("RAM[-1]"), None);
+        assert_eq!(evaluate("A / (D - 7)"), None);
+        assert!(parse_expression("RAM[0] >=").is_err());
+        assert!(parse_expression("SPX > 0").is_err());
+    }
+
+    #[test]
+    fn test_invariant_report() {
+        let mut hardware = Hardware::default();
+        hardware.ram[0] = 2050;
+        let invariants = [
+            Invariant::new("RAM[0] >= 256").unwrap(),
+            Invariant::new("RAM[0] <= 2047").unwrap(),
+        ];
+
+        assert_eq!(
+            check_invariants(&invariants, &hardware),
+            Some("RAM[0] <= 2047 violated (RAM[0] = 2050)".to_owned())
+        );
+
+        hardware.ram[0] = 256;
+        assert_eq!(check_invariants(&invariants, &hardware), None);
+    }
+}
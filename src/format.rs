@@ -0,0 +1,204 @@
+use crate::parse_utils::strip_comment;
+
+const INDENT: &str = "    ";
+
+enum Line {
+    Blank,
+    Comment(String),
+    Code {
+        indented: bool,
+        code: String,
+        comment: Option<String>,
+    },
+}
+
+// Keeps banners like `////` and `// --` as they are.
+fn format_comment(text: &str) -> String {
+    let text = text.trim_end();
+    if text.is_empty() || text.starts_with(|c: char| c == '/' || c.is_whitespace()) {
+        format!("//{}", text)
+    } else {
+        format!("// {}", text)
+    }
+}
+
+fn format_source(input: &str, mut format_code: impl FnMut(&str) -> (bool, String)) -> String {
+    let mut lines = vec![];
+    for line in input.lines() {
+        let code = strip_comment(line).map_or(line, |(_, code)| code);
+        let comment = line[code.len()..].strip_prefix("//").map(format_comment);
+        let code = code.trim();
+        lines.push(match (code.is_empty(), comment) {
+            (true, None) => Line::Blank,
+            (true, Some(comment)) => Line::Comment(comment),
+            (false, comment) => {
+                let (indented, code) = format_code(code);
+                Line::Code {
+                    indented,
+                    code,
+                    comment,
+                }
+            }
+        });
+    }
+
+    let width = |line: &Line| match line {
+        Line::Code { indented, code, .. } => *indented as usize * INDENT.len() + code.len(),
+        _ => 0,
+    };
+    let mut output = String::new();
+    let mut comment_column = 0;
+    for (index, line) in lines.iter().enumerate() {
+        if index == 0 || !matches!(lines[index - 1], Line::Code { .. }) {
+            // Inline comments line up within each run of code lines.
+            comment_column = lines[index..]
+                .iter()
+                .take_while(|line| matches!(line, Line::Code { .. }))
+                .filter(|line| {
+                    matches!(
+                        line,
+                        Line::Code {
+                            comment: Some(_),
+                            ..
+                        }
+                    )
+                })
+                .map(width)
+                .max()
+                .unwrap_or(0);
+        }
+        match line {
+            Line::Blank => {
+                if !output.is_empty() && !output.ends_with("\n\n") {
+                    output.push('\n');
+                }
+            }
+            Line::Comment(comment) => {
+                // Comments go with the code after them, except for the file's header.
+                let indented = lines[index..].iter().find_map(|line| match line {
+                    Line::Code { indented, .. } => Some(*indented),
+                    _ => None,
+                });
+                let header = !lines[..index]
+                    .iter()
+                    .any(|line| matches!(line, Line::Code { .. }));
+                if indented == Some(true) && !header {
+                    output.push_str(INDENT);
+                }
+                output.push_str(comment);
+                output.push('\n');
+            }
+            Line::Code {
+                indented,
+                code,
+                comment,
+            } => {
+                if *indented {
+                    output.push_str(INDENT);
+                }
+                output.push_str(code);
+                if let Some(comment) = comment {
+                    output.push_str(&" ".repeat(comment_column - width(line) + 1));
+                    output.push_str(comment);
+                }
+                output.push('\n');
+            }
+        }
+    }
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+
+    output
+}
+
+// Labels start at the margin and instructions are indented under them.
+pub fn format_asm(input: &str) -> String {
+    let macros: Vec<_> = input
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let directive = words.next()?;
+            directive
+                .eq_ignore_ascii_case(".macro")
+                .then(|| words.next())
+                .flatten()
+        })
+        .collect();
+    format_source(input, |code| {
+        if let Some(label) = code
+            .strip_prefix('(')
+            .and_then(|code| code.strip_suffix(')'))
+        {
+            (false, format!("({})", label.trim()))
+        } else if code.starts_with('.') {
+            // Only the directive itself, macro names and parameters keep their case.
+            let mut directive: Vec<_> = code.split_whitespace().map(str::to_owned).collect();
+            directive[0].make_ascii_lowercase();
+            (true, directive.join(" "))
+        } else if let Some(value) = code.strip_prefix('@') {
+            (true, format!("@{}", value.trim()))
+        } else if code
+            .split_whitespace()
+            .next()
+            .is_some_and(|word| macros.contains(&word))
+        {
+            let invocation: Vec<_> = code.split_whitespace().collect();
+            (true, invocation.join(" "))
+        } else {
+            let instruction: String = code.split_whitespace().collect();
+            (true, instruction.to_uppercase())
+        }
+    })
+}
+
+// Function bodies are indented, names keep their case since the VM is case sensitive.
+pub fn format_vm(input: &str) -> String {
+    let mut in_function = false;
+    format_source(input, |code| {
+        let mut words = code
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        words[0].make_ascii_lowercase();
+        if matches!(words[0].as_str(), "push" | "pop") {
+            if let Some(segment) = words.get_mut(1) {
+                segment.make_ascii_lowercase();
+            }
+        }
+        let is_function = words[0] == "function";
+        in_function |= is_function;
+        (in_function && !is_function, words.join(" "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_asm() {
+        assert_eq!(
+            format_asm(
+                "//Counts down\n\n\n   @ 10\nd = a   // D is 10\n( LOOP )\n  d=d-1;jgt //loop\n\n.DATA  16\n"
+            ),
+            "// Counts down\n\n    @10\n    D=A       // D is 10\n(LOOP)\n    D=D-1;JGT // loop\n\n    .data 16\n"
+        );
+        assert_eq!(
+            format_asm(".MACRO goto Target\n@Target\n0;jmp\n.ENDMACRO\ngoto   LOOP\n"),
+            "    .macro goto Target\n    @Target\n    0;JMP\n    .endmacro\n    goto LOOP\n"
+        );
+    }
+
+    #[test]
+    fn test_format_vm() {
+        assert_eq!(
+            format_vm(
+                "PUSH Constant 7\n\nFunction Main.main 2\n// Adds\npush   local 0 // x\npop \
+                 that 10 //y\nCall Math.multiply 2\n//// end\nreturn\n"
+            ),
+            "push constant 7\n\nfunction Main.main 2\n    // Adds\n    push local 0 // x\n    \
+             pop that 10  // y\n    call Math.multiply 2\n    //// end\n    return\n"
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     borrow::Borrow,
-    ops::{Index, IndexMut},
+    ops::{Index, IndexMut, Range},
 };
 
 #[cfg(not(feature = "bit32"))]
@@ -13,7 +13,10 @@ pub type Word = i32;
 #[cfg(feature = "bit32")]
 pub type UWord = u32;
 
-use crate::hardware_parse::assemble_hack_file;
+use crate::hardware_parse::{
+    assemble, data_words, expand_macros, parse_hack_binary, parse_instructions, program_errors,
+    ProgramError, RomFragment,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Instruction {
@@ -33,7 +36,7 @@ pub enum DestinationRegisters {
     D,
 }
 
-fn encode_jmp(jump_condition: JumpCondition) -> UWord {
+const fn encode_jmp(jump_condition: JumpCondition) -> UWord {
     use JumpCondition::*;
     match jump_condition {
         NoJump => 0,
@@ -47,7 +50,7 @@ fn encode_jmp(jump_condition: JumpCondition) -> UWord {
     }
 }
 
-fn encode_dst_registers(dst_registers: DestinationRegisters) -> UWord {
+const fn encode_dst_registers(dst_registers: DestinationRegisters) -> UWord {
     use DestinationRegisters::*;
     match dst_registers {
         NoDestination => 0,
@@ -61,11 +64,168 @@ fn encode_dst_registers(dst_registers: DestinationRegisters) -> UWord {
     }
 }
 
+// The comp field of a C-instruction, including the a bit that selects M over A.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Computation {
+    Zero,
+    One,
+    MinusOne,
+    D,
+    A,
+    M,
+    NotD,
+    NotA,
+    NotM,
+    MinusD,
+    MinusA,
+    MinusM,
+    DPlusOne,
+    APlusOne,
+    MPlusOne,
+    DMinusOne,
+    AMinusOne,
+    MMinusOne,
+    DPlusA,
+    DPlusM,
+    DMinusA,
+    AMinusD,
+    DMinusM,
+    MMinusD,
+    DAndA,
+    DAndM,
+    DOrA,
+    DOrM,
+}
+
+impl Computation {
+    pub const ALL: [Computation; 28] = [
+        Computation::Zero,
+        Computation::One,
+        Computation::MinusOne,
+        Computation::D,
+        Computation::A,
+        Computation::M,
+        Computation::NotD,
+        Computation::NotA,
+        Computation::NotM,
+        Computation::MinusD,
+        Computation::MinusA,
+        Computation::MinusM,
+        Computation::DPlusOne,
+        Computation::APlusOne,
+        Computation::MPlusOne,
+        Computation::DMinusOne,
+        Computation::AMinusOne,
+        Computation::MMinusOne,
+        Computation::DPlusA,
+        Computation::DPlusM,
+        Computation::DMinusA,
+        Computation::AMinusD,
+        Computation::DMinusM,
+        Computation::MMinusD,
+        Computation::DAndA,
+        Computation::DAndM,
+        Computation::DOrA,
+        Computation::DOrM,
+    ];
+
+    pub const fn code(self) -> UWord {
+        match self {
+            Computation::Zero => 0x01AA,
+            Computation::One => 0x01BF,
+            Computation::MinusOne => 0x01BA,
+            Computation::D => 0x018C,
+            Computation::A => 0x01B0,
+            Computation::M => 0x01F0,
+            Computation::NotD => 0x018D,
+            Computation::NotA => 0x01B1,
+            Computation::NotM => 0x01F1,
+            Computation::MinusD => 0x018F,
+            Computation::MinusA => 0x01B3,
+            Computation::MinusM => 0x01F3,
+            Computation::DPlusOne => 0x019F,
+            Computation::APlusOne => 0x01B7,
+            Computation::MPlusOne => 0x01F7,
+            Computation::DMinusOne => 0x018E,
+            Computation::AMinusOne => 0x01B2,
+            Computation::MMinusOne => 0x01F2,
+            Computation::DPlusA => 0x0182,
+            Computation::DPlusM => 0x01C2,
+            Computation::DMinusA => 0x0193,
+            Computation::AMinusD => 0x0187,
+            Computation::DMinusM => 0x01D3,
+            Computation::MMinusD => 0x01C7,
+            Computation::DAndA => 0x0180,
+            Computation::DAndM => 0x01C0,
+            Computation::DOrA => 0x0195,
+            Computation::DOrM => 0x01D5,
+        }
+    }
+
+    pub fn from_code(code: UWord) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|computation| computation.code() == code)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Computation::Zero => "0",
+            Computation::One => "1",
+            Computation::MinusOne => "-1",
+            Computation::D => "D",
+            Computation::A => "A",
+            Computation::M => "M",
+            Computation::NotD => "!D",
+            Computation::NotA => "!A",
+            Computation::NotM => "!M",
+            Computation::MinusD => "-D",
+            Computation::MinusA => "-A",
+            Computation::MinusM => "-M",
+            Computation::DPlusOne => "D+1",
+            Computation::APlusOne => "A+1",
+            Computation::MPlusOne => "M+1",
+            Computation::DMinusOne => "D-1",
+            Computation::AMinusOne => "A-1",
+            Computation::MMinusOne => "M-1",
+            Computation::DPlusA => "D+A",
+            Computation::DPlusM => "D+M",
+            Computation::DMinusA => "D-A",
+            Computation::AMinusD => "A-D",
+            Computation::DMinusM => "D-M",
+            Computation::MMinusD => "M-D",
+            Computation::DAndA => "A&D",
+            Computation::DAndM => "D&M",
+            Computation::DOrA => "A|D",
+            Computation::DOrM => "D|M",
+        }
+    }
+}
+
 impl Instruction {
-    pub fn new(raw: UWord) -> Instruction {
+    pub const fn new(raw: UWord) -> Instruction {
         Instruction { raw }
     }
 
+    // Values that don't fit in an A-instruction are cut to the bits it has.
+    pub const fn a(value: Word) -> Instruction {
+        Instruction {
+            raw: value as UWord & !(1 << (UWord::BITS - 1)),
+        }
+    }
+
+    pub const fn c(
+        dst_registers: DestinationRegisters,
+        computation: Computation,
+        jump_condition: JumpCondition,
+    ) -> Instruction {
+        Self::create(dst_registers, computation.code(), jump_condition)
+    }
+
+    pub fn raw(&self) -> UWord {
+        self.raw
+    }
+
     pub fn from_legacy(legacy_raw: u16) -> Instruction {
         let raw =
             (legacy_raw as UWord >> 15) << (Word::BITS - 1) | (legacy_raw & !(1 << 15)) as UWord;
@@ -73,7 +233,15 @@ impl Instruction {
         Instruction { raw }
     }
 
-    pub fn create(
+    // None for A-instructions whose value doesn't fit in 15 bits.
+    // UWord is only wider than u16 with bit32.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn to_legacy(&self) -> Option<u16> {
+        let value = self.raw & !(1 << (UWord::BITS - 1));
+        (value < 1 << 15).then_some(((self.raw >> (UWord::BITS - 1)) << 15 | value) as u16)
+    }
+
+    pub const fn create(
         dst_registers: DestinationRegisters,
         calculation_value: UWord,
         jump_condition: JumpCondition,
@@ -107,6 +275,7 @@ impl std::fmt::Display for Instruction {
     }
 }
 
+#[derive(PartialEq, Eq)]
 enum YRegister {
     A,
     M,
@@ -197,6 +366,12 @@ impl Instruction {
         self.flag(3)
     }
 
+    pub fn reads_m(&self) -> bool {
+        self.instruction_type() == InstructionType::C
+            && self.y_register() == YRegister::M
+            && !self.zero_y()
+    }
+
     pub fn loaded_value(&self) -> Word {
         self.raw as Word
     }
@@ -216,37 +391,7 @@ impl Instruction {
     }
 
     pub fn op_name(&self) -> &'static str {
-        match (self.raw >> 6) & 0x01FF {
-            0x01AA => "0",
-            0x01BF => "1",
-            0x01BA => "-1",
-            0x018C => "D",
-            0x01B0 => "A",
-            0x01F0 => "M",
-            0x018D => "!D",
-            0x01B1 => "!A",
-            0x01F1 => "!M",
-            0x018F => "-D",
-            0x01B3 => "-A",
-            0x01F3 => "-M",
-            0x019F => "D+1",
-            0x01B7 => "A+1",
-            0x01F7 => "M+1",
-            0x018E => "D-1",
-            0x01B2 => "A-1",
-            0x01F2 => "M-1",
-            0x0182 => "D+A",
-            0x01C2 => "D+M",
-            0x0193 => "D-A",
-            0x0187 => "A-D",
-            0x01D3 => "D-M",
-            0x01C7 => "M-D",
-            0x0180 => "A&D",
-            0x01C0 => "D&M",
-            0x0195 => "A|D",
-            0x01D5 => "D|M",
-            _ => "???",
-        }
+        Computation::from_code((self.raw >> 6) & 0x01FF).map_or("???", Computation::name)
     }
 }
 
@@ -272,6 +417,120 @@ pub struct RAM {
     pub contents: Box<[Word; MEM_SIZE]>,
 }
 
+// A RAM address that's known to be in range, so indexing with it can't go past the end or wrap
+// around through a negative Word.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address(Word);
+
+impl Address {
+    pub const SCREEN: Address = Address(RAM::SCREEN);
+    pub const KBD: Address = Address(RAM::KBD);
+    pub const STATIC_START: Address = Address(16);
+    pub const STACK_START: Address = Address(256);
+    pub const HEAP_START: Address = Address(2048);
+
+    pub fn new(value: Word) -> Option<Self> {
+        Self::from_index(usize::try_from(value).ok()?)
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        (index < MEM_SIZE).then_some(Address(index as Word))
+    }
+
+    pub const fn word(self) -> Word {
+        self.0
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn offset(self, offset: usize) -> Option<Self> {
+        Self::from_index(self.index().checked_add(offset)?)
+    }
+
+    pub fn region(self) -> MemoryRegion {
+        MemoryRegion::of(self.0)
+    }
+}
+
+impl TryFrom<Word> for Address {
+    type Error = String;
+
+    fn try_from(value: Word) -> Result<Self, Self::Error> {
+        Self::new(value).ok_or_else(|| format!("{} isn't a RAM address", value))
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RAM[{}]", self.0)
+    }
+}
+
+// A word of the screen's memory map, the row is the y coordinate and the column counts words
+// from the left. Only made from a pixel or an address on the screen, so both are in range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScreenAddress {
+    row: Word,
+    column: Word,
+}
+
+impl ScreenAddress {
+    // Also returns the pixel's bit within the word.
+    pub fn of_pixel(x: Word, y: Word) -> Option<(Self, Word)> {
+        if !(0..RAM::SCREEN_WIDTH).contains(&x) || !(0..RAM::SCREEN_HEIGHT).contains(&y) {
+            return None;
+        }
+        let screen_address = ScreenAddress {
+            row: y,
+            column: x / Word::BITS as Word,
+        };
+        Some((screen_address, x % Word::BITS as Word))
+    }
+
+    pub const fn row(self) -> Word {
+        self.row
+    }
+
+    pub const fn column(self) -> Word {
+        self.column
+    }
+
+    pub fn address(self) -> Address {
+        Address(RAM::SCREEN + self.row * RAM::SCREEN_ROW_LENGTH + self.column)
+    }
+}
+
+impl TryFrom<Address> for ScreenAddress {
+    type Error = String;
+
+    fn try_from(address: Address) -> Result<Self, Self::Error> {
+        if address.region() != MemoryRegion::Screen {
+            return Err(format!("{} isn't on the screen", address));
+        }
+        let offset = address.word() - RAM::SCREEN;
+        Ok(ScreenAddress {
+            row: offset / RAM::SCREEN_ROW_LENGTH,
+            column: offset % RAM::SCREEN_ROW_LENGTH,
+        })
+    }
+}
+
+impl Index<Address> for RAM {
+    type Output = Word;
+
+    fn index(&self, address: Address) -> &Self::Output {
+        &self.contents[address.index()]
+    }
+}
+
+impl IndexMut<Address> for RAM {
+    fn index_mut(&mut self, address: Address) -> &mut Self::Output {
+        &mut self.contents[address.index()]
+    }
+}
+
 impl Index<Word> for RAM {
     type Output = Word;
 
@@ -288,28 +547,245 @@ impl IndexMut<Word> for RAM {
 
 impl RAM {
     pub const SCREEN: Word = (MEM_SIZE / 2) as Word;
-    pub const KBD: Word = Self::SCREEN + Self::SCREEN_ROW_LENGTH * 256;
-    pub const SCREEN_ROW_LENGTH: Word = 512 / Word::BITS as Word;
+    pub const KBD: Word = Self::SCREEN + Self::SCREEN_ROW_LENGTH * Self::SCREEN_HEIGHT;
+    pub const SCREEN_WIDTH: Word = 512;
+    pub const SCREEN_HEIGHT: Word = 256;
+    pub const SCREEN_ROW_LENGTH: Word = Self::SCREEN_WIDTH / Word::BITS as Word;
 
+    // Pixels off the screen are always white.
     pub fn get_pixel(&self, x: Word, y: Word) -> bool {
-        (self[Self::SCREEN + y * Self::SCREEN_ROW_LENGTH + x / (Word::BITS as Word)]
-            & (1 << (x % (Word::BITS as Word))))
-            != 0
+        ScreenAddress::of_pixel(x, y)
+            .is_some_and(|(screen_address, bit)| self[screen_address.address()] & (1 << bit) != 0)
     }
 
+    // Pixels off the screen are ignored rather than written somewhere else in the RAM.
     pub fn set_pixel(&mut self, x: Word, y: Word, value: bool) {
+        let Some((screen_address, bit)) = ScreenAddress::of_pixel(x, y) else {
+            return;
+        };
         if value {
-            self[Self::SCREEN + y * Self::SCREEN_ROW_LENGTH + x / (Word::BITS as Word)] |=
-                1 << (x % (Word::BITS as Word));
+            self[screen_address.address()] |= 1 << bit;
         } else {
-            self[Self::SCREEN + y * Self::SCREEN_ROW_LENGTH + x / (Word::BITS as Word)] &=
-                !(1 << (x % (Word::BITS as Word)));
+            self[screen_address.address()] &= !(1 << bit);
         }
     }
 
+    // None when y is off the screen.
+    pub fn screen_row(&self, y: Word) -> Option<&[Word]> {
+        let (screen_address, _) = ScreenAddress::of_pixel(0, y)?;
+        let start = screen_address.address().word();
+        Some(self.slice(start..start + Self::SCREEN_ROW_LENGTH))
+    }
+
+    // Like slice indexing, panics if the range leaves the RAM.
+    pub fn slice(&self, range: Range<Word>) -> &[Word] {
+        &self.contents[range.start as usize..range.end as usize]
+    }
+
+    pub fn write_slice(&mut self, address: Word, values: &[Word]) {
+        self.contents[address as usize..address as usize + values.len()].copy_from_slice(values);
+    }
+
+    // The address and value of every cell that isn't zero.
+    pub fn nonzero(&self) -> impl Iterator<Item = (Word, Word)> + '_ {
+        self.contents
+            .iter()
+            .enumerate()
+            .filter(|(_, &value)| value != 0)
+            .map(|(address, &value)| (address as Word, value))
+    }
+
+    // Every pixel row by row from the top left, true for black.
+    pub fn screen_as_bitvec(&self) -> Vec<bool> {
+        (0..Self::SCREEN_HEIGHT)
+            .flat_map(|y| (0..Self::SCREEN_WIDTH).map(move |x| self.get_pixel(x, y)))
+            .collect()
+    }
+
     pub fn set_keyboard(&mut self, value: Word) {
         self[Self::KBD] = value;
     }
+
+    // Only printable ASCII and newline have key codes, returns whether `c` is one of them.
+    pub fn press_char(&mut self, c: char) -> bool {
+        let code = match c {
+            '\n' => HackKey::NewLine.code(),
+            ' '..='~' => c as Word,
+            _ => return false,
+        };
+        self.set_keyboard(code);
+        true
+    }
+
+    pub fn press_key(&mut self, key: HackKey) {
+        self.set_keyboard(key.code());
+    }
+
+    pub fn changes_from(&self, old: &RAM) -> Vec<MemoryChange> {
+        old.contents
+            .iter()
+            .zip(self.contents.iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(address, (&old, &new))| MemoryChange {
+                address: address as Word,
+                old,
+                new,
+            })
+            .collect()
+    }
+}
+
+// The keys that don't type a character, printable characters use their ASCII code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HackKey {
+    NewLine,
+    Backspace,
+    Left,
+    Up,
+    Right,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    Esc,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+}
+
+impl HackKey {
+    // In key code order, from 128.
+    pub const ALL: [HackKey; 25] = [
+        HackKey::NewLine,
+        HackKey::Backspace,
+        HackKey::Left,
+        HackKey::Up,
+        HackKey::Right,
+        HackKey::Down,
+        HackKey::Home,
+        HackKey::End,
+        HackKey::PageUp,
+        HackKey::PageDown,
+        HackKey::Insert,
+        HackKey::Delete,
+        HackKey::Esc,
+        HackKey::F1,
+        HackKey::F2,
+        HackKey::F3,
+        HackKey::F4,
+        HackKey::F5,
+        HackKey::F6,
+        HackKey::F7,
+        HackKey::F8,
+        HackKey::F9,
+        HackKey::F10,
+        HackKey::F11,
+        HackKey::F12,
+    ];
+
+    pub fn code(self) -> Word {
+        128 + self as Word
+    }
+
+    pub fn from_code(code: Word) -> Option<Self> {
+        Self::ALL.get(usize::try_from(code - 128).ok()?).copied()
+    }
+
+    // The names test scripts use.
+    pub fn name(self) -> &'static str {
+        match self {
+            HackKey::NewLine => "newline",
+            HackKey::Backspace => "backspace",
+            HackKey::Left => "left",
+            HackKey::Up => "up",
+            HackKey::Right => "right",
+            HackKey::Down => "down",
+            HackKey::Home => "home",
+            HackKey::End => "end",
+            HackKey::PageUp => "pageup",
+            HackKey::PageDown => "pagedown",
+            HackKey::Insert => "insert",
+            HackKey::Delete => "delete",
+            HackKey::Esc => "esc",
+            HackKey::F1 => "f1",
+            HackKey::F2 => "f2",
+            HackKey::F3 => "f3",
+            HackKey::F4 => "f4",
+            HackKey::F5 => "f5",
+            HackKey::F6 => "f6",
+            HackKey::F7 => "f7",
+            HackKey::F8 => "f8",
+            HackKey::F9 => "f9",
+            HackKey::F10 => "f10",
+            HackKey::F11 => "f11",
+            HackKey::F12 => "f12",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|key| key.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryChange {
+    pub address: Word,
+    pub old: Word,
+    pub new: Word,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryRegion {
+    Registers,
+    Static,
+    Stack,
+    Heap,
+    Screen,
+    Keyboard,
+    Unused,
+}
+
+impl MemoryRegion {
+    pub fn of(address: Word) -> Self {
+        match address {
+            0..=15 => MemoryRegion::Registers,
+            16..=255 => MemoryRegion::Static,
+            256..=2047 => MemoryRegion::Stack,
+            _ if address < RAM::SCREEN => MemoryRegion::Heap,
+            _ if address < RAM::KBD => MemoryRegion::Screen,
+            _ if address == RAM::KBD => MemoryRegion::Keyboard,
+            _ => MemoryRegion::Unused,
+        }
+    }
+}
+
+impl std::fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryRegion::Registers => write!(f, "Registers"),
+            MemoryRegion::Static => write!(f, "Static"),
+            MemoryRegion::Stack => write!(f, "Stack"),
+            MemoryRegion::Heap => write!(f, "Heap"),
+            MemoryRegion::Screen => write!(f, "Screen"),
+            MemoryRegion::Keyboard => write!(f, "Keyboard"),
+            MemoryRegion::Unused => write!(f, "Unused"),
+        }
+    }
 }
 
 pub trait Emulator {
@@ -320,6 +796,8 @@ pub trait Emulator {
     fn get_ram_value(&self, address: Word) -> Word;
     fn set_ram_value(&mut self, address: Word, value: Word);
     fn pc(&self) -> Word;
+    // The instruction at PC, none when PC is outside the ROM.
+    fn current_instruction(&self) -> Option<Instruction>;
     fn step(&mut self) -> bool;
     fn load_program(&mut self, program: impl IntoIterator<Item = impl Borrow<Instruction>>);
     fn run_program(&mut self);
@@ -355,32 +833,15 @@ impl Emulator for Hardware {
         self.ram[address] = value;
     }
 
-    fn step(&mut self) -> bool {
-        self.ticks += 1;
-        let instruction = *self.current_instruction();
-        match instruction.instruction_type() {
-            InstructionType::A => {
-                self.a = instruction.loaded_value();
-                self.pc += 1;
-            }
-            InstructionType::C => {
-                let result = self.compute(instruction);
-                self.pc = if instruction.jump_condition().is_true(result) {
-                    self.a
-                } else {
-                    self.pc + 1
-                };
-                self.set(instruction, result);
-            }
-        }
-
-        for breakpoint in &self.breakpoints {
-            if self.get_breakpoint_var(&breakpoint.var) == breakpoint.value {
-                return true;
-            }
-        }
+    fn current_instruction(&self) -> Option<Instruction> {
+        usize::try_from(self.pc)
+            .ok()
+            .and_then(|pc| self.rom.get(pc))
+            .copied()
+    }
 
-        false
+    fn step(&mut self) -> bool {
+        self.step_with_events().is_some()
     }
 
     fn load_program(&mut self, program: impl IntoIterator<Item = impl Borrow<Instruction>>) {
@@ -403,8 +864,10 @@ impl Emulator for Hardware {
             rom: self.rom.clone(),
             breakpoints: self.breakpoints.clone(),
             length: self.length,
+            data: std::mem::take(&mut self.data),
             ..Default::default()
         };
+        self.apply_data();
     }
 }
 
@@ -418,6 +881,8 @@ pub struct Hardware {
     pub breakpoints: Vec<Breakpoint>,
     pub length: usize,
     pub ticks: u64,
+    // RAM values from data directives, set again on every reset.
+    pub data: Vec<(Word, Word)>,
 }
 
 impl Default for Hardware {
@@ -433,6 +898,7 @@ impl Default for Hardware {
             breakpoints: vec![],
             length: 32 * 1024,
             ticks: 0,
+            data: vec![],
         }
     }
 }
@@ -520,40 +986,144 @@ impl Hardware {
         }
     }
 
-    pub fn run(&mut self, step_count: u64) -> bool {
+    // Breakpoints are checked after the instruction executes, `step` is the tick it executed on.
+    pub fn step_with_events(&mut self) -> Option<BreakpointEvent> {
+        self.ticks += 1;
+        let instruction = *self.current_instruction();
+        match instruction.instruction_type() {
+            InstructionType::A => {
+                self.a = instruction.loaded_value();
+                self.pc += 1;
+            }
+            InstructionType::C => {
+                let result = self.compute(instruction);
+                self.pc = if instruction.jump_condition().is_true(result) {
+                    self.a
+                } else {
+                    self.pc + 1
+                };
+                self.set(instruction, result);
+            }
+        }
+
+        self.hit_breakpoint().map(|breakpoint| BreakpointEvent {
+            breakpoint,
+            step: self.ticks,
+        })
+    }
+
+    pub fn halted(&self) -> bool {
+        self.pc as usize >= self.length
+    }
+
+    // Stops early at a breakpoint or once the PC runs past the end of the program.
+    pub fn run(&mut self, step_count: u64) -> Option<BreakpointEvent> {
         for _ in 0..step_count {
-            if self.step() {
-                return true;
+            if self.halted() {
+                break;
+            }
+            let event = self.step_with_events();
+            if event.is_some() {
+                return event;
             }
         }
 
-        false
+        None
+    }
+
+    pub fn next_write_address(&self) -> Option<Word> {
+        let instruction = self.current_instruction();
+        (instruction.instruction_type() == InstructionType::C && instruction.dst_has_m())
+            .then_some(self.a)
+    }
+
+    pub fn run_until_write(&mut self, address: Word, max_steps: u64) -> Option<BreakpointEvent> {
+        for _ in 0..max_steps {
+            let writes_address = self.next_write_address() == Some(address);
+            let event = self.step_with_events();
+            if event.is_some() {
+                return event;
+            }
+            if writes_address {
+                break;
+            }
+        }
+
+        None
     }
 
     pub fn from_file_contents(contents: &str) -> Self {
         let mut instance = Self::default();
-        let instructions = assemble_hack_file(contents).unwrap().1;
+        let expansion = expand_macros(contents).unwrap();
+        let assembly_instructions = parse_instructions(&expansion.text).unwrap().1;
+        let instructions = assemble(&assembly_instructions).unwrap();
 
         instance.length = instructions.len();
         for (i, instruction) in instructions.into_iter().enumerate() {
             instance.rom[i] = instruction;
         }
+        instance.data = data_words(&assembly_instructions);
+        instance.apply_data();
 
         instance
     }
 
-    pub fn from_hack_file_contents(contents: &str) -> Self {
+    fn apply_data(&mut self) {
+        for &(address, value) in &self.data {
+            self.ram[address] = value;
+        }
+    }
+
+    pub fn try_from_file_contents(contents: &str) -> Result<Self, Vec<ProgramError>> {
+        let errors = program_errors(contents);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self::from_file_contents(contents))
+    }
+
+    pub fn from_hack_binary(contents: &str) -> Result<Self, Vec<ProgramError>> {
         let mut instance = Self::default();
+        instance.load_program(parse_hack_binary(contents)?);
+        Ok(instance)
+    }
 
-        for (i, raw) in contents
-            .lines()
-            .map(|l| UWord::from_str_radix(l.trim(), 2).unwrap())
-            .enumerate()
-        {
-            instance.rom[i] = Instruction { raw };
+    // Fragments can leave gaps between them but can't overlap, the program still starts at ROM[0].
+    pub fn from_fragments(fragments: &[RomFragment]) -> Result<Self, String> {
+        let mut instance = Self {
+            length: 0,
+            ..Self::default()
+        };
+        let mut owners: Vec<Option<&str>> = vec![None; MEM_SIZE];
+        for fragment in fragments {
+            if fragment.end() > MEM_SIZE {
+                return Err(format!(
+                    "{} ends at ROM[{}], past the end of the ROM",
+                    fragment.name,
+                    fragment.end() - 1
+                ));
+            }
+            for (address, instruction) in (fragment.origin..).zip(&fragment.instructions) {
+                if let Some(owner) = owners[address].replace(&fragment.name) {
+                    return Err(format!(
+                        "{} and {} overlap at ROM[{}]",
+                        owner, fragment.name, address
+                    ));
+                }
+                instance.rom[address] = *instruction;
+            }
+            instance.length = instance.length.max(fragment.end());
+            instance.data.extend(&fragment.data);
         }
+        instance.apply_data();
 
-        instance
+        Ok(instance)
+    }
+
+    pub fn hit_breakpoint(&self) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| self.get_breakpoint_var(&breakpoint.var) == breakpoint.value)
     }
 
     pub fn get_breakpoints(&self) -> &Vec<Breakpoint> {
@@ -590,6 +1160,21 @@ impl std::fmt::Display for BreakpointVar {
     }
 }
 
+// Where a frame step on the hardware ends.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StepGoal {
+    // Right after the next instruction that writes to the address.
+    Write(Word),
+    // Once the ticks get there.
+    Ticks(u64),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BreakpointEvent {
+    pub breakpoint: usize,
+    pub step: u64,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Breakpoint {
     pub var: BreakpointVar,
@@ -599,6 +1184,7 @@ pub struct Breakpoint {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hardware_parse::hack_binary;
 
     #[test]
     fn test_increment_hardware() {
@@ -666,6 +1252,35 @@ mod tests {
         assert_eq!(emulator.d(), 0);
     }
 
+    #[test]
+    fn test_builder() {
+        const INCREMENT_D: Instruction = Instruction::c(
+            DestinationRegisters::D,
+            Computation::DPlusOne,
+            JumpCondition::NoJump,
+        );
+
+        assert_eq!(INCREMENT_D, Instruction::from_legacy(59344));
+        assert_eq!(Instruction::a(1337), Instruction::from_legacy(1337));
+        assert_eq!(
+            Instruction::c(
+                DestinationRegisters::AM,
+                Computation::MMinusD,
+                JumpCondition::JLT
+            )
+            .to_string(),
+            "AM=M-D;JLT"
+        );
+        for computation in Computation::ALL {
+            let instruction = Instruction::c(
+                DestinationRegisters::NoDestination,
+                computation,
+                JumpCondition::NoJump,
+            );
+            assert_eq!(instruction.op_name(), computation.name());
+        }
+    }
+
     #[test]
     fn test_load_hardware() {
         let mut hardware = Hardware::default();
@@ -707,7 +1322,7 @@ mod tests {
     }
 
     fn test_jump_setting_a(emulator: &mut impl Emulator) {
-        emulator.load_program(&[Instruction::create(
+        emulator.load_program([Instruction::create(
             DestinationRegisters::A,
             0x01BF, // 1
             JumpCondition::JMP,
@@ -718,4 +1333,245 @@ mod tests {
 
         assert_eq!(emulator.pc(), 0);
     }
+
+    #[test]
+    fn test_run_until_write() {
+        let mut hardware =
+            Hardware::from_file_contents("(LOOP)\n@100\nM=M+1\n@101\nM=M+1\n@LOOP\n0;JMP\n");
+
+        assert_eq!(hardware.run_until_write(101, 1000), None);
+        assert_eq!(hardware.pc, 4);
+        assert_eq!(hardware.run_until_write(101, 1000), None);
+        assert_eq!(hardware.ram[100], 2);
+        assert_eq!(hardware.ram[101], 2);
+
+        assert_eq!(hardware.run_until_write(102, 10), None);
+        assert_eq!(hardware.ticks, 20);
+    }
+
+    #[test]
+    fn test_screen_helpers() {
+        let mut ram = Hardware::default().ram;
+        ram.set_pixel(0, 1, true);
+        ram.set_pixel(17, 1, true);
+        ram.set_pixel(511, 255, true);
+
+        let row = ram.screen_row(1).unwrap();
+        assert_eq!(row.len() as Word, RAM::SCREEN_ROW_LENGTH);
+        assert_eq!(row[0], if Word::BITS == 16 { 1 } else { 1 | 1 << 17 });
+        assert_eq!(row[1], if Word::BITS == 16 { 2 } else { 0 });
+        let pixels = ram.screen_as_bitvec();
+        assert_eq!(
+            pixels
+                .iter()
+                .enumerate()
+                .filter(|(_, &on)| on)
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>(),
+            vec![512, 512 + 17, 512 * 256 - 1]
+        );
+        assert_eq!(ram.screen_row(RAM::SCREEN_HEIGHT), None);
+        assert_eq!(ram.screen_row(-1), None);
+    }
+
+    #[test]
+    fn test_addresses() {
+        assert_eq!(Address::new(-1), None);
+        assert_eq!(Address::new(100).map(Address::index), Some(100));
+        assert_eq!(Address::from_index(MEM_SIZE), None);
+        assert_eq!(
+            Address::KBD.offset(1).map(Address::region),
+            Some(MemoryRegion::Unused)
+        );
+        assert_eq!(
+            Address::try_from(-5),
+            Err("-5 isn't a RAM address".to_owned())
+        );
+
+        let (screen_address, bit) = ScreenAddress::of_pixel(20, 3).unwrap();
+        assert_eq!(bit, 20 % Word::BITS as Word);
+        assert_eq!(
+            ScreenAddress::try_from(screen_address.address()),
+            Ok(screen_address)
+        );
+        assert_eq!(
+            (screen_address.row(), screen_address.column()),
+            (3, 20 / Word::BITS as Word)
+        );
+        assert_eq!(ScreenAddress::of_pixel(512, 0), None);
+        assert!(ScreenAddress::try_from(Address::KBD).is_err());
+
+        let mut ram = Hardware::default().ram;
+        ram.set_pixel(-1, 0, true);
+        assert!(ram.changes_from(&Hardware::default().ram).is_empty());
+    }
+
+    #[test]
+    fn test_bulk_access() {
+        let mut ram = Hardware::default().ram;
+        ram.write_slice(100, &[1, 0, -3]);
+
+        assert_eq!(ram.slice(99..103), [0, 1, 0, -3]);
+        assert_eq!(ram.nonzero().collect::<Vec<_>>(), vec![(100, 1), (102, -3)]);
+    }
+
+    #[test]
+    fn test_keyboard() {
+        let mut ram = Hardware::default().ram;
+
+        assert_eq!(HackKey::Esc.code(), 140);
+        assert_eq!(HackKey::F12.code(), 152);
+        assert_eq!(HackKey::from_code(133), Some(HackKey::Down));
+        assert_eq!(HackKey::from_code(100), None);
+        assert_eq!(HackKey::from_name("PageUp"), Some(HackKey::PageUp));
+        assert!(ram.press_char('a'));
+        assert_eq!(ram[RAM::KBD], 97);
+        assert!(!ram.press_char('é'));
+        ram.press_key(HackKey::Backspace);
+        assert_eq!(ram[RAM::KBD], 129);
+    }
+
+    #[test]
+    fn test_data_reset() {
+        let mut hardware = Hardware::from_file_contents(".data 100\n.word 5, 6\n@100\nM=0\n");
+        assert_eq!((hardware.ram[100], hardware.ram[101]), (5, 6));
+
+        hardware.run(2);
+        assert_eq!(hardware.ram[100], 0);
+        hardware.reset();
+        assert_eq!((hardware.ram[100], hardware.ram[101]), (5, 6));
+    }
+
+    #[test]
+    fn test_from_hack_binary() {
+        // @7, D=A, @16, M=D
+        let mut hardware = Hardware::from_hack_binary(
+            "0000000000000111\r\n1110110000010000\n\n0000000000010000\n1110001100001000\n",
+        )
+        .unwrap();
+        assert_eq!(hardware.length, 4);
+        assert_eq!(hardware.run(10), None);
+        assert!(hardware.halted());
+        assert_eq!(hardware.ram[16], 7);
+
+        let errors = Hardware::from_hack_binary("0000000000000111\n111\n@7\n").unwrap_err();
+        assert_eq!(
+            errors
+                .iter()
+                .map(ProgramError::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "line 2: 111 isn't a binary instruction",
+                "line 3: @7 isn't a binary instruction"
+            ]
+        );
+
+        let program = Hardware::from_file_contents("@7\nD=A\n@16\nM=D\n@32767\n0;JMP\n");
+        let rom = &program.rom[..program.length];
+        let binary = hack_binary(rom);
+        assert!(binary.starts_with("0000000000000111\n1110110000010000\n"));
+        assert_eq!(parse_hack_binary(&binary).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_from_fragments() {
+        // Labels stay within their fragment.
+        let boot = RomFragment::from_file("Boot.asm", "@100\n0;JMP\n", 0).unwrap();
+        let main = RomFragment::from_file(
+            "Main.asm",
+            "// origin: 100\n(MAIN)\n@7\nD=A\n@16\nM=D\n(END)\n@END\n0;JMP\n",
+            boot.end(),
+        )
+        .unwrap();
+        let mut hardware = Hardware::from_fragments(&[boot.clone(), main]).unwrap();
+        for _ in 0..6 {
+            hardware.step();
+        }
+
+        assert_eq!(hardware.ram[16], 7);
+        assert_eq!(hardware.pc, 104);
+        assert_eq!(hardware.length, 106);
+        assert_eq!(
+            Hardware::from_fragments(&[
+                boot.clone(),
+                RomFragment {
+                    name: "Patch.hack".to_owned(),
+                    ..boot
+                }
+            ]),
+            Err("Boot.asm and Patch.hack overlap at ROM[0]".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_step_with_events() {
+        let mut hardware = Hardware::from_file_contents("@5\nD=A\n@16\nM=D\n@16\nM=M+1\n");
+        hardware.add_breakpoint(&Breakpoint {
+            var: BreakpointVar::RAM(16),
+            value: 6,
+        });
+        hardware.add_breakpoint(&Breakpoint {
+            var: BreakpointVar::D,
+            value: 5,
+        });
+
+        assert_eq!(hardware.step_with_events(), None);
+        assert_eq!(
+            hardware.step_with_events(),
+            Some(BreakpointEvent {
+                breakpoint: 1,
+                step: 2
+            })
+        );
+        // A breakpoint that still holds triggers again on the following step.
+        assert_eq!(
+            hardware.run(10),
+            Some(BreakpointEvent {
+                breakpoint: 1,
+                step: 3
+            })
+        );
+
+        hardware.remove_breakpoint(1);
+        assert_eq!(
+            hardware.run(10),
+            Some(BreakpointEvent {
+                breakpoint: 0,
+                step: 6
+            })
+        );
+        assert_eq!(hardware.ram[16], 6);
+
+        assert!(hardware.halted());
+        assert_eq!(hardware.run(10), None);
+        assert_eq!(hardware.ticks, 6);
+    }
+
+    #[test]
+    fn test_ram_changes() {
+        let old = RAM::default();
+        let mut new = old.clone();
+        new[3] = 7;
+        new[RAM::SCREEN] = -1;
+
+        assert_eq!(
+            new.changes_from(&old),
+            vec![
+                MemoryChange {
+                    address: 3,
+                    old: 0,
+                    new: 7
+                },
+                MemoryChange {
+                    address: RAM::SCREEN,
+                    old: 0,
+                    new: -1
+                },
+            ]
+        );
+        assert_eq!(MemoryRegion::of(3), MemoryRegion::Registers);
+        assert_eq!(MemoryRegion::of(300), MemoryRegion::Stack);
+        assert_eq!(MemoryRegion::of(RAM::SCREEN), MemoryRegion::Screen);
+        assert_eq!(MemoryRegion::of(RAM::KBD), MemoryRegion::Keyboard);
+    }
 }
@@ -1,19 +1,25 @@
 use crate::{
+    expression::BinaryOperator,
     hardware::*,
-    parse_utils::{is_not0, non_comment_lines, AndThenConsuming, IResult, ParsableWord},
+    metadata::ProgramMetadata,
+    parse_utils::{
+        code_lines, is_not0, non_comment_lines, strip_comment, AndThenConsuming, IResult,
+        ParsableWord,
+    },
 };
 
 use hashbrown::HashMap;
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
-    character::complete::{alphanumeric1, char, space0},
-    combinator::{cut, map, recognize, success, value},
+    character::complete::{alphanumeric1, char, space0, space1, u32},
+    combinator::{cut, map, map_opt, recognize, success, value},
     error::{ParseError, VerboseError},
-    multi::{many1, many1_count},
-    sequence::{delimited, preceded, terminated, tuple},
+    multi::{many0, many1, many1_count, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     Parser,
 };
+use std::borrow::Cow;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum AssemblyInstruction {
@@ -21,14 +27,94 @@ pub enum AssemblyInstruction {
     Label(String),
     AtIdentifierInstruction(String),
     AtNumberInstruction(Word),
+    // `@(SCREEN+32)`, worked out once the symbols are known.
+    AtExpression(ConstantExpression),
+    // `.data` sets where in RAM the values of the following `.word` directives go.
+    DataAddress(Word),
+    DataWords(Vec<Word>),
 }
 
+impl AssemblyInstruction {
+    // Labels and data directives don't take up ROM.
+    pub fn in_rom(&self) -> bool {
+        !matches!(
+            self,
+            AssemblyInstruction::Label(_)
+                | AssemblyInstruction::DataAddress(_)
+                | AssemblyInstruction::DataWords(_)
+        )
+    }
+}
+
+// Sums, differences and products of numbers and symbols, there's no division since `/` starts
+// a comment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConstantExpression {
+    Number(i64),
+    Symbol(String),
+    Negate(Box<ConstantExpression>),
+    Binary(
+        BinaryOperator,
+        Box<ConstantExpression>,
+        Box<ConstantExpression>,
+    ),
+}
+
+impl ConstantExpression {
+    // None when a symbol is unknown or the arithmetic overflows.
+    pub fn evaluate(&self, symbols: &HashMap<&str, Word>) -> Option<i64> {
+        match self {
+            ConstantExpression::Number(value) => Some(*value),
+            ConstantExpression::Symbol(symbol) => symbols.get(symbol.as_str()).map(|&v| v as i64),
+            ConstantExpression::Negate(operand) => operand.evaluate(symbols)?.checked_neg(),
+            ConstantExpression::Binary(operator, lhs, rhs) => {
+                let lhs = lhs.evaluate(symbols)?;
+                let rhs = rhs.evaluate(symbols)?;
+                match operator {
+                    BinaryOperator::Add => lhs.checked_add(rhs),
+                    BinaryOperator::Sub => lhs.checked_sub(rhs),
+                    BinaryOperator::Mul => lhs.checked_mul(rhs),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            ConstantExpression::Number(_) => vec![],
+            ConstantExpression::Symbol(symbol) => vec![symbol],
+            ConstantExpression::Negate(operand) => operand.symbols(),
+            ConstantExpression::Binary(_, lhs, rhs) => {
+                let mut symbols = lhs.symbols();
+                symbols.extend(rhs.symbols());
+                symbols
+            }
+        }
+    }
+}
+
+// The same distinction for a line of code.
+pub fn is_rom_line(code: &str) -> bool {
+    !code.starts_with('(') && !code.starts_with('.')
+}
+
+// The course allows `$` and `:` in symbols too, VM translators name return addresses like
+// `Main.main$ret.1`.
 fn parse_identifier(input: &str) -> IResult<&str, &str> {
-    recognize(many1_count(alt((alphanumeric1, tag("_"), tag(".")))))(input)
+    recognize(many1_count(alt((
+        alphanumeric1,
+        tag("_"),
+        tag("."),
+        tag("$"),
+        tag(":"),
+    ))))(input)
 }
 
-fn create_c_instruction(args: (DestinationRegisters, UWord, JumpCondition)) -> AssemblyInstruction {
-    AssemblyInstruction::Instruction(Instruction::create(args.0, args.1, args.2))
+fn create_c_instruction(
+    args: (DestinationRegisters, Computation, JumpCondition),
+) -> AssemblyInstruction {
+    AssemblyInstruction::Instruction(Instruction::c(args.0, args.1, args.2))
 }
 
 fn parse_at_number_instruction(input: &str) -> IResult<&str, AssemblyInstruction> {
@@ -74,48 +160,48 @@ fn parse_destination_registers(input: &str) -> IResult<&str, DestinationRegister
     ))(input)
 }
 
-fn parse_calculation(input: &str) -> IResult<&str, UWord> {
+fn parse_calculation(input: &str) -> IResult<&str, Computation> {
     is_not(";/")
         .and_then_consuming(delimited(
             space0,
             alt((
                 alt((
-                    value(0x01AA, tag_no_whitespace("0")),
-                    value(0x01BF, tag_no_whitespace("1")),
-                    value(0x01BA, tag_no_whitespace("-1")),
-                    value(0x018C, tag_no_whitespace("D")),
-                    value(0x01B0, tag_no_whitespace("A")),
-                    value(0x01F0, tag_no_whitespace("M")),
-                    value(0x018D, tag_no_whitespace("!D")),
-                    value(0x01B1, tag_no_whitespace("!A")),
-                    value(0x01F1, tag_no_whitespace("!M")),
-                    value(0x018F, tag_no_whitespace("-D")),
-                    value(0x01B3, tag_no_whitespace("-A")),
-                    value(0x01F3, tag_no_whitespace("-M")),
-                    value(0x019F, tag_no_whitespace("D+1")),
-                    value(0x01B7, tag_no_whitespace("A+1")),
-                    value(0x01F7, tag_no_whitespace("M+1")),
-                    value(0x018E, tag_no_whitespace("D-1")),
-                    value(0x01B2, tag_no_whitespace("A-1")),
-                    value(0x01F2, tag_no_whitespace("M-1")),
-                    value(0x0182, tag_no_whitespace("D+A")),
-                    value(0x0182, tag_no_whitespace("A+D")),
-                    value(0x01C2, tag_no_whitespace("D+M")),
+                    value(Computation::Zero, tag_no_whitespace("0")),
+                    value(Computation::One, tag_no_whitespace("1")),
+                    value(Computation::MinusOne, tag_no_whitespace("-1")),
+                    value(Computation::D, tag_no_whitespace("D")),
+                    value(Computation::A, tag_no_whitespace("A")),
+                    value(Computation::M, tag_no_whitespace("M")),
+                    value(Computation::NotD, tag_no_whitespace("!D")),
+                    value(Computation::NotA, tag_no_whitespace("!A")),
+                    value(Computation::NotM, tag_no_whitespace("!M")),
+                    value(Computation::MinusD, tag_no_whitespace("-D")),
+                    value(Computation::MinusA, tag_no_whitespace("-A")),
+                    value(Computation::MinusM, tag_no_whitespace("-M")),
+                    value(Computation::DPlusOne, tag_no_whitespace("D+1")),
+                    value(Computation::APlusOne, tag_no_whitespace("A+1")),
+                    value(Computation::MPlusOne, tag_no_whitespace("M+1")),
+                    value(Computation::DMinusOne, tag_no_whitespace("D-1")),
+                    value(Computation::AMinusOne, tag_no_whitespace("A-1")),
+                    value(Computation::MMinusOne, tag_no_whitespace("M-1")),
+                    value(Computation::DPlusA, tag_no_whitespace("D+A")),
+                    value(Computation::DPlusA, tag_no_whitespace("A+D")),
+                    value(Computation::DPlusM, tag_no_whitespace("D+M")),
                 )),
                 alt((
-                    value(0x01C2, tag_no_whitespace("M+D")),
-                    value(0x0193, tag_no_whitespace("D-A")),
-                    value(0x0187, tag_no_whitespace("A-D")),
-                    value(0x01D3, tag_no_whitespace("D-M")),
-                    value(0x01C7, tag_no_whitespace("M-D")),
-                    value(0x0180, tag_no_whitespace("D&A")),
-                    value(0x0180, tag_no_whitespace("A&D")),
-                    value(0x01C0, tag_no_whitespace("D&M")),
-                    value(0x01C0, tag_no_whitespace("M&D")),
-                    value(0x0195, tag_no_whitespace("D|A")),
-                    value(0x0195, tag_no_whitespace("A|D")),
-                    value(0x01D5, tag_no_whitespace("D|M")),
-                    value(0x01D5, tag_no_whitespace("M|D")),
+                    value(Computation::DPlusM, tag_no_whitespace("M+D")),
+                    value(Computation::DMinusA, tag_no_whitespace("D-A")),
+                    value(Computation::AMinusD, tag_no_whitespace("A-D")),
+                    value(Computation::DMinusM, tag_no_whitespace("D-M")),
+                    value(Computation::MMinusD, tag_no_whitespace("M-D")),
+                    value(Computation::DAndA, tag_no_whitespace("D&A")),
+                    value(Computation::DAndA, tag_no_whitespace("A&D")),
+                    value(Computation::DAndM, tag_no_whitespace("D&M")),
+                    value(Computation::DAndM, tag_no_whitespace("M&D")),
+                    value(Computation::DOrA, tag_no_whitespace("D|A")),
+                    value(Computation::DOrA, tag_no_whitespace("A|D")),
+                    value(Computation::DOrM, tag_no_whitespace("D|M")),
+                    value(Computation::DOrM, tag_no_whitespace("M|D")),
                 )),
             )),
             space0,
@@ -218,10 +304,65 @@ fn c_instruction(input: &str) -> IResult<&str, AssemblyInstruction> {
     )(input)
 }
 
+fn constant_primary(input: &str) -> IResult<&str, ConstantExpression> {
+    delimited(
+        space0,
+        alt((
+            map(u32, |value| ConstantExpression::Number(value as i64)),
+            map(parse_identifier, |symbol| {
+                ConstantExpression::Symbol(symbol.to_owned())
+            }),
+            delimited(char('('), constant_sum, char(')')),
+            map(preceded(char('-'), constant_primary), |operand| {
+                ConstantExpression::Negate(Box::new(operand))
+            }),
+        )),
+        space0,
+    )(input)
+}
+
+fn constant_binary<'a>(
+    mut operand: impl FnMut(&'a str) -> IResult<&'a str, ConstantExpression>,
+    mut operator: impl FnMut(&'a str) -> IResult<&'a str, BinaryOperator>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, ConstantExpression> {
+    move |input| {
+        let (input, first) = operand(input)?;
+        let (input, rest) = many0(pair(&mut operator, &mut operand))(input)?;
+
+        Ok((
+            input,
+            rest.into_iter().fold(first, |lhs, (operator, rhs)| {
+                ConstantExpression::Binary(operator, Box::new(lhs), Box::new(rhs))
+            }),
+        ))
+    }
+}
+
+fn constant_product(input: &str) -> IResult<&str, ConstantExpression> {
+    constant_binary(constant_primary, value(BinaryOperator::Mul, char('*')))(input)
+}
+
+fn constant_sum(input: &str) -> IResult<&str, ConstantExpression> {
+    constant_binary(
+        constant_product,
+        alt((
+            value(BinaryOperator::Add, char('+')),
+            value(BinaryOperator::Sub, char('-')),
+        )),
+    )(input)
+}
+
 fn a_instruction(input: &str) -> IResult<&str, AssemblyInstruction> {
     preceded(
         tag("@"),
-        alt((parse_at_number_instruction, parse_at_identifier_instruction)),
+        alt((
+            map(
+                delimited(char('('), constant_sum, char(')')),
+                AssemblyInstruction::AtExpression,
+            ),
+            parse_at_number_instruction,
+            parse_at_identifier_instruction,
+        )),
     )(input)
 }
 
@@ -229,49 +370,624 @@ fn create_label(input: &str) -> IResult<&str, AssemblyInstruction> {
     delimited(tag("("), parse_label, tag(")"))(input)
 }
 
+fn data_directive(input: &str) -> IResult<&str, AssemblyInstruction> {
+    alt((
+        map(
+            preceded(pair(tag(".data"), space1), ParsableWord::parse_word),
+            AssemblyInstruction::DataAddress,
+        ),
+        map(
+            preceded(
+                pair(tag(".word"), space1),
+                separated_list1(
+                    delimited(space0, char(','), space0),
+                    ParsableWord::parse_word,
+                ),
+            ),
+            AssemblyInstruction::DataWords,
+        ),
+    ))(input)
+}
+
 pub fn instruction(input: &str) -> IResult<&str, AssemblyInstruction> {
-    alt((c_instruction, a_instruction, create_label))(input)
+    alt((data_directive, c_instruction, a_instruction, create_label))(input)
 }
 
 pub fn parse_instructions(input: &str) -> IResult<&str, Vec<AssemblyInstruction>> {
     non_comment_lines(instruction)(input)
 }
 
+const MAX_MACRO_DEPTH: usize = 16;
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<String>,
+}
+
+// Assembly with the macros expanded, every line of the text remembers the source line it came
+// from so that the ROM still maps back to what was written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacroExpansion {
+    pub text: String,
+    pub source_lines: Vec<usize>,
+}
+
+impl MacroExpansion {
+    pub fn source_line(&self, line: usize) -> usize {
+        self.source_lines[line - 1]
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || "_.$:".contains(c)
+}
+
+fn substitute(code: &str, substitutions: &HashMap<&str, &str>) -> String {
+    let mut output = String::new();
+    let mut rest = code;
+    while let Some(start) = rest.find(is_identifier_char) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+        let identifier = &rest[..end];
+        output.push_str(substitutions.get(identifier).unwrap_or(&identifier));
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn expand_invocation(
+    macros: &HashMap<String, Macro>,
+    code: &str,
+    depth: usize,
+    report: &dyn Fn(String) -> ProgramError,
+) -> Result<Option<Vec<String>>, ProgramError> {
+    let mut words = code.split(|c: char| c.is_whitespace() || c == ',');
+    let Some(definition) = words.next().and_then(|name| macros.get(name)) else {
+        return Ok(None);
+    };
+    let arguments: Vec<_> = words.filter(|word| !word.is_empty()).collect();
+    if arguments.len() != definition.parameters.len() {
+        return Err(report(format!(
+            "the macro takes {} arguments but got {}",
+            definition.parameters.len(),
+            arguments.len()
+        )));
+    }
+    if depth == MAX_MACRO_DEPTH {
+        return Err(report(format!(
+            "macros are nested more than {} deep",
+            MAX_MACRO_DEPTH
+        )));
+    }
+    let substitutions: HashMap<&str, &str> = definition
+        .parameters
+        .iter()
+        .map(String::as_str)
+        .zip(arguments)
+        .collect();
+    let mut lines = vec![];
+    for body_line in &definition.body {
+        let body_line = substitute(body_line, &substitutions);
+        match expand_invocation(macros, &body_line, depth + 1, report)? {
+            Some(expanded) => lines.extend(expanded),
+            None => lines.push(body_line),
+        }
+    }
+    Ok(Some(lines))
+}
+
+// A `.macro NAME PARAMETER...` block up to `.endmacro` defines a macro, which is then used like
+// an instruction with the arguments in place of the parameters. Macros have to be defined before
+// they're used.
+pub fn expand_macros(input: &str) -> Result<MacroExpansion, ProgramError> {
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    // The macro being defined and the line its definition starts on.
+    let mut defining: Option<(String, Macro, usize)> = None;
+    let mut text = String::new();
+    let mut source_lines = vec![];
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let untrimmed_code = strip_comment(line).map_or(line, |(_, code)| code);
+        let code = untrimmed_code.trim();
+        let report = |message: String| ProgramError {
+            line: line_number,
+            message,
+            text: code.to_owned(),
+        };
+        let mut words = code.split_whitespace();
+        let directive = words.next().unwrap_or_default().to_lowercase();
+        let mut push = |line: &str| {
+            text.push_str(line);
+            text.push('\n');
+            source_lines.push(line_number);
+        };
+        match (directive.as_str(), &mut defining) {
+            (".macro", Some(_)) => {
+                return Err(report("macros can't be defined inside macros".to_owned()))
+            }
+            (".macro", None) => {
+                let Some(name) = words.next() else {
+                    return Err(report("the macro has no name".to_owned()));
+                };
+                let parameters = words
+                    .flat_map(|word| word.split(','))
+                    .filter(|parameter| !parameter.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+                let definition = Macro {
+                    parameters,
+                    body: vec![],
+                };
+                defining = Some((name.to_owned(), definition, line_number));
+                push("");
+            }
+            (".endmacro", None) => {
+                return Err(report(".endmacro without a .macro".to_owned()));
+            }
+            (".endmacro", Some(_)) => {
+                let (name, definition, _) = defining.take().unwrap();
+                macros.insert(name, definition);
+                push("");
+            }
+            (_, Some((_, definition, _))) => {
+                if !code.is_empty() {
+                    definition.body.push(code.to_owned());
+                }
+                push("");
+            }
+            (_, None) => match expand_invocation(&macros, code, 0, &report)? {
+                // The comment stays with the first instruction, assertions rely on it.
+                Some(expanded) => {
+                    let comment = &line[untrimmed_code.len()..];
+                    for (index, expanded_line) in expanded.iter().enumerate() {
+                        if index == 0 {
+                            push(&format!("{} {}", expanded_line, comment));
+                        } else {
+                            push(expanded_line);
+                        }
+                    }
+                    if expanded.is_empty() {
+                        push(comment);
+                    }
+                }
+                None => push(line),
+            },
+        }
+    }
+    if let Some((name, _, line)) = defining {
+        return Err(ProgramError {
+            line,
+            message: format!("the macro {} has no .endmacro", name),
+            text: format!(".macro {}", name),
+        });
+    }
+
+    Ok(MacroExpansion { text, source_lines })
+}
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Assembly with every `#include "file.asm"` line replaced by the file it names, so that all the
+// files share one symbol table. Every line of the text remembers the file and line it came from,
+// and the line of the top file it's part of, which is the `#include` for included lines.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IncludeExpansion {
+    pub name: String,
+    pub text: String,
+    pub sources: Vec<(String, usize)>,
+    pub top_lines: Vec<usize>,
+}
+
+impl IncludeExpansion {
+    // Moves an error in the text to the top file, naming the included file it's really in.
+    pub fn top_error(&self, error: ProgramError) -> ProgramError {
+        let Some((file, line)) = self.sources.get(error.line.wrapping_sub(1)) else {
+            return error;
+        };
+        ProgramError {
+            line: self.top_lines[error.line - 1],
+            message: if *file == self.name {
+                error.message
+            } else {
+                format!("{}, line {}: {}", file, line, error.message)
+            },
+            text: error.text,
+        }
+    }
+}
+
+fn expand_file(
+    expansion: &mut IncludeExpansion,
+    including: &mut Vec<String>,
+    input: &str,
+    top_line: Option<usize>,
+    read: &mut dyn FnMut(&str) -> Result<String, String>,
+) -> Result<(), ProgramError> {
+    let file = including.last().cloned().unwrap_or_default();
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let top_line = top_line.unwrap_or(line_number);
+        expansion.sources.push((file.clone(), line_number));
+        expansion.top_lines.push(top_line);
+        let code = strip_comment(line).map_or(line, |(_, code)| code).trim();
+        let error_line = expansion.sources.len();
+        let report = |message: String| ProgramError {
+            line: error_line,
+            message,
+            text: code.to_owned(),
+        };
+        let Some(path) = code.strip_prefix("#include") else {
+            expansion.text.push_str(line);
+            expansion.text.push('\n');
+            continue;
+        };
+        let Some(path) = path
+            .trim()
+            .strip_prefix('"')
+            .and_then(|path| path.strip_suffix('"'))
+            .filter(|path| !path.is_empty())
+        else {
+            return Err(report("#include takes a file name in quotes".to_owned()));
+        };
+        if including.iter().any(|included| included == path) {
+            return Err(report(format!("{} is already being included", path)));
+        }
+        if including.len() > MAX_INCLUDE_DEPTH {
+            return Err(report(format!(
+                "includes are nested more than {} deep",
+                MAX_INCLUDE_DEPTH
+            )));
+        }
+        let contents = read(path).map_err(report)?;
+        expansion.text.push('\n');
+        including.push(path.to_owned());
+        expand_file(expansion, including, &contents, Some(top_line), read)?;
+        including.pop();
+    }
+    Ok(())
+}
+
+// `read` gets the file names as written, finding them is up to the caller.
+pub fn expand_includes(
+    name: &str,
+    input: &str,
+    read: &mut dyn FnMut(&str) -> Result<String, String>,
+) -> Result<IncludeExpansion, ProgramError> {
+    let mut expansion = IncludeExpansion {
+        name: name.to_owned(),
+        ..Default::default()
+    };
+    let mut including = vec![name.to_owned()];
+    match expand_file(&mut expansion, &mut including, input, None, read) {
+        Ok(()) => Ok(expansion),
+        Err(error) => Err(expansion.top_error(error)),
+    }
+}
+
+// Maps every assembled instruction to the source line it came from.
+pub fn instruction_line_numbers(input: &str) -> Vec<usize> {
+    let Ok(expansion) = expand_macros(input) else {
+        return vec![];
+    };
+    code_lines(&expansion.text)
+        .filter(|(_, code)| is_rom_line(code))
+        .map(|(line, _)| expansion.source_line(line))
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelWrite {
+    pub address: usize,
+    pub label: String,
+    pub label_address: usize,
+}
+
+pub fn instruction_labels(assembly_instructions: &[AssemblyInstruction]) -> Vec<(String, usize)> {
+    let mut labels = vec![];
+    let mut address = 0;
+    for assembly_instruction in assembly_instructions {
+        match assembly_instruction {
+            AssemblyInstruction::Label(label) => labels.push((label.clone(), address)),
+            _ if assembly_instruction.in_rom() => address += 1,
+            _ => {}
+        }
+    }
+
+    labels
+}
+
+// Writes to M right after loading A with an instruction label, as if the program could modify
+// its own ROM.
+pub fn label_writes(assembly_instructions: &[AssemblyInstruction]) -> Vec<LabelWrite> {
+    let labels = instruction_labels(assembly_instructions);
+    let label_addresses: HashMap<&str, usize> = labels
+        .iter()
+        .map(|(label, address)| (label.as_str(), *address))
+        .collect();
+
+    // A write right after a label can also be reached from a jump with a different A.
+    let mut writes = vec![];
+    let mut loaded_label = None;
+    let mut address = 0;
+    for assembly_instruction in assembly_instructions {
+        match assembly_instruction {
+            AssemblyInstruction::Label(_) => loaded_label = None,
+            AssemblyInstruction::AtIdentifierInstruction(label) => {
+                loaded_label = label_addresses
+                    .get(label.as_str())
+                    .map(|&label_address| (label, label_address));
+            }
+            AssemblyInstruction::Instruction(instruction) => {
+                if let Some((label, label_address)) = loaded_label.take() {
+                    if instruction.dst_has_m() {
+                        writes.push(LabelWrite {
+                            address,
+                            label: label.clone(),
+                            label_address,
+                        });
+                    }
+                }
+            }
+            AssemblyInstruction::AtNumberInstruction(_) | AssemblyInstruction::AtExpression(_) => {
+                loaded_label = None
+            }
+            AssemblyInstruction::DataAddress(_) | AssemblyInstruction::DataWords(_) => {}
+        }
+        if assembly_instruction.in_rom() {
+            address += 1;
+        }
+    }
+
+    writes
+}
+
+pub const PREDEFINED_SYMBOLS: [(&str, Word); 23] = [
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", RAM::SCREEN),
+    ("KBD", RAM::KBD),
+];
+
 pub fn assemble_hack_file(input: &str) -> IResult<&str, Vec<Instruction>> {
-    map(parse_instructions, |v| assemble(&v))(input)
-}
-
-pub fn assemble(assembly_instructions: &[AssemblyInstruction]) -> Vec<Instruction> {
-    let mut at_identifier_map: HashMap<&str, Word> = HashMap::from([
-        ("R0", 0),
-        ("R1", 1),
-        ("R2", 2),
-        ("R3", 3),
-        ("R4", 4),
-        ("R5", 5),
-        ("R6", 6),
-        ("R7", 7),
-        ("R8", 8),
-        ("R9", 9),
-        ("R10", 10),
-        ("R11", 11),
-        ("R12", 12),
-        ("R13", 13),
-        ("R14", 14),
-        ("R15", 15),
-        ("SP", 0),
-        ("LCL", 1),
-        ("ARG", 2),
-        ("THIS", 3),
-        ("THAT", 4),
-        ("SCREEN", RAM::SCREEN),
-        ("KBD", RAM::KBD),
-    ]);
-
-    let mut index = 0;
+    map_opt(parse_instructions, |v| assemble(&v).ok())(input)
+}
+
+// Variables get addresses from 16 up, the schemes differ in which comes first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AllocationOrder {
+    // The book's reference assembler.
+    #[default]
+    FirstAppearance,
+    Alphabetical,
+}
+
+impl std::str::FromStr for AllocationOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-appearance" => Ok(AllocationOrder::FirstAppearance),
+            "alphabetical" => Ok(AllocationOrder::Alphabetical),
+            _ => Err(format!(
+                "unknown allocation order {}, expected first-appearance or alphabetical",
+                s
+            )),
+        }
+    }
+}
+
+// Lenient takes the extensions, like macros, data directives and `A+D`, strict only takes what
+// the official assembler does, so that what assembles gives the same .hack file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AssemblerMode {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+impl AssemblerMode {
+    pub const ALL: [AssemblerMode; 2] = [AssemblerMode::Lenient, AssemblerMode::Strict];
+
+    // The source for the lenient assembler, strict mode checks it and takes the whitespace out.
+    pub fn source(self, input: &str) -> Result<Cow<'_, str>, Vec<ProgramError>> {
+        match self {
+            AssemblerMode::Lenient => Ok(Cow::Borrowed(input)),
+            AssemblerMode::Strict => strict_source(input).map(Cow::Owned),
+        }
+    }
+}
+
+impl std::str::FromStr for AssemblerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lenient" => Ok(AssemblerMode::Lenient),
+            "strict" => Ok(AssemblerMode::Strict),
+            _ => Err(format!(
+                "unknown assembler mode {}, expected lenient or strict",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for AssemblerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssemblerMode::Lenient => write!(f, "Lenient"),
+            AssemblerMode::Strict => write!(f, "Strict"),
+        }
+    }
+}
+
+const OFFICIAL_DESTINATIONS: [&str; 7] = ["M", "D", "MD", "A", "AM", "AD", "AMD"];
+
+const OFFICIAL_COMPUTATIONS: [&str; 28] = [
+    "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A", "D-A",
+    "A-D", "D&A", "D|A", "M", "!M", "-M", "M+1", "M-1", "D+M", "D-M", "M-D", "D&M", "D|M",
+];
+
+const OFFICIAL_JUMPS: [&str; 7] = ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+// The largest constant the official assembler takes, whatever the word size.
+const OFFICIAL_MAX_CONSTANT: u32 = 32767;
+
+fn is_official_symbol(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && !symbol.starts_with(|c: char| c.is_ascii_digit())
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+}
+
+// The official assembler drops all whitespace before looking at a line.
+fn strict_line_error(code: &str, written: &str) -> Option<String> {
+    if code.is_empty() {
+        return None;
+    }
+    if code.starts_with('.') {
+        return Some(format!(
+            "{} isn't part of the official assembly language",
+            written
+        ));
+    }
+    if let Some(label) = code.strip_prefix('(') {
+        let valid = label.strip_suffix(')').is_some_and(is_official_symbol);
+        return (!valid).then(|| format!("{} isn't a valid label", written));
+    }
+    if let Some(value) = code.strip_prefix('@') {
+        if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+            let in_range = value
+                .parse::<u32>()
+                .is_ok_and(|value| value <= OFFICIAL_MAX_CONSTANT);
+            return (!in_range).then(|| {
+                format!(
+                    "{} is out of range, constants go from 0 to {}",
+                    written, OFFICIAL_MAX_CONSTANT
+                )
+            });
+        }
+        return (!is_official_symbol(value)).then(|| format!("{} isn't a valid symbol", written));
+    }
+    let (destination, rest) = code.split_once('=').unwrap_or(("", code));
+    let (computation, jump) = rest.split_once(';').unwrap_or((rest, ""));
+    let valid = (destination.is_empty() || OFFICIAL_DESTINATIONS.contains(&destination))
+        && OFFICIAL_COMPUTATIONS.contains(&computation)
+        && (jump.is_empty() || OFFICIAL_JUMPS.contains(&jump));
+    (!valid).then(|| format!("{} isn't a valid instruction", written))
+}
+
+// The lines keep their comments and numbers, so metadata and errors still line up.
+pub fn strict_source(input: &str) -> Result<String, Vec<ProgramError>> {
+    let mut source = String::with_capacity(input.len());
+    let mut errors = vec![];
+    let mut labels = hashbrown::HashSet::new();
+    let mut instruction_lines = vec![];
+    for (index, line) in input.split_inclusive('\n').enumerate() {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let (written, comment) = match content.find("//") {
+            Some(start) => content.split_at(start),
+            None => (content, ""),
+        };
+        let written = written.trim();
+        let code: String = written.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut report = |message: String| {
+            errors.push(ProgramError {
+                line: index + 1,
+                message,
+                text: written.to_owned(),
+            })
+        };
+        if let Some(message) = strict_line_error(&code, written) {
+            report(message);
+        } else if let Some(label) = code.strip_prefix('(') {
+            let label = label.trim_end_matches(')');
+            let predefined = PREDEFINED_SYMBOLS
+                .iter()
+                .any(|(symbol, _)| *symbol == label);
+            if predefined || !labels.insert(label.to_owned()) {
+                report(format!("the label {} is defined more than once", label));
+            }
+        } else if !code.is_empty() {
+            instruction_lines.push((index + 1, written));
+        }
+        source.push_str(&code);
+        if !code.is_empty() && !comment.is_empty() {
+            source.push(' ');
+        }
+        source.push_str(comment);
+        source.push_str(&line[content.len()..]);
+    }
+    if let Some(&line) = instruction_lines.get(MEM_SIZE) {
+        errors.push(rom_overflow_error(line, instruction_lines.len()));
+    }
+
+    if errors.is_empty() {
+        Ok(source)
+    } else {
+        Err(errors)
+    }
+}
+
+// Fails when an expression's value doesn't fit in an A-instruction, which program_errors reports
+// with the same order and origin.
+pub fn assemble(assembly_instructions: &[AssemblyInstruction]) -> Result<Vec<Instruction>, String> {
+    assemble_with_order(assembly_instructions, AllocationOrder::FirstAppearance)
+}
+
+pub fn assemble_with_order(
+    assembly_instructions: &[AssemblyInstruction],
+    order: AllocationOrder,
+) -> Result<Vec<Instruction>, String> {
+    assemble_program(assembly_instructions, order, 0)
+}
+
+// Labels point into the ROM, so they move with the program's origin.
+pub fn assemble_at(
+    assembly_instructions: &[AssemblyInstruction],
+    origin: Word,
+) -> Result<Vec<Instruction>, String> {
+    assemble_program(
+        assembly_instructions,
+        AllocationOrder::FirstAppearance,
+        origin,
+    )
+}
+
+// Labels and predefined symbols, and then variables from 16 up.
+fn symbol_table(
+    assembly_instructions: &[AssemblyInstruction],
+    order: AllocationOrder,
+    origin: Word,
+) -> HashMap<&str, Word> {
+    let mut at_identifier_map: HashMap<&str, Word> = HashMap::from(PREDEFINED_SYMBOLS);
+
+    let mut index = origin;
     for assembly_instruction in assembly_instructions.iter() {
         let AssemblyInstruction::Label(label) = assembly_instruction else {
-            index += 1;
+            index += assembly_instruction.in_rom() as Word;
             continue;
         };
         if at_identifier_map.contains_key(label.as_str()) {
@@ -281,29 +997,328 @@ pub fn assemble(assembly_instructions: &[AssemblyInstruction]) -> Vec<Instructio
         at_identifier_map.insert(label.as_str(), index);
     }
 
-    let mut rom: Vec<Instruction> = vec![];
-
-    let mut static_var_index = 16;
+    let mut variables: Vec<&str> = vec![];
     for assembly_instruction in assembly_instructions.iter() {
-        match assembly_instruction {
-            AssemblyInstruction::Instruction(instruction) => rom.push(*instruction),
-            AssemblyInstruction::Label(_) => {}
+        let identifiers = match assembly_instruction {
+            AssemblyInstruction::AtIdentifierInstruction(identifier) => vec![identifier.as_str()],
+            AssemblyInstruction::AtExpression(expression) => expression.symbols(),
+            _ => continue,
+        };
+        for identifier in identifiers {
+            if !at_identifier_map.contains_key(identifier) && !variables.contains(&identifier) {
+                variables.push(identifier);
+            }
+        }
+    }
+    if order == AllocationOrder::Alphabetical {
+        variables.sort_unstable();
+    }
+    for (static_var_index, variable) in (16..).zip(variables) {
+        at_identifier_map.insert(variable, static_var_index);
+    }
+
+    at_identifier_map
+}
+
+fn assemble_program(
+    assembly_instructions: &[AssemblyInstruction],
+    order: AllocationOrder,
+    origin: Word,
+) -> Result<Vec<Instruction>, String> {
+    let at_identifier_map = symbol_table(assembly_instructions, order, origin);
+
+    let mut instructions = vec![];
+    for assembly_instruction in assembly_instructions {
+        let instruction = match assembly_instruction {
+            AssemblyInstruction::Instruction(instruction) => *instruction,
+            AssemblyInstruction::Label(_)
+            | AssemblyInstruction::DataAddress(_)
+            | AssemblyInstruction::DataWords(_) => continue,
             AssemblyInstruction::AtIdentifierInstruction(identifier) => {
-                if !at_identifier_map.contains_key(identifier.as_str()) {
-                    at_identifier_map.insert(identifier.as_str(), static_var_index);
-                    static_var_index += 1;
+                Instruction::new(at_identifier_map[identifier.as_str()] as UWord)
+            }
+            AssemblyInstruction::AtNumberInstruction(value) => Instruction::new(*value as UWord),
+            AssemblyInstruction::AtExpression(expression) => {
+                match expression.evaluate(&at_identifier_map) {
+                    Some(value) if (0..=Word::MAX as i64).contains(&value) => {
+                        Instruction::new(value as UWord)
+                    }
+                    _ => {
+                        return Err(format!(
+                            "The expression at ROM[{}] doesn't fit in an A-instruction, the value \
+                             has to be from 0 to {}",
+                            origin as usize + instructions.len(),
+                            Word::MAX
+                        ))
+                    }
+                }
+            }
+        };
+        instructions.push(instruction);
+    }
+    Ok(instructions)
+}
+
+// The RAM values set by data directives, as address and value. Values before the first `.data`
+// go from RAM[0].
+pub fn data_words(assembly_instructions: &[AssemblyInstruction]) -> Vec<(Word, Word)> {
+    let mut words = vec![];
+    let mut address: Word = 0;
+    for assembly_instruction in assembly_instructions {
+        match assembly_instruction {
+            AssemblyInstruction::DataAddress(data_address) => address = *data_address,
+            AssemblyInstruction::DataWords(values) => {
+                for value in values {
+                    words.push((address, *value));
+                    address += 1;
                 }
-                rom.push(Instruction::new(
-                    at_identifier_map[identifier.as_str()] as UWord,
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramError {
+    pub line: usize,
+    pub message: String,
+    // The offending line as written.
+    pub text: String,
+}
+
+impl std::fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn rom_overflow_error((line, code): (usize, &str), instruction_count: usize) -> ProgramError {
+    ProgramError {
+        line,
+        message: format!(
+            "the program has {} instructions but the ROM only holds {}",
+            instruction_count, MEM_SIZE
+        ),
+        text: code.to_owned(),
+    }
+}
+
+// Everything that would keep the assembler from producing the whole program, so that nothing is
+// loaded instead of a truncated ROM.
+pub fn program_errors(input: &str) -> Vec<ProgramError> {
+    program_errors_at(input, AllocationOrder::FirstAppearance, 0)
+}
+
+// Expressions use labels and variables, so they're checked with the order and origin the program
+// is assembled with.
+pub fn program_errors_at(input: &str, order: AllocationOrder, origin: Word) -> Vec<ProgramError> {
+    let expansion = match expand_macros(input) {
+        Ok(expansion) => expansion,
+        Err(error) => return vec![error],
+    };
+    let mut errors = expanded_program_errors(&expansion.text, order, origin);
+    for error in &mut errors {
+        error.line = expansion.source_line(error.line);
+    }
+    errors
+}
+
+fn expanded_program_errors(input: &str, order: AllocationOrder, origin: Word) -> Vec<ProgramError> {
+    let mut errors = vec![];
+    let mut labels = hashbrown::HashSet::new();
+    let mut instruction_lines = vec![];
+    for (line, code) in code_lines(input) {
+        let mut report = |message: String| {
+            errors.push(ProgramError {
+                line,
+                message,
+                text: code.to_owned(),
+            })
+        };
+        let number = code
+            .strip_prefix('@')
+            .map(str::trim)
+            .filter(|value| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()));
+        if let Some(number) = number {
+            if number.parse::<Word>().is_err() {
+                report(format!(
+                    "@{} doesn't fit in an A-instruction, the largest value is {}",
+                    number,
+                    Word::MAX
                 ));
             }
-            AssemblyInstruction::AtNumberInstruction(value) => {
-                rom.push(Instruction::new(*value as UWord))
+        } else if !matches!(parse_instructions(code), Ok(("", _))) {
+            report(format!("{} isn't a valid instruction", code));
+        } else if let Some(label) = code.strip_prefix('(') {
+            let label = label.trim_end_matches(')').trim();
+            if !labels.insert(label) {
+                report(format!("the label {} is defined more than once", label));
             }
+            continue;
+        } else if !is_rom_line(code) {
+            continue;
         }
+        instruction_lines.push((line, code));
+    }
+    if let Some(&line) = instruction_lines.get(MEM_SIZE) {
+        errors.push(rom_overflow_error(line, instruction_lines.len()));
+    }
+    if !errors.is_empty() {
+        return errors;
     }
 
-    rom
+    let (_, assembly_instructions) = parse_instructions(input).unwrap();
+    let mut symbols = None;
+    let mut data_address = 0;
+    for (assembly_instruction, (line, code)) in assembly_instructions.iter().zip(code_lines(input))
+    {
+        match assembly_instruction {
+            AssemblyInstruction::DataAddress(address) => data_address = *address as i64,
+            AssemblyInstruction::DataWords(values) => {
+                let end = data_address + values.len() as i64;
+                if data_address < 0 || end > MEM_SIZE as i64 {
+                    errors.push(ProgramError {
+                        line,
+                        message: format!("{} writes outside the RAM", code),
+                        text: code.to_owned(),
+                    });
+                }
+                data_address = end;
+            }
+            AssemblyInstruction::AtExpression(expression) => {
+                symbols.get_or_insert_with(|| symbol_table(&assembly_instructions, order, origin));
+                let value = expression.evaluate(symbols.as_ref().unwrap());
+                if !value.is_some_and(|value| (0..=Word::MAX as i64).contains(&value)) {
+                    errors.push(ProgramError {
+                        line,
+                        message: format!(
+                            "{} doesn't fit in an A-instruction, the value has to be from 0 to {}",
+                            code,
+                            Word::MAX
+                        ),
+                        text: code.to_owned(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    errors
+}
+
+// The official assembler writes one 16 character binary instruction per line, blank lines are
+// skipped. Lines as wide as the word are taken as they are, for 32 bit programs.
+pub fn parse_hack_binary(input: &str) -> Result<Vec<Instruction>, Vec<ProgramError>> {
+    let mut instructions = vec![];
+    let mut errors = vec![];
+    let mut overflow = None;
+    for (index, code) in input.lines().enumerate() {
+        let code = code.trim();
+        if code.is_empty() {
+            continue;
+        }
+        let binary = code.bytes().all(|digit| digit == b'0' || digit == b'1');
+        match code.len() {
+            16 if binary => instructions.push(Instruction::from_legacy(
+                u16::from_str_radix(code, 2).unwrap(),
+            )),
+            width if binary && width == UWord::BITS as usize => {
+                instructions.push(Instruction::new(UWord::from_str_radix(code, 2).unwrap()))
+            }
+            _ => errors.push(ProgramError {
+                line: index + 1,
+                message: format!("{} isn't a binary instruction", code),
+                text: code.to_owned(),
+            }),
+        }
+        if instructions.len() == MEM_SIZE + 1 && overflow.is_none() {
+            overflow = Some((index + 1, code));
+        }
+    }
+    if let Some(line) = overflow {
+        errors.push(rom_overflow_error(line, instructions.len()));
+    }
+
+    if errors.is_empty() {
+        Ok(instructions)
+    } else {
+        Err(errors)
+    }
+}
+
+// The inverse of `parse_hack_binary`, instructions that don't fit in 16 bits are written with
+// all of the word's bits.
+pub fn hack_binary(program: &[Instruction]) -> String {
+    program
+        .iter()
+        .map(|instruction| match instruction.to_legacy() {
+            Some(legacy) => format!("{:016b}\n", legacy),
+            None => format!(
+                "{:0width$b}\n",
+                instruction.raw(),
+                width = UWord::BITS as usize
+            ),
+        })
+        .collect()
+}
+
+pub fn hack_program_errors(input: &str) -> Vec<ProgramError> {
+    parse_hack_binary(input).err().unwrap_or_default()
+}
+
+// A program loaded into part of the ROM, alongside other fragments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomFragment {
+    pub name: String,
+    pub origin: usize,
+    pub instructions: Vec<Instruction>,
+    pub data: Vec<(Word, Word)>,
+}
+
+impl RomFragment {
+    // An `// origin:` header places an .asm file, otherwise it goes at `default_origin`.
+    pub fn from_file(
+        name: &str,
+        contents: &str,
+        default_origin: usize,
+    ) -> Result<Self, Vec<ProgramError>> {
+        let (origin, instructions, data) = if name.to_lowercase().ends_with(".hack") {
+            (default_origin, parse_hack_binary(contents)?, vec![])
+        } else {
+            let origin = ProgramMetadata::from_file_contents(contents)
+                .origin
+                .unwrap_or(default_origin);
+            let errors =
+                program_errors_at(contents, AllocationOrder::FirstAppearance, origin as Word);
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+            let expansion = expand_macros(contents).unwrap();
+            let (_, assembly_instructions) = parse_instructions(&expansion.text).unwrap();
+            let instructions =
+                assemble_at(&assembly_instructions, origin as Word).map_err(|message| {
+                    vec![ProgramError {
+                        line: 1,
+                        message,
+                        text: String::new(),
+                    }]
+                })?;
+            (origin, instructions, data_words(&assembly_instructions))
+        };
+        Ok(RomFragment {
+            name: name.to_owned(),
+            origin,
+            instructions,
+            data,
+        })
+    }
+
+    pub fn end(&self) -> usize {
+        self.origin + self.instructions.len()
+    }
 }
 
 #[cfg(test)]
@@ -390,6 +1405,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_constant_expressions() {
+        let program = "@(SCREEN+32)\n@( 2 * 256 - -1 )\n@(END*2-(R2+1))\n(END)\n@(x+1)\n";
+        let (_, instructions) = parse_instructions(program).unwrap();
+        assert_eq!(
+            assemble(&instructions)
+                .unwrap()
+                .iter()
+                .map(|instruction| instruction.raw())
+                .collect::<Vec<_>>(),
+            [RAM::SCREEN as UWord + 32, 513, 3, 17]
+        );
+        assert_eq!(
+            program_errors("@(0-1)\n@(SCREEN*SCREEN*SCREEN)\n")
+                .into_iter()
+                .map(|error| error.line)
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn test_translator_symbols() {
+        let program = "@Main.main$ret.1\n0;JMP\n(Main.main$ret.1)\n@Sys:x\nM=0\n";
+        let (_, instructions) = parse_instructions(program).unwrap();
+
+        assert_eq!(
+            assemble(&instructions)
+                .unwrap()
+                .iter()
+                .map(|instruction| instruction.to_string())
+                .collect::<Vec<_>>(),
+            vec!["@2", "0;JMP", "@16", "M=0"]
+        );
+    }
+
     #[test]
     fn test_at_label() {
         assert_eq!(
@@ -432,4 +1483,243 @@ mod tests {
         assert_eq!(compare_no_whitespace("a b   c", "abd"), Error);
         assert_eq!(compare_no_whitespace("  ", "def"), Incomplete);
     }
+
+    #[test]
+    fn test_instruction_line_numbers() {
+        let program = "// comment\n@2\n\n(LOOP)\n  D=A // inline\n0;JMP\n";
+        assert_eq!(instruction_line_numbers(program), vec![2, 5, 6]);
+    }
+
+    #[test]
+    fn test_expand_includes() {
+        let files: HashMap<&str, &str> = HashMap::from_iter([
+            ("Math.asm", "#include \"Consts.asm\"\n(DOUBLE)\nD=D+A\n"),
+            ("Consts.asm", "@TWO\n"),
+            ("Loop.asm", "#include \"Loop.asm\"\n"),
+            ("Bad.asm", "\nD=Q\n"),
+        ]);
+        let mut read = |path: &str| {
+            files
+                .get(path)
+                .map(|contents| contents.to_string())
+                .ok_or_else(|| format!("{} not found", path))
+        };
+        let expansion = expand_includes(
+            "Main.asm",
+            "@DOUBLE\n#include \"Math.asm\"\n0;JMP\n",
+            &mut read,
+        )
+        .unwrap();
+        assert_eq!(
+            expansion.text,
+            "@DOUBLE\n\n\n@TWO\n(DOUBLE)\nD=D+A\n0;JMP\n"
+        );
+        assert_eq!(expansion.top_lines, vec![1, 2, 2, 2, 2, 2, 3]);
+        assert_eq!(expansion.sources[3], ("Consts.asm".to_owned(), 1));
+        // Labels in included files are seen from the top file.
+        assert!(program_errors(&expansion.text).is_empty());
+
+        let error =
+            expand_includes("Main.asm", "\n#include \"Loop.asm\"\n", &mut read).unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(
+            error.message,
+            "Loop.asm, line 1: Loop.asm is already being included"
+        );
+        assert_eq!(
+            expand_includes("Main.asm", "#include Math.asm\n", &mut read)
+                .unwrap_err()
+                .message,
+            "#include takes a file name in quotes"
+        );
+
+        let expansion = expand_includes("Main.asm", "#include \"Bad.asm\"\n", &mut read).unwrap();
+        let errors: Vec<_> = program_errors(&expansion.text)
+            .into_iter()
+            .map(|error| expansion.top_error(error))
+            .collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 1);
+        assert!(errors[0].message.starts_with("Bad.asm, line 2: "));
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let program = ".macro push value\n@value\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n.endmacro\n\
+                       .macro goto target\n@target\n0;JMP\n.endmacro\n\
+                       .macro twice a, b\npush a\npush b\n.endmacro\n\
+                       (LOOP)\ntwice 7, 8 // two pushes\ngoto LOOP\n";
+        let expansion = expand_macros(program).unwrap();
+        let push = |value| format!("@{}\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n", value);
+        let expected = format!("(LOOP)\n{}{}@LOOP\n0;JMP\n", push(7), push(8));
+        assert_eq!(
+            assemble(&parse_instructions(&expansion.text).unwrap().1),
+            assemble(&parse_instructions(&expected).unwrap().1)
+        );
+        assert!(expansion.text.contains("@7 // two pushes\n"));
+
+        let mut lines = vec![18; 12];
+        lines.extend([19, 19]);
+        assert_eq!(instruction_line_numbers(program), lines);
+        assert_eq!(
+            program_errors(&format!("{}twice 1\nD=Q\n", program))
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>(),
+            ["line 20: the macro takes 2 arguments but got 1"]
+        );
+        assert_eq!(
+            expand_macros(".macro loop\nloop\n.endmacro\nloop\n")
+                .unwrap_err()
+                .message,
+            "macros are nested more than 16 deep"
+        );
+        assert!(expand_macros(".macro push value\n@value\n").is_err());
+        assert!(expand_macros(".endmacro\n").is_err());
+    }
+
+    #[test]
+    fn test_strict_source() {
+        let source = "( LOOP ) // start\n\n  A M = M + 1\n@ LOOP\nD;JGT\n";
+        assert_eq!(
+            AssemblerMode::Strict.source(source).unwrap(),
+            "(LOOP) // start\n\nAM=M+1\n@LOOP\nD;JGT\n"
+        );
+        assert_eq!(AssemblerMode::Lenient.source(source).unwrap(), source);
+
+        let messages: Vec<_> =
+            strict_source("@32768\nD=A+D\nMA=D\n(LOOP)\n(LOOP)\n(SCREEN)\n@1x\n.data 16\n@32767\n")
+                .unwrap_err()
+                .into_iter()
+                .map(|error| error.to_string())
+                .collect();
+        assert_eq!(
+            messages,
+            [
+                "line 1: @32768 is out of range, constants go from 0 to 32767",
+                "line 2: D=A+D isn't a valid instruction",
+                "line 3: MA=D isn't a valid instruction",
+                "line 5: the label LOOP is defined more than once",
+                "line 6: the label SCREEN is defined more than once",
+                "line 7: @1x isn't a valid symbol",
+                "line 8: .data 16 isn't part of the official assembly language",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_program_errors() {
+        let too_long = "D=0\n".repeat(MEM_SIZE) + "(END)\n@END\n";
+        let messages = |errors: Vec<ProgramError>| {
+            errors
+                .into_iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            messages(program_errors(
+                "@99999999999\n(LOOP)\nD=Q\n(LOOP)\n@LOOP\n0;JMP\n"
+            )),
+            vec![
+                format!(
+                    "line 1: @99999999999 doesn't fit in an A-instruction, the largest value is {}",
+                    Word::MAX
+                ),
+                "line 3: D=Q isn't a valid instruction".to_owned(),
+                "line 4: the label LOOP is defined more than once".to_owned(),
+            ]
+        );
+        assert_eq!(
+            program_errors("@1\n  D=Q // typo\n")[0],
+            ProgramError {
+                line: 2,
+                message: "D=Q isn't a valid instruction".to_owned(),
+                text: "D=Q".to_owned(),
+            }
+        );
+        assert_eq!(
+            messages(program_errors(&too_long)),
+            vec!["line 32770: the program has 32769 instructions but the ROM only holds 32768"]
+        );
+        assert_eq!(
+            messages(hack_program_errors("0000000000000001\n2\n")),
+            vec!["line 2: 2 isn't a binary instruction"]
+        );
+    }
+
+    #[test]
+    fn test_rom_fragment() {
+        let boot = RomFragment::from_file("Boot.asm", "@100\n0;JMP\n", 0).unwrap();
+        let main = RomFragment::from_file("Main.asm", "// origin: 100\n(MAIN)\n@MAIN\n0;JMP\n", 2)
+            .unwrap();
+        let data = RomFragment::from_file("Data.hack", "0000000000000111\n", main.end()).unwrap();
+
+        assert_eq!(boot.end(), 2);
+        assert_eq!(main.origin, 100);
+        assert_eq!(main.instructions[0].loaded_value(), 100);
+        assert_eq!(data.origin, 102);
+
+        // LOOP is 1000 here, so the expression only overflows with the real origin.
+        let program = format!("// origin: 1000\n(LOOP)\n@(LOOP+{})\n", Word::MAX - 999);
+        assert!(RomFragment::from_file("Loop.asm", &program, 0).is_err());
+        let overflow = format!("@({}+1)\n", Word::MAX);
+        assert!(assemble(&parse_instructions(&overflow).unwrap().1).is_err());
+    }
+
+    #[test]
+    fn test_data_words() {
+        let program = ".word 3\n@0\n.data 1024\n(START)\n.word 1, -2 ,3\nD=M\n";
+        let (_, instructions) = parse_instructions(program).unwrap();
+
+        assert_eq!(
+            data_words(&instructions),
+            vec![(0, 3), (1024, 1), (1025, -2), (1026, 3)]
+        );
+        assert_eq!(
+            instruction_labels(&instructions),
+            vec![("START".to_owned(), 1)]
+        );
+        assert_eq!(instruction_line_numbers(program), vec![2, 6]);
+        assert_eq!(
+            program_errors(".data 32767\n.word 1, 2\n")[0].to_string(),
+            "line 2: .word 1, 2 writes outside the RAM"
+        );
+    }
+
+    #[test]
+    fn test_allocation_order() {
+        let (_, instructions) = parse_instructions("@sum\n@i\n@END\n(END)\n@i\n").unwrap();
+        let addresses = |order| {
+            assemble_with_order(&instructions, order)
+                .unwrap()
+                .iter()
+                .map(|instruction| instruction.loaded_value())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            addresses(AllocationOrder::FirstAppearance),
+            vec![16, 17, 3, 17]
+        );
+        assert_eq!(
+            addresses(AllocationOrder::Alphabetical),
+            vec![17, 16, 3, 16]
+        );
+    }
+
+    #[test]
+    fn test_label_writes() {
+        let program = "(LOOP)\n@LOOP\nM=M+1\n@LOOP\n(SKIP)\nM=0\n@i\nM=1\n@LOOP\n0;JMP\n";
+        let (_, instructions) = parse_instructions(program).unwrap();
+
+        assert_eq!(
+            label_writes(&instructions),
+            vec![LabelWrite {
+                address: 1,
+                label: "LOOP".to_owned(),
+                label_address: 0,
+            }]
+        );
+    }
 }
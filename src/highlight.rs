@@ -0,0 +1,123 @@
+use std::ops::Range;
+
+use crate::parse_utils::strip_comment;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsmToken {
+    Comment,
+    Label,
+    Directive,
+    Symbol,
+    Number,
+    Destination,
+    Computation,
+    Jump,
+}
+
+fn push_trimmed(
+    tokens: &mut Vec<(Range<usize>, AsmToken)>,
+    line: &str,
+    range: Range<usize>,
+    token: AsmToken,
+) {
+    let text = &line[range.clone()];
+    let start = range.start + (text.len() - text.trim_start().len());
+    let end = range.end - (text.len() - text.trim_end().len());
+    if start < end {
+        tokens.push((start..end, token));
+    }
+}
+
+fn highlight_line(line: &str) -> Vec<(Range<usize>, AsmToken)> {
+    let code = strip_comment(line).map_or(line, |(_, code)| code);
+    let mut tokens = vec![];
+    let trimmed = code.trim_start();
+    let start = code.len() - trimmed.len();
+    let trimmed = trimmed.trim_end();
+    let end = start + trimmed.len();
+    if trimmed.starts_with('(') {
+        tokens.push((start..end, AsmToken::Label));
+    } else if trimmed.starts_with('.') {
+        let directive_end = trimmed
+            .find(char::is_whitespace)
+            .map_or(end, |index| start + index);
+        tokens.push((start..directive_end, AsmToken::Directive));
+        push_trimmed(&mut tokens, line, directive_end..end, AsmToken::Number);
+    } else if let Some(value) = trimmed.strip_prefix('@') {
+        let token = if value.trim().starts_with(|c: char| c.is_ascii_digit()) {
+            AsmToken::Number
+        } else {
+            AsmToken::Symbol
+        };
+        tokens.push((start..end, token));
+    } else if !trimmed.is_empty() {
+        let computation_start = trimmed.find('=').map_or(start, |index| {
+            push_trimmed(
+                &mut tokens,
+                line,
+                start..start + index,
+                AsmToken::Destination,
+            );
+            start + index + 1
+        });
+        let computation_end = trimmed.find(';').map_or(end, |index| start + index);
+        push_trimmed(
+            &mut tokens,
+            line,
+            computation_start..computation_end.max(computation_start),
+            AsmToken::Computation,
+        );
+        if computation_end < end {
+            push_trimmed(&mut tokens, line, computation_end + 1..end, AsmToken::Jump);
+        }
+    }
+    if code.len() < line.len() {
+        tokens.push((code.len()..line.len(), AsmToken::Comment));
+    }
+    tokens
+}
+
+// Byte ranges of the highlighted parts of Hack assembly, in order. Whatever's between them, like
+// whitespace and the `=` and `;` separators, isn't highlighted.
+pub fn highlight_asm(input: &str) -> Vec<(Range<usize>, AsmToken)> {
+    let mut tokens = vec![];
+    let mut line_start = 0;
+    for line in input.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        tokens.extend(
+            highlight_line(content)
+                .into_iter()
+                .map(|(range, token)| (line_start + range.start..line_start + range.end, token)),
+        );
+        line_start += line.len();
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_asm() {
+        let input = "(LOOP) // start\n  @i\n@16\nAM = M+1 ; JGT\n.data 1, 2\n";
+        let tokens: Vec<_> = highlight_asm(input)
+            .into_iter()
+            .map(|(range, token)| (&input[range], token))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                ("(LOOP)", AsmToken::Label),
+                ("// start", AsmToken::Comment),
+                ("@i", AsmToken::Symbol),
+                ("@16", AsmToken::Number),
+                ("AM", AsmToken::Destination),
+                ("M+1", AsmToken::Computation),
+                ("JGT", AsmToken::Jump),
+                (".data", AsmToken::Directive),
+                ("1, 2", AsmToken::Number),
+            ]
+        );
+    }
+}
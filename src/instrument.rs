@@ -0,0 +1,185 @@
+use hashbrown::HashMap;
+
+use crate::hardware::{Word, RAM};
+use crate::vm::VMCommand;
+use crate::vm_parse::{command_line_numbers, parse_commands};
+
+// A counter the instrumented program adds to every time a function is entered or, with a label,
+// every time a loop goes around.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counter {
+    pub function_name: String,
+    pub label: Option<String>,
+    pub address: Word,
+}
+
+impl Counter {
+    pub fn name(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{} loop {}", self.function_name, label),
+            None => self.function_name.clone(),
+        }
+    }
+
+    // Counts wrap around past 65535.
+    pub fn value(&self, ram: &RAM) -> u16 {
+        ram[self.address] as u16
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instrumentation {
+    pub files: Vec<(String, String)>,
+    pub counters: Vec<Counter>,
+}
+
+// Counters take the top of the heap, so they stay put when the program runs elsewhere, and only
+// meet the heap's allocations once it's nearly full.
+pub fn counters_start(counter_count: usize) -> Word {
+    RAM::SCREEN - counter_count as Word
+}
+
+// Adds a counter after every function and every label that's jumped back to, the code bumping it
+// leaves THAT and the stack as they were. Files that don't parse are left alone.
+pub fn instrument(files: &[(String, String)]) -> Instrumentation {
+    let parsed: Vec<_> = files
+        .iter()
+        .map(|(_, contents)| {
+            parse_commands(contents)
+                .ok()
+                .map(|(_, commands)| (commands, command_line_numbers(contents)))
+        })
+        .collect();
+
+    let mut counted_lines = vec![];
+    for (commands, line_numbers) in parsed.iter().flatten() {
+        let mut file_counters = vec![];
+        let mut function_name = "";
+        for (index, command) in commands.iter().enumerate() {
+            match command {
+                VMCommand::Function { name, .. } => {
+                    function_name = name;
+                    file_counters.push((line_numbers[index], name.clone(), None));
+                }
+                VMCommand::Label { name } if is_loop(&commands[index + 1..], name) => {
+                    file_counters.push((
+                        line_numbers[index],
+                        function_name.to_owned(),
+                        Some(name.clone()),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        counted_lines.push(file_counters);
+    }
+
+    let mut address = counters_start(counted_lines.iter().map(Vec::len).sum());
+    let mut counters = vec![];
+    let mut file_counters = counted_lines.into_iter();
+    let files = files
+        .iter()
+        .zip(&parsed)
+        .map(|((name, contents), parsed)| {
+            if parsed.is_none() {
+                return (name.clone(), contents.clone());
+            }
+            let mut additions = HashMap::new();
+            for (line_number, function_name, label) in file_counters.next().unwrap() {
+                additions.insert(line_number, address);
+                counters.push(Counter {
+                    function_name,
+                    label,
+                    address,
+                });
+                address += 1;
+            }
+            let mut instrumented = String::new();
+            for (index, line) in contents.split_inclusive('\n').enumerate() {
+                instrumented.push_str(line);
+                if let Some(address) = additions.get(&(index + 1)) {
+                    if !line.ends_with('\n') {
+                        instrumented.push('\n');
+                    }
+                    instrumented.push_str(&increment(*address));
+                }
+            }
+            (name.clone(), instrumented)
+        })
+        .collect();
+
+    Instrumentation { files, counters }
+}
+
+// Whether the rest of the label's function jumps back to it.
+fn is_loop(rest: &[VMCommand], label: &str) -> bool {
+    rest.iter()
+        .take_while(|command| !matches!(command, VMCommand::Function { .. }))
+        .any(|command| match command {
+            VMCommand::Goto { label_name } | VMCommand::IfGoto { label_name } => {
+                label_name == label
+            }
+            _ => false,
+        })
+}
+
+fn increment(address: Word) -> String {
+    format!(
+        "push pointer 1\npush constant {}\npop pointer 1\npush that 0\npush constant 1\nadd\n\
+         pop that 0\npop pointer 1\n",
+        address
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_instrument() {
+        let files = vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 0\npush constant 3\npop pointer 1\n\
+                 call Main.count 0\ncall Main.count 0\npush pointer 1\npop temp 0\n\
+                 push constant 0\nreturn"
+                    .to_owned(),
+            ),
+            (
+                "Main.vm".to_owned(),
+                "function Main.count 1 // counts down from 3\n\
+                 push constant 3\npop local 0\nlabel LOOP\npush local 0\npush constant 1\nsub\n\
+                 pop local 0\npush local 0\nif-goto LOOP\nlabel DONE\npush constant 0\nreturn\n"
+                    .to_owned(),
+            ),
+            ("Broken.vm".to_owned(), "push nothing\n".to_owned()),
+        ];
+        let instrumentation = instrument(&files);
+        assert_eq!(
+            instrumentation
+                .counters
+                .iter()
+                .map(|counter| (counter.name(), counter.address))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Sys.init".to_owned(), 16381),
+                ("Main.count".to_owned(), 16382),
+                ("Main.count loop LOOP".to_owned(), 16383),
+            ]
+        );
+        assert_eq!(instrumentation.files[2], files[2]);
+
+        let mut vm = VM::from_file_contents(instrumentation.files[..2].to_vec());
+        vm.run(1000);
+        assert!(vm.halted());
+        let counts: Vec<_> = instrumentation
+            .counters
+            .iter()
+            .map(|counter| counter.value(&vm.run_state.ram))
+            .collect();
+        assert_eq!(counts, vec![1, 2, 6]);
+        // THAT as Sys.init left it.
+        assert_eq!(vm.run_state.ram[5], 3);
+    }
+}
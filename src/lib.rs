@@ -1,10 +1,42 @@
+pub mod annotation;
+pub mod assertion;
 pub(crate) mod characters;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
+pub mod compressed_trace;
+#[cfg(feature = "cross-check")]
+pub mod cross_check;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod exam_archive;
+pub mod expression;
+pub mod format;
 pub mod hardware;
 pub mod hardware_parse;
+pub mod highlight;
+pub mod instrument;
+pub mod lint;
+pub mod machine;
+pub mod metadata;
 mod os;
 pub(crate) mod parse_utils;
+pub mod profile_export;
+pub mod project;
+pub mod provenance;
+pub mod recording;
+pub mod report;
+pub mod script;
+pub mod selftest;
+pub mod semantic_diff;
+pub mod session;
+pub mod source_map;
+pub mod stack_depth;
+pub mod test_script;
+pub mod thread_tuning;
+pub mod translator;
 pub mod vm;
 pub mod vm_parse;
+pub mod watch;
 
 #[cfg(feature = "emulator")]
 pub mod emulator;
@@ -0,0 +1,299 @@
+use hashbrown::{HashMap, HashSet};
+
+use crate::hardware::{InstructionType, JumpCondition, RAM};
+use crate::hardware_parse::{
+    expand_macros, parse_instructions, AssemblyInstruction, PREDEFINED_SYMBOLS,
+};
+use crate::parse_utils::code_lines;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintFinding {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// Programs that don't parse aren't linted, the assembler reports their errors.
+pub fn lint_asm(input: &str) -> Vec<LintFinding> {
+    let Ok(expansion) = expand_macros(input) else {
+        return vec![];
+    };
+    let mut findings = lint_expanded_asm(&expansion.text);
+    for finding in &mut findings {
+        finding.line = expansion.source_line(finding.line);
+    }
+    findings
+}
+
+fn lint_expanded_asm(input: &str) -> Vec<LintFinding> {
+    let Ok((_, assembly_instructions)) = parse_instructions(input) else {
+        return vec![];
+    };
+    let labels: HashSet<&str> = assembly_instructions
+        .iter()
+        .filter_map(|assembly_instruction| match assembly_instruction {
+            AssemblyInstruction::Label(label) => Some(label.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut findings = vec![];
+    let mut reported_variables = HashSet::new();
+    // Whether A holds the keyboard's address.
+    let mut keyboard_loaded = false;
+    // Variables named like a label in another case are only suspicious when jumped to, programs
+    // often have a `column` variable next to a `COLUMN` loop.
+    let mut jump_suspect = None;
+    for (assembly_instruction, (line, _)) in assembly_instructions.iter().zip(code_lines(input)) {
+        if let (Some((line, name, label)), AssemblyInstruction::Instruction(instruction)) =
+            (jump_suspect.take(), assembly_instruction)
+        {
+            if instruction.jump_condition() != JumpCondition::NoJump
+                && reported_variables.insert(name)
+            {
+                findings.push(LintFinding {
+                    line,
+                    message: format!(
+                        "@{} jumps to a new variable, did you mean the label {}?",
+                        name, label
+                    ),
+                });
+            }
+        }
+        let mut report = |message: String| findings.push(LintFinding { line, message });
+        match assembly_instruction {
+            AssemblyInstruction::Label(_) => keyboard_loaded = false,
+            AssemblyInstruction::DataAddress(_) | AssemblyInstruction::DataWords(_) => {}
+            AssemblyInstruction::AtIdentifierInstruction(name) => {
+                keyboard_loaded = name == "KBD";
+                if labels.contains(name.as_str())
+                    || PREDEFINED_SYMBOLS.iter().any(|(symbol, _)| symbol == name)
+                {
+                    continue;
+                }
+                if let Some((symbol, _)) = PREDEFINED_SYMBOLS
+                    .iter()
+                    .find(|(symbol, _)| symbol.eq_ignore_ascii_case(name))
+                {
+                    if reported_variables.insert(name) {
+                        report(format!(
+                            "@{} allocates a new variable, did you mean {}?",
+                            name, symbol
+                        ));
+                    }
+                } else if let Some(label) =
+                    labels.iter().find(|label| label.eq_ignore_ascii_case(name))
+                {
+                    jump_suspect = Some((line, name, *label));
+                }
+            }
+            AssemblyInstruction::AtNumberInstruction(value) => keyboard_loaded = *value == RAM::KBD,
+            AssemblyInstruction::AtExpression(_) => keyboard_loaded = false,
+            AssemblyInstruction::Instruction(instruction) => {
+                if instruction.instruction_type() != InstructionType::C {
+                    continue;
+                }
+                let jumps = instruction.jump_condition() != JumpCondition::NoJump;
+                if !jumps
+                    && !instruction.dst_has_a()
+                    && !instruction.dst_has_d()
+                    && !instruction.dst_has_m()
+                {
+                    report(format!(
+                        "{} has no destination and no jump, so it does nothing",
+                        instruction
+                    ));
+                }
+                if jumps && instruction.dst_has_a() {
+                    report(format!(
+                        "{} writes A and jumps, the jump goes to the address A had before",
+                        instruction
+                    ));
+                } else if jumps && (instruction.reads_m() || instruction.dst_has_m()) {
+                    report(format!(
+                        "{} uses A both as the address of M and as the jump target",
+                        instruction
+                    ));
+                }
+                if keyboard_loaded && instruction.dst_has_m() {
+                    report(format!(
+                        "{} writes to the keyboard register, which is read only",
+                        instruction
+                    ));
+                }
+                keyboard_loaded &= !instruction.dst_has_a();
+            }
+        }
+    }
+
+    findings
+}
+
+// The values a command pops and pushes, for commands that only touch the working stack.
+fn stack_effect(words: &[&str]) -> Option<(usize, usize)> {
+    match words {
+        ["push", ..] => Some((0, 1)),
+        ["pop", ..] | ["if-goto", ..] => Some((1, 0)),
+        ["add" | "sub" | "eq" | "gt" | "lt" | "and" | "or"] => Some((2, 1)),
+        ["neg" | "not"] => Some((1, 1)),
+        ["call", _, argument_count] => Some((argument_count.parse().ok()?, 1)),
+        ["return"] => Some((1, 0)),
+        _ => None,
+    }
+}
+
+// Compiled Jack often ends a function with an unreachable label after both branches of an if
+// return, so only reachable commands count.
+fn falls_through(commands: &[Vec<&str>]) -> bool {
+    let labels: HashMap<&str, usize> = commands
+        .iter()
+        .enumerate()
+        .filter_map(|(index, words)| match words[..] {
+            ["label", name] => Some((name, index)),
+            _ => None,
+        })
+        .collect();
+    let mut reachable = vec![false; commands.len()];
+    let mut pending = vec![0];
+    let mut falls_through = false;
+    while let Some(index) = pending.pop() {
+        if index >= commands.len() {
+            falls_through = true;
+            continue;
+        }
+        if std::mem::replace(&mut reachable[index], true) {
+            continue;
+        }
+        match commands[index][..] {
+            ["return"] => {}
+            ["goto", label] => pending.extend(labels.get(label)),
+            ["if-goto", label] => {
+                pending.extend(labels.get(label));
+                pending.push(index + 1);
+            }
+            _ => pending.push(index + 1),
+        }
+    }
+    falls_through
+}
+
+fn fall_through_finding(
+    function: Option<(&str, usize)>,
+    commands: &[Vec<&str>],
+    place: &str,
+) -> Option<LintFinding> {
+    let (name, line) = function?;
+    falls_through(commands).then(|| LintFinding {
+        line,
+        message: format!("{} {} without a return", name, place),
+    })
+}
+
+// Works on the text rather than the parsed commands, so that it can point out mistakes that
+// don't parse like popping into constant. The stack is only followed from the start of each
+// function up to the first label, anything after one can be reached with a different stack.
+pub fn lint_vm(input: &str) -> Vec<LintFinding> {
+    let mut findings = vec![];
+    // The current function's name and the line it starts on.
+    let mut function: Option<(&str, usize)> = None;
+    let mut function_commands = vec![];
+    let mut height = None;
+    for (line, code) in code_lines(input) {
+        let words: Vec<&str> = code.split_whitespace().collect();
+        if let ["function", name, ..] = words[..] {
+            findings.extend(fall_through_finding(
+                function,
+                &function_commands,
+                "falls through to the next function",
+            ));
+            function_commands.clear();
+            function = Some((name, line));
+            height = Some(0);
+        } else if let ["pop", "constant", ..] = words[..] {
+            findings.push(LintFinding {
+                line,
+                message: format!("{} pops into constant, which isn't a memory segment", code),
+            });
+        }
+
+        if let (Some((pops, pushes)), Some(current)) = (stack_effect(&words), height) {
+            if current < pops {
+                let function_name = function.map_or("", |(name, _)| name);
+                findings.push(LintFinding {
+                    line,
+                    message: format!(
+                        "{} pops {} but {} has only pushed {} by then",
+                        code, pops, function_name, current
+                    ),
+                });
+                height = None;
+            } else {
+                height = Some(current - pops + pushes);
+            }
+        }
+        if matches!(words[0], "label" | "goto" | "return") {
+            height = None;
+        }
+        function_commands.push(words);
+    }
+    findings.extend(fall_through_finding(
+        function,
+        &function_commands,
+        "reaches the end of the file",
+    ));
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint_asm() {
+        let program = "(LOOP)\n@loop\n0;JMP\n@sp\nD+1\n@KBD\nM=0\nAM=M-1\nD;JGT\n@LOOP\nM=D;JGT\n\
+                       A=M;JMP\n@i\nM=1\n@Loop\nM=1\n";
+        let messages: Vec<_> = lint_asm(program)
+            .into_iter()
+            .map(|finding| finding.to_string())
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "line 2: @loop jumps to a new variable, did you mean the label LOOP?",
+                "line 4: @sp allocates a new variable, did you mean SP?",
+                "line 5: D+1 has no destination and no jump, so it does nothing",
+                "line 7: M=0 writes to the keyboard register, which is read only",
+                "line 8: AM=M-1 writes to the keyboard register, which is read only",
+                "line 11: M=D;JGT uses A both as the address of M and as the jump target",
+                "line 12: A=M;JMP writes A and jumps, the jump goes to the address A had before",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_vm() {
+        let program = "push constant 1\nfunction Main.f 0\npush constant 1\nadd\nreturn\n\
+                       function Main.g 1\npush constant 2\npop constant 0\nlabel L\nadd\ngoto L\n\
+                       function Main.h 0\ncall Main.g 1\n";
+        let messages: Vec<_> = lint_vm(program)
+            .into_iter()
+            .map(|finding| finding.to_string())
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "line 4: add pops 2 but Main.f has only pushed 1 by then",
+                "line 8: pop constant 0 pops into constant, which isn't a memory segment",
+                "line 13: call Main.g 1 pops 1 but Main.h has only pushed 0 by then",
+                "line 12: Main.h reaches the end of the file without a return",
+            ]
+        );
+    }
+}
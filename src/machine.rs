@@ -0,0 +1,240 @@
+use crate::hardware::{self, Address, Hardware, Word, RAM};
+use crate::vm::{self, VM};
+use crate::vm_parse::parse_commands;
+
+// Either emulator behind one interface, for tools that don't care which kind of program they
+// were given.
+pub enum Machine {
+    Hardware(Box<Hardware>),
+    VM(Box<VM>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    Hardware(hardware::Breakpoint),
+    VM(vm::Breakpoint),
+}
+
+impl Machine {
+    pub fn from_asm(name: &str, contents: &str) -> Result<Self, String> {
+        Hardware::try_from_file_contents(contents)
+            .map(|hardware| Machine::Hardware(Box::new(hardware)))
+            .map_err(|errors| format!("Failed to assemble {}, {}", name, errors[0]))
+    }
+
+    pub fn from_hack(name: &str, contents: &str) -> Result<Self, String> {
+        Hardware::from_hack_binary(contents)
+            .map(|hardware| Machine::Hardware(Box::new(hardware)))
+            .map_err(|errors| format!("{} isn't a valid .hack file, {}", name, errors[0]))
+    }
+
+    pub fn from_vm_files(files: &[(String, String)]) -> Result<Self, String> {
+        if files.is_empty() {
+            return Err("There are no .vm files to load".to_owned());
+        }
+        let all_file_commands = files
+            .iter()
+            .map(|(file_name, contents)| match parse_commands(contents) {
+                Ok((_, commands)) => Ok((
+                    file_name
+                        .rsplit_once('.')
+                        .map_or(file_name.as_str(), |(stem, _)| stem)
+                        .to_owned(),
+                    commands,
+                )),
+                Err(_) => Err(format!("Failed to parse {}", file_name)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Machine::VM(Box::new(VM::from_all_file_commands(
+            all_file_commands,
+        ))))
+    }
+
+    // A single .asm or .hack file runs on the hardware, anything else has to be .vm files.
+    pub fn from_file_contents(files: &[(String, String)]) -> Result<Self, String> {
+        let extension = |name: &str| {
+            name.rsplit_once('.')
+                .map(|(_, extension)| extension.to_lowercase())
+        };
+        match files {
+            [(name, contents)] if extension(name).as_deref() == Some("asm") => {
+                Self::from_asm(name, contents)
+            }
+            [(name, contents)] if extension(name).as_deref() == Some("hack") => {
+                Self::from_hack(name, contents)
+            }
+            _ => match files
+                .iter()
+                .find(|(name, _)| extension(name).as_deref() != Some("vm"))
+            {
+                Some((name, _)) => Err(format!("{} can't be loaded with other files", name)),
+                None => Self::from_vm_files(files),
+            },
+        }
+    }
+
+    pub fn ram(&self) -> &RAM {
+        match self {
+            Machine::Hardware(hardware) => &hardware.ram,
+            Machine::VM(vm) => &vm.run_state.ram,
+        }
+    }
+
+    pub fn ram_mut(&mut self) -> &mut RAM {
+        match self {
+            Machine::Hardware(hardware) => &mut hardware.ram,
+            Machine::VM(vm) => &mut vm.run_state.ram,
+        }
+    }
+
+    pub fn peek(&self, address: Address) -> Word {
+        self.ram()[address]
+    }
+
+    pub fn poke(&mut self, address: Address, value: Word) {
+        self.ram_mut()[address] = value;
+    }
+
+    pub fn screen(&self) -> &[Word] {
+        self.ram().slice(RAM::SCREEN..RAM::KBD)
+    }
+
+    pub fn ticks(&self) -> u64 {
+        match self {
+            Machine::Hardware(hardware) => hardware.ticks,
+            Machine::VM(vm) => vm.run_state.ticks,
+        }
+    }
+
+    pub fn halted(&self) -> bool {
+        match self {
+            Machine::Hardware(hardware) => hardware.halted(),
+            Machine::VM(vm) => vm.halted(),
+        }
+    }
+
+    // Why the OS call the VM is stuck on failed.
+    pub fn fault(&self) -> Option<&str> {
+        match self {
+            Machine::Hardware(_) => None,
+            Machine::VM(vm) => vm.run_state.os_fault.as_deref(),
+        }
+    }
+
+    // Returns the index of the breakpoint hit, if any.
+    pub fn step(&mut self) -> Option<usize> {
+        match self {
+            Machine::Hardware(hardware) => {
+                hardware.step_with_events().map(|event| event.breakpoint)
+            }
+            Machine::VM(vm) => vm.step(),
+        }
+    }
+
+    pub fn run(&mut self, step_count: u64) -> Option<usize> {
+        match self {
+            Machine::Hardware(hardware) => hardware.run(step_count).map(|event| event.breakpoint),
+            Machine::VM(vm) => vm.run(step_count),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        match self {
+            Machine::Hardware(hardware) => hardware::Emulator::reset(hardware.as_mut()),
+            Machine::VM(vm) => vm.reset(),
+        }
+    }
+
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        match self {
+            Machine::Hardware(hardware) => hardware
+                .get_breakpoints()
+                .iter()
+                .cloned()
+                .map(Breakpoint::Hardware)
+                .collect(),
+            Machine::VM(vm) => vm
+                .get_breakpoints()
+                .iter()
+                .cloned()
+                .map(Breakpoint::VM)
+                .collect(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: &Breakpoint) -> Result<(), String> {
+        match (self, breakpoint) {
+            (Machine::Hardware(hardware), Breakpoint::Hardware(breakpoint)) => {
+                hardware.add_breakpoint(breakpoint)
+            }
+            (Machine::VM(vm), Breakpoint::VM(breakpoint)) => vm.add_breakpoint(breakpoint),
+            (Machine::Hardware(_), Breakpoint::VM(_)) => {
+                return Err("VM breakpoints can't be set on the hardware".to_owned())
+            }
+            (Machine::VM(_), Breakpoint::Hardware(_)) => {
+                return Err("Hardware breakpoints can't be set on the VM".to_owned())
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) {
+        match self {
+            Machine::Hardware(hardware) => hardware.remove_breakpoint(index),
+            Machine::VM(vm) => vm.remove_breakpoint(index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::BreakpointVar;
+
+    #[test]
+    fn test_machine() {
+        let files = |name: &str, contents: &str| vec![(name.to_owned(), contents.to_owned())];
+        let mut hardware =
+            Machine::from_file_contents(&files("Add.asm", "@5\nD=A\n@0\nM=D\n")).unwrap();
+        hardware
+            .add_breakpoint(&Breakpoint::Hardware(hardware::Breakpoint {
+                var: BreakpointVar::PC,
+                value: 2,
+            }))
+            .unwrap();
+        assert_eq!(hardware.run(10), Some(0));
+        assert_eq!(hardware.run(10), None);
+        assert!(hardware.halted());
+        let r0 = Address::new(0).unwrap();
+        assert_eq!(hardware.peek(r0), 5);
+        hardware.reset();
+        assert_eq!((hardware.peek(r0), hardware.ticks()), (0, 0));
+        assert_eq!(hardware.breakpoints().len(), 1);
+        assert!(hardware
+            .add_breakpoint(&Breakpoint::VM(vm::Breakpoint::SP(0)))
+            .is_err());
+
+        let mut vm = Machine::from_file_contents(&files(
+            "Main.vm",
+            "push constant 7\npush constant 8\nadd\n",
+        ))
+        .unwrap();
+        vm.add_breakpoint(&Breakpoint::VM(vm::Breakpoint::Line {
+            file_name: "Main".to_owned(),
+            line_number: 2,
+        }))
+        .unwrap();
+        assert_eq!(vm.run(10), Some(0));
+        assert_eq!(vm.run(10), None);
+        assert!(vm.halted());
+        assert_eq!(vm.peek(Address::STACK_START), 15);
+        vm.poke(Address::SCREEN, -1);
+        assert_eq!(vm.screen()[0], -1);
+
+        assert!(Machine::from_file_contents(&[
+            ("Main.vm".to_owned(), String::new()),
+            ("Add.asm".to_owned(), String::new()),
+        ])
+        .is_err());
+    }
+}
@@ -1,42 +1,31 @@
 #![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
 #![warn(clippy::all, rust_2018_idioms)]
-#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] //Hide console window in release builds on Windows, this blocks stdout.
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] //Hide console window in release builds on Windows, this blocks stdout, nand2tetris-cli runs the subcommands there.
 
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(status) = nand2tetris::cli::run_subcommand(&args) {
+        std::process::exit(status);
+    }
+    let paths: Vec<_> = args.iter().map(std::path::PathBuf::from).collect();
+
     let native_options = eframe::NativeOptions {
         viewport: eframe::egui::ViewportBuilder::default()
             .with_inner_size(eframe::epaint::Vec2::new(1200.0, 900.0)),
+        #[cfg(feature = "wgpu")]
+        renderer: eframe::Renderer::Wgpu,
         ..Default::default()
     };
     eframe::run_native(
         "Emulator",
         native_options,
-        Box::new(|cc| {
+        Box::new(move |cc| {
             cc.egui_ctx.set_pixels_per_point(1.0);
             cc.egui_ctx.set_visuals(eframe::egui::Visuals::dark());
-            Box::new(nand2tetris::emulator::EmulatorApp::new(cc))
+            Box::new(nand2tetris::emulator::EmulatorApp::new(cc).with_paths(&paths))
         }),
     )
     .unwrap();
 }
-
-#[cfg(target_arch = "wasm32")]
-fn main() {
-    // Redirect `log` message to `console.log` and friends:
-    eframe::WebLogger::init(log::LevelFilter::Debug).ok();
-
-    let web_options = eframe::WebOptions::default();
-
-    wasm_bindgen_futures::spawn_local(async {
-        eframe::WebRunner::new()
-            .start(
-                "the_canvas_id", // hardcode it
-                web_options,
-                Box::new(|cc| Box::new(nand2tetris::emulator::EmulatorApp::new(cc))),
-            )
-            .await
-            .expect("failed to start eframe");
-    });
-}
@@ -0,0 +1,146 @@
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, space0, u64},
+    combinator::{all_consuming, map, rest},
+    sequence::{preceded, terminated, tuple},
+};
+
+use crate::parse_utils::IResult;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramMetadata {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub speed_hint: Option<u64>,
+    // Where in the ROM the program is loaded.
+    pub origin: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum MetadataField {
+    Name(String),
+    Author(String),
+    SpeedHint(u64),
+    Origin(u64),
+}
+
+fn field<'a, O>(
+    key: &'static str,
+    value_parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    preceded(
+        tuple((tag(key), space0, char(':'), space0)),
+        terminated(value_parser, space0),
+    )
+}
+
+fn text_value(input: &str) -> IResult<&str, String> {
+    map(rest, |value: &str| value.trim_end().to_owned())(input)
+}
+
+fn metadata_comment(input: &str) -> IResult<&str, MetadataField> {
+    all_consuming(preceded(
+        tuple((space0, tag("//"), space0)),
+        alt((
+            map(field("name", text_value), MetadataField::Name),
+            map(field("author", text_value), MetadataField::Author),
+            map(field("speed-hint", u64), MetadataField::SpeedHint),
+            map(field("origin", u64), MetadataField::Origin),
+        )),
+    ))(input)
+}
+
+impl ProgramMetadata {
+    // Only the leading comment block of a file is considered a header.
+    pub fn from_file_contents(contents: &str) -> Self {
+        let mut metadata = Self::default();
+        for line in contents.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if !trimmed.starts_with("//") {
+                break;
+            }
+            match metadata_comment(trimmed) {
+                Ok((_, MetadataField::Name(name))) => metadata.name = Some(name),
+                Ok((_, MetadataField::Author(author))) => metadata.author = Some(author),
+                Ok((_, MetadataField::SpeedHint(speed_hint))) => {
+                    metadata.speed_hint = Some(speed_hint)
+                }
+                Ok((_, MetadataField::Origin(origin))) => metadata.origin = Some(origin as usize),
+                Err(_) => {}
+            }
+        }
+
+        metadata
+    }
+
+    pub fn merge(&mut self, other: ProgramMetadata) {
+        self.name = self.name.take().or(other.name);
+        self.author = self.author.take().or(other.author);
+        self.speed_hint = self.speed_hint.or(other.speed_hint);
+        self.origin = self.origin.or(other.origin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::fmt::Display for ProgramMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.name, &self.author) {
+            (Some(name), Some(author)) => write!(f, "{name} by {author}"),
+            (Some(name), None) => write!(f, "{name}"),
+            (None, Some(author)) => write!(f, "by {author}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_header() {
+        let contents = r#"
+        // name: Pong
+        // author:   Jane Doe
+        // speed-hint: 2000000
+        // origin: 1024
+        // A regular comment
+        @0
+        // name: Not Pong
+        "#;
+
+        assert_eq!(
+            ProgramMetadata::from_file_contents(contents),
+            ProgramMetadata {
+                name: Some("Pong".to_owned()),
+                author: Some("Jane Doe".to_owned()),
+                speed_hint: Some(2000000),
+                origin: Some(1024),
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_speed_hint() {
+        let metadata = ProgramMetadata::from_file_contents("// speed-hint: fast");
+
+        assert!(metadata.is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut metadata = ProgramMetadata::from_file_contents("// name: Dino");
+        metadata.merge(ProgramMetadata::from_file_contents(
+            "// name: Other\n// author: Someone",
+        ));
+
+        assert_eq!(metadata.to_string(), "Dino by Someone");
+    }
+}
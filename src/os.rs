@@ -2,32 +2,174 @@ use hashbrown::HashMap;
 
 use crate::{
     characters::character_bitmaps,
-    hardware::{Word, RAM},
-    vm::{PushSegment, RunState},
+    hardware::{Address, HackKey, Word, RAM},
+    vm::{PushSegment, Register, RunState, StackCollision},
 };
 
-#[derive(Clone)]
+pub const HEAP_START: Word = Address::HEAP_START.word();
+
+// Steps Sys.wait takes for every millisecond, about what the OS's own delay loop takes.
+pub const WAIT_STEPS_PER_MILLISECOND: Word = 50;
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct OS {
     memory: Memory,
     screen: Screen,
     output: Output,
+    keyboard: Keyboard,
+    // The steps left of a Sys.wait.
+    wait_steps: Option<i64>,
+}
+
+// The OS's state in plain values, for saving it in sessions. The heap's holes and blocks are
+// start and size pairs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OSFields {
+    pub holes: Vec<(Word, Word)>,
+    pub blocks: Vec<(Word, Word)>,
+    pub color: bool,
+    pub cursor: (Word, Word),
+    pub pressed_key: Option<Word>,
+    pub line: Option<Vec<Word>>,
+    pub wait_steps: Option<i64>,
+}
+
+impl OS {
+    pub fn fields(&self) -> OSFields {
+        let sorted = |map: &HashMap<Word, Word>| {
+            let mut pairs: Vec<_> = map.iter().map(|(&start, &size)| (start, size)).collect();
+            pairs.sort();
+            pairs
+        };
+        OSFields {
+            holes: sorted(&self.memory.hole_starts),
+            blocks: sorted(&self.memory.allocs),
+            color: self.screen.color,
+            cursor: (self.output.row, self.output.col),
+            pressed_key: self.keyboard.pressed,
+            line: self.keyboard.line.clone(),
+            wait_steps: self.wait_steps,
+        }
+    }
+
+    pub fn from_fields(fields: OSFields) -> Self {
+        OS {
+            memory: Memory {
+                hole_starts: fields.holes.iter().copied().collect(),
+                hole_ends: fields
+                    .holes
+                    .iter()
+                    .map(|&(start, size)| (start + size, size))
+                    .collect(),
+                allocs: fields.blocks.into_iter().collect(),
+            },
+            screen: Screen {
+                color: fields.color,
+            },
+            output: Output {
+                row: fields.cursor.0,
+                col: fields.cursor.1,
+            },
+            keyboard: Keyboard {
+                pressed: fields.pressed_key,
+                line: fields.line,
+            },
+            wait_steps: fields.wait_steps,
+        }
+    }
 }
 
 impl Default for OS {
     fn default() -> Self {
         Self {
-            memory: Memory::new(0x0800, RAM::SCREEN - 0x0800),
+            memory: Memory::new(HEAP_START, RAM::SCREEN - HEAP_START),
             screen: Screen { color: true },
             output: Output { row: 0, col: 0 },
+            keyboard: Default::default(),
+            wait_steps: None,
         }
     }
 }
 
 type Func = fn(&mut RunState) -> Word;
+// Returns None until it's done waiting, in the meantime the call is made again every step.
+type BlockingFunc = fn(&mut RunState) -> Result<Option<Word>, NativeCall>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NativeCall {
+    Returned,
+    Blocked,
+    Halted,
+    // The call can't go on, it's made again if the run goes on.
+    Failed(String),
+}
 
 impl RunState {
-    pub fn call_os(&mut self, function_name: &str) -> bool {
-        let function = match function_name {
+    pub fn call_os(&mut self, function_name: &str) -> Option<NativeCall> {
+        if let Some(function) = Self::os_function(function_name) {
+            self.call(function);
+            return Some(NativeCall::Returned);
+        }
+
+        let function = Self::blocking_os_function(function_name)?;
+        Some(match function(self) {
+            Ok(Some(return_value)) => {
+                self.ram.push(return_value);
+                NativeCall::Returned
+            }
+            Ok(None) => NativeCall::Blocked,
+            Err(stop) => stop,
+        })
+    }
+
+    // Returns whether this is the first collision, which should stop the run.
+    pub fn record_stack_collision(&mut self) -> bool {
+        if self.stack_collision.is_some() {
+            return false;
+        }
+        self.stack_collision = self.check_stack_collision();
+        self.stack_collision.is_some()
+    }
+
+    // The stack grows towards the heap, which the native OS fills from its start.
+    fn check_stack_collision(&self) -> Option<StackCollision> {
+        let sp = self.ram[Register::SP];
+        if sp <= HEAP_START {
+            return None;
+        }
+        if sp > RAM::SCREEN {
+            return Some(StackCollision {
+                sp,
+                address: RAM::SCREEN,
+            });
+        }
+        self.os
+            .memory
+            .lowest_allocation_below(sp)
+            .map(|address| StackCollision { sp, address })
+    }
+
+    pub fn is_os_function(function_name: &str) -> bool {
+        Self::os_function(function_name).is_some()
+            || Self::blocking_os_function(function_name).is_some()
+    }
+
+    fn blocking_os_function(function_name: &str) -> Option<BlockingFunc> {
+        let function: BlockingFunc = match function_name {
+            "Keyboard.readChar" => Self::keyboard_read_char,
+            "Keyboard.readLine" => Self::keyboard_read_line,
+            "Keyboard.readInt" => Self::keyboard_read_int,
+            "Sys.wait" => Self::sys_wait,
+            "Sys.halt" => Self::sys_halt,
+            "Sys.error" => Self::sys_error,
+            _ => return None,
+        };
+
+        Some(function)
+    }
+
+    fn os_function(function_name: &str) -> Option<Func> {
+        let function: Func = match function_name {
             "Math.init" => Self::noop,
             "Math.multiply" => Self::math_multiply,
             "Math.divide" => Self::math_divide,
@@ -37,6 +179,7 @@ impl RunState {
             "Math.abs" => Self::math_abs,
             "Array.new" => Self::memory_alloc,
             "Array.dispose" => Self::memory_dealloc,
+            "Keyboard.init" => Self::noop,
             "Keyboard.keyPressed" => Self::keyboard_key_pressed,
             "Screen.init" => Self::noop,
             "Screen.clearScreen" => Self::screen_clear_screen,
@@ -69,14 +212,10 @@ impl RunState {
             "Output.printInt" => Self::output_print_int,
             "Output.println" => Self::output_println,
             "Output.backSpace" => Self::output_backspace,
-            "Sys.error" => {
-                panic!()
-            }
-            _ => return false,
+            _ => return None,
         };
 
-        self.call(function);
-        true
+        Some(function)
     }
 
     fn call(&mut self, f: Func) {
@@ -88,6 +227,42 @@ impl RunState {
         0
     }
 
+    // Prints ERR and the code and halts, like the official OS.
+    fn sys_error(&mut self) -> Result<Option<Word>, NativeCall> {
+        let code = self.ram.get(0, PushSegment::Argument, 0);
+        for c in format!("ERR{}", code).bytes() {
+            Output::print_char(self, c as Word);
+        }
+
+        Err(NativeCall::Halted)
+    }
+
+    fn sys_wait(&mut self) -> Result<Option<Word>, NativeCall> {
+        let duration = self.ram.get(0, PushSegment::Argument, 0);
+        if duration < 0 {
+            return Err(NativeCall::Failed(format!(
+                "Sys.wait was called with a negative duration, {}",
+                duration
+            )));
+        }
+
+        let steps = self
+            .os
+            .wait_steps
+            .get_or_insert(duration as i64 * WAIT_STEPS_PER_MILLISECOND as i64);
+        *steps -= 1;
+        if *steps > 0 {
+            return Ok(None);
+        }
+        self.os.wait_steps = None;
+
+        Ok(Some(0))
+    }
+
+    fn sys_halt(&mut self) -> Result<Option<Word>, NativeCall> {
+        Err(NativeCall::Halted)
+    }
+
     fn math_multiply(&mut self) -> Word {
         let x = self.ram.get(0, PushSegment::Argument, 0);
         let y = self.ram.get(0, PushSegment::Argument, 1);
@@ -229,6 +404,89 @@ impl RunState {
         self.ram[RAM::KBD]
     }
 
+    // A key once it's been pressed and released.
+    fn read_key(&mut self) -> Option<Word> {
+        let key = self.ram[RAM::KBD];
+        match self.os.keyboard.pressed {
+            None => {
+                if key != 0 {
+                    self.os.keyboard.pressed = Some(key);
+                }
+                None
+            }
+            Some(_) if key != 0 => None,
+            Some(pressed) => {
+                self.os.keyboard.pressed = None;
+                Some(pressed)
+            }
+        }
+    }
+
+    fn keyboard_read_char(&mut self) -> Result<Option<Word>, NativeCall> {
+        let Some(c) = self.read_key() else {
+            return Ok(None);
+        };
+        Output::print_char(self, c);
+
+        Ok(Some(c))
+    }
+
+    // Prints the message and echoes the keys until a new line, backspace erases.
+    fn read_line(&mut self) -> Option<Vec<Word>> {
+        if self.os.keyboard.line.is_none() {
+            let address = self.ram.get(0, PushSegment::Argument, 0);
+            Output::print_string(self, VMString { address });
+            self.os.keyboard.line = Some(vec![]);
+        }
+
+        let c = self.read_key()?;
+        if c == HackKey::NewLine.code() {
+            self.os.output.println();
+            return self.os.keyboard.line.take();
+        }
+        let line = self.os.keyboard.line.as_mut().unwrap();
+        if c != HackKey::Backspace.code() {
+            line.push(c);
+            Output::print_char(self, c);
+        } else if line.pop().is_some() {
+            Output::backspace(self);
+        }
+
+        None
+    }
+
+    fn keyboard_read_line(&mut self) -> Result<Option<Word>, NativeCall> {
+        let Some(line) = self.read_line() else {
+            return Ok(None);
+        };
+        let Some(s) = VMString::new(self, line.len() as Word) else {
+            return Err(NativeCall::Failed(format!(
+                "Keyboard.readLine couldn't allocate a string of {} characters",
+                line.len()
+            )));
+        };
+        for c in line {
+            s.append_char(self, c).unwrap();
+        }
+
+        Ok(Some(s.address))
+    }
+
+    // An optional minus and the digits up to the first other character.
+    fn keyboard_read_int(&mut self) -> Result<Option<Word>, NativeCall> {
+        let Some(line) = self.read_line() else {
+            return Ok(None);
+        };
+        let is_negative = line.first() == Some(&(b'-' as Word));
+        let digits = b'0' as Word..=b'9' as Word;
+        let value = line[is_negative as usize..]
+            .iter()
+            .map_while(|&c| digits.contains(&c).then(|| c - b'0' as Word))
+            .fold(0, |acc: Word, d| acc.wrapping_mul(10).wrapping_add(d));
+
+        Ok(Some(if is_negative { -value } else { value }))
+    }
+
     fn memory_peek(&mut self) -> Word {
         let address = self.ram.get(0, PushSegment::Argument, 0);
 
@@ -331,7 +589,7 @@ impl RunState {
     }
 
     fn string_backspace(&mut self) -> Word {
-        129
+        HackKey::Backspace.code()
     }
 
     fn string_double_quote(&mut self) -> Word {
@@ -339,7 +597,7 @@ impl RunState {
     }
 
     fn string_new_line(&mut self) -> Word {
-        128
+        HackKey::NewLine.code()
     }
 
     fn output_move_cursor(&mut self) -> Word {
@@ -388,7 +646,15 @@ impl RunState {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default, PartialEq, Eq)]
+struct Keyboard {
+    // The key that's down, it's read once it's released.
+    pressed: Option<Word>,
+    // What readLine or readInt got so far, after printing its message.
+    line: Option<Vec<Word>>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
 struct Output {
     row: Word,
     col: Word,
@@ -561,8 +827,9 @@ impl VMString {
         if is_negative {
             start += 1;
         }
-        let value = run_state.ram.contents
-            [start as usize..(self.address + 2 + self.length(run_state)) as usize]
+        let value = run_state
+            .ram
+            .slice(start..self.address + 2 + self.length(run_state))
             .iter()
             .try_fold(0, |acc, &i| {
                 (i as u8 as char).to_digit(10).map(|d| acc * 10 + d as i64)
@@ -619,14 +886,14 @@ impl VMString {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 struct Memory {
     hole_starts: HashMap<Word, Word>,
     hole_ends: HashMap<Word, Word>,
     allocs: HashMap<Word, Word>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 struct Screen {
     color: bool,
 }
@@ -659,6 +926,14 @@ impl Memory {
         Some(hole_start)
     }
 
+    fn lowest_allocation_below(&self, address: Word) -> Option<Word> {
+        self.allocs
+            .keys()
+            .copied()
+            .filter(|&start| start < address)
+            .min()
+    }
+
     fn dealloc(&mut self, address: Word) -> bool {
         let Some(size) = self.allocs.remove(&address) else {
             return false;
@@ -701,6 +976,11 @@ mod tests {
                 os: Default::default(),
                 call_stack: vec![],
                 breakpoints: vec![],
+                ticks: 0,
+                max_call_depth: 0,
+                max_sp: 0,
+                stack_collision: None,
+                os_fault: None,
             };
 
             instance.ram[Register::ARG] = 100;
@@ -719,21 +999,17 @@ mod tests {
         run_state.ram.set(0, PopSegment::Argument, 0, s);
         assert_eq!(run_state.string_length(), 0);
 
-        run_state
-            .ram
-            .set(0, PopSegment::Argument, 1, '5' as u8 as Word);
+        run_state.ram.set(0, PopSegment::Argument, 1, b'5' as Word);
         assert_eq!(run_state.string_append_char(), s);
         assert_eq!(run_state.string_length(), 1);
 
         run_state.ram.set(0, PopSegment::Argument, 1, 0);
-        assert_eq!(run_state.string_char_at(), '5' as u8 as Word);
+        assert_eq!(run_state.string_char_at(), b'5' as Word);
         assert_eq!(run_state.string_int_value(), 5);
 
-        run_state
-            .ram
-            .set(0, PopSegment::Argument, 2, '9' as u8 as Word);
+        run_state.ram.set(0, PopSegment::Argument, 2, b'9' as Word);
         assert_eq!(run_state.string_set_char_at(), 0);
-        assert_eq!(run_state.string_char_at(), '9' as u8 as Word);
+        assert_eq!(run_state.string_char_at(), b'9' as Word);
         assert_eq!(run_state.string_int_value(), 9);
 
         run_state.ram.set(0, PopSegment::Argument, 1, Word::MAX);
@@ -752,4 +1028,45 @@ mod tests {
         );
         assert_eq!(run_state.string_int_value(), Word::MIN);
     }
+
+    #[test]
+    fn test_blocking_calls() {
+        let mut run_state = RunState::test_instance();
+        run_state.ram.set(0, PopSegment::Argument, 0, 0);
+        let message = run_state.string_new();
+        run_state.ram.set(0, PopSegment::Argument, 0, message);
+
+        let mut value = None;
+        for key in [b'-' as Word, b'4' as Word, b'x' as Word]
+            .into_iter()
+            .chain([
+                HackKey::Backspace.code(),
+                b'2' as Word,
+                HackKey::NewLine.code(),
+            ])
+        {
+            assert_eq!(value, None);
+            run_state.ram[RAM::KBD] = key;
+            assert_eq!(run_state.keyboard_read_int(), Ok(None));
+            run_state.ram[RAM::KBD] = 0;
+            value = run_state.keyboard_read_int().unwrap();
+        }
+        assert_eq!(value, Some(-42));
+
+        // A key code past 255 isn't a digit, whatever its low byte.
+        for key in [b'1' as Word, 256 + b'2' as Word, HackKey::NewLine.code()] {
+            run_state.ram[RAM::KBD] = key;
+            assert_eq!(run_state.keyboard_read_int(), Ok(None));
+            run_state.ram[RAM::KBD] = 0;
+            value = run_state.keyboard_read_int().unwrap();
+        }
+        assert_eq!(value, Some(1));
+
+        run_state.ram.set(0, PopSegment::Argument, 0, 2);
+        for _ in 1..2 * WAIT_STEPS_PER_MILLISECOND {
+            assert_eq!(run_state.sys_wait(), Ok(None));
+        }
+        assert_eq!(run_state.sys_wait(), Ok(Some(0)));
+        assert_eq!(run_state.sys_wait(), Ok(None));
+    }
 }
@@ -1,4 +1,4 @@
-use std::ops::RangeFrom;
+use std::ops::{Range, RangeFrom};
 
 use nom::{
     branch::alt,
@@ -95,3 +95,22 @@ where
         |v| v.into_iter().flatten().collect::<Vec<_>>(),
     ))
 }
+
+// Yields the 1-based line number and the trimmed contents of every line with code on it.
+pub fn code_lines(input: &str) -> impl Iterator<Item = (usize, &str)> {
+    code_spans(input).map(|(line, span)| (line, &input[span]))
+}
+
+// Like `code_lines`, with byte ranges into the input instead of the contents.
+pub fn code_spans(input: &str) -> impl Iterator<Item = (usize, Range<usize>)> + '_ {
+    let mut line_start = 0;
+    input
+        .split_inclusive('\n')
+        .enumerate()
+        .filter_map(move |(index, line)| {
+            let start = line_start + line.len() - line.trim_start().len();
+            line_start += line.len();
+            let code = strip_comment(line).map_or(line, |(_, code)| code).trim();
+            (!code.is_empty()).then_some((index + 1, start..start + code.len()))
+        })
+}
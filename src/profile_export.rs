@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::recording::TraceEntry;
+use crate::vm::{Program, VMCommand};
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// A sampled profile in speedscope's file format, see https://www.speedscope.app. `stacks` are
+// indices into `frame_names` from the outermost call in, with how many samples were taken in
+// each of them.
+pub fn speedscope_json(
+    name: &str,
+    frame_names: &[&str],
+    stacks: &BTreeMap<Vec<usize>, u64>,
+) -> String {
+    let frames: Vec<_> = frame_names
+        .iter()
+        .map(|frame_name| format!("{{\"name\":{}}}", json_string(frame_name)))
+        .collect();
+    let samples: Vec<_> = stacks
+        .keys()
+        .map(|stack| {
+            let indices: Vec<_> = stack.iter().map(usize::to_string).collect();
+            format!("[{}]", indices.join(","))
+        })
+        .collect();
+    let weights: Vec<_> = stacks.values().map(u64::to_string).collect();
+    format!(
+        "{{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\
+         \"exporter\":\"nand2rust\",\"name\":{name},\
+         \"shared\":{{\"frames\":[{frames}]}},\
+         \"profiles\":[{{\"type\":\"sampled\",\"name\":{name},\"unit\":\"none\",\
+         \"startValue\":0,\"endValue\":{total},\"samples\":[{samples}],\"weights\":[{weights}]}}]}}",
+        name = json_string(name),
+        frames = frames.join(","),
+        total = stacks.values().sum::<u64>(),
+        samples = samples.join(","),
+        weights = weights.join(","),
+    )
+}
+
+// Calls and returns in a recorded VM trace as Chrome trace events, see
+// https://perfetto.dev. A step is shown as a microsecond. Native OS calls take a single step,
+// returns without a recorded call are dropped and calls still running at the end of the trace end
+// with it.
+pub fn chrome_trace_json(program: &Program, trace: &[TraceEntry]) -> String {
+    let mut events = vec![];
+    let mut depth = 0;
+    for (index, entry) in trace.iter().enumerate() {
+        let Some(command) = program.all_commands.get(entry.location) else {
+            continue;
+        };
+        let next_location = trace.get(index + 1).map(|next| next.location);
+        match command {
+            VMCommand::Call { function_name, .. } if next_location == Some(entry.location + 1) => {
+                events.push(format!(
+                    "{{\"name\":{},\"cat\":\"native\",\"ph\":\"X\",\"ts\":{},\"dur\":1,\"pid\":1,\"tid\":1}}",
+                    json_string(function_name),
+                    entry.step
+                ));
+            }
+            VMCommand::Call { function_name, .. } => {
+                depth += 1;
+                events.push(format!(
+                    "{{\"name\":{},\"ph\":\"B\",\"ts\":{},\"pid\":1,\"tid\":1}}",
+                    json_string(function_name),
+                    entry.step
+                ));
+            }
+            VMCommand::Return if depth > 0 => {
+                depth -= 1;
+                events.push(end_event(entry.step + 1));
+            }
+            _ => {}
+        }
+    }
+    let last_step = trace.last().map_or(0, |entry| entry.step + 1);
+    events.extend((0..depth).map(|_| end_event(last_step)));
+    format!(
+        "{{\"traceEvents\":[{}],\"displayTimeUnit\":\"ns\"}}",
+        events.join(",")
+    )
+}
+
+fn end_event(step: u64) -> String {
+    format!("{{\"ph\":\"E\",\"ts\":{},\"pid\":1,\"tid\":1}}", step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_speedscope_json() {
+        let stacks = BTreeMap::from([(vec![0], 2), (vec![0, 1], 3)]);
+        assert_eq!(
+            speedscope_json("Pong \"1\"", &["Sys.init", "Main.main"], &stacks),
+            "{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\
+             \"exporter\":\"nand2rust\",\"name\":\"Pong \\\"1\\\"\",\
+             \"shared\":{\"frames\":[{\"name\":\"Sys.init\"},{\"name\":\"Main.main\"}]},\
+             \"profiles\":[{\"type\":\"sampled\",\"name\":\"Pong \\\"1\\\"\",\"unit\":\"none\",\
+             \"startValue\":0,\"endValue\":5,\"samples\":[[0],[0,1]],\"weights\":[2,3]}]}"
+        );
+    }
+
+    #[test]
+    fn test_chrome_trace_json() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Sys.vm".to_owned(),
+            "function Sys.init 0\ncall Sys.main 0\ncall Math.abs 1\nreturn\n\
+             function Sys.main 0\npush constant 1\nreturn\n"
+                .to_owned(),
+        )]);
+        let mut trace = vec![];
+        while !vm.halted() {
+            trace.push(TraceEntry {
+                step: vm.run_state.ticks,
+                location: vm.run_state.current_command_index,
+            });
+            vm.step();
+        }
+
+        // The return from Sys.init has no recorded call and the trace ends inside Sys.main.
+        assert_eq!(
+            chrome_trace_json(&vm.program, &trace),
+            "{\"traceEvents\":[\
+             {\"name\":\"Sys.main\",\"ph\":\"B\",\"ts\":1,\"pid\":1,\"tid\":1},\
+             {\"ph\":\"E\",\"ts\":5,\"pid\":1,\"tid\":1},\
+             {\"name\":\"Math.abs\",\"cat\":\"native\",\"ph\":\"X\",\"ts\":5,\"dur\":1,\"pid\":1,\"tid\":1}\
+             ],\"displayTimeUnit\":\"ns\"}"
+        );
+        assert_eq!(
+            chrome_trace_json(&vm.program, &trace[..3]),
+            "{\"traceEvents\":[\
+             {\"name\":\"Sys.main\",\"ph\":\"B\",\"ts\":1,\"pid\":1,\"tid\":1},\
+             {\"ph\":\"E\",\"ts\":3,\"pid\":1,\"tid\":1}\
+             ],\"displayTimeUnit\":\"ns\"}"
+        );
+    }
+}
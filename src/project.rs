@@ -0,0 +1,425 @@
+use nom::{
+    character::complete::space1, combinator::all_consuming, sequence::separated_pair, Finish,
+};
+
+use std::path::{Path, PathBuf};
+
+use crate::hardware::{self, Address, Hardware, Word, MEM_SIZE};
+use crate::hardware_parse::{AssemblerMode, ProgramError};
+use crate::machine::{Breakpoint, Machine};
+use crate::parse_utils::ParsableWord;
+use crate::session::{
+    hardware_breakpoint, vm_breakpoint, write_hardware_breakpoint_var, write_vm_breakpoint,
+};
+use crate::vm;
+
+pub const DEFAULT_HEADLESS_STEPS: u64 = 10_000_000;
+
+// A program and how to run it, saved as a .n2r file with a setting per line:
+//
+//     file Main.vm
+//     entry Main.main
+//     speed 200000
+//     breakpoint function Main.loop
+//     ram 0 256
+//     assembler strict
+//     vm-os Math
+//
+// Files are relative to the project file and it's a VM project when they're all .vm files.
+// Breakpoints are written like in sessions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Project {
+    pub files: Vec<PathBuf>,
+    // A function in VM projects and a ROM address in the others.
+    pub entry: Option<String>,
+    pub speed: Option<u64>,
+    pub hardware_breakpoints: Vec<hardware::Breakpoint>,
+    pub vm_breakpoints: Vec<vm::Breakpoint>,
+    // Written to RAM once the program is loaded.
+    pub ram: Vec<(Address, Word)>,
+    pub assembler_mode: Option<AssemblerMode>,
+    // OS classes that run from the project's VM files instead of natively.
+    pub vm_os_classes: Vec<String>,
+}
+
+impl Project {
+    pub fn is_vm(&self) -> bool {
+        self.files.iter().all(|file| {
+            file.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("vm"))
+        })
+    }
+
+    pub fn entry_address(&self) -> Option<Word> {
+        self.entry.as_ref()?.parse().ok()
+    }
+
+    pub fn source_paths(&self, directory: &Path) -> Vec<PathBuf> {
+        self.files.iter().map(|file| directory.join(file)).collect()
+    }
+
+    pub fn read_sources(&self, directory: &Path) -> Result<Vec<(String, String)>, String> {
+        self.source_paths(directory)
+            .into_iter()
+            .map(|path| {
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                Ok((name, contents))
+            })
+            .collect()
+    }
+
+    // The program with the project's entry, breakpoints and RAM set. Several .asm files are
+    // assembled as one program.
+    pub fn load_machine(&self, sources: &[(String, String)]) -> Result<Machine, String> {
+        let mut machine = if self.is_vm() {
+            Machine::from_vm_files(sources)?
+        } else {
+            match sources {
+                [(name, contents)] if name.to_lowercase().ends_with(".hack") => {
+                    Machine::from_hack(name, contents)?
+                }
+                _ => {
+                    let text = sources
+                        .iter()
+                        .map(|(_, contents)| contents.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    let hardware = self
+                        .assembler_mode
+                        .unwrap_or_default()
+                        .source(&text)
+                        .and_then(|source| Hardware::try_from_file_contents(&source))
+                        .map_err(program_errors_text)?;
+                    Machine::Hardware(Box::new(hardware))
+                }
+            }
+        };
+        match &mut machine {
+            Machine::VM(vm) => {
+                let program = &mut vm.program;
+                program.vm_os_classes = self
+                    .vm_os_classes
+                    .iter()
+                    .filter(|class_name| program.file_name_to_index.contains_key(*class_name))
+                    .cloned()
+                    .collect();
+                if let Some(entry) = &self.entry {
+                    if program.function_index(entry).is_none() {
+                        return Err(format!("No function is called {}", entry));
+                    }
+                    program.entry_function = Some(entry.clone());
+                    vm.reset();
+                }
+            }
+            Machine::Hardware(hardware) => {
+                if let Some(entry) = self.entry_address() {
+                    hardware.pc = entry;
+                }
+            }
+        }
+        let breakpoints = self
+            .hardware_breakpoints
+            .iter()
+            .cloned()
+            .map(Breakpoint::Hardware)
+            .chain(self.vm_breakpoints.iter().cloned().map(Breakpoint::VM));
+        for breakpoint in breakpoints {
+            machine.add_breakpoint(&breakpoint)?;
+        }
+        for &(address, value) in &self.ram {
+            machine.poke(address, value);
+        }
+        Ok(machine)
+    }
+}
+
+pub fn parse_project(contents: &str) -> Result<Project, String> {
+    let mut project = Project::default();
+    let mut entry_line = 0;
+    let mut breakpoint_lines = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", line_number, message);
+        let (key, value) = line
+            .split_once(' ')
+            .map_or((line, ""), |(key, value)| (key, value.trim()));
+        if value.is_empty() {
+            return Err(error(format!("{} needs a value", key)));
+        }
+        match key {
+            "file" => {
+                let file = PathBuf::from(value);
+                if !file.extension().is_some_and(|extension| {
+                    ["vm", "asm", "hack"]
+                        .iter()
+                        .any(|known| extension.eq_ignore_ascii_case(known))
+                }) {
+                    return Err(error(format!("{} isn't a .vm, .asm or .hack file", value)));
+                }
+                project.files.push(file);
+            }
+            "entry" => {
+                project.entry = Some(value.to_owned());
+                entry_line = line_number;
+            }
+            "speed" => {
+                project.speed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| error(format!("invalid speed {}", value)))?,
+                )
+            }
+            "breakpoint" => breakpoint_lines.push((line_number, value)),
+            "ram" => {
+                let (address, value) = all_consuming(separated_pair(
+                    Word::parse_word,
+                    space1,
+                    Word::parse_word,
+                ))(value)
+                .finish()
+                .ok()
+                .and_then(|(_, (address, value))| Some((Address::new(address)?, value)))
+                .ok_or_else(|| error(format!("expected an address and a value, got {}", value)))?;
+                project.ram.push((address, value));
+            }
+            "assembler" => project.assembler_mode = Some(value.parse().map_err(error)?),
+            "vm-os" => project.vm_os_classes.push(value.to_owned()),
+            _ => return Err(error(format!("unknown setting {}", key))),
+        }
+    }
+
+    if project.files.is_empty() {
+        return Err("The project has no files".to_owned());
+    }
+    let is_vm = project.is_vm();
+    if !is_vm
+        && project.entry.is_some()
+        && project
+            .entry_address()
+            .is_none_or(|address| !(0..MEM_SIZE as i64).contains(&(address as i64)))
+    {
+        return Err(format!(
+            "line {}: the entry of a hardware project is a ROM address",
+            entry_line
+        ));
+    }
+    for (line_number, text) in breakpoint_lines {
+        let invalid = || format!("line {}: invalid breakpoint {}", line_number, text);
+        if is_vm {
+            let (_, breakpoint) = all_consuming(vm_breakpoint)(text)
+                .finish()
+                .map_err(|_| invalid())?;
+            project.vm_breakpoints.push(breakpoint);
+        } else {
+            let (_, breakpoint) = all_consuming(hardware_breakpoint)(text)
+                .finish()
+                .map_err(|_| invalid())?;
+            project.hardware_breakpoints.push(breakpoint);
+        }
+    }
+
+    Ok(project)
+}
+
+impl std::fmt::Display for Project {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in &self.files {
+            writeln!(f, "file {}", file.display())?;
+        }
+        if let Some(entry) = &self.entry {
+            writeln!(f, "entry {}", entry)?;
+        }
+        if let Some(speed) = self.speed {
+            writeln!(f, "speed {}", speed)?;
+        }
+        for breakpoint in &self.hardware_breakpoints {
+            write!(f, "breakpoint ")?;
+            write_hardware_breakpoint_var(f, &breakpoint.var)?;
+            writeln!(f, " {}", breakpoint.value)?;
+        }
+        for breakpoint in &self.vm_breakpoints {
+            write!(f, "breakpoint ")?;
+            write_vm_breakpoint(f, breakpoint)?;
+            writeln!(f)?;
+        }
+        for (address, value) in &self.ram {
+            writeln!(f, "ram {} {}", address.word(), value)?;
+        }
+        if let Some(assembler_mode) = self.assembler_mode {
+            writeln!(f, "assembler {}", assembler_mode.to_string().to_lowercase())?;
+        }
+        for class_name in &self.vm_os_classes {
+            writeln!(f, "vm-os {}", class_name)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeadlessStop {
+    Halted,
+    BreakpointHit(usize),
+    Fault(String),
+    StepLimit,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadlessRun {
+    pub steps: u64,
+    pub stop: HeadlessStop,
+    // RAM[0] to RAM[15] when the run stopped.
+    pub registers: Vec<Word>,
+}
+
+impl std::fmt::Display for HeadlessRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.stop {
+            HeadlessStop::Halted => writeln!(f, "Halted after {} steps", self.steps)?,
+            HeadlessStop::BreakpointHit(index) => {
+                writeln!(f, "Breakpoint {} hit after {} steps", index + 1, self.steps)?
+            }
+            HeadlessStop::Fault(message) => {
+                writeln!(f, "Stopped after {} steps: {}", self.steps, message)?
+            }
+            HeadlessStop::StepLimit => writeln!(f, "Still running after {} steps", self.steps)?,
+        }
+        for (address, value) in self.registers.iter().enumerate() {
+            writeln!(f, "RAM[{}] = {}", address, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn program_errors_text(errors: Vec<ProgramError>) -> String {
+    errors
+        .into_iter()
+        .map(|error| format!("line {}: {}", error.line, error.message))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Runs the project's program without the emulator, until it halts, hits a breakpoint or has run
+// `max_steps`.
+pub fn run_headless(
+    project: &Project,
+    sources: Vec<(String, String)>,
+    max_steps: u64,
+) -> Result<HeadlessRun, String> {
+    let mut machine = project.load_machine(&sources)?;
+    let breakpoint = machine.run(max_steps.saturating_sub(machine.ticks()));
+    let stop = match (breakpoint, machine.fault()) {
+        (Some(index), _) => HeadlessStop::BreakpointHit(index),
+        (None, Some(message)) => HeadlessStop::Fault(message.to_owned()),
+        (None, None) if machine.halted() => HeadlessStop::Halted,
+        (None, None) => HeadlessStop::StepLimit,
+    };
+    Ok(HeadlessRun {
+        steps: machine.ticks(),
+        stop,
+        registers: (0..16)
+            .map(|index| machine.peek(Address::from_index(index).unwrap()))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_project() {
+        let contents = "// Pong\nfile Main.vm\nfile lib/Ball.vm\nentry Main.main\nspeed 200000\n\
+                        breakpoint function Ball.move\nram 0 300\nassembler strict\nvm-os Math\n";
+        let project = parse_project(contents).unwrap();
+        assert_eq!(
+            project,
+            Project {
+                files: vec![PathBuf::from("Main.vm"), PathBuf::from("lib/Ball.vm")],
+                entry: Some("Main.main".to_owned()),
+                speed: Some(200000),
+                hardware_breakpoints: vec![],
+                vm_breakpoints: vec![vm::Breakpoint::CurrentFunction("Ball.move".to_owned())],
+                ram: vec![(Address::new(0).unwrap(), 300)],
+                assembler_mode: Some(AssemblerMode::Strict),
+                vm_os_classes: vec!["Math".to_owned()],
+            }
+        );
+        assert_eq!(parse_project(&project.to_string()), Ok(project));
+
+        let project = parse_project("file Max.asm\nentry 2\nbreakpoint PC 10\n").unwrap();
+        assert_eq!(project.entry_address(), Some(2));
+        assert_eq!(
+            project.hardware_breakpoints,
+            vec![hardware::Breakpoint {
+                var: hardware::BreakpointVar::PC,
+                value: 10,
+            }]
+        );
+
+        assert_eq!(
+            parse_project("file Max.asm\nentry main\n"),
+            Err("line 2: the entry of a hardware project is a ROM address".to_owned())
+        );
+        assert_eq!(
+            parse_project("file Main.vm\nbreakpoint PC 10\n"),
+            Err("line 2: invalid breakpoint PC 10".to_owned())
+        );
+        assert_eq!(
+            parse_project("file Main.vm\nram 0\n"),
+            Err("line 2: expected an address and a value, got 0".to_owned())
+        );
+        assert_eq!(
+            parse_project("colour blue\n"),
+            Err("line 1: unknown setting colour".to_owned())
+        );
+        assert_eq!(
+            parse_project("file Other.n2r\n"),
+            Err("line 1: Other.n2r isn't a .vm, .asm or .hack file".to_owned())
+        );
+        assert_eq!(
+            parse_project("speed 10\n"),
+            Err("The project has no files".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_run_headless() {
+        let project = parse_project(
+            "file Main.vm\nentry Main.double\nram 2 300\nram 300 21\n\
+             breakpoint function Main.never\n",
+        )
+        .unwrap();
+        let sources = vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\nlabel LOOP\ngoto LOOP\n\
+             function Main.double 0\npush argument 0\npush argument 0\nadd\npop temp 0\n\
+             push constant 0\nreturn\nfunction Main.never 0\nreturn\n"
+                .to_owned(),
+        )];
+        let run = run_headless(&project, sources, 100).unwrap();
+        assert_eq!(run.stop, HeadlessStop::Halted);
+        assert_eq!(run.registers[5], 42);
+
+        // Stops at the breakpoint on @END, after the instruction before it.
+        let project = parse_project("file Loop.asm\nbreakpoint PC 4\nram 16 9\n").unwrap();
+        let sources = vec![(
+            "Loop.asm".to_owned(),
+            "@16\nD=M\n@17\nM=D\n(END)\n@END\n0;JMP\n".to_owned(),
+        )];
+        let run = run_headless(&project, sources, 100).unwrap();
+        assert_eq!(run.stop, HeadlessStop::BreakpointHit(0));
+        assert_eq!(run.steps, 4);
+        assert!(run
+            .to_string()
+            .starts_with("Breakpoint 1 hit after 4 steps\nRAM[0] = 0\n"));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::hardware::{Word, MEM_SIZE, RAM};
+
+// Compared a block at a time, most blocks don't change between steps.
+const BLOCK_WORDS: usize = 64;
+
+// The location of the last write to each RAM address. Locations are ROM addresses for hardware
+// programs and command indices for VM programs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    last_writes: Vec<Option<usize>>,
+    previous: Option<RAM>,
+}
+
+impl Default for Provenance {
+    fn default() -> Self {
+        Provenance {
+            last_writes: vec![None; MEM_SIZE],
+            previous: None,
+        }
+    }
+}
+
+impl Provenance {
+    pub fn record_write(&mut self, address: Word, location: usize) {
+        if let Some(last_write) = self.last_writes.get_mut(address as usize) {
+            *last_write = Some(location);
+        }
+    }
+
+    // For engines that don't report their writes, every address that differs from the RAM seen
+    // by the previous call is attributed to `location`. The first call only remembers the RAM.
+    pub fn record_changes(&mut self, ram: &RAM, location: usize) {
+        let Some(previous) = &mut self.previous else {
+            self.previous = Some(ram.clone());
+            return;
+        };
+        for (block_index, (old_block, new_block)) in previous
+            .contents
+            .chunks_mut(BLOCK_WORDS)
+            .zip(ram.contents.chunks(BLOCK_WORDS))
+            .enumerate()
+        {
+            if old_block == new_block {
+                continue;
+            }
+            for (offset, (old, new)) in old_block.iter().zip(new_block).enumerate() {
+                if old != new {
+                    self.last_writes[block_index * BLOCK_WORDS + offset] = Some(location);
+                }
+            }
+            old_block.copy_from_slice(new_block);
+        }
+    }
+
+    pub fn last_write(&self, address: Word) -> Option<usize> {
+        *self.last_writes.get(address as usize)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::Hardware;
+
+    #[test]
+    fn test_provenance() {
+        let mut provenance = Provenance::default();
+        provenance.record_write(5, 10);
+        provenance.record_write(5, 12);
+        assert_eq!(provenance.last_write(5), Some(12));
+        assert_eq!(provenance.last_write(6), None);
+        assert_eq!(provenance.last_write(-1), None);
+
+        let mut ram = Hardware::default().ram;
+        provenance.record_changes(&ram, 1);
+        ram[100] = 3;
+        ram[RAM::SCREEN] = -1;
+        provenance.record_changes(&ram, 2);
+        provenance.record_changes(&ram, 3);
+        ram[100] = 4;
+        provenance.record_changes(&ram, 4);
+        assert_eq!(provenance.last_write(100), Some(4));
+        assert_eq!(provenance.last_write(RAM::SCREEN), Some(2));
+        assert_eq!(provenance.last_write(101), None);
+    }
+}
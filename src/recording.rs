@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use crate::compressed_trace::CompressedTrace;
+
+pub const DEFAULT_TRACE_LIMIT: usize = 10_000;
+
+// A location is a ROM address for hardware programs and a command index for VM programs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub step: u64,
+    pub location: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileEntry {
+    pub name: String,
+    pub count: u64,
+}
+
+// `trace` keeps the most recent `trace_limit` steps for quick access, `full_trace` keeps all of
+// them compressed.
+#[derive(Clone, Debug)]
+pub struct Recording {
+    pub trace: VecDeque<TraceEntry>,
+    pub trace_limit: usize,
+    pub full_trace: CompressedTrace,
+    pub coverage: Vec<u64>,
+}
+
+impl Recording {
+    pub fn new(location_count: usize) -> Self {
+        Recording {
+            trace: VecDeque::new(),
+            trace_limit: DEFAULT_TRACE_LIMIT,
+            full_trace: CompressedTrace::default(),
+            coverage: vec![0; location_count],
+        }
+    }
+
+    pub fn record(&mut self, step: u64, location: usize) {
+        if self.trace.len() == self.trace_limit {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(TraceEntry { step, location });
+        self.full_trace.push(TraceEntry { step, location });
+        if let Some(count) = self.coverage.get_mut(location) {
+            *count += 1;
+        }
+    }
+
+    pub fn total_steps(&self) -> u64 {
+        self.coverage.iter().sum()
+    }
+
+    pub fn covered_count(&self) -> usize {
+        self.coverage.iter().filter(|&&count| count > 0).count()
+    }
+
+    pub fn profile(&self, regions: &[(String, usize)]) -> Vec<ProfileEntry> {
+        profile(&self.coverage, regions)
+    }
+
+    // Bytes held by the recording, roughly.
+    pub fn memory_size(&self) -> usize {
+        self.trace.capacity() * std::mem::size_of::<TraceEntry>()
+            + self.full_trace.compressed_size()
+            + self.coverage.capacity() * std::mem::size_of::<u64>()
+    }
+}
+
+// Limits for everything that's recorded during a run. Traces and keyframes lose their oldest
+// data first, like a ring, checkpoints lose the one used least recently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordingBudget {
+    pub max_trace_bytes: usize,
+    pub max_snapshots: usize,
+    // Keyframes for time travel, how far back it goes is this many keyframe intervals.
+    pub max_keyframes: usize,
+    pub max_undo_points: usize,
+}
+
+impl Default for RecordingBudget {
+    fn default() -> Self {
+        RecordingBudget {
+            max_trace_bytes: 64 << 20,
+            max_snapshots: 32,
+            max_keyframes: 256,
+            max_undo_points: 50,
+        }
+    }
+}
+
+impl RecordingBudget {
+    pub fn trim_trace(&self, trace: &mut CompressedTrace) {
+        trace.trim_to(self.max_trace_bytes);
+    }
+
+    // The keyframes are oldest first.
+    pub fn trim_keyframes<T>(&self, keyframes: &mut Vec<T>) {
+        let excess = keyframes.len().saturating_sub(self.max_keyframes);
+        keyframes.drain(..excess);
+    }
+
+    // Undo points are oldest first too.
+    pub fn trim_undo_points<T>(&self, undo_points: &mut Vec<T>) {
+        let excess = undo_points.len().saturating_sub(self.max_undo_points);
+        undo_points.drain(..excess);
+    }
+
+    // `last_used` orders the snapshots by when they were last taken or restored.
+    pub fn trim_snapshots<T>(&self, snapshots: &mut Vec<T>, last_used: impl Fn(&T) -> u64) {
+        while snapshots.len() > self.max_snapshots {
+            let (least_recent, _) = snapshots
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, snapshot)| last_used(snapshot))
+                .unwrap();
+            snapshots.remove(least_recent);
+        }
+    }
+}
+
+// `counts` are indexed by location and `regions` are named starting locations, e.g. labels or
+// functions. Each location counts towards the closest region that starts at or before it.
+pub fn profile(counts: &[u64], regions: &[(String, usize)]) -> Vec<ProfileEntry> {
+    let mut regions: Vec<_> = regions.iter().collect();
+    regions.sort_by_key(|(_, start)| *start);
+
+    let mut profile: Vec<ProfileEntry> = vec![];
+    for (location, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let name = match regions.partition_point(|(_, start)| *start <= location) {
+            0 => "(top level)",
+            index => regions[index - 1].0.as_str(),
+        };
+        match profile.iter_mut().find(|entry| entry.name == name) {
+            Some(entry) => entry.count += count,
+            None => profile.push(ProfileEntry {
+                name: name.to_owned(),
+                count,
+            }),
+        }
+    }
+    profile.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+    profile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording() {
+        let mut recording = Recording {
+            trace_limit: 3,
+            ..Recording::new(4)
+        };
+        for (step, location) in [0, 1, 2, 1, 2, 3].into_iter().enumerate() {
+            recording.record(step as u64, location);
+        }
+
+        assert_eq!(
+            recording
+                .trace
+                .iter()
+                .map(|entry| entry.step)
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(recording.full_trace.len(), 6);
+        assert_eq!(recording.coverage, vec![1, 2, 2, 1]);
+        assert_eq!(recording.total_steps(), 6);
+        assert_eq!(recording.covered_count(), 4);
+        assert!(recording.memory_size() >= 6 * std::mem::size_of::<TraceEntry>());
+        assert_eq!(
+            recording.profile(&[("LOOP".to_owned(), 1), ("END".to_owned(), 3)]),
+            vec![
+                ProfileEntry {
+                    name: "LOOP".to_owned(),
+                    count: 4,
+                },
+                ProfileEntry {
+                    name: "(top level)".to_owned(),
+                    count: 1,
+                },
+                ProfileEntry {
+                    name: "END".to_owned(),
+                    count: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recording_budget() {
+        let budget = RecordingBudget {
+            max_trace_bytes: 0,
+            max_snapshots: 2,
+            max_keyframes: 2,
+            max_undo_points: 1,
+        };
+
+        let mut trace = CompressedTrace::default();
+        for step in 0..crate::compressed_trace::BLOCK_ENTRIES as u64 * 2 + 10 {
+            trace.push(TraceEntry { step, location: 0 });
+        }
+        budget.trim_trace(&mut trace);
+        assert_eq!(trace.len(), 10);
+
+        let mut keyframes = vec![1, 2, 3];
+        budget.trim_keyframes(&mut keyframes);
+        assert_eq!(keyframes, vec![2, 3]);
+        budget.trim_undo_points(&mut keyframes);
+        assert_eq!(keyframes, vec![3]);
+
+        // (name, last used)
+        let mut snapshots = vec![("a", 3), ("b", 1), ("c", 2)];
+        budget.trim_snapshots(&mut snapshots, |&(_, last_used)| last_used);
+        assert_eq!(snapshots, vec![("a", 3), ("c", 2)]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt::Write;
+
+use crate::recording::Recording;
+
+const MAX_PROFILE_BARS: usize = 20;
+
+pub struct ReportSource<'a> {
+    pub title: &'a str,
+    pub recording: &'a Recording,
+    pub regions: &'a [(String, usize)],
+    pub location_text: &'a dyn Fn(usize) -> String,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse;margin-bottom:2em}\
+th,td{border:1px solid #ccc;padding:2px 8px;text-align:left}\
+th{cursor:pointer;background:#eee}\
+td.num{text-align:right;font-family:monospace}\
+td.code{font-family:monospace}\
+tr.uncovered{color:#999}";
+
+// Clicking a header sorts its table, the trace filter hides rows that don't contain the text.
+const SCRIPT: &str = "document.querySelectorAll('th').forEach((th,i)=>th.onclick=()=>{\
+const body=th.closest('table').tBodies[0];\
+const rows=[...body.rows];\
+const key=r=>r.cells[i].dataset.sort??r.cells[i].textContent;\
+const asc=th.dataset.asc!=='1';th.dataset.asc=asc?'1':'0';\
+rows.sort((a,b)=>{const x=key(a),y=key(b);const d=(isNaN(x)||isNaN(y))?x.localeCompare(y):x-y;\
+return asc?d:-d;});rows.forEach(r=>body.appendChild(r));});\
+const filter=document.getElementById('trace-filter');\
+if(filter)filter.oninput=()=>{for(const r of document.getElementById('trace').tBodies[0].rows)\
+r.hidden=!r.textContent.includes(filter.value);};";
+
+fn profile_chart(html: &mut String, source: &ReportSource) {
+    let profile = source.recording.profile(source.regions);
+    let max_count = profile.first().map_or(1, |entry| entry.count.max(1));
+    let bars = profile.len().min(MAX_PROFILE_BARS);
+    let _ = write!(
+        html,
+        "<svg width=\"720\" height=\"{}\" role=\"img\">",
+        bars * 22 + 4
+    );
+    for (index, entry) in profile.iter().take(bars).enumerate() {
+        let width = entry.count as f64 / max_count as f64 * 480.0;
+        let y = index * 22;
+        let _ = write!(
+            html,
+            "<text x=\"0\" y=\"{}\" font-size=\"12\">{}</text>\
+             <rect x=\"180\" y=\"{}\" width=\"{:.1}\" height=\"16\" fill=\"#4a7ebb\"/>\
+             <text x=\"{:.1}\" y=\"{}\" font-size=\"12\">{}</text>",
+            y + 14,
+            escape_html(&entry.name),
+            y + 2,
+            width,
+            184.0 + width,
+            y + 14,
+            entry.count
+        );
+    }
+    html.push_str("</svg>");
+
+    html.push_str(
+        "<table><thead><tr><th>Region</th><th>Steps</th><th>Share</th></tr></thead><tbody>",
+    );
+    let total_steps = source.recording.total_steps().max(1);
+    for entry in &profile {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{:.1}%</td></tr>",
+            escape_html(&entry.name),
+            entry.count,
+            entry.count as f64 / total_steps as f64 * 100.0
+        );
+    }
+    html.push_str("</tbody></table>");
+}
+
+fn coverage_section(html: &mut String, source: &ReportSource) {
+    let coverage = &source.recording.coverage;
+    let _ = write!(
+        html,
+        "<p>{} of {} locations executed.</p>",
+        source.recording.covered_count(),
+        coverage.len()
+    );
+
+    // One column per location, executed ones are filled.
+    let _ = write!(
+        html,
+        "<svg width=\"720\" height=\"24\" viewBox=\"0 0 {} 1\" preserveAspectRatio=\"none\" \
+         role=\"img\"><rect width=\"{}\" height=\"1\" fill=\"#eee\"/>",
+        coverage.len().max(1),
+        coverage.len().max(1)
+    );
+    for (location, &count) in coverage.iter().enumerate() {
+        if count > 0 {
+            let _ = write!(
+                html,
+                "<rect x=\"{}\" width=\"1\" height=\"1\" fill=\"#3a9a5b\"/>",
+                location
+            );
+        }
+    }
+    html.push_str("</svg>");
+
+    html.push_str(
+        "<table><thead><tr><th>Location</th><th>Code</th><th>Executions</th></tr></thead><tbody>",
+    );
+    for (location, &count) in coverage.iter().enumerate() {
+        let _ = write!(
+            html,
+            "<tr{}><td class=\"num\">{}</td><td class=\"code\">{}</td><td class=\"num\">{}</td></tr>",
+            if count == 0 { " class=\"uncovered\"" } else { "" },
+            location,
+            escape_html(&(source.location_text)(location)),
+            count
+        );
+    }
+    html.push_str("</tbody></table>");
+}
+
+fn trace_section(html: &mut String, source: &ReportSource) {
+    let _ = write!(
+        html,
+        "<p>The last {} recorded steps.</p>\
+         <input id=\"trace-filter\" placeholder=\"Filter\">\
+         <table id=\"trace\"><thead><tr><th>Step</th><th>Location</th><th>Code</th></tr></thead>\
+         <tbody>",
+        source.recording.trace.len()
+    );
+    for entry in &source.recording.trace {
+        let _ = write!(
+            html,
+            "<tr><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"code\">{}</td></tr>",
+            entry.step,
+            entry.location,
+            escape_html(&(source.location_text)(entry.location))
+        );
+    }
+    html.push_str("</tbody></table>");
+}
+
+// A standalone page that opens in any browser without the emulator.
+pub fn html_report(source: &ReportSource) -> String {
+    let title = escape_html(source.title);
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title>\
+         <style>{}</style></head><body><h1>{}</h1><p>{} steps recorded.</p>",
+        title,
+        STYLE,
+        title,
+        source.recording.total_steps()
+    );
+    html.push_str("<h2>Profile</h2>");
+    profile_chart(&mut html, source);
+    html.push_str("<h2>Coverage</h2>");
+    coverage_section(&mut html, source);
+    html.push_str("<h2>Trace</h2>");
+    trace_section(&mut html, source);
+    let _ = write!(html, "<script>{}</script></body></html>", SCRIPT);
+
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_report() {
+        let mut recording = Recording::new(3);
+        recording.record(0, 0);
+        recording.record(1, 1);
+        let regions = vec![("<LOOP>".to_owned(), 1)];
+        let html = html_report(&ReportSource {
+            title: "Test & Run",
+            recording: &recording,
+            regions: &regions,
+            location_text: &|location| format!("@{}", location),
+        });
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Test &amp; Run</title>"));
+        assert!(html.contains("&lt;LOOP&gt;"));
+        assert!(html.contains("2 of 3 locations executed."));
+        assert!(html.contains("<tr class=\"uncovered\"><td class=\"num\">2</td>"));
+        assert!(html.ends_with("</script></body></html>"));
+    }
+}
@@ -0,0 +1,292 @@
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, multispace0},
+    combinator::{all_consuming, map, map_opt, not, value},
+    error::convert_error,
+    sequence::{preceded, separated_pair, terminated},
+    Finish,
+};
+
+use crate::expression::{
+    expression, is_address, parse_expression, Expression, ExpressionContext, Variable,
+};
+use crate::hardware::{Hardware, Word};
+use crate::parse_utils::IResult;
+use crate::vm::RunState;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Variable(Variable),
+    RAM(Expression),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Statement {
+    Log(Expression),
+    Assign(Target, Expression),
+    Continue,
+}
+
+pub trait ScriptContext: ExpressionContext {
+    fn set_ram_value(&mut self, address: Word, value: Word);
+    fn set_variable_value(&mut self, variable: Variable, value: Word) -> bool;
+}
+
+impl ScriptContext for Hardware {
+    fn set_ram_value(&mut self, address: Word, value: Word) {
+        self.ram[address] = value;
+    }
+
+    fn set_variable_value(&mut self, variable: Variable, value: Word) -> bool {
+        match variable {
+            Variable::A => self.a = value,
+            Variable::D => self.d = value,
+            Variable::M if is_address(self.a as i64) => self.ram[self.a] = value,
+            Variable::M => return false,
+            Variable::PC => self.pc = value,
+            Variable::SP => self.ram[0] = value,
+            Variable::LCL => self.ram[1] = value,
+            Variable::ARG => self.ram[2] = value,
+            Variable::THIS => self.ram[3] = value,
+            Variable::THAT => self.ram[4] = value,
+        }
+        true
+    }
+}
+
+impl ScriptContext for RunState {
+    fn set_ram_value(&mut self, address: Word, value: Word) {
+        self.ram[address] = value;
+    }
+
+    fn set_variable_value(&mut self, variable: Variable, value: Word) -> bool {
+        match variable {
+            Variable::A | Variable::D | Variable::M | Variable::PC => return false,
+            Variable::SP => self.ram[0] = value,
+            Variable::LCL => self.ram[1] = value,
+            Variable::ARG => self.ram[2] = value,
+            Variable::THIS => self.ram[3] = value,
+            Variable::THAT => self.ram[4] = value,
+        }
+        true
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ScriptOutcome {
+    pub log: Vec<String>,
+    pub resume: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script {
+    pub source: String,
+    pub statements: Vec<Statement>,
+}
+
+impl Script {
+    pub fn new(source: &str) -> Result<Self, String> {
+        Ok(Script {
+            source: source.trim().to_owned(),
+            statements: parse_statements(source)?,
+        })
+    }
+
+    // Statements after a failed one are skipped and the run stays paused.
+    pub fn run(&self, context: &mut impl ScriptContext) -> ScriptOutcome {
+        let mut outcome = ScriptOutcome::default();
+        for statement in &self.statements {
+            match statement {
+                Statement::Log(expression) => match expression.evaluate(context) {
+                    Some(result) => outcome.log.push(format!("{expression} = {result}")),
+                    None => return failed(outcome, expression),
+                },
+                Statement::Assign(target, expression) => {
+                    let Some(result) = expression.evaluate(context) else {
+                        return failed(outcome, expression);
+                    };
+                    let assigned = match target {
+                        Target::Variable(variable) => {
+                            context.set_variable_value(*variable, result as Word)
+                        }
+                        Target::RAM(address) => match address.evaluate(context) {
+                            Some(address) if is_address(address) => {
+                                context.set_ram_value(address as Word, result as Word);
+                                true
+                            }
+                            _ => false,
+                        },
+                    };
+                    if !assigned {
+                        outcome.log.push(format!("{} can't be assigned", target));
+                        outcome.resume = false;
+                        return outcome;
+                    }
+                }
+                Statement::Continue => outcome.resume = true,
+            }
+        }
+        outcome
+    }
+}
+
+fn failed(mut outcome: ScriptOutcome, expression: &Expression) -> ScriptOutcome {
+    outcome
+        .log
+        .push(format!("{expression} could not be evaluated"));
+    outcome.resume = false;
+    outcome
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MessagePart {
+    Text(String),
+    Expression(Expression),
+}
+
+// A message with `{expression}` placeholders, like `SP = {SP}, top = {RAM[SP - 1]}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageTemplate {
+    pub source: String,
+    pub parts: Vec<MessagePart>,
+}
+
+impl MessageTemplate {
+    pub fn new(source: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = source;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(MessagePart::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed {{ in \"{source}\""))?;
+            parts.push(MessagePart::Expression(parse_expression(
+                &rest[start + 1..start + end],
+            )?));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(MessagePart::Text(rest.to_owned()));
+        }
+
+        Ok(MessageTemplate {
+            source: source.to_owned(),
+            parts,
+        })
+    }
+
+    pub fn render(&self, context: &impl ExpressionContext) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                MessagePart::Text(text) => text.clone(),
+                MessagePart::Expression(expression) => expression
+                    .evaluate(context)
+                    .map_or("?".to_owned(), |value| value.to_string()),
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Variable(variable) => write!(f, "{variable}"),
+            Target::RAM(address) => write!(f, "RAM[{address}]"),
+        }
+    }
+}
+
+fn target(input: &str) -> IResult<&str, Target> {
+    map_opt(expression, |expression| match expression {
+        Expression::Variable(variable) => Some(Target::Variable(variable)),
+        Expression::RAM(address) => Some(Target::RAM(*address)),
+        _ => None,
+    })(input)
+}
+
+fn statement(input: &str) -> IResult<&str, Statement> {
+    preceded(
+        multispace0,
+        alt((
+            value(
+                Statement::Continue,
+                terminated(tag("continue"), multispace0),
+            ),
+            map(preceded(tag("log "), expression), Statement::Log),
+            map(
+                separated_pair(target, terminated(char('='), not(char('='))), expression),
+                |(target, expression)| Statement::Assign(target, expression),
+            ),
+        )),
+    )(input)
+}
+
+// Statements are separated by newlines or semicolons, neither of which can appear inside one.
+fn parse_statements(input: &str) -> Result<Vec<Statement>, String> {
+    input
+        .split(['\n', ';'])
+        .filter(|source| !source.trim().is_empty())
+        .map(|source| {
+            all_consuming(statement)(source)
+                .finish()
+                .map(|(_, statement)| statement)
+                .map_err(|error| convert_error(source, error))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let script = Script::new("log RAM[SP - 1]; RAM[300] = D + 1\ncontinue").unwrap();
+        let mut hardware = Hardware {
+            d: 41,
+            ..Default::default()
+        };
+        hardware.ram[0] = 257;
+        hardware.ram[256] = 7;
+
+        assert_eq!(
+            script.run(&mut hardware),
+            ScriptOutcome {
+                log: vec!["RAM[(SP - 1)] = 7".to_owned()],
+                resume: true,
+            }
+        );
+        assert_eq!(hardware.ram[300], 42);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(Script::new("1 = 2").is_err());
+        assert!(Script::new("log").is_err());
+
+        let script = Script::new("M = 3; continue").unwrap();
+        let mut hardware = Hardware {
+            a: -1,
+            ..Default::default()
+        };
+        let outcome = script.run(&mut hardware);
+        assert_eq!(outcome.log, vec!["M can't be assigned".to_owned()]);
+        assert!(!outcome.resume);
+    }
+
+    #[test]
+    fn test_message_template() {
+        let mut hardware = Hardware::default();
+        hardware.ram[0] = 257;
+        hardware.ram[256] = -3;
+
+        let template = MessageTemplate::new("SP = {SP}, top = {RAM[SP - 1]}, {RAM[-1]}").unwrap();
+        assert_eq!(template.render(&hardware), "SP = 257, top = -3, ?");
+        assert!(MessageTemplate::new("SP = {SP").is_err());
+        assert!(MessageTemplate::new("{SP +}").is_err());
+    }
+}
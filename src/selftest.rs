@@ -0,0 +1,270 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::hardware::{Address, Word, RAM};
+use crate::machine::Machine;
+use crate::translator::translate;
+use crate::vm::VM;
+
+// Small programs with known results, run to find builds that got miscompiled. A panic counts as a
+// failure of the check it happened in.
+const MAX_STEPS: u64 = 100_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    pub group: &'static str,
+    pub name: String,
+    pub failure: Option<String>,
+}
+
+type Computation = fn(Word, Word) -> Word;
+type JumpCondition = fn(Word) -> bool;
+
+// D starts as 37 and both A and M as 100.
+const COMPUTATIONS: [(&str, Computation); 18] = [
+    ("0", |_, _| 0),
+    ("1", |_, _| 1),
+    ("-1", |_, _| -1),
+    ("D", |x, _| x),
+    ("A", |_, y| y),
+    ("!D", |x, _| !x),
+    ("!A", |_, y| !y),
+    ("-D", |x, _| -x),
+    ("-A", |_, y| -y),
+    ("D+1", |x, _| x + 1),
+    ("A+1", |_, y| y + 1),
+    ("D-1", |x, _| x - 1),
+    ("A-1", |_, y| y - 1),
+    ("D+A", |x, y| x + y),
+    ("D-A", |x, y| x - y),
+    ("A-D", |x, y| y - x),
+    ("D&A", |x, y| x & y),
+    ("D|A", |x, y| x | y),
+];
+
+const JUMPS: [(&str, JumpCondition); 7] = [
+    ("JGT", |d| d > 0),
+    ("JEQ", |d| d == 0),
+    ("JGE", |d| d >= 0),
+    ("JLT", |d| d < 0),
+    ("JNE", |d| d != 0),
+    ("JLE", |d| d <= 0),
+    ("JMP", |_| true),
+];
+
+// The result is left on the stack and popped to RAM[5], checks are named by their last line.
+const VM_CHECKS: [(&str, Word); 16] = [
+    ("push constant 7\npush constant 8\nadd", 15),
+    ("push constant 7\npush constant 8\nsub", -1),
+    ("push constant 7\nneg", -7),
+    ("push constant 7\npush constant 7\neq", -1),
+    ("push constant 7\npush constant 8\ngt", 0),
+    ("push constant 7\npush constant 8\nlt", -1),
+    ("push constant 12\npush constant 10\nand", 8),
+    ("push constant 12\npush constant 10\nor", 14),
+    ("push constant 0\nnot", -1),
+    ("push constant 5\npop local 1\npush local 1", 5),
+    ("push constant 6\npop temp 7\npush temp 7", 6),
+    ("push constant 4\npop static 3\npush static 3", 4),
+    (
+        "push constant 3000\npop pointer 0\npush constant 9\npop this 2\n\
+         push constant 3002\npop pointer 1\npush that 0",
+        9,
+    ),
+    (
+        "push constant 0\npop local 0\npush constant 10\npop local 1\nlabel LOOP\n\
+         push local 0\npush local 1\nadd\npop local 0\npush local 1\npush constant 1\nsub\n\
+         pop local 1\npush local 1\nif-goto LOOP\npush local 0",
+        55,
+    ),
+    ("push constant 21\ncall Sys.double 1", 42),
+    ("push constant 10\ncall Sys.fib 1", 55),
+];
+
+const VM_FUNCTIONS: &str = "function Sys.double 0\npush argument 0\npush argument 0\nadd\nreturn\n\
+     function Sys.fib 0\npush argument 0\npush constant 2\nlt\nif-goto BASE\n\
+     push argument 0\npush constant 1\nsub\ncall Sys.fib 1\n\
+     push argument 0\npush constant 2\nsub\ncall Sys.fib 1\nadd\nreturn\n\
+     label BASE\npush argument 0\nreturn\n";
+
+const OS_CHECKS: [(&str, Word); 9] = [
+    (
+        "push constant 7\nneg\npush constant 6\ncall Math.multiply 2",
+        -42,
+    ),
+    ("push constant 100\npush constant 7\ncall Math.divide 2", 14),
+    ("push constant 1000\ncall Math.sqrt 1", 31),
+    ("push constant 3\npush constant 8\ncall Math.min 2", 3),
+    ("push constant 3\npush constant 8\ncall Math.max 2", 8),
+    ("push constant 5\nneg\ncall Math.abs 1", 5),
+    (
+        "push constant 3\ncall String.new 1\npush constant 49\ncall String.appendChar 2\n\
+         push constant 50\ncall String.appendChar 2\ncall String.intValue 1",
+        12,
+    ),
+    (
+        "push constant 10\ncall Memory.alloc 1\npop pointer 1\npush constant 77\npop that 3\n\
+         push pointer 1\npush constant 3\nadd\ncall Memory.peek 1",
+        77,
+    ),
+    (
+        "push constant 0\npush constant 0\ncall Screen.drawPixel 2\npop temp 1\n\
+         push constant 16384\ncall Memory.peek 1",
+        1,
+    ),
+];
+
+pub fn run_self_test() -> Vec<CheckResult> {
+    let mut results = vec![];
+    let mut check = |group, name: String, check: &dyn Fn() -> Result<(), String>| {
+        let failure = match catch_unwind(AssertUnwindSafe(check)) {
+            Ok(result) => result.err(),
+            Err(_) => Some("panicked".to_owned()),
+        };
+        results.push(CheckResult {
+            group,
+            name,
+            failure,
+        });
+    };
+
+    for (comp, computation) in COMPUTATIONS {
+        for comp in [comp.to_owned(), comp.replace('A', "M")] {
+            let expected = computation(37, 100);
+            check("ISA", format!("D={}", comp), &|| {
+                let hardware = run_asm(&format!(
+                    "@100\nD=A\nM=D\n@37\nD=A\n@100\nD={}\n@0\nM=D\n",
+                    comp
+                ))?;
+                expect(&hardware, 0, expected)
+            });
+            if !comp.contains('A') {
+                break;
+            }
+        }
+    }
+    for (jump, condition) in JUMPS {
+        for d in ["-1", "0", "1"] {
+            let expected = condition(d.parse().unwrap()) as Word;
+            check("ISA", format!("D={};{}", d, jump), &|| {
+                let hardware = run_asm(&format!(
+                    "@TAKEN\nD={};{}\n@0\nM=0\n@END\n0;JMP\n(TAKEN)\n@0\nM=1\n(END)\n",
+                    d, jump
+                ))?;
+                expect(&hardware, 0, expected)
+            });
+        }
+    }
+    check("ISA", "sum loop".to_owned(), &|| {
+        let hardware = run_asm(
+            "@i\nM=1\n@sum\nM=0\n(LOOP)\n@i\nD=M\n@100\nD=D-A\n@STOP\nD;JGT\n\
+             @i\nD=M\n@sum\nM=D+M\n@i\nM=M+1\n@LOOP\n0;JMP\n(STOP)\n@sum\nD=M\n@0\nM=D\n",
+        )?;
+        expect(&hardware, 0, 5050)
+    });
+
+    for (body, expected) in VM_CHECKS {
+        let name = body.lines().last().unwrap_or_default().to_owned();
+        check("VM", name.clone(), &|| {
+            let vm = run_vm(body, VM_FUNCTIONS)?;
+            expect(&vm, 5, expected)
+        });
+        check("Translation", name, &|| {
+            let vm = VM::from_file_contents(vec![vm_file(body, VM_FUNCTIONS)]);
+            let hardware = run_asm(&translate(&vm.program).asm)?;
+            expect(&hardware, 5, expected)
+        });
+    }
+    for (body, expected) in OS_CHECKS {
+        let name = body.lines().last().unwrap_or_default().to_owned();
+        check("OS", name, &|| {
+            let vm = run_vm(body, "")?;
+            expect(&vm, 5, expected)
+        });
+    }
+    // The top row of an A, one row down.
+    check("OS", "call Output.printChar 1".to_owned(), &|| {
+        let vm = run_vm("push constant 65\ncall Output.printChar 1", "")?;
+        expect(&vm, RAM::SCREEN + RAM::SCREEN_ROW_LENGTH, 12)
+    });
+
+    results
+}
+
+pub fn self_test_report(results: &[CheckResult]) -> String {
+    let mut report = String::new();
+    for result in results {
+        match &result.failure {
+            Some(failure) => report.push_str(&format!(
+                "FAIL {}: {}: {}\n",
+                result.group, result.name, failure
+            )),
+            None => report.push_str(&format!("ok   {}: {}\n", result.group, result.name)),
+        }
+    }
+    let passed = results
+        .iter()
+        .filter(|result| result.failure.is_none())
+        .count();
+    report.push_str(&format!("{} of {} checks passed\n", passed, results.len()));
+    report
+}
+
+fn run_to_halt(mut machine: Machine) -> Result<Machine, String> {
+    machine.run(MAX_STEPS);
+    if !machine.halted() {
+        return Err(format!("still running after {} steps", MAX_STEPS));
+    }
+    Ok(machine)
+}
+
+fn run_asm(source: &str) -> Result<Machine, String> {
+    run_to_halt(Machine::from_asm("Check.asm", source)?)
+}
+
+fn vm_file(body: &str, functions: &str) -> (String, String) {
+    (
+        "Sys.vm".to_owned(),
+        format!(
+            "function Sys.init 2\n{}\npop temp 0\npush constant 0\nreturn\n{}",
+            body, functions
+        ),
+    )
+}
+
+fn run_vm(body: &str, functions: &str) -> Result<Machine, String> {
+    run_to_halt(Machine::from_vm_files(&[vm_file(body, functions)])?)
+}
+
+fn expect(machine: &Machine, address: Word, expected: Word) -> Result<(), String> {
+    let value = machine.peek(Address::try_from(address)?);
+    if value == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "RAM[{}] is {}, expected {}",
+            address, value, expected
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test() {
+        let results = run_self_test();
+        let report = self_test_report(&results);
+        assert!(
+            results.iter().all(|result| result.failure.is_none()),
+            "{}",
+            report
+        );
+        assert!(report.starts_with("ok   ISA: D=0\n"));
+        assert!(report.ends_with(&format!(
+            "{} of {} checks passed\n",
+            results.len(),
+            results.len()
+        )));
+    }
+}
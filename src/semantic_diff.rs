@@ -0,0 +1,258 @@
+use hashbrown::{HashMap, HashSet};
+
+use crate::hardware::{Instruction, InstructionType, JumpCondition, Word, RAM};
+use crate::hardware_parse::{
+    assemble_with_order, expand_macros, instruction_labels, instruction_line_numbers,
+    parse_hack_binary, parse_instructions, AllocationOrder, AssemblyInstruction,
+    PREDEFINED_SYMBOLS,
+};
+
+const FIRST_VARIABLE: Word = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiffInstruction {
+    pub instruction: Instruction,
+    // Variables can be allocated in a different order as long as it's consistent.
+    pub variable: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub address: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ROM[{}]: {}", self.address, self.message)
+    }
+}
+
+fn from_asm(contents: &str, order: AllocationOrder) -> Result<Vec<DiffInstruction>, String> {
+    let expansion = expand_macros(contents).map_err(|error| error.to_string())?;
+    let (_, assembly_instructions) = parse_instructions(&expansion.text)
+        .map_err(|_| "the program doesn't assemble".to_owned())?;
+    let labels = instruction_labels(&assembly_instructions);
+    let symbols: HashSet<&str> = labels
+        .iter()
+        .map(|(label, _)| label.as_str())
+        .chain(PREDEFINED_SYMBOLS.iter().map(|(symbol, _)| *symbol))
+        .collect();
+    let variables = assembly_instructions
+        .iter()
+        .filter(|assembly_instruction| assembly_instruction.in_rom())
+        .map(|assembly_instruction| {
+            matches!(
+                assembly_instruction,
+                AssemblyInstruction::AtIdentifierInstruction(name) if !symbols.contains(name.as_str())
+            )
+        });
+
+    Ok(assemble_with_order(&assembly_instructions, order)?
+        .into_iter()
+        .zip(variables)
+        .map(|(instruction, variable)| DiffInstruction {
+            instruction,
+            variable,
+        })
+        .collect())
+}
+
+fn parse_hack(contents: &str) -> Result<Vec<Instruction>, String> {
+    parse_hack_binary(contents).map_err(|errors| errors[0].to_string())
+}
+
+// Without symbols, an A-instruction in the variable range is taken for a variable unless it's
+// loaded for a jump.
+fn from_hack(contents: &str) -> Result<Vec<DiffInstruction>, String> {
+    let instructions = parse_hack(contents)?;
+    Ok(instructions
+        .iter()
+        .enumerate()
+        .map(|(index, &instruction)| {
+            let loads_jump_target = instructions.get(index + 1).is_some_and(|next| {
+                next.instruction_type() == InstructionType::C
+                    && next.jump_condition() != JumpCondition::NoJump
+            });
+            DiffInstruction {
+                instruction,
+                variable: instruction.instruction_type() == InstructionType::A
+                    && (FIRST_VARIABLE..RAM::SCREEN).contains(&instruction.loaded_value())
+                    && !loads_jump_target,
+            }
+        })
+        .collect())
+}
+
+pub fn load_diff_program(file_name: &str, contents: &str) -> Result<Vec<DiffInstruction>, String> {
+    let program = match file_name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("asm") => from_asm(contents, AllocationOrder::FirstAppearance),
+        Some("hack") => from_hack(contents),
+        _ => Err("not an .asm or .hack file".to_owned()),
+    };
+    program.map_err(|error| format!("{}: {}", file_name, error))
+}
+
+// Label names don't survive assembly, so only the variables need matching up.
+pub fn semantic_diff(left: &[DiffInstruction], right: &[DiffInstruction]) -> Vec<Difference> {
+    let mut left_to_right: HashMap<Word, (Word, usize)> = HashMap::new();
+    let mut right_to_left: HashMap<Word, (Word, usize)> = HashMap::new();
+    let mut differences = vec![];
+    for address in 0..left.len().max(right.len()) {
+        let message = match (left.get(address), right.get(address)) {
+            (Some(l), Some(r)) if l.variable && r.variable => {
+                let (l_value, r_value) =
+                    (l.instruction.loaded_value(), r.instruction.loaded_value());
+                let matched_right = *left_to_right.entry(l_value).or_insert((r_value, address));
+                let matched_left = *right_to_left.entry(r_value).or_insert((l_value, address));
+                if matched_right.0 != r_value {
+                    Some(format!(
+                        "@{} and @{}, but RAM[{}] matched RAM[{}] at ROM[{}]",
+                        l_value, r_value, l_value, matched_right.0, matched_right.1
+                    ))
+                } else if matched_left.0 != l_value {
+                    Some(format!(
+                        "@{} and @{}, but RAM[{}] matched RAM[{}] at ROM[{}]",
+                        l_value, r_value, matched_left.0, r_value, matched_left.1
+                    ))
+                } else {
+                    None
+                }
+            }
+            (Some(l), Some(r)) => (l.instruction != r.instruction)
+                .then(|| format!("{} and {}", l.instruction, r.instruction)),
+            (Some(l), None) => Some(format!("{} is only in the first program", l.instruction)),
+            (None, Some(r)) => Some(format!("{} is only in the second program", r.instruction)),
+            (None, None) => unreachable!(),
+        };
+        differences.extend(message.map(|message| Difference { address, message }));
+    }
+
+    differences
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub difference: Difference,
+    // The source line the expected instruction was assembled from.
+    pub line: Option<(usize, String)>,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.difference)?;
+        if let Some((line, code)) = &self.line {
+            write!(f, " (line {}: {})", line, code)?;
+        }
+        Ok(())
+    }
+}
+
+// The source tells which A-instructions are variables, so without an order the binary only has to
+// allocate them consistently. With one it has to match byte for byte.
+pub fn check_assembler_output(
+    asm: &str,
+    hack: &str,
+    order: Option<AllocationOrder>,
+) -> Result<Option<Mismatch>, String> {
+    let mut expected = from_asm(asm, order.unwrap_or_default())?;
+    if order.is_some() {
+        for instruction in &mut expected {
+            instruction.variable = false;
+        }
+    }
+    let actual: Vec<_> = parse_hack(hack)?
+        .into_iter()
+        .zip(
+            expected
+                .iter()
+                .map(|instruction| instruction.variable)
+                .chain(std::iter::repeat(false)),
+        )
+        .map(|(instruction, variable)| DiffInstruction {
+            instruction,
+            variable: variable && instruction.instruction_type() == InstructionType::A,
+        })
+        .collect();
+    let Some(difference) = semantic_diff(&expected, &actual).into_iter().next() else {
+        return Ok(None);
+    };
+    let source_lines: Vec<&str> = asm.lines().collect();
+    let line = instruction_line_numbers(asm)
+        .get(difference.address)
+        .map(|&line| (line, source_lines[line - 1].trim().to_owned()));
+    Ok(Some(Mismatch { difference, line }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_semantic_diff() {
+        let left = load_diff_program(
+            "Left.asm",
+            "@i\nM=0\n@sum\nM=0\n(LOOP)\n@i\nD=M\n@LOOP\nD;JGT\n",
+        )
+        .unwrap();
+        let right = load_diff_program(
+            "Right.asm",
+            "@sum\nM=0\n@i\nM=0\n(START)\n@i\nD=M\n@START\nD;JGT\n",
+        )
+        .unwrap();
+        let hack = load_diff_program(
+            "Right.hack",
+            "0000000000010000\n1110101010001000\n0000000000010001\n1110101010001000\n\
+             0000000000010000\n1111110000010000\n0000000000000100\n1110001100000001\n",
+        )
+        .unwrap();
+
+        assert!(semantic_diff(&left, &hack).is_empty());
+        assert_eq!(
+            semantic_diff(&left, &right)
+                .into_iter()
+                .map(|difference| difference.to_string())
+                .collect::<Vec<_>>(),
+            vec!["ROM[4]: @16 and @17, but RAM[16] matched RAM[16] at ROM[0]"]
+        );
+        assert_eq!(
+            semantic_diff(&left, &left[..7]),
+            vec![Difference {
+                address: 7,
+                message: "D;JGT is only in the first program".to_owned(),
+            }]
+        );
+        assert!(load_diff_program("Left.hack", "0101\n2\n").is_err());
+    }
+
+    #[test]
+    fn test_check_assembler_output() {
+        let asm = "@j\nM=0\n@i\nM=1\n(LOOP)\n@i\nD=M // i\n@LOOP\nD;JGT\n";
+        let swapped = "0000000000010001\n1110101010001000\n0000000000010000\n1110111111001000\n\
+                       0000000000010000\n1111110000010000\n0000000000000100\n1110001100000001\n";
+
+        assert_eq!(check_assembler_output(asm, swapped, None), Ok(None));
+        assert_eq!(
+            check_assembler_output(asm, swapped, Some(AllocationOrder::Alphabetical)),
+            Ok(None)
+        );
+        assert!(
+            check_assembler_output(asm, swapped, Some(AllocationOrder::FirstAppearance))
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            check_assembler_output(
+                asm,
+                &swapped.replace("0000000000010000\n1111", "0000000000010001\n1111"),
+                None
+            )
+            .unwrap()
+            .map(|mismatch| mismatch.to_string()),
+            Some(
+                "ROM[4]: @17 and @17, but RAM[17] matched RAM[16] at ROM[2] (line 6: @i)"
+                    .to_owned()
+            )
+        );
+    }
+}
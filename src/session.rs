@@ -0,0 +1,702 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while1},
+    character::complete::{i64, line_ending, not_line_ending, space1, u32, u64},
+    combinator::{all_consuming, map, map_res, opt, value, verify},
+    multi::{count, many0},
+    sequence::{preceded, separated_pair, terminated, tuple},
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+use crate::hardware::{
+    Breakpoint, BreakpointVar, Hardware, Instruction, UWord, Word, MEM_SIZE, RAM,
+};
+use crate::os::{OSFields, OS};
+use crate::parse_utils::{IResult, ParsableWord};
+use crate::vm::{self, Frame, RunState, SegmentInit, StackCollision};
+
+// A row of the ROM, or of a VM file.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bookmark {
+    pub file: Option<String>,
+    pub row: usize,
+}
+
+// The first bookmark after `from`, or before it when going backwards, wrapping around.
+pub fn next_bookmark<'a>(
+    bookmarks: &'a BTreeSet<Bookmark>,
+    from: &Bookmark,
+    forward: bool,
+) -> Option<&'a Bookmark> {
+    if forward {
+        bookmarks
+            .range((Bound::Excluded(from), Bound::Unbounded))
+            .next()
+            .or_else(|| bookmarks.first())
+    } else {
+        bookmarks
+            .range(..from)
+            .next_back()
+            .or_else(|| bookmarks.last())
+    }
+}
+
+// Where the user was looking, so that a restored session doesn't start from the top.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ViewState {
+    pub selected_file: Option<String>,
+    // Pixels each grid is scrolled down by, keyed by the grid.
+    pub scroll_offsets: BTreeMap<String, u32>,
+    pub bookmarks: BTreeSet<Bookmark>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Session {
+    Hardware {
+        hardware: Hardware,
+        view: ViewState,
+    },
+    VM {
+        files: Vec<(String, String)>,
+        // Loaded but left out of the program.
+        disabled_files: Vec<String>,
+        // OS classes that run from their .vm files instead of natively.
+        vm_os_classes: Vec<String>,
+        segment_init: SegmentInit,
+        entry_function: Option<String>,
+        breakpoints: Vec<vm::Breakpoint>,
+        // Where the program got to, without the breakpoints. Sessions saved before runs were
+        // start the program over.
+        run_state: Option<Box<RunState>>,
+        view: ViewState,
+    },
+}
+
+impl Session {
+    pub fn view(&self) -> &ViewState {
+        match self {
+            Session::Hardware { view, .. } | Session::VM { view, .. } => view,
+        }
+    }
+
+    pub fn view_mut(&mut self) -> &mut ViewState {
+        match self {
+            Session::Hardware { view, .. } | Session::VM { view, .. } => view,
+        }
+    }
+}
+
+pub(crate) fn write_hardware_breakpoint_var(
+    f: &mut std::fmt::Formatter<'_>,
+    var: &BreakpointVar,
+) -> std::fmt::Result {
+    match var {
+        BreakpointVar::A => write!(f, "A"),
+        BreakpointVar::D => write!(f, "D"),
+        BreakpointVar::M => write!(f, "M"),
+        BreakpointVar::PC => write!(f, "PC"),
+        BreakpointVar::RAM(address) => write!(f, "RAM {address}"),
+    }
+}
+
+pub(crate) fn write_vm_breakpoint(
+    f: &mut std::fmt::Formatter<'_>,
+    breakpoint: &vm::Breakpoint,
+) -> std::fmt::Result {
+    match breakpoint {
+        vm::Breakpoint::SP(value) => write!(f, "SP {value}"),
+        vm::Breakpoint::CurrentFunction(function_name) => write!(f, "function {function_name}"),
+        vm::Breakpoint::Line {
+            file_name,
+            line_number,
+        } => write!(f, "line {line_number} {file_name}"),
+        vm::Breakpoint::RAM { address, value } => write!(f, "RAM {address} {value}"),
+        vm::Breakpoint::LCL(value) => write!(f, "LCL {value}"),
+        vm::Breakpoint::Local { offset, value } => write!(f, "local {offset} {value}"),
+        vm::Breakpoint::ARG(value) => write!(f, "ARG {value}"),
+        vm::Breakpoint::Argument { offset, value } => write!(f, "argument {offset} {value}"),
+        vm::Breakpoint::This(value) => write!(f, "THIS {value}"),
+        vm::Breakpoint::ThisPointer { offset, value } => write!(f, "this {offset} {value}"),
+        vm::Breakpoint::That(value) => write!(f, "THAT {value}"),
+        vm::Breakpoint::ThatPointer { offset, value } => write!(f, "that {offset} {value}"),
+        vm::Breakpoint::Temp { offset, value } => write!(f, "temp {offset} {value}"),
+    }
+}
+
+fn write_run_state(f: &mut std::fmt::Formatter<'_>, run_state: &RunState) -> std::fmt::Result {
+    writeln!(
+        f,
+        "run {} {}",
+        run_state.current_file_index, run_state.current_command_index
+    )?;
+    writeln!(f, "ticks {}", run_state.ticks)?;
+    writeln!(f, "depth {} {}", run_state.max_call_depth, run_state.max_sp)?;
+    for frame in &run_state.call_stack {
+        writeln!(f, "frame {}", frame.function_index)?;
+    }
+    if let Some(StackCollision { sp, address }) = run_state.stack_collision {
+        writeln!(f, "collision {sp} {address}")?;
+    }
+    if let Some(os_fault) = &run_state.os_fault {
+        writeln!(f, "fault {os_fault}")?;
+    }
+    let os = run_state.os.fields();
+    for (start, size) in &os.holes {
+        writeln!(f, "heap hole {start} {size}")?;
+    }
+    for (start, size) in &os.blocks {
+        writeln!(f, "heap block {start} {size}")?;
+    }
+    writeln!(f, "color {}", os.color as u8)?;
+    writeln!(f, "cursor {} {}", os.cursor.0, os.cursor.1)?;
+    if let Some(key) = os.pressed_key {
+        writeln!(f, "key {key}")?;
+    }
+    if let Some(line) = &os.line {
+        write!(f, "line")?;
+        for key in line {
+            write!(f, " {key}")?;
+        }
+        writeln!(f)?;
+    }
+    if let Some(wait_steps) = os.wait_steps {
+        writeln!(f, "wait {wait_steps}")?;
+    }
+    for (address, value) in run_state.ram.nonzero() {
+        writeln!(f, "ram {address} {value}")?;
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Session::Hardware { hardware, .. } => {
+                writeln!(f, "hardware")?;
+                writeln!(f, "a {}", hardware.a)?;
+                writeln!(f, "d {}", hardware.d)?;
+                writeln!(f, "pc {}", hardware.pc)?;
+                writeln!(f, "ticks {}", hardware.ticks)?;
+                writeln!(f, "rom {}", hardware.length)?;
+                for instruction in &hardware.rom[..hardware.length] {
+                    writeln!(
+                        f,
+                        "{:0width$b}",
+                        instruction.raw(),
+                        width = UWord::BITS as usize
+                    )?;
+                }
+                for (address, value) in hardware.ram.nonzero() {
+                    writeln!(f, "ram {address} {value}")?;
+                }
+                for breakpoint in &hardware.breakpoints {
+                    write!(f, "breakpoint ")?;
+                    write_hardware_breakpoint_var(f, &breakpoint.var)?;
+                    writeln!(f, " {}", breakpoint.value)?;
+                }
+            }
+            Session::VM {
+                files,
+                disabled_files,
+                vm_os_classes,
+                segment_init,
+                entry_function,
+                breakpoints,
+                run_state,
+                ..
+            } => {
+                writeln!(f, "vm")?;
+                for (name, contents) in files {
+                    writeln!(f, "file {} {}", contents.lines().count(), name)?;
+                    for line in contents.lines() {
+                        writeln!(f, "{line}")?;
+                    }
+                }
+                for name in disabled_files {
+                    writeln!(f, "disabled {name}")?;
+                }
+                for class_name in vm_os_classes {
+                    writeln!(f, "vm-os {class_name}")?;
+                }
+                let SegmentInit {
+                    sp,
+                    lcl,
+                    arg,
+                    this,
+                    that,
+                } = segment_init;
+                writeln!(f, "segments {sp} {lcl} {arg} {this} {that}")?;
+                if let Some(entry_function) = entry_function {
+                    writeln!(f, "entry {entry_function}")?;
+                }
+                for breakpoint in breakpoints {
+                    write!(f, "breakpoint ")?;
+                    write_vm_breakpoint(f, breakpoint)?;
+                    writeln!(f)?;
+                }
+                if let Some(run_state) = run_state {
+                    write_run_state(f, run_state)?;
+                }
+            }
+        }
+        let view = self.view();
+        if let Some(selected_file) = &view.selected_file {
+            writeln!(f, "view file {selected_file}")?;
+        }
+        for (grid, offset) in &view.scroll_offsets {
+            writeln!(f, "view scroll {offset} {grid}")?;
+        }
+        for bookmark in &view.bookmarks {
+            match &bookmark.file {
+                Some(file) => writeln!(f, "view bookmark {} {file}", bookmark.row)?,
+                None => writeln!(f, "view bookmark {}", bookmark.row)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn line<'a, O>(
+    parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    terminated(parser, line_ending)
+}
+
+fn field<'a, O>(
+    name: &'static str,
+    parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    line(preceded(tuple((tag(name), space1)), parser))
+}
+
+fn address(input: &str) -> IResult<&str, Word> {
+    verify(Word::parse_word, |address| {
+        (0..MEM_SIZE as i64).contains(&(*address as i64))
+    })(input)
+}
+
+fn instruction(input: &str) -> IResult<&str, Instruction> {
+    map(
+        map_res(take_while1(|c| c == '0' || c == '1'), |raw| {
+            UWord::from_str_radix(raw, 2)
+        }),
+        Instruction::new,
+    )(input)
+}
+
+fn hardware_breakpoint_var(input: &str) -> IResult<&str, BreakpointVar> {
+    alt((
+        value(BreakpointVar::A, tag("A")),
+        value(BreakpointVar::D, tag("D")),
+        value(BreakpointVar::M, tag("M")),
+        value(BreakpointVar::PC, tag("PC")),
+        map(preceded(tag("RAM "), address), BreakpointVar::RAM),
+    ))(input)
+}
+
+pub(crate) fn hardware_breakpoint(input: &str) -> IResult<&str, Breakpoint> {
+    map(
+        separated_pair(hardware_breakpoint_var, space1, Word::parse_word),
+        |(var, value)| Breakpoint { var, value },
+    )(input)
+}
+
+fn hardware_session(input: &str) -> IResult<&str, Session> {
+    let (input, _) = line(tag("hardware"))(input)?;
+    let (input, a) = field("a", Word::parse_word)(input)?;
+    let (input, d) = field("d", Word::parse_word)(input)?;
+    let (input, pc) = field("pc", Word::parse_word)(input)?;
+    let (input, ticks) = field("ticks", u64)(input)?;
+    let (input, length) = field("rom", verify(u64, |length| *length <= MEM_SIZE as u64))(input)?;
+    let (input, instructions) = count(line(instruction), length as usize)(input)?;
+    let (input, ram_values) = many0(field(
+        "ram",
+        separated_pair(address, space1, Word::parse_word),
+    ))(input)?;
+    let (input, breakpoints) = many0(field("breakpoint", hardware_breakpoint))(input)?;
+
+    let mut hardware = Hardware {
+        a,
+        d,
+        pc,
+        ticks,
+        length: length as usize,
+        breakpoints,
+        ..Default::default()
+    };
+    hardware.rom[..instructions.len()].copy_from_slice(&instructions);
+    for (address, value) in ram_values {
+        hardware.ram[address] = value;
+    }
+
+    let (input, view) = view_state(input)?;
+
+    Ok((input, Session::Hardware { hardware, view }))
+}
+
+fn view_state(input: &str) -> IResult<&str, ViewState> {
+    let (input, selected_file) = opt(field("view file", not_line_ending))(input)?;
+    let (input, scroll_offsets) = many0(field(
+        "view scroll",
+        separated_pair(u32, space1, not_line_ending),
+    ))(input)?;
+    let (input, bookmarks) = many0(field(
+        "view bookmark",
+        map(
+            tuple((u64, opt(preceded(space1, not_line_ending)))),
+            |(row, file): (u64, Option<&str>)| Bookmark {
+                file: file.map(str::to_owned),
+                row: row as usize,
+            },
+        ),
+    ))(input)?;
+
+    Ok((
+        input,
+        ViewState {
+            selected_file: selected_file.map(str::to_owned),
+            scroll_offsets: scroll_offsets
+                .into_iter()
+                .map(|(offset, grid)| (grid.to_owned(), offset))
+                .collect(),
+            bookmarks: bookmarks.into_iter().collect(),
+        },
+    ))
+}
+
+fn offset_value(input: &str) -> IResult<&str, (Word, Word)> {
+    separated_pair(Word::parse_word, space1, Word::parse_word)(input)
+}
+
+pub(crate) fn vm_breakpoint(input: &str) -> IResult<&str, vm::Breakpoint> {
+    alt((
+        map(preceded(tag("SP "), Word::parse_word), vm::Breakpoint::SP),
+        map(preceded(tag("function "), not_line_ending), |name: &str| {
+            vm::Breakpoint::CurrentFunction(name.to_owned())
+        }),
+        map(
+            preceded(
+                tag("line "),
+                separated_pair(Word::parse_word::<&str>, space1, not_line_ending),
+            ),
+            |(line_number, file_name)| vm::Breakpoint::Line {
+                file_name: file_name.to_owned(),
+                line_number,
+            },
+        ),
+        map(
+            preceded(
+                tag("RAM "),
+                separated_pair(address, space1, Word::parse_word),
+            ),
+            |(address, value)| vm::Breakpoint::RAM { address, value },
+        ),
+        map(preceded(tag("LCL "), Word::parse_word), vm::Breakpoint::LCL),
+        map(preceded(tag("local "), offset_value), |(offset, value)| {
+            vm::Breakpoint::Local { offset, value }
+        }),
+        map(preceded(tag("ARG "), Word::parse_word), vm::Breakpoint::ARG),
+        map(
+            preceded(tag("argument "), offset_value),
+            |(offset, value)| vm::Breakpoint::Argument { offset, value },
+        ),
+        map(
+            preceded(tag("THIS "), Word::parse_word),
+            vm::Breakpoint::This,
+        ),
+        map(preceded(tag("this "), offset_value), |(offset, value)| {
+            vm::Breakpoint::ThisPointer { offset, value }
+        }),
+        map(
+            preceded(tag("THAT "), Word::parse_word),
+            vm::Breakpoint::That,
+        ),
+        map(preceded(tag("that "), offset_value), |(offset, value)| {
+            vm::Breakpoint::ThatPointer { offset, value }
+        }),
+        map(preceded(tag("temp "), offset_value), |(offset, value)| {
+            vm::Breakpoint::Temp { offset, value }
+        }),
+    ))(input)
+}
+
+fn vm_file(input: &str) -> IResult<&str, (String, String)> {
+    let (input, (line_count, name)) =
+        field("file", separated_pair(u64, space1, not_line_ending))(input)?;
+    let (input, lines) = count(line(not_line_ending), line_count as usize)(input)?;
+
+    Ok((input, (name.to_owned(), lines.join("\n"))))
+}
+
+fn segment_init(input: &str) -> IResult<&str, SegmentInit> {
+    map(
+        tuple((
+            Word::parse_word,
+            preceded(space1, Word::parse_word),
+            preceded(space1, Word::parse_word),
+            preceded(space1, Word::parse_word),
+            preceded(space1, Word::parse_word),
+        )),
+        |(sp, lcl, arg, this, that)| SegmentInit {
+            sp,
+            lcl,
+            arg,
+            this,
+            that,
+        },
+    )(input)
+}
+
+fn run_state(input: &str) -> IResult<&str, RunState> {
+    let (input, (current_file_index, current_command_index)) =
+        field("run", separated_pair(u64, space1, u64))(input)?;
+    let (input, ticks) = field("ticks", u64)(input)?;
+    let (input, (max_call_depth, max_sp)) =
+        field("depth", separated_pair(u64, space1, Word::parse_word))(input)?;
+    let (input, call_stack) = many0(field(
+        "frame",
+        map(u64, |function_index| Frame {
+            function_index: function_index as usize,
+        }),
+    ))(input)?;
+    let (input, stack_collision) = opt(field(
+        "collision",
+        map(offset_value, |(sp, address)| StackCollision { sp, address }),
+    ))(input)?;
+    let (input, os_fault) = opt(field("fault", not_line_ending))(input)?;
+    let (input, holes) = many0(field("heap hole", offset_value))(input)?;
+    let (input, blocks) = many0(field("heap block", offset_value))(input)?;
+    let (input, color) = field(
+        "color",
+        alt((value(false, tag("0")), value(true, tag("1")))),
+    )(input)?;
+    let (input, cursor) = field("cursor", offset_value)(input)?;
+    let (input, pressed_key) = opt(field("key", Word::parse_word))(input)?;
+    let (input, keyboard_line) = opt(line(preceded(
+        tag("line"),
+        many0(preceded(space1, Word::parse_word)),
+    )))(input)?;
+    let (input, wait_steps) = opt(field("wait", i64))(input)?;
+    let (input, ram_values) = many0(field(
+        "ram",
+        separated_pair(address, space1, Word::parse_word),
+    ))(input)?;
+
+    let mut run_state = RunState {
+        current_file_index: current_file_index as usize,
+        current_command_index: current_command_index as usize,
+        ram: RAM {
+            contents: Box::new([0; MEM_SIZE]),
+        },
+        os: OS::from_fields(OSFields {
+            holes,
+            blocks,
+            color,
+            cursor,
+            pressed_key,
+            line: keyboard_line,
+            wait_steps,
+        }),
+        call_stack,
+        breakpoints: vec![],
+        ticks,
+        max_call_depth: max_call_depth as usize,
+        max_sp,
+        stack_collision,
+        os_fault: os_fault.map(str::to_owned),
+    };
+    for (address, value) in ram_values {
+        run_state.ram[address] = value;
+    }
+
+    Ok((input, run_state))
+}
+
+fn vm_session(input: &str) -> IResult<&str, Session> {
+    let (input, _) = line(tag("vm"))(input)?;
+    let (input, files) = many0(vm_file)(input)?;
+    let (input, disabled_files) = many0(field("disabled", not_line_ending))(input)?;
+    let (input, vm_os_classes) = many0(field("vm-os", not_line_ending))(input)?;
+    let (input, segments) = opt(field("segments", segment_init))(input)?;
+    let (input, entry_function) = opt(field("entry", not_line_ending))(input)?;
+    let (input, breakpoints) = many0(field("breakpoint", vm_breakpoint))(input)?;
+    let (input, run_state) = opt(run_state)(input)?;
+    let (input, view) = view_state(input)?;
+
+    Ok((
+        input,
+        Session::VM {
+            files,
+            disabled_files: disabled_files.into_iter().map(str::to_owned).collect(),
+            vm_os_classes: vm_os_classes.into_iter().map(str::to_owned).collect(),
+            segment_init: segments.unwrap_or_default(),
+            entry_function: entry_function.map(str::to_owned),
+            breakpoints,
+            run_state: run_state.map(Box::new),
+            view,
+        },
+    ))
+}
+
+pub fn parse_session(input: &str) -> IResult<&str, Session> {
+    all_consuming(alt((hardware_session, vm_session)))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hardware_session() {
+        let mut hardware = Hardware::from_file_contents("@5\nD=A\n@16\nM=D\n");
+        hardware.run(4);
+        hardware.add_breakpoint(&Breakpoint {
+            var: BreakpointVar::RAM(16),
+            value: 5,
+        });
+
+        let rom: String = [
+            0b0000000000000101,
+            0b1110110000010000,
+            0b0000000000010000,
+            0b1110001100001000,
+        ]
+        .into_iter()
+        .map(|legacy| {
+            format!(
+                "{:0width$b}\n",
+                Instruction::from_legacy(legacy).raw(),
+                width = UWord::BITS as usize
+            )
+        })
+        .collect();
+        assert_eq!(
+            Session::Hardware {
+                hardware,
+                view: Default::default(),
+            }
+            .to_string(),
+            format!(
+                "hardware\na 16\nd 5\npc 4\nticks 4\nrom 4\n{}ram 16 5\nbreakpoint RAM 16 5\n",
+                rom
+            )
+        );
+    }
+
+    #[test]
+    fn test_hardware_session_round_trip() {
+        let mut hardware =
+            Hardware::from_file_contents("@100\nD=A\n@SCREEN\nM=-1\n(END)\n@END\n0;JMP\n");
+        hardware.run(10);
+        hardware.add_breakpoint(&Breakpoint {
+            var: BreakpointVar::PC,
+            value: 4,
+        });
+        hardware.add_breakpoint(&Breakpoint {
+            var: BreakpointVar::RAM(100),
+            value: -1,
+        });
+        let session = Session::Hardware {
+            hardware,
+            view: ViewState {
+                selected_file: None,
+                scroll_offsets: [("RAM".to_owned(), 120), ("ROM".to_owned(), 0)].into(),
+                bookmarks: [Bookmark { file: None, row: 3 }].into(),
+            },
+        };
+
+        assert_eq!(parse_session(&session.to_string()), Ok(("", session)));
+    }
+
+    #[test]
+    fn test_vm_session_round_trip() {
+        let files = vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 0\npush constant 3\ncall Memory.alloc 1\npop static 0\n\
+                 push constant 65\ncall Output.printChar 1\npop temp 0\ncall Keyboard.readChar 0\n\
+                 return"
+                    .to_owned(),
+            ),
+            ("Empty.vm".to_owned(), "".to_owned()),
+        ];
+        let mut vm = vm::VM::from_file_contents(files.clone());
+        vm.run(100);
+        let session = Session::VM {
+            files,
+            disabled_files: vec!["Empty.vm".to_owned()],
+            vm_os_classes: vec!["Math".to_owned()],
+            segment_init: SegmentInit {
+                sp: 300,
+                ..Default::default()
+            },
+            entry_function: Some("Sys.init".to_owned()),
+            breakpoints: vec![
+                vm::Breakpoint::CurrentFunction("Main.main".to_owned()),
+                vm::Breakpoint::Line {
+                    file_name: "My Main.vm".to_owned(),
+                    line_number: 2,
+                },
+                vm::Breakpoint::ThatPointer {
+                    offset: 1,
+                    value: -5,
+                },
+            ],
+            run_state: Some(Box::new(vm.run_state)),
+            view: ViewState {
+                selected_file: Some("Sys".to_owned()),
+                scroll_offsets: [("VM Sys".to_owned(), 40)].into(),
+                bookmarks: [Bookmark {
+                    file: Some("Sys".to_owned()),
+                    row: 2,
+                }]
+                .into(),
+            },
+        };
+
+        assert_eq!(parse_session(&session.to_string()), Ok(("", session)));
+        // Sessions from before the setup and the run were saved.
+        let Ok((_, Session::VM { run_state, .. })) =
+            parse_session("vm\nfile 1 Main.vm\npush constant 1\n")
+        else {
+            panic!("expected a VM session");
+        };
+        assert_eq!(run_state, None);
+    }
+
+    #[test]
+    fn test_next_bookmark() {
+        let bookmark = |row| Bookmark { file: None, row };
+        let bookmarks: BTreeSet<_> = [bookmark(3), bookmark(10)].into();
+        assert_eq!(
+            next_bookmark(&bookmarks, &bookmark(0), true),
+            Some(&bookmark(3))
+        );
+        assert_eq!(
+            next_bookmark(&bookmarks, &bookmark(3), true),
+            Some(&bookmark(10))
+        );
+        assert_eq!(
+            next_bookmark(&bookmarks, &bookmark(10), true),
+            Some(&bookmark(3))
+        );
+        assert_eq!(
+            next_bookmark(&bookmarks, &bookmark(3), false),
+            Some(&bookmark(10))
+        );
+        assert_eq!(
+            next_bookmark(&bookmarks, &bookmark(5), false),
+            Some(&bookmark(3))
+        );
+        assert_eq!(next_bookmark(&BTreeSet::new(), &bookmark(5), false), None);
+    }
+
+    #[test]
+    fn test_invalid_session() {
+        assert!(parse_session("hardware\na 0\nd 0\npc 0\nticks 0\nrom 1\n").is_err());
+        assert!(parse_session("vm\nram 40000 1\n").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::ops::Range;
+
+// Where the translation of every VM command went in the ROM, commands are indices into
+// `Program::all_commands`. Labels translate to nothing, so their ranges are empty, and code
+// that isn't any command's, like the bootstrap, sits between the ranges.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    ranges: Vec<Range<usize>>,
+}
+
+impl SourceMap {
+    // Commands are pushed in order, each translated after the one before it.
+    pub fn push(&mut self, addresses: Range<usize>) {
+        debug_assert!(self
+            .ranges
+            .last()
+            .is_none_or(|last| last.end <= addresses.start));
+        self.ranges.push(addresses);
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn addresses(&self, command: usize) -> Option<Range<usize>> {
+        self.ranges.get(command).cloned()
+    }
+
+    // The command whose translation holds the instruction at `address`.
+    pub fn command_at(&self, address: usize) -> Option<usize> {
+        let command = self.ranges.partition_point(|range| range.end <= address);
+        self.ranges
+            .get(command)
+            .filter(|range| range.contains(&address))
+            .map(|_| command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map() {
+        let mut source_map = SourceMap::default();
+        // A bootstrap in 0..4, then a push, a label and an add.
+        source_map.push(4..11);
+        source_map.push(11..11);
+        source_map.push(11..16);
+
+        assert_eq!(source_map.len(), 3);
+        assert_eq!(source_map.addresses(1), Some(11..11));
+        assert_eq!(source_map.addresses(3), None);
+        assert_eq!(source_map.command_at(0), None);
+        assert_eq!(source_map.command_at(4), Some(0));
+        assert_eq!(source_map.command_at(10), Some(0));
+        assert_eq!(source_map.command_at(11), Some(2));
+        assert_eq!(source_map.command_at(16), None);
+    }
+}
@@ -0,0 +1,248 @@
+use hashbrown::HashMap;
+
+use crate::diagnostics::{STACK_END, STACK_START};
+use crate::hardware::{Word, RAM};
+use crate::vm::{Program, VMCommand};
+
+// The words a call pushes before the callee starts: return address, LCL, ARG, THIS and THAT.
+const SAVED_FRAME_WORDS: usize = 5;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StaticDepth {
+    // The call chain that needs the most stack, starting at the entry function.
+    Bounded { chain: Vec<String>, words: usize },
+    Recursive { cycle: Vec<String> },
+}
+
+impl std::fmt::Display for StaticDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StaticDepth::Bounded { chain, words } => write!(
+                f,
+                "{} calls deep, about {} stack words: {}",
+                chain.len(),
+                words,
+                chain.join(" → ")
+            ),
+            StaticDepth::Recursive { cycle } => {
+                write!(f, "Unbounded, recursion through {}", cycle.join(" → "))
+            }
+        }
+    }
+}
+
+fn function_name(program: &Program, function_index: usize) -> String {
+    program
+        .function_name(function_index)
+        .unwrap_or("(top level)")
+        .to_owned()
+}
+
+struct Analysis<'a> {
+    program: &'a Program,
+    // The stack words each function needs with its deepest callee, which is None for leaves.
+    costs: HashMap<usize, (usize, Option<usize>)>,
+    visiting: Vec<usize>,
+}
+
+impl Analysis<'_> {
+    // Fails with the cycle when the function can recurse.
+    fn cost(&mut self, function_index: usize) -> Result<usize, Vec<usize>> {
+        if let Some(&(words, _)) = self.costs.get(&function_index) {
+            return Ok(words);
+        }
+        if let Some(position) = self.visiting.iter().position(|&i| i == function_index) {
+            let mut cycle = self.visiting[position..].to_vec();
+            cycle.push(function_index);
+            return Err(cycle);
+        }
+        self.visiting.push(function_index);
+
+        let program = self.program;
+        // Branches are ignored, which is exact for compiled Jack where both sides of an if leave
+        // the stack at the same height.
+        let mut height: usize = 0;
+        let mut words = 0;
+        let mut deepest_callee = None;
+        for command in program.function_commands(function_index) {
+            match command {
+                VMCommand::Function {
+                    local_var_count, ..
+                } => height = (*local_var_count).max(0) as usize,
+                VMCommand::Push { .. } => height += 1,
+                VMCommand::Pop { .. }
+                | VMCommand::IfGoto { .. }
+                | VMCommand::Add
+                | VMCommand::Sub
+                | VMCommand::Eq
+                | VMCommand::Gt
+                | VMCommand::Lt
+                | VMCommand::And
+                | VMCommand::Or => height = height.saturating_sub(1),
+                VMCommand::Call {
+                    function_name,
+                    argument_count,
+                } => {
+                    let callee = (!program.is_native(function_name))
+                        .then(|| program.function_index(function_name))
+                        .flatten();
+                    let callee_words = match callee {
+                        Some(callee) => self.cost(callee)?,
+                        None => 0,
+                    };
+                    let call_words = height + SAVED_FRAME_WORDS + callee_words;
+                    if call_words > words {
+                        words = call_words;
+                        deepest_callee = callee;
+                    }
+                    height = height.saturating_sub((*argument_count).max(0) as usize) + 1;
+                }
+                _ => {}
+            }
+            words = words.max(height);
+        }
+
+        self.visiting.pop();
+        self.costs.insert(function_index, (words, deepest_callee));
+        Ok(words)
+    }
+}
+
+impl StaticDepth {
+    pub fn analyze(program: &Program, entry: usize) -> Self {
+        if entry >= program.function_metadata.len() {
+            return StaticDepth::Bounded {
+                chain: vec![],
+                words: 0,
+            };
+        }
+        let mut analysis = Analysis {
+            program,
+            costs: HashMap::new(),
+            visiting: vec![],
+        };
+        match analysis.cost(entry) {
+            Ok(words) => {
+                let mut chain = vec![function_name(program, entry)];
+                let mut current = entry;
+                while let Some(&(_, Some(callee))) = analysis.costs.get(&current) {
+                    chain.push(function_name(program, callee));
+                    current = callee;
+                }
+                StaticDepth::Bounded { chain, words }
+            }
+            Err(cycle) => StaticDepth::Recursive {
+                cycle: cycle
+                    .into_iter()
+                    .map(|function_index| function_name(program, function_index))
+                    .collect(),
+            },
+        }
+    }
+}
+
+fn region_name(address: i64) -> &'static str {
+    if address >= RAM::SCREEN as i64 {
+        "the screen"
+    } else {
+        "the heap"
+    }
+}
+
+// Bounded programs are checked against their worst case, recursive ones against how deep they
+// actually got.
+pub fn stack_depth_warning(
+    static_depth: &StaticDepth,
+    stack_start: Word,
+    max_sp: Word,
+) -> Option<String> {
+    let stack_start = stack_start.max(STACK_START) as i64;
+    let stack_end = STACK_END as i64;
+    match static_depth {
+        StaticDepth::Bounded { chain, words } => {
+            let top = stack_start + *words as i64 - 1;
+            (top > stack_end).then(|| {
+                format!(
+                    "{} may need {} stack words, reaching RAM[{}] in {}",
+                    chain.join(" → "),
+                    words,
+                    top,
+                    region_name(top)
+                )
+            })
+        }
+        StaticDepth::Recursive { cycle } => {
+            let used = max_sp as i64 - stack_start;
+            let capacity = stack_end + 1 - stack_start;
+            (used * 4 > capacity * 3).then(|| {
+                format!(
+                    "Recursion through {} has used {} of the {} stack words, going deeper \
+                     overwrites {}",
+                    cycle.join(" → "),
+                    used,
+                    capacity,
+                    region_name(max_sp as i64)
+                )
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_static_depth() {
+        let vm = VM::from_file_contents(vec![(
+            "Sys.vm".to_owned(),
+            "function Sys.init 1\npush constant 1\npush constant 2\ncall Sys.add 2\npop local 0\n\
+             call Sys.leaf 0\nreturn\n\
+             function Sys.add 0\npush argument 0\npush argument 1\nadd\nreturn\n\
+             function Sys.leaf 2\npush constant 0\nreturn\n"
+                .to_owned(),
+        )]);
+        let entry = vm.run_state.call_stack[0].function_index;
+
+        // Sys.init's local and the pushed arguments, Sys.add's frame and its two pushes.
+        assert_eq!(
+            StaticDepth::analyze(&vm.program, entry),
+            StaticDepth::Bounded {
+                chain: vec!["Sys.init".to_owned(), "Sys.add".to_owned()],
+                words: 10,
+            }
+        );
+        assert_eq!(
+            stack_depth_warning(&StaticDepth::analyze(&vm.program, entry), 2040, 0),
+            Some(
+                "Sys.init → Sys.add may need 10 stack words, reaching RAM[2049] in the heap"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn test_recursive_depth() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Sys.vm".to_owned(),
+            "function Sys.init 0\ncall Sys.down 0\nreturn\n\
+             function Sys.down 0\ncall Sys.down 0\nreturn\n"
+                .to_owned(),
+        )]);
+        let entry = vm.run_state.call_stack[0].function_index;
+        let static_depth = StaticDepth::analyze(&vm.program, entry);
+
+        assert_eq!(
+            static_depth,
+            StaticDepth::Recursive {
+                cycle: vec!["Sys.down".to_owned(), "Sys.down".to_owned()],
+            }
+        );
+        vm.run(100);
+        assert_eq!(vm.run_state.max_call_depth, 50);
+        assert!(stack_depth_warning(&static_depth, 256, vm.run_state.max_sp).is_none());
+        vm.run(600);
+        assert!(stack_depth_warning(&static_depth, 256, vm.run_state.max_sp).is_some());
+    }
+}
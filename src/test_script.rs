@@ -0,0 +1,1170 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::expression::is_address;
+use crate::hardware::{HackKey, Hardware, Word, RAM};
+use crate::machine::Machine;
+
+pub const MAX_TEST_STEPS: u64 = 100_000_000;
+// Loops whose bodies don't step count against this instead.
+pub const MAX_LOOP_ITERATIONS: u64 = MAX_TEST_STEPS;
+
+// FNV-1a over the pixels, eight per byte from the left of each row, so the hash doesn't depend
+// on the word size.
+pub fn screen_hash(ram: &RAM) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for y in 0..RAM::SCREEN_HEIGHT {
+        for byte_x in (0..RAM::SCREEN_WIDTH).step_by(8) {
+            let byte = (0..8).fold(0u8, |byte, bit| {
+                byte | ((ram.get_pixel(byte_x + bit, y) as u8) << bit)
+            });
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Separator,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            previous = c;
+                        }
+                        None => return Err(format!("Line {}: Unterminated comment", line)),
+                    }
+                }
+            }
+            ',' | ';' | '!' => tokens.push((Token::Separator, line)),
+            '{' => tokens.push((Token::Open, line)),
+            '}' => tokens.push((Token::Close, line)),
+            '"' | '\'' => {
+                let text: String =
+                    std::iter::from_fn(|| chars.next_if(|&next| next != c)).collect();
+                if chars.next().is_none() {
+                    return Err(format!("Line {}: Unterminated string", line));
+                }
+                tokens.push((Token::Text(text), line));
+            }
+            c => {
+                let mut word = c.to_string();
+                word.extend(std::iter::from_fn(|| {
+                    chars.next_if(|&c| !c.is_whitespace() && !",;!{}\"".contains(c))
+                }));
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Segment entries are relative to a pointer in RAM, temp entries to a fixed address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentBase {
+    Pointer(Word),
+    Fixed(Word),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestVariable {
+    RAM(Word),
+    A,
+    D,
+    PC,
+    Time,
+    Segment(SegmentBase, Word),
+}
+
+fn parse_index(name: &str, index: &str) -> Result<Word, String> {
+    index
+        .parse::<Word>()
+        .ok()
+        .filter(|&index| index >= 0)
+        .ok_or_else(|| format!("Invalid index in {}[{}]", name, index))
+}
+
+impl TestVariable {
+    fn new(source: &str) -> Result<Self, String> {
+        let lowercase = source.to_lowercase();
+        if let Some((name, index)) = lowercase
+            .strip_suffix(']')
+            .and_then(|source| source.split_once('['))
+        {
+            let index = parse_index(name, index)?;
+            let base = match name {
+                "ram" => return Ok(TestVariable::RAM(index)),
+                "local" => SegmentBase::Pointer(1),
+                "argument" => SegmentBase::Pointer(2),
+                "this" => SegmentBase::Pointer(3),
+                "that" => SegmentBase::Pointer(4),
+                "temp" => SegmentBase::Fixed(5),
+                _ => return Err(format!("Unknown variable {}", source)),
+            };
+            return Ok(TestVariable::Segment(base, index));
+        }
+        Ok(match lowercase.as_str() {
+            "a" => TestVariable::A,
+            "d" => TestVariable::D,
+            "pc" => TestVariable::PC,
+            "time" => TestVariable::Time,
+            "sp" => TestVariable::RAM(0),
+            "local" => TestVariable::RAM(1),
+            "argument" => TestVariable::RAM(2),
+            "this" => TestVariable::RAM(3),
+            "that" => TestVariable::RAM(4),
+            _ => return Err(format!("Unknown variable {}", source)),
+        })
+    }
+}
+
+fn parse_value(source: &str) -> Result<Word, String> {
+    let invalid = || format!("Invalid value {}", source);
+    let (radix, digits) = match source.get(..2).map(str::to_uppercase).as_deref() {
+        Some("%B") => (2, &source[2..]),
+        Some("%X") => (16, &source[2..]),
+        Some("%D") => (10, &source[2..]),
+        _ => (10, source),
+    };
+    let value = i64::from_str_radix(digits, radix).map_err(|_| invalid())?;
+    let min = if radix == 10 { Word::MIN as i64 } else { 0 };
+    if !(min..=(1 << Word::BITS) - 1).contains(&value) {
+        return Err(invalid());
+    }
+
+    Ok(value as Word)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueFormat {
+    Binary,
+    Decimal,
+    Hex,
+    String,
+}
+
+// An output-list entry, e.g. `RAM[0]%D2.6.2` is RAM[0] in decimal, 6 characters wide with 2
+// spaces on either side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputColumn {
+    pub name: String,
+    pub variable: TestVariable,
+    pub format: ValueFormat,
+    pub pad_left: usize,
+    pub len: usize,
+    pub pad_right: usize,
+}
+
+impl OutputColumn {
+    fn new(source: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid output format in {}", source);
+        let (name, format) = source.split_once('%').unwrap_or((source, "D1.6.1"));
+        let mut format_chars = format.chars();
+        let value_format = match format_chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('B') => ValueFormat::Binary,
+            Some('D') => ValueFormat::Decimal,
+            Some('X') => ValueFormat::Hex,
+            Some('S') => ValueFormat::String,
+            _ => return Err(invalid()),
+        };
+        let widths = format_chars
+            .as_str()
+            .split('.')
+            .map(|width| width.parse::<usize>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [pad_left, len, pad_right] = widths[..] else {
+            return Err(invalid());
+        };
+
+        Ok(OutputColumn {
+            name: name.to_owned(),
+            variable: TestVariable::new(name)?,
+            format: value_format,
+            pad_left,
+            len,
+            pad_right,
+        })
+    }
+
+    fn width(&self) -> usize {
+        self.pad_left + self.len + self.pad_right
+    }
+
+    fn header(&self) -> String {
+        let name: String = self.name.chars().take(self.width()).collect();
+        let left = (self.width() - name.len()) / 2;
+        format!(
+            "{}{}{}",
+            " ".repeat(left),
+            name,
+            " ".repeat(self.width() - left - name.len())
+        )
+    }
+
+    fn cell(&self, value: Word) -> String {
+        let unsigned = value as i64 & ((1 << Word::BITS) - 1);
+        let len = self.len;
+        let text = match self.format {
+            ValueFormat::Decimal => format!("{:>len$}", value),
+            ValueFormat::String => format!("{:<len$}", value),
+            ValueFormat::Binary => format!("{:0>len$b}", unsigned),
+            ValueFormat::Hex => format!("{:0>len$X}", unsigned),
+        };
+        // Values that don't fit keep their rightmost digits.
+        let text = &text[text.len() - len.min(text.len())..];
+        format!(
+            "{}{}{}",
+            " ".repeat(self.pad_left),
+            text,
+            " ".repeat(self.pad_right)
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Condition {
+    variable: TestVariable,
+    comparison: Comparison,
+    value: Word,
+}
+
+impl Condition {
+    fn holds(&self, value: Word) -> bool {
+        match self.comparison {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Less => value < self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Statement {
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<OutputColumn>),
+    Set(TestVariable, Word),
+    Step,
+    Output,
+    Echo(String),
+    ClearEcho,
+    AssertPixel { x: Word, y: Word, value: bool },
+    AssertScreenHash(u64),
+    KeyPress { key: Word, steps: u64 },
+    Ignored,
+    Repeat(Option<u64>, Vec<Statement>),
+    While(Condition, Vec<Statement>),
+}
+
+// A quoted character, a key name such as `newline` or `f1`, or a key code.
+fn parse_key(token: &Token) -> Result<Word, String> {
+    let invalid = |key: &str| format!("Invalid key {}", key);
+    match token {
+        Token::Text(text) => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if (' '..='~').contains(&c) => Ok(c as Word),
+                _ => Err(invalid(text)),
+            }
+        }
+        Token::Word(word) => {
+            if word.eq_ignore_ascii_case("space") {
+                return Ok(' ' as Word);
+            }
+            if let Some(key) = HackKey::from_name(word) {
+                return Ok(key.code());
+            }
+            parse_value(word).map_err(|_| invalid(word))
+        }
+        _ => Err("Expected a key".to_owned()),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position.min(self.tokens.len().saturating_sub(1)))
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect_open(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Open) => Ok(()),
+            _ => Err("Expected {".to_owned()),
+        }
+    }
+
+    fn block(&mut self, nested: bool) -> Result<Vec<Statement>, String> {
+        let mut statements = vec![];
+        loop {
+            let line = self.line();
+            match self.next() {
+                None if nested => return Err("Missing }".to_owned()),
+                None => return Ok(statements),
+                Some(Token::Close) if nested => return Ok(statements),
+                Some(Token::Separator) => {}
+                Some(Token::Word(command)) => statements.push(
+                    self.statement(&command)
+                        .map_err(|error| format!("Line {}: {}", line, error))?,
+                ),
+                Some(_) => return Err(format!("Line {}: Expected a command", line)),
+            }
+        }
+    }
+
+    // Arguments run until the separator or brace that ends the command.
+    fn arguments(&mut self) -> Vec<Token> {
+        let mut arguments = vec![];
+        while let Some(token @ (Token::Word(_) | Token::Text(_))) = self.peek().cloned() {
+            arguments.push(token);
+            self.position += 1;
+        }
+        arguments
+    }
+
+    fn statement(&mut self, command: &str) -> Result<Statement, String> {
+        let command = command.to_lowercase();
+        if command == "key-press" {
+            let (key, steps) = match self.arguments().as_slice() {
+                [key, Token::Word(for_word), Token::Word(steps)] if for_word == "for" => (
+                    parse_key(key)?,
+                    steps
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&steps| steps > 0)
+                        .ok_or_else(|| format!("Invalid step count {}", steps))?,
+                ),
+                _ => return Err("Expected key-press <key> for <steps>".to_owned()),
+            };
+            return Ok(Statement::KeyPress { key, steps });
+        }
+        if command == "repeat" {
+            let count = match self.peek().cloned() {
+                Some(Token::Word(count)) => {
+                    self.position += 1;
+                    Some(
+                        count
+                            .parse::<u64>()
+                            .map_err(|_| format!("Invalid repeat count {}", count))?,
+                    )
+                }
+                _ => None,
+            };
+            self.expect_open()?;
+            return Ok(Statement::Repeat(count, self.block(true)?));
+        }
+
+        let arguments: Vec<_> = self
+            .arguments()
+            .into_iter()
+            .map(|token| match token {
+                Token::Word(word) | Token::Text(word) => word,
+                _ => unreachable!(),
+            })
+            .collect();
+        let argument = |index: usize| {
+            arguments
+                .get(index)
+                .map(String::as_str)
+                .ok_or_else(|| format!("Missing argument for {}", command))
+        };
+        Ok(match command.as_str() {
+            "while" => {
+                let comparison = match argument(1)? {
+                    "=" => Comparison::Equal,
+                    "<>" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    ">" => Comparison::Greater,
+                    "<=" => Comparison::LessOrEqual,
+                    ">=" => Comparison::GreaterOrEqual,
+                    operator => return Err(format!("Unknown comparison {}", operator)),
+                };
+                let condition = Condition {
+                    variable: TestVariable::new(argument(0)?)?,
+                    comparison,
+                    value: parse_value(argument(2)?)?,
+                };
+                self.expect_open()?;
+                Statement::While(condition, self.block(true)?)
+            }
+            "load" => Statement::Load(arguments.first().cloned()),
+            "output-file" => Statement::OutputFile(argument(0)?.to_owned()),
+            "compare-to" => Statement::CompareTo(argument(0)?.to_owned()),
+            "output-list" => Statement::OutputList(
+                arguments
+                    .iter()
+                    .map(|column| OutputColumn::new(column))
+                    .collect::<Result<_, _>>()?,
+            ),
+            "set" => Statement::Set(TestVariable::new(argument(0)?)?, parse_value(argument(1)?)?),
+            "ticktock" | "vmstep" => Statement::Step,
+            "output" => Statement::Output,
+            "echo" => Statement::Echo(arguments.join(" ")),
+            "clear-echo" => Statement::ClearEcho,
+            "assert-pixel" => {
+                let coordinate = |index: usize, limit: Word| {
+                    let text = argument(index)?;
+                    text.parse::<Word>()
+                        .ok()
+                        .filter(|value| (0..limit).contains(value))
+                        .ok_or_else(|| format!("Invalid pixel coordinate {}", text))
+                };
+                Statement::AssertPixel {
+                    x: coordinate(0, RAM::SCREEN_WIDTH)?,
+                    y: coordinate(1, RAM::SCREEN_HEIGHT)?,
+                    value: match argument(2)? {
+                        "0" => false,
+                        "1" => true,
+                        value => return Err(format!("Invalid pixel value {}", value)),
+                    },
+                }
+            }
+            "assert-screen-hash" => {
+                let hash = argument(0)?;
+                Statement::AssertScreenHash(
+                    u64::from_str_radix(hash.trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("Invalid screen hash {}", hash))?,
+                )
+            }
+            "breakpoint" | "clear-breakpoints" => Statement::Ignored,
+            "tick" | "tock" | "eval" => {
+                return Err(format!("{} is only supported in chip tests", command))
+            }
+            _ => return Err(format!("Unknown command {}", command)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TestFailure {
+    Comparison {
+        line: usize,
+        expected: Option<String>,
+        actual: String,
+    },
+    Assertion(String),
+    Error(String),
+}
+
+impl std::fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TestFailure::Comparison {
+                line,
+                expected: Some(expected),
+                actual,
+            } => write!(
+                f,
+                "Comparison failure at line {}: expected \"{}\", got \"{}\"",
+                line, expected, actual
+            ),
+            TestFailure::Comparison {
+                line,
+                expected: None,
+                actual,
+            } => write!(
+                f,
+                "Comparison failure at line {}: the compare file ended, got \"{}\"",
+                line, actual
+            ),
+            TestFailure::Assertion(message) | TestFailure::Error(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestOutcome {
+    pub output: Vec<String>,
+    pub expected: Vec<String>,
+    pub echo: Vec<String>,
+    pub steps: u64,
+    pub result: Result<(), TestFailure>,
+}
+
+impl TestOutcome {
+    pub fn error(error: String) -> Self {
+        TestOutcome {
+            output: vec![],
+            expected: vec![],
+            echo: vec![],
+            steps: 0,
+            result: Err(TestFailure::Error(error)),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TestEvent {
+    Output(String),
+    Echo(String),
+}
+
+// Where a script loads programs and compare files from and writes its output to.
+pub trait TestFiles {
+    fn read(&self, name: &str) -> Result<String, String>;
+    // All .vm files in the script's directory, in a subdirectory or a single file.
+    fn vm_files(&self, name: Option<&str>) -> Result<Vec<(String, String)>, String>;
+    fn write(&self, name: &str, contents: &str) -> Result<(), String>;
+}
+
+pub struct DirectoryFiles(pub PathBuf);
+
+impl TestFiles for DirectoryFiles {
+    fn read(&self, name: &str) -> Result<String, String> {
+        let path = self.0.join(name);
+        std::fs::read_to_string(&path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))
+    }
+
+    fn vm_files(&self, name: Option<&str>) -> Result<Vec<(String, String)>, String> {
+        if let Some(name) = name.filter(|name| name.to_lowercase().ends_with(".vm")) {
+            return Ok(vec![(name.to_owned(), self.read(name)?)]);
+        }
+        let directory = name.map_or_else(|| self.0.clone(), |name| self.0.join(name));
+        let mut paths: Vec<_> = std::fs::read_dir(&directory)
+            .map_err(|error| format!("Failed to read {}: {}", directory.display(), error))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("vm"))
+            })
+            .collect();
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                Ok((name.into_owned(), contents))
+            })
+            .collect()
+    }
+
+    fn write(&self, name: &str, contents: &str) -> Result<(), String> {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents)
+            .map_err(|error| format!("Failed to write {}: {}", path.display(), error))
+    }
+}
+
+// What test scripts need on top of the machine, variables are named like in the course's tools.
+trait TestMachine: Sized {
+    fn load(files: &impl TestFiles, name: Option<&str>) -> Result<Self, String>;
+    fn address(&self, variable: TestVariable) -> Result<Option<Word>, String>;
+    fn hardware_mut(&mut self, variable: TestVariable) -> Result<&mut Hardware, String>;
+    fn get(&self, variable: TestVariable) -> Result<Word, String>;
+    fn set(&mut self, variable: TestVariable, value: Word) -> Result<(), String>;
+}
+
+impl TestMachine for Machine {
+    fn load(files: &impl TestFiles, name: Option<&str>) -> Result<Self, String> {
+        let extension = name
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_lowercase());
+        match (name, extension.as_deref()) {
+            (Some(name), Some("asm")) => Machine::from_asm(name, &files.read(name)?),
+            (Some(name), Some("hack")) => Machine::from_hack(name, &files.read(name)?),
+            (Some(_), Some("hdl")) => Err("Chip tests aren't supported".to_owned()),
+            _ => Machine::from_vm_files(&files.vm_files(name)?),
+        }
+    }
+
+    fn address(&self, variable: TestVariable) -> Result<Option<Word>, String> {
+        let address = match variable {
+            TestVariable::RAM(address) => address as i64,
+            TestVariable::Segment(SegmentBase::Pointer(pointer), index) => {
+                self.ram()[pointer] as i64 + index as i64
+            }
+            TestVariable::Segment(SegmentBase::Fixed(base), index) => base as i64 + index as i64,
+            _ => return Ok(None),
+        };
+        if !is_address(address) {
+            return Err(format!("RAM[{}] is out of range", address));
+        }
+        Ok(Some(address as Word))
+    }
+
+    fn hardware_mut(&mut self, variable: TestVariable) -> Result<&mut Hardware, String> {
+        match self {
+            Machine::Hardware(hardware) => Ok(hardware),
+            Machine::VM(_) => Err(format!("{:?} isn't available in VM tests", variable)),
+        }
+    }
+
+    fn get(&self, variable: TestVariable) -> Result<Word, String> {
+        if let Some(address) = self.address(variable)? {
+            return Ok(self.ram()[address]);
+        }
+        match (self, variable) {
+            (_, TestVariable::Time) => Ok(self.ticks() as Word),
+            (Machine::Hardware(hardware), TestVariable::A) => Ok(hardware.a),
+            (Machine::Hardware(hardware), TestVariable::D) => Ok(hardware.d),
+            (Machine::Hardware(hardware), TestVariable::PC) => Ok(hardware.pc),
+            _ => Err(format!("{:?} isn't available in VM tests", variable)),
+        }
+    }
+
+    fn set(&mut self, variable: TestVariable, value: Word) -> Result<(), String> {
+        if let Some(address) = self.address(variable)? {
+            self.ram_mut()[address] = value;
+            return Ok(());
+        }
+        match variable {
+            TestVariable::A => self.hardware_mut(variable)?.a = value,
+            TestVariable::D => self.hardware_mut(variable)?.d = value,
+            TestVariable::PC => self.hardware_mut(variable)?.pc = value,
+            _ => return Err("time can't be set".to_owned()),
+        }
+        Ok(())
+    }
+}
+
+struct TestRun<'a, F: TestFiles> {
+    files: &'a F,
+    observer: &'a mut dyn FnMut(&TestEvent),
+    machine: Option<Machine>,
+    columns: Vec<OutputColumn>,
+    output: Vec<String>,
+    output_file: Option<String>,
+    expected: Vec<String>,
+    comparing: bool,
+    echo: Vec<String>,
+    steps: u64,
+    iterations: u64,
+    cancelled: &'a AtomicBool,
+    // The key held on the keyboard and the number of steps left until it is released.
+    held_key: Option<(Word, u64)>,
+}
+
+impl<F: TestFiles> TestRun<'_, F> {
+    fn machine(&mut self) -> Result<&mut Machine, TestFailure> {
+        self.machine
+            .as_mut()
+            .ok_or_else(|| TestFailure::Error("No program was loaded".to_owned()))
+    }
+
+    fn iterate(&mut self) -> Result<(), TestFailure> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(TestFailure::Error("The test was cancelled".to_owned()));
+        }
+        if self.iterations == MAX_LOOP_ITERATIONS {
+            return Err(TestFailure::Error(format!(
+                "The test's loops didn't finish within {} iterations",
+                MAX_LOOP_ITERATIONS
+            )));
+        }
+        self.iterations += 1;
+        Ok(())
+    }
+
+    fn push_line(&mut self, line: String) -> Result<(), TestFailure> {
+        (self.observer)(&TestEvent::Output(line.clone()));
+        self.output.push(line);
+        let index = self.output.len() - 1;
+        let actual = self.output[index].trim_end();
+        let expected = self.expected.get(index).map(|line| line.trim_end());
+        if self.comparing && expected != Some(actual) {
+            return Err(TestFailure::Comparison {
+                line: index + 1,
+                expected: expected.map(str::to_owned),
+                actual: actual.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, statements: &[Statement]) -> Result<(), TestFailure> {
+        for statement in statements {
+            match statement {
+                Statement::Load(name) => {
+                    self.machine = Some(
+                        Machine::load(self.files, name.as_deref()).map_err(TestFailure::Error)?,
+                    )
+                }
+                Statement::OutputFile(name) => self.output_file = Some(name.clone()),
+                Statement::CompareTo(name) => {
+                    let contents = self.files.read(name).map_err(TestFailure::Error)?;
+                    self.expected = contents.lines().map(str::to_owned).collect();
+                    self.comparing = true;
+                }
+                Statement::OutputList(columns) => {
+                    self.columns.clone_from(columns);
+                    let header = columns.iter().map(OutputColumn::header).collect::<Vec<_>>();
+                    self.push_line(format!("|{}|", header.join("|")))?;
+                }
+                Statement::Set(variable, value) => self
+                    .machine()?
+                    .set(*variable, *value)
+                    .map_err(TestFailure::Error)?,
+                Statement::Step => {
+                    if self.steps == MAX_TEST_STEPS {
+                        return Err(TestFailure::Error(format!(
+                            "The test didn't finish within {} steps",
+                            MAX_TEST_STEPS
+                        )));
+                    }
+                    let held_key = self.held_key;
+                    let machine = self.machine()?;
+                    if let Some((key, _)) = held_key {
+                        machine.ram_mut().set_keyboard(key);
+                    }
+                    machine.step();
+                    if let Some((_, 1)) = held_key {
+                        machine.ram_mut().set_keyboard(0);
+                    }
+                    self.held_key = held_key
+                        .map(|(key, remaining)| (key, remaining - 1))
+                        .filter(|(_, remaining)| *remaining > 0);
+                    self.steps += 1;
+                }
+                Statement::Output => {
+                    let machine = self
+                        .machine
+                        .as_ref()
+                        .ok_or_else(|| TestFailure::Error("No program was loaded".to_owned()))?;
+                    let cells = self
+                        .columns
+                        .iter()
+                        .map(|column| Ok(column.cell(machine.get(column.variable)?)))
+                        .collect::<Result<Vec<_>, String>>()
+                        .map_err(TestFailure::Error)?;
+                    self.push_line(format!("|{}|", cells.join("|")))?;
+                }
+                Statement::Echo(text) => {
+                    (self.observer)(&TestEvent::Echo(text.clone()));
+                    self.echo.push(text.clone());
+                }
+                Statement::ClearEcho => self.echo.clear(),
+                Statement::AssertPixel { x, y, value } => {
+                    let steps = self.steps;
+                    let actual = self.machine()?.ram().get_pixel(*x, *y);
+                    if actual != *value {
+                        return Err(TestFailure::Assertion(format!(
+                            "assert-pixel {} {} {} failed after {} steps: the pixel is {}",
+                            x, y, *value as u8, steps, actual as u8
+                        )));
+                    }
+                }
+                Statement::AssertScreenHash(hash) => {
+                    let steps = self.steps;
+                    let actual = screen_hash(self.machine()?.ram());
+                    if actual != *hash {
+                        return Err(TestFailure::Assertion(format!(
+                            "assert-screen-hash {:016x} failed after {} steps: the screen hash \
+                             is {:016x}",
+                            hash, steps, actual
+                        )));
+                    }
+                }
+                Statement::KeyPress { key, steps } => {
+                    self.machine()?.ram_mut().set_keyboard(*key);
+                    self.held_key = Some((*key, *steps));
+                }
+                Statement::Ignored => {}
+                Statement::Repeat(count, body) => {
+                    for _ in 0..count.unwrap_or(u64::MAX) {
+                        self.iterate()?;
+                        self.execute(body)?;
+                    }
+                }
+                Statement::While(condition, body) => {
+                    while condition.holds(
+                        self.machine()?
+                            .get(condition.variable)
+                            .map_err(TestFailure::Error)?,
+                    ) {
+                        self.iterate()?;
+                        self.execute(body)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// A CPU or VM emulator test script, as used by the course's .tst files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestScript {
+    statements: Vec<Statement>,
+}
+
+impl TestScript {
+    pub fn new(input: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+        };
+        Ok(TestScript {
+            statements: parser.block(false)?,
+        })
+    }
+
+    pub fn run(&self, files: &impl TestFiles, observer: &mut dyn FnMut(&TestEvent)) -> TestOutcome {
+        self.run_cancellable(files, observer, &AtomicBool::new(false))
+    }
+
+    // Stops with an error soon after `cancelled` is set.
+    pub fn run_cancellable(
+        &self,
+        files: &impl TestFiles,
+        observer: &mut dyn FnMut(&TestEvent),
+        cancelled: &AtomicBool,
+    ) -> TestOutcome {
+        let mut run = TestRun {
+            files,
+            observer,
+            machine: None,
+            columns: vec![],
+            output: vec![],
+            output_file: None,
+            expected: vec![],
+            comparing: false,
+            echo: vec![],
+            steps: 0,
+            iterations: 0,
+            cancelled,
+            held_key: None,
+        };
+        let mut result = run.execute(&self.statements);
+        if let Some(output_file) = &run.output_file {
+            let mut contents = run.output.join("\n");
+            contents.push('\n');
+            if let Err(error) = files.write(output_file, &contents) {
+                result = result.and(Err(TestFailure::Error(error)));
+            }
+        }
+
+        TestOutcome {
+            output: run.output,
+            expected: run.expected,
+            echo: run.echo,
+            steps: run.steps,
+            result,
+        }
+    }
+}
+
+pub fn run_test_file(
+    path: &Path,
+    observer: &mut dyn FnMut(&TestEvent),
+    cancelled: &AtomicBool,
+) -> TestOutcome {
+    let script = std::fs::read_to_string(path)
+        .map_err(|error| format!("Failed to read {}: {}", path.display(), error))
+        .and_then(|contents| TestScript::new(&contents));
+    match script {
+        Ok(script) => {
+            let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+            script.run_cancellable(&DirectoryFiles(directory), observer, cancelled)
+        }
+        Err(error) => TestOutcome::error(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use hashbrown::HashMap;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryFiles {
+        files: HashMap<String, String>,
+        written: RefCell<HashMap<String, String>>,
+    }
+
+    impl TestFiles for MemoryFiles {
+        fn read(&self, name: &str) -> Result<String, String> {
+            self.files
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("No file {}", name))
+        }
+
+        fn vm_files(&self, name: Option<&str>) -> Result<Vec<(String, String)>, String> {
+            let mut files: Vec<_> = self
+                .files
+                .iter()
+                .filter(|(file_name, _)| {
+                    file_name.ends_with(".vm") && name.is_none_or(|name| name == *file_name)
+                })
+                .map(|(file_name, contents)| (file_name.clone(), contents.clone()))
+                .collect();
+            files.sort();
+            Ok(files)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> Result<(), String> {
+            self.written
+                .borrow_mut()
+                .insert(name.to_owned(), contents.to_owned());
+            Ok(())
+        }
+    }
+
+    fn files(files: &[(&str, &str)]) -> MemoryFiles {
+        MemoryFiles {
+            files: files
+                .iter()
+                .map(|(name, contents)| (name.to_string(), contents.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    const ADD_ASM: &str = "@0\nD=M\n@1\nD=D+M\n@2\nM=D\n";
+
+    const ADD_TST: &str = "load Add.asm,
+        output-file Add.out,
+        compare-to Add.cmp,
+        output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+
+        /* Adds two numbers */
+        set RAM[0] 3, set RAM[1] 5;
+        repeat 6 {
+            ticktock;
+        }
+        output; // The sum
+        set PC 0, set RAM[0] %X10;
+        while PC < 6 { ticktock; }
+        output;
+        ";
+
+    #[test]
+    fn test_cpu_script() {
+        let files = files(&[
+            ("Add.asm", ADD_ASM),
+            (
+                "Add.cmp",
+                "|  RAM[0]  |  RAM[1]  |  RAM[2]  |\n\
+                 |       3  |       5  |       8  |\n\
+                 |      16  |       5  |      21  |\n",
+            ),
+        ]);
+        let mut events = vec![];
+        let outcome = TestScript::new(ADD_TST)
+            .unwrap()
+            .run(&files, &mut |event| events.push(event.clone()));
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.steps, 12);
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            files.written.borrow()["Add.out"],
+            "|  RAM[0]  |  RAM[1]  |  RAM[2]  |\n\
+             |       3  |       5  |       8  |\n\
+             |      16  |       5  |      21  |\n"
+        );
+    }
+
+    #[test]
+    fn test_comparison_failure() {
+        let files = files(&[
+            ("Add.asm", ADD_ASM),
+            (
+                "Add.cmp",
+                "|  RAM[0]  |  RAM[1]  |  RAM[2]  |\n|       3  |       5  |       9  |\n",
+            ),
+        ]);
+        let outcome = TestScript::new(ADD_TST).unwrap().run(&files, &mut |_| {});
+
+        assert_eq!(
+            outcome.result,
+            Err(TestFailure::Comparison {
+                line: 2,
+                expected: Some("|       3  |       5  |       9  |".to_owned()),
+                actual: "|       3  |       5  |       8  |".to_owned(),
+            })
+        );
+        assert_eq!(outcome.output.len(), 2);
+    }
+
+    #[test]
+    fn test_vm_script() {
+        let files = files(&[(
+            "Add.vm",
+            "push constant 7\npush constant 8\nadd\npop local 1\n",
+        )]);
+        let outcome = TestScript::new(
+            "load Add.vm, output-list RAM[0]%D1.4.1 local[1]%B1.4.1 temp[0]%X1.2.1;
+            set sp 256, set local 300, set temp[0] 255;
+            repeat 4 { vmstep; } output;",
+        )
+        .unwrap()
+        .run(&files, &mut |_| {});
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(
+            outcome.output,
+            vec!["|RAM[0]|local[|temp|", "|  256 | 1111 | FF |"]
+        );
+    }
+
+    #[test]
+    fn test_screen_assertions() {
+        let files = files(&[("Dot.asm", "@SCREEN\nM=1\n@2\nD=A\n@SCREEN\nM=M+D\n")]);
+        let blank_hash = screen_hash(&Hardware::default().ram);
+        let script = |assertions: &str| {
+            TestScript::new(&format!(
+                "load Dot.asm, repeat 6 {{ ticktock; }} {}",
+                assertions
+            ))
+            .unwrap()
+            .run(&files, &mut |_| {})
+        };
+
+        let outcome = script("assert-pixel 0 0 1, assert-pixel 1 0 1, assert-pixel 2 0 0;");
+        assert_eq!(outcome.result, Ok(()));
+        let outcome = script("assert-pixel 511 255 1;");
+        assert_eq!(
+            outcome.result,
+            Err(TestFailure::Assertion(
+                "assert-pixel 511 255 1 failed after 6 steps: the pixel is 0".to_owned()
+            ))
+        );
+        let outcome = script(&format!("assert-screen-hash {:x};", blank_hash));
+        let Err(TestFailure::Assertion(message)) = outcome.result else {
+            panic!("expected an assertion failure");
+        };
+        let actual = message.rsplit(' ').next().unwrap();
+        let outcome = script(&format!("assert-screen-hash {};", actual));
+        assert_eq!(outcome.result, Ok(()));
+
+        assert!(TestScript::new("assert-pixel 512 0 1;").is_err());
+        assert!(TestScript::new("assert-pixel 0 0 2;").is_err());
+        assert!(TestScript::new("assert-screen-hash xyz;").is_err());
+    }
+
+    #[test]
+    fn test_key_press() {
+        let files = files(&[("Echo.asm", "(LOOP)\n@KBD\nD=M\n@0\nM=D\n@LOOP\n0;JMP\n")]);
+        let outcome = TestScript::new(
+            "load Echo.asm, output-list RAM[0]%D1.3.1;
+            key-press 'A' for 6; repeat 4 { ticktock; } output;
+            repeat 6 { ticktock; } output;
+            key-press newline for 6; repeat 6 { ticktock; } output;
+            key-press ' ' for 4; repeat 6 { ticktock; } output;",
+        )
+        .unwrap()
+        .run(&files, &mut |_| {});
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(
+            outcome.output,
+            vec!["|RAM[0|", "|  65 |", "|   0 |", "| 128 |", "|  32 |"]
+        );
+        assert!(TestScript::new("key-press 'AB' for 3;").is_err());
+        assert!(TestScript::new("key-press 'A' for 0;").is_err());
+        assert!(TestScript::new("key-press 'A';").is_err());
+        assert_eq!(
+            TestScript::new("key-press f12 for 1;").unwrap().statements,
+            vec![Statement::KeyPress { key: 152, steps: 1 }]
+        );
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(TestScript::new("repeat 3 { ticktock;").is_err());
+        assert!(TestScript::new("output-list RAM[0]%Q1.2.3;").is_err());
+        assert!(TestScript::new("set RAM[0];").is_err());
+        assert!(TestScript::new("tick, tock;").is_err());
+        assert!(TestScript::new("echo \"unterminated;").is_err());
+
+        let outcome = TestScript::new("load Add.vm, set A 1;")
+            .unwrap()
+            .run(&files(&[("Add.vm", "push constant 1\n")]), &mut |_| {});
+        assert!(matches!(outcome.result, Err(TestFailure::Error(_))));
+        let outcome = TestScript::new("set RAM[0] 1;")
+            .unwrap()
+            .run(&files(&[]), &mut |_| {});
+        assert_eq!(
+            outcome.result,
+            Err(TestFailure::Error("No program was loaded".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_loop_limits() {
+        let outcome = TestScript::new("repeat { }")
+            .unwrap()
+            .run(&files(&[]), &mut |_| {});
+        assert_eq!(
+            outcome.result,
+            Err(TestFailure::Error(format!(
+                "The test's loops didn't finish within {} iterations",
+                MAX_LOOP_ITERATIONS
+            )))
+        );
+
+        let files = files(&[("Add.vm", "push constant 1\n")]);
+        let outcome = TestScript::new("load Add.vm, while RAM[100] = 0 { echo \"x\"; }")
+            .unwrap()
+            .run_cancellable(&files, &mut |_| {}, &AtomicBool::new(true));
+        assert_eq!(
+            outcome.result,
+            Err(TestFailure::Error("The test was cancelled".to_owned()))
+        );
+        assert!(outcome.echo.is_empty());
+    }
+}
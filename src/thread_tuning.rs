@@ -0,0 +1,168 @@
+// Pins the thread that runs a program to one core and raises its priority, so benchmarks don't
+// move around with whatever else the machine is doing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThreadTuning {
+    // None lets the scheduler pick.
+    pub core: Option<usize>,
+    pub high_priority: bool,
+}
+
+impl ThreadTuning {
+    pub fn is_supported() -> bool {
+        cfg!(any(unix, windows))
+    }
+
+    // Applies to the calling thread. Turning a setting off undoes an earlier call.
+    pub fn apply(&self) -> Result<(), String> {
+        if let Some(core) = self.core {
+            if core >= core_count() {
+                return Err(format!(
+                    "There's no core {}, this machine has {}",
+                    core,
+                    core_count()
+                ));
+            }
+        }
+        set_affinity(self.core).map_err(|error| format!("Couldn't pin the thread: {}", error))?;
+        set_high_priority(self.high_priority)
+            .map_err(|error| format!("Couldn't change the thread's priority: {}", error))
+    }
+}
+
+// All of the machine's cores, not just those the calling thread may run on.
+#[cfg(unix)]
+pub fn core_count() -> usize {
+    let count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    usize::try_from(count).unwrap_or(1).max(1)
+}
+
+#[cfg(not(unix))]
+pub fn core_count() -> usize {
+    std::thread::available_parallelism().map_or(1, |count| count.get())
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(core: Option<usize>) -> Result<(), String> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        match core {
+            Some(core) => libc::CPU_SET(core, &mut set),
+            None => (0..core_count()).for_each(|core| libc::CPU_SET(core, &mut set)),
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn set_affinity(core: Option<usize>) -> Result<(), String> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess, GetCurrentThread, GetProcessAffinityMask, SetThreadAffinityMask,
+    };
+
+    let mask = match core {
+        Some(core) if core >= usize::BITS as usize => {
+            return Err("Only the first 64 cores can be picked".to_owned())
+        }
+        Some(core) => 1 << core,
+        None => {
+            let (mut process_mask, mut system_mask) = (0, 0);
+            if unsafe {
+                GetProcessAffinityMask(GetCurrentProcess(), &mut process_mask, &mut system_mask)
+            } == 0
+            {
+                return Err(std::io::Error::last_os_error().to_string());
+            }
+            process_mask
+        }
+    };
+    if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn set_affinity(core: Option<usize>) -> Result<(), String> {
+    if core.is_some() {
+        return Err("this system doesn't let threads be pinned".to_owned());
+    }
+    Ok(())
+}
+
+// Linux gives every thread its own nice value, elsewhere it's the whole process's. Going below 0
+// usually needs root or a raised RLIMIT_NICE.
+#[cfg(unix)]
+fn set_high_priority(high_priority: bool) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    let who = unsafe { libc::gettid() } as libc::id_t;
+    #[cfg(not(target_os = "linux"))]
+    let who = 0;
+    let nice = if high_priority { -10 } else { 0 };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, who, nice) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn set_high_priority(high_priority: bool) -> Result<(), String> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_NORMAL,
+    };
+
+    let priority = if high_priority {
+        THREAD_PRIORITY_HIGHEST
+    } else {
+        THREAD_PRIORITY_NORMAL
+    };
+    if unsafe { SetThreadPriority(GetCurrentThread(), priority) } == 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_high_priority(high_priority: bool) -> Result<(), String> {
+    if high_priority {
+        return Err("this system doesn't let thread priorities change".to_owned());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_tuning() {
+        std::thread::spawn(|| {
+            // CPU 0 may be outside the cpuset the tests run in.
+            let allowed = unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                assert_eq!(
+                    libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set),
+                    0
+                );
+                (0..core_count()).find(|&core| libc::CPU_ISSET(core, &set))
+            };
+            let pinned = ThreadTuning {
+                core: allowed,
+                high_priority: false,
+            };
+            assert_eq!(pinned.apply(), Ok(()));
+            assert_eq!(std::thread::available_parallelism().unwrap().get(), 1);
+            assert_eq!(ThreadTuning::default().apply(), Ok(()));
+
+            let missing = ThreadTuning {
+                core: Some(core_count()),
+                high_priority: false,
+            };
+            assert!(missing.apply().unwrap_err().starts_with("There's no core"));
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+use hashbrown::HashSet;
+
+use crate::hardware::{Word, MEM_SIZE};
+use crate::source_map::SourceMap;
+use crate::vm::{PopSegment, Program, PushSegment, VMCommand};
+
+const TEMP_BASE: Word = 5;
+const POINTER_BASE: Word = 3;
+
+// A VM program translated to Hack assembly, the way the course's VM translator does it.
+pub struct Translation {
+    pub asm: String,
+    pub source_map: SourceMap,
+    // What the translation can't vouch for, like calls to functions that weren't loaded.
+    pub warnings: Vec<String>,
+}
+
+struct Emitter {
+    asm: String,
+    address: usize,
+    // Numbers the labels for return addresses and comparisons.
+    label_count: usize,
+}
+
+impl Emitter {
+    fn instructions(&mut self, instructions: &[&str]) {
+        for instruction in instructions {
+            self.asm.push_str(instruction);
+            self.asm.push('\n');
+        }
+        self.address += instructions.len();
+    }
+
+    fn instruction(&mut self, instruction: String) {
+        self.instructions(&[&instruction]);
+    }
+
+    fn label(&mut self, label: &str) {
+        self.asm.push_str(&format!("({})\n", label));
+    }
+
+    fn comment(&mut self, comment: String) {
+        self.asm.push_str(&format!("// {}\n", comment));
+    }
+
+    fn unique_label(&mut self, scope: &str, kind: &str) -> String {
+        self.label_count += 1;
+        format!("{}${}.{}", scope, kind, self.label_count)
+    }
+
+    fn push_d(&mut self) {
+        self.instructions(&["@SP", "A=M", "M=D", "@SP", "M=M+1"]);
+    }
+
+    fn pop_d(&mut self) {
+        self.instructions(&["@SP", "AM=M-1", "D=M"]);
+    }
+
+    fn constant_to_d(&mut self, value: Word) {
+        if value < 0 {
+            self.instruction(format!("@{}", (value as i64).unsigned_abs()));
+            self.instructions(&["D=-A"]);
+        } else {
+            self.instruction(format!("@{}", value));
+            self.instructions(&["D=A"]);
+        }
+    }
+
+    // Leaves the address of `base[offset]` in A, where `base` holds a pointer.
+    fn indirect_address(&mut self, base: &str, offset: Word) {
+        if offset == 0 {
+            self.instruction(format!("@{}", base));
+            self.instructions(&["A=M"]);
+        } else {
+            self.constant_to_d(offset);
+            self.instruction(format!("@{}", base));
+            self.instructions(&["A=D+M"]);
+        }
+    }
+
+    fn binary(&mut self, computation: &str) {
+        self.pop_d();
+        self.instructions(&["A=A-1"]);
+        self.instruction(format!("M={}", computation));
+    }
+
+    fn unary(&mut self, computation: &str) {
+        self.instructions(&["@SP", "A=M-1"]);
+        self.instruction(format!("M={}", computation));
+    }
+
+    fn comparison(&mut self, scope: &str, jump: &str) {
+        let true_label = self.unique_label(scope, "cmp");
+        self.pop_d();
+        self.instructions(&["A=A-1", "D=M-D", "M=-1"]);
+        self.instruction(format!("@{}", true_label));
+        self.instruction(format!("D;{}", jump));
+        self.instructions(&["@SP", "A=M-1", "M=0"]);
+        self.label(&true_label);
+    }
+
+    fn call(&mut self, scope: &str, function_name: &str, argument_count: Word) {
+        let return_label = self.unique_label(scope, "ret");
+        self.instruction(format!("@{}", return_label));
+        self.instructions(&["D=A"]);
+        self.push_d();
+        for register in ["LCL", "ARG", "THIS", "THAT"] {
+            self.instruction(format!("@{}", register));
+            self.instructions(&["D=M"]);
+            self.push_d();
+        }
+        self.instructions(&["@SP", "D=M"]);
+        self.instruction(format!("@{}", 5 + argument_count as i64));
+        self.instructions(&["D=D-A", "@ARG", "M=D", "@SP", "D=M", "@LCL", "M=D"]);
+        self.instruction(format!("@{}", function_name));
+        self.instructions(&["0;JMP"]);
+        self.label(&return_label);
+    }
+
+    fn r#return(&mut self) {
+        // R13 holds the frame and R14 the return address, which the return value can overwrite
+        // when there are no arguments.
+        self.instructions(&[
+            "@LCL", "D=M", "@R13", "M=D", "@5", "A=D-A", "D=M", "@R14", "M=D",
+        ]);
+        self.pop_d();
+        self.instructions(&["@ARG", "A=M", "M=D", "@ARG", "D=M+1", "@SP", "M=D"]);
+        for register in ["THAT", "THIS", "ARG", "LCL"] {
+            self.instructions(&["@R13", "AM=M-1", "D=M"]);
+            self.instruction(format!("@{}", register));
+            self.instructions(&["M=D"]);
+        }
+        self.instructions(&["@R14", "A=M", "0;JMP"]);
+    }
+
+    fn push(&mut self, file_name: &str, segment: PushSegment, offset: Word) {
+        match segment {
+            PushSegment::Constant => self.constant_to_d(offset),
+            PushSegment::Static => {
+                self.instruction(format!("@{}.{}", file_name, offset));
+                self.instructions(&["D=M"]);
+            }
+            PushSegment::Local => self.push_indirect("LCL", offset),
+            PushSegment::Argument => self.push_indirect("ARG", offset),
+            PushSegment::This => self.push_indirect("THIS", offset),
+            PushSegment::That => self.push_indirect("THAT", offset),
+            PushSegment::Temp => {
+                self.instruction(format!("@{}", TEMP_BASE + offset));
+                self.instructions(&["D=M"]);
+            }
+            PushSegment::Pointer => {
+                self.instruction(format!("@{}", POINTER_BASE + offset));
+                self.instructions(&["D=M"]);
+            }
+        }
+        self.push_d();
+    }
+
+    fn push_indirect(&mut self, base: &str, offset: Word) {
+        self.indirect_address(base, offset);
+        self.instructions(&["D=M"]);
+    }
+
+    fn pop(&mut self, file_name: &str, segment: PopSegment, offset: Word) {
+        let base = match segment {
+            PopSegment::Static => Err(format!("{}.{}", file_name, offset)),
+            PopSegment::Temp => Err((TEMP_BASE + offset).to_string()),
+            PopSegment::Pointer => Err((POINTER_BASE + offset).to_string()),
+            PopSegment::Local => Ok("LCL"),
+            PopSegment::Argument => Ok("ARG"),
+            PopSegment::This => Ok("THIS"),
+            PopSegment::That => Ok("THAT"),
+        };
+        match base {
+            Err(address) => {
+                self.pop_d();
+                self.instruction(format!("@{}", address));
+                self.instructions(&["M=D"]);
+            }
+            Ok(base) if offset == 0 => {
+                self.pop_d();
+                self.instruction(format!("@{}", base));
+                self.instructions(&["A=M", "M=D"]);
+            }
+            Ok(base) => {
+                self.constant_to_d(offset);
+                self.instruction(format!("@{}", base));
+                self.instructions(&["D=D+M", "@R13", "M=D"]);
+                self.pop_d();
+                self.instructions(&["@R13", "A=M", "M=D"]);
+            }
+        }
+    }
+}
+
+// The bootstrap sets SP and calls Sys.init when there is one, otherwise the program starts at
+// the first command. Either way the program halts by running past its end.
+// Statics are named after their file and left for the assembler to allocate.
+pub fn translate(program: &Program) -> Translation {
+    let mut emitter = Emitter {
+        asm: String::new(),
+        address: 0,
+        label_count: 0,
+    };
+    let mut source_map = SourceMap::default();
+    let mut warnings = vec![];
+
+    emitter.comment("bootstrap".to_owned());
+    emitter.instructions(&["@256", "D=A", "@SP", "M=D"]);
+    let bootstrapped = program.function_index("Sys.init").is_some();
+    if bootstrapped {
+        emitter.call("bootstrap", "Sys.init", 0);
+        // Past the last instruction, which halts.
+        emitter.instructions(&["@bootstrap$end", "0;JMP"]);
+    }
+
+    let mut defined = HashSet::new();
+    let mut called = vec![];
+    for file in &program.files {
+        // Commands before the first function are scoped to the file.
+        let mut scope = file.name.clone();
+        for (index, command) in file.commands(&program.all_commands).iter().enumerate() {
+            let start = emitter.address;
+            emitter.comment(format!("{}.vm {}: {}", file.name, index + 1, command));
+            match command {
+                VMCommand::Add => emitter.binary("D+M"),
+                VMCommand::Sub => emitter.binary("M-D"),
+                VMCommand::And => emitter.binary("D&M"),
+                VMCommand::Or => emitter.binary("D|M"),
+                VMCommand::Neg => emitter.unary("-M"),
+                VMCommand::Not => emitter.unary("!M"),
+                VMCommand::Eq => emitter.comparison(&scope, "JEQ"),
+                VMCommand::Gt => emitter.comparison(&scope, "JGT"),
+                VMCommand::Lt => emitter.comparison(&scope, "JLT"),
+                VMCommand::Push { segment, offset } => emitter.push(&file.name, *segment, *offset),
+                VMCommand::Pop { segment, offset } => emitter.pop(&file.name, *segment, *offset),
+                VMCommand::Label { name } => emitter.label(&format!("{}${}", scope, name)),
+                VMCommand::Goto { label_name } => {
+                    emitter.instruction(format!("@{}${}", scope, label_name));
+                    emitter.instructions(&["0;JMP"]);
+                }
+                VMCommand::IfGoto { label_name } => {
+                    emitter.pop_d();
+                    emitter.instruction(format!("@{}${}", scope, label_name));
+                    emitter.instructions(&["D;JNE"]);
+                }
+                VMCommand::Function {
+                    name,
+                    local_var_count,
+                } => {
+                    if !defined.insert(name.clone()) {
+                        warnings.push(format!(
+                            "{} is defined more than once, the copy in {}.vm won't assemble",
+                            name, file.name
+                        ));
+                    }
+                    scope.clone_from(name);
+                    emitter.label(name);
+                    for _ in 0..*local_var_count {
+                        emitter.instructions(&["@SP", "A=M", "M=0", "@SP", "M=M+1"]);
+                    }
+                }
+                VMCommand::Call {
+                    function_name,
+                    argument_count,
+                } => {
+                    called.push(function_name.clone());
+                    emitter.call(&scope, function_name, *argument_count);
+                }
+                VMCommand::Return => emitter.r#return(),
+            }
+            source_map.push(start..emitter.address);
+        }
+    }
+
+    let mut missing: Vec<_> = called
+        .into_iter()
+        .filter(|function_name| !defined.contains(function_name))
+        .collect();
+    missing.sort();
+    missing.dedup();
+    warnings.extend(missing.into_iter().map(|function_name| {
+        format!(
+            "{} isn't in the loaded files, load its .vm file for the translation to run",
+            function_name
+        )
+    }));
+
+    if bootstrapped {
+        emitter.label("bootstrap$end");
+    }
+    if emitter.address > MEM_SIZE {
+        warnings.push(format!(
+            "the translation has {} instructions but the ROM only holds {}",
+            emitter.address, MEM_SIZE
+        ));
+    }
+
+    Translation {
+        asm: emitter.asm,
+        source_map,
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::Hardware;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_translate() {
+        let vm = VM::from_file_contents(vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 0\n\
+                 push constant 5\n\
+                 push constant 7\n\
+                 call Main.max 2\n\
+                 pop temp 1\n\
+                 push constant 3\n\
+                 push constant 3\n\
+                 eq\n\
+                 pop temp 2\n\
+                 push constant 2\n\
+                 push constant 3\n\
+                 lt\n\
+                 not\n\
+                 pop static 0\n\
+                 push static 0\n\
+                 pop temp 3\n\
+                 push constant 0\n\
+                 return\n"
+                    .to_owned(),
+            ),
+            (
+                "Main.vm".to_owned(),
+                "function Main.max 1\n\
+                 push argument 0\n\
+                 push argument 1\n\
+                 gt\n\
+                 if-goto FIRST\n\
+                 push argument 1\n\
+                 pop local 0\n\
+                 goto END\n\
+                 label FIRST\n\
+                 push argument 0\n\
+                 pop local 0\n\
+                 label END\n\
+                 push local 0\n\
+                 push constant 10\n\
+                 sub\n\
+                 neg\n\
+                 return\n"
+                    .to_owned(),
+            ),
+        ]);
+        let translation = translate(&vm.program);
+        assert!(translation.warnings.is_empty());
+        assert_eq!(translation.source_map.len(), vm.program.all_commands.len());
+        // label FIRST translates to nothing.
+        let label = vm.program.files[1].starting_command_index + 8;
+        let addresses = translation.source_map.addresses(label).unwrap();
+        assert!(addresses.is_empty());
+        assert_eq!(
+            translation.source_map.command_at(addresses.start),
+            Some(label + 1)
+        );
+
+        let mut hardware = Hardware::try_from_file_contents(&translation.asm).unwrap();
+        hardware.run(10_000);
+        assert!(hardware.halted());
+        // 10 - max(5, 7)
+        assert_eq!(hardware.ram[6], 3);
+        assert_eq!(hardware.ram[7], -1);
+        assert_eq!(hardware.ram[8], 0);
+
+        let vm = VM::from_file_contents(vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\ncall Output.printInt 1\nreturn\n".to_owned(),
+        )]);
+        assert_eq!(
+            translate(&vm.program).warnings,
+            vec![
+                "Output.printInt isn't in the loaded files, load its .vm file for the translation \
+                 to run"
+            ]
+        );
+    }
+}
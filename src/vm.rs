@@ -1,14 +1,15 @@
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use std::{
     fs,
-    ops::{Index, IndexMut, RangeInclusive},
+    ops::{Index, IndexMut, Range, RangeInclusive},
     path::PathBuf,
+    sync::Arc,
 };
 
 use crate::{
     hardware::{Word, RAM},
-    os::OS,
-    vm_parse::parse_commands,
+    os::{NativeCall, OS},
+    vm_parse::{command_spans, parse_commands},
 };
 
 impl Index<Register> for RAM {
@@ -113,9 +114,218 @@ pub struct Program {
     pub function_metadata: Vec<FunctionMetadata>,
     pub file_name_to_index: HashMap<String, usize>,
     pub files: Vec<File>,
+    // OS classes whose functions run from the loaded VM files instead of natively.
+    pub vm_os_classes: HashSet<String>,
+    // Starts the program instead of Sys.init, takes effect on reset.
+    pub entry_function: Option<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkConflict {
+    DuplicateFunction {
+        name: String,
+        file_names: Vec<String>,
+    },
+    DuplicateLabel {
+        function_name: String,
+        label_name: String,
+    },
+}
+
+impl std::fmt::Display for LinkConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkConflict::DuplicateFunction { name, file_names } => {
+                write!(f, "{} is defined in {}", name, file_names.join(", "))
+            }
+            LinkConflict::DuplicateLabel {
+                function_name,
+                label_name,
+            } => write!(
+                f,
+                "{} is defined more than once in {}",
+                label_name, function_name
+            ),
+        }
+    }
+}
+
+impl Program {
+    // Without a Sys.init of its own the program uses the native one, which calls Main.main.
+    pub fn bootstrap_function(&self) -> Option<usize> {
+        self.entry_function
+            .as_ref()
+            .and_then(|name| self.function_name_to_index.get(name))
+            .or_else(|| self.function_name_to_index.get("Sys.init"))
+            .or_else(|| self.function_name_to_index.get("Main.main"))
+            .copied()
+    }
+
+    // Whether the run state's places in the program exist, a saved run state may come from
+    // another version of it. An empty program still starts in file 0 and function 0.
+    pub fn fits(&self, run_state: &RunState) -> bool {
+        run_state.current_file_index < self.files.len().max(1)
+            && run_state.current_command_index <= self.all_commands.len()
+            && run_state
+                .call_stack
+                .iter()
+                .all(|frame| frame.function_index < self.function_metadata.len().max(1))
+    }
+
+    // Without a resolution the last definition of a function or a label is used.
+    pub fn link_conflicts(&self) -> Vec<LinkConflict> {
+        let mut function_files: Vec<(&str, Vec<String>)> = vec![];
+        let mut label_conflicts = vec![];
+        for file in &self.files {
+            let mut function_name = None;
+            let mut labels = HashSet::new();
+            for command in file.commands(&self.all_commands) {
+                match command {
+                    VMCommand::Function { name, .. } => {
+                        function_name = Some(name);
+                        labels.clear();
+                        match function_files.iter_mut().find(|(other, _)| other == name) {
+                            Some((_, file_names)) => file_names.push(file.name.clone()),
+                            None => function_files.push((name, vec![file.name.clone()])),
+                        }
+                    }
+                    VMCommand::Label { name } if !labels.insert(name) => {
+                        label_conflicts.push(LinkConflict::DuplicateLabel {
+                            function_name: function_name.cloned().unwrap_or_default(),
+                            label_name: name.clone(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        function_files
+            .into_iter()
+            .filter(|(_, file_names)| file_names.len() > 1)
+            .map(|(name, file_names)| LinkConflict::DuplicateFunction {
+                name: name.to_owned(),
+                file_names,
+            })
+            .chain(label_conflicts)
+            .collect()
+    }
+
+    // Commands in program order, with their place in the source when the program was parsed from
+    // it.
+    pub fn iter_commands(&self) -> impl Iterator<Item = CommandEntry<'_>> {
+        self.files.iter().flat_map(move |file| {
+            let spans = file.source.as_deref().map(command_spans);
+            let lines: Vec<&str> = file
+                .source
+                .as_deref()
+                .map_or(vec![], |source| source.lines().collect());
+            file.commands(&self.all_commands)
+                .iter()
+                .enumerate()
+                .map(|(index, command)| {
+                    let (line, span) = spans
+                        .as_ref()
+                        .and_then(|spans| spans.get(index).cloned())
+                        .unzip();
+                    CommandEntry {
+                        file,
+                        line,
+                        command,
+                        source_line: line.and_then(|line| lines.get(line - 1).copied()),
+                        span,
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+
+    pub fn function_starts(&self) -> Vec<(String, usize)> {
+        self.function_name_to_index
+            .iter()
+            .map(|(name, &index)| (name.clone(), self.function_metadata[index].command_index))
+            .collect()
+    }
+
+    // Mirrors the check in `VM::run`, OS functions run natively unless their class is loaded.
+    pub fn is_native(&self, function_name: &str) -> bool {
+        let class_name = function_name
+            .split_once('.')
+            .map_or(function_name, |(class_name, _)| class_name);
+        !self.vm_os_classes.contains(class_name) && RunState::is_os_function(function_name)
+    }
+
+    pub fn function_index(&self, function_name: &str) -> Option<usize> {
+        self.function_name_to_index.get(function_name).copied()
+    }
+
+    // Runs up to the next function, or the end of the file.
+    pub fn function_commands(&self, function_index: usize) -> &[VMCommand] {
+        let metadata = &self.function_metadata[function_index];
+        let file = &self.files[metadata.file_index];
+        let end = self
+            .function_metadata
+            .get(function_index + 1)
+            .filter(|next| next.file_index == metadata.file_index)
+            .map_or(file.starting_command_index + file.command_count, |next| {
+                next.command_index
+            });
+        &self.all_commands[metadata.command_index..end]
+    }
+
+    // The synthetic top-level frame has no name.
+    pub fn function_name(&self, function_index: usize) -> Option<&str> {
+        match &self.all_commands[self.function_metadata[function_index].command_index] {
+            VMCommand::Function { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn function_file_name(&self, function_name: &str) -> Option<&str> {
+        let function_index = *self.function_name_to_index.get(function_name)?;
+        Some(&self.files[self.function_metadata[function_index].file_index].name)
+    }
+
+    // Makes calls to a function defined in several files go to the definition in `file_name`.
+    pub fn use_function_from(&mut self, function_name: &str, file_name: &str) -> bool {
+        let Some(&file_index) = self.file_name_to_index.get(file_name) else {
+            return false;
+        };
+        let found = self.function_metadata.iter().position(|metadata| {
+            metadata.file_index == file_index
+                && matches!(
+                    &self.all_commands[metadata.command_index],
+                    VMCommand::Function { name, .. } if name == function_name
+                )
+        });
+        if let Some(function_index) = found {
+            self.function_name_to_index
+                .insert(function_name.to_owned(), function_index);
+        }
+        found.is_some()
+    }
+}
+
+pub struct CommandEntry<'a> {
+    pub file: &'a File,
+    pub line: Option<usize>,
+    pub command: &'a VMCommand,
+    // Byte range of the command in the file's source.
+    pub span: Option<Range<usize>>,
+    source_line: Option<&'a str>,
+}
+
+// The alternate form prints the command's line as written, with its indentation and comments.
+impl std::fmt::Display for CommandEntry<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.source_line {
+            Some(source_line) if f.alternate() => write!(f, "{}", source_line),
+            _ => write!(f, "{}", self.command),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct RunState {
     pub current_file_index: usize,
     pub current_command_index: usize,
@@ -123,12 +333,83 @@ pub struct RunState {
     pub os: OS,
     pub call_stack: Vec<Frame>,
     pub breakpoints: Vec<Breakpoint>,
+    pub ticks: u64,
+    // The deepest the program got since the last reset, updated when a function starts.
+    pub max_call_depth: usize,
+    pub max_sp: Word,
+    // Only the first collision since the last reset stops the run.
+    pub stack_collision: Option<StackCollision>,
+    // Why the OS call the program is stuck on failed, it's cleared when the call's made again.
+    pub os_fault: Option<String>,
+}
+
+impl std::fmt::Debug for RunState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunState")
+            .field("current_file_index", &self.current_file_index)
+            .field("current_command_index", &self.current_command_index)
+            .field("call_stack", &self.call_stack)
+            .field("ticks", &self.ticks)
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackCollision {
+    pub sp: Word,
+    // The start of the heap block or the screen that the stack reached.
+    pub address: Word,
+}
+
+impl std::fmt::Display for StackCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.address >= RAM::SCREEN {
+            write!(
+                f,
+                "Stack/heap collision: SP is {}, the stack grew into the screen at {}",
+                self.sp, self.address
+            )
+        } else {
+            write!(
+                f,
+                "Stack/heap collision: SP is {}, the stack overlaps the heap block at {}",
+                self.sp, self.address
+            )
+        }
+    }
+}
+
+// A return address and the caller's LCL, ARG, THIS and THAT.
+const SAVED_FRAME_WORDS: Word = 5;
+
+// The segment pointers a program starts with, course test scripts set them for programs that
+// run without Sys.init.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentInit {
+    pub sp: Word,
+    pub lcl: Word,
+    pub arg: Word,
+    pub this: Word,
+    pub that: Word,
+}
+
+impl Default for SegmentInit {
+    fn default() -> Self {
+        Self {
+            sp: 256,
+            lcl: 0,
+            arg: 0,
+            this: 0,
+            that: 0,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct VM {
     pub run_state: RunState,
     pub program: Program,
+    pub segment_init: SegmentInit,
 }
 
 impl VM {
@@ -147,17 +428,21 @@ impl VM {
     }
 
     pub fn from_file_contents(file_contents: Vec<(String, String)>) -> Self {
-        Self::from_all_file_commands(
+        let mut vm = Self::from_all_file_commands(
             file_contents
-                .into_iter()
+                .iter()
                 .map(|(name, contents)| {
                     (
                         name.rsplit_once('.').unwrap().0.to_owned(),
-                        parse_commands(&contents).unwrap().1,
+                        parse_commands(contents).unwrap().1,
                     )
                 })
                 .collect(),
-        )
+        );
+        for (file, (_, contents)) in vm.program.files.iter_mut().zip(file_contents) {
+            file.source = Some(contents.into());
+        }
+        vm
     }
 
     pub fn from_all_file_commands(all_file_commands: Vec<(String, Vec<VMCommand>)>) -> Self {
@@ -190,16 +475,24 @@ impl VM {
             function_metadata,
             file_name_to_index,
             files: files.into_iter().collect(),
+            vm_os_classes: HashSet::new(),
+            entry_function: None,
         };
 
         Self::new(program)
     }
 
+    // Like the official VM emulator, programs with Sys.init start as if the bootstrap code called
+    // it, other programs start at the first function of the first file.
     pub fn new(program: Program) -> Self {
-        let current_file_index = *program.file_name_to_index.get("Sys").unwrap_or(&0);
-        let current_command_index = program.files[current_file_index].starting_command_index;
-        let function_index = *program.function_name_to_index.get("Sys.init").unwrap_or(&0);
-        Self {
+        let function_index = program.bootstrap_function().unwrap_or(0);
+        let (current_file_index, current_command_index) = program
+            .function_metadata
+            .get(function_index)
+            .map_or((0, 0), |metadata| {
+                (metadata.file_index, metadata.command_index)
+            });
+        let mut vm = Self {
             program,
             run_state: RunState {
                 current_file_index,
@@ -208,25 +501,107 @@ impl VM {
                 os: Default::default(),
                 call_stack: vec![Frame { function_index }],
                 breakpoints: vec![],
+                ticks: 0,
+                max_call_depth: 0,
+                max_sp: 0,
+                stack_collision: None,
+                os_fault: None,
             },
-        }
+            segment_init: Default::default(),
+        };
+        vm.set_segment_init(Default::default());
+        vm
     }
 
     pub fn reset(&mut self) {
+        let segment_init = self.segment_init;
         *self = VM::new(self.program.clone());
+        self.set_segment_init(segment_init);
+    }
+
+    // Takes effect right away if the program hasn't started yet and otherwise on reset.
+    pub fn set_segment_init(&mut self, segment_init: SegmentInit) {
+        self.segment_init = segment_init;
+        if self.run_state.ticks == 0 {
+            let ram = &mut self.run_state.ram;
+            ram[Register::SP] = segment_init.sp;
+            ram[Register::LCL] = segment_init.lcl;
+            ram[Register::ARG] = segment_init.arg;
+            ram[Register::THIS] = segment_init.this;
+            ram[Register::THAT] = segment_init.that;
+            if self.program.bootstrap_function().is_some() {
+                self.push_bootstrap_frame();
+            }
+        }
     }
 
-    pub fn step(&mut self) {
+    // The frame `call Sys.init 0` leaves, returning from Sys.init halts.
+    fn push_bootstrap_frame(&mut self) {
+        let ram = &mut self.run_state.ram;
+        ram.push(self.program.all_commands.len() as Word);
+        for i in 1..=4 {
+            ram.push(ram[i]);
+        }
+        ram[Register::ARG] = ram[Register::SP] - SAVED_FRAME_WORDS;
+        ram[Register::LCL] = ram[Register::SP];
+    }
+
+    // Where the entry function's stack starts.
+    pub fn stack_start(&self) -> Word {
+        if self.program.bootstrap_function().is_some() {
+            self.segment_init.sp + SAVED_FRAME_WORDS
+        } else {
+            self.segment_init.sp
+        }
+    }
+
+    pub fn step(&mut self) -> Option<usize> {
         self.run(1)
     }
 
-    pub fn run(&mut self, num_steps: u64) {
+    pub fn halted(&self) -> bool {
+        self.run_state.current_command_index >= self.program.all_commands.len()
+    }
+
+    // Stops after a step that lands on a line or function breakpoint and returns its index, the
+    // breakpoint the run starts on doesn't stop it. A failed OS call stops it too.
+    pub fn run(&mut self, num_steps: u64) -> Option<usize> {
+        let breakpoints = self.breakpoint_commands();
+        if breakpoints.is_empty() {
+            self.run_commands(num_steps);
+            return None;
+        }
+        for _ in 0..num_steps {
+            if self.halted() {
+                break;
+            }
+            self.run_commands(1);
+            if self.run_state.os_fault.is_some() {
+                break;
+            }
+            if let Some(&index) = breakpoints.get(&self.run_state.current_command_index) {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    // Runs without looking at breakpoints.
+    pub fn run_commands(&mut self, num_steps: u64) {
         let files = &self.program.files;
         let run_state = &mut self.run_state;
 
         let mut static_segment = *files[run_state.current_file_index].static_segment.start();
         for _ in 0..num_steps {
-            match &self.program.all_commands[run_state.current_command_index] {
+            let Some(command) = self
+                .program
+                .all_commands
+                .get(run_state.current_command_index)
+            else {
+                break;
+            };
+            run_state.ticks += 1;
+            match command {
                 VMCommand::Add => {
                     let y = run_state.ram.pop();
                     *run_state.ram.stack_top() = run_state.ram.stack_top().wrapping_add(y);
@@ -315,7 +690,13 @@ impl VM {
                     for _ in 0..*local_var_count {
                         run_state.ram.push(0);
                     }
+                    run_state.max_call_depth =
+                        run_state.max_call_depth.max(run_state.call_stack.len());
+                    run_state.max_sp = run_state.max_sp.max(run_state.ram[Register::SP]);
                     run_state.current_command_index += 1;
+                    if run_state.record_stack_collision() {
+                        break;
+                    }
                 }
                 VMCommand::Call {
                     function_name,
@@ -333,7 +714,36 @@ impl VM {
                     let local_segment = run_state.ram[Register::SP];
                     run_state.ram[Register::LCL] = local_segment;
                     run_state.ram[Register::ARG] = argument_segment;
-                    if run_state.call_os(function_name) {
+                    let class_name = function_name
+                        .split_once('.')
+                        .map_or(function_name.as_str(), |(class_name, _)| class_name);
+                    run_state.os_fault = None;
+                    let native_call = if self.program.vm_os_classes.contains(class_name) {
+                        None
+                    } else {
+                        run_state.call_os(function_name)
+                    };
+                    if let Some(NativeCall::Blocked | NativeCall::Halted | NativeCall::Failed(_)) =
+                        native_call
+                    {
+                        // Undone, so the call is made again on the next step.
+                        let frame = run_state.ram[Register::LCL];
+                        for i in 1..=4 {
+                            run_state.ram[i] = run_state.ram[frame - 5 + i];
+                        }
+                        run_state.ram[Register::SP] = frame - 5;
+                        match native_call {
+                            Some(NativeCall::Halted) => {
+                                run_state.current_command_index = self.program.all_commands.len();
+                                break;
+                            }
+                            Some(NativeCall::Failed(message)) => {
+                                run_state.os_fault = Some(message);
+                                break;
+                            }
+                            _ => {}
+                        }
+                    } else if native_call == Some(NativeCall::Returned) {
                         let frame = run_state.ram[Register::LCL];
                         run_state.current_command_index = run_state.ram[frame - 5] as usize;
                         let return_value = run_state.ram.pop();
@@ -344,6 +754,10 @@ impl VM {
                         for i in 1..=4 {
                             run_state.ram[i] = run_state.ram[frame - 5 + i];
                         }
+                        // Allocations can land under a stack that's already past the heap start.
+                        if run_state.record_stack_collision() {
+                            break;
+                        }
                     } else {
                         let function_index = self.program.function_name_to_index[function_name];
                         let function_metadata = &self.program.function_metadata[function_index];
@@ -369,11 +783,12 @@ impl VM {
                     }
                     run_state.call_stack.pop();
 
-                    let last_frame = run_state.call_stack.last().unwrap();
-                    let file_index =
-                        self.program.function_metadata[last_frame.function_index].file_index;
-                    run_state.current_file_index = file_index;
-                    static_segment = *files[file_index].static_segment.start();
+                    if let Some(last_frame) = run_state.call_stack.last() {
+                        let file_index =
+                            self.program.function_metadata[last_frame.function_index].file_index;
+                        run_state.current_file_index = file_index;
+                        static_segment = *files[file_index].static_segment.start();
+                    }
                 }
             }
         }
@@ -398,13 +813,99 @@ impl VM {
     pub fn remove_breakpoint(&mut self, index: usize) {
         self.run_state.breakpoints.remove(index);
     }
+
+    // The commands that line and function breakpoints pause the run at, with the index of the
+    // breakpoint. Breakpoints on rows or functions that aren't linked never hit.
+    pub fn breakpoint_commands(&self) -> HashMap<usize, usize> {
+        let program = &self.program;
+        let mut commands = HashMap::new();
+        for (index, breakpoint) in self.run_state.breakpoints.iter().enumerate().rev() {
+            let command_index = match breakpoint {
+                Breakpoint::Line {
+                    file_name,
+                    line_number,
+                } => program
+                    .file_name_to_index
+                    .get(file_name)
+                    .and_then(|&file_index| {
+                        let file = &program.files[file_index];
+                        let row = *line_number as usize;
+                        (row < file.commands(&program.all_commands).len())
+                            .then_some(file.starting_command_index + row)
+                    }),
+                Breakpoint::CurrentFunction(function_name) => program
+                    .function_name_to_index
+                    .get(function_name)
+                    .map(|&function_index| program.function_metadata[function_index].command_index),
+                _ => None,
+            };
+            if let Some(command_index) = command_index {
+                commands.insert(command_index, index);
+            }
+        }
+        commands
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Frame {
     pub function_index: usize,
 }
 
+// Where a run started by a step over, a step out or a frame step ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepGoal {
+    // Back at `depth` calls and, for a step over, off the command it started at, which a blocked
+    // native call keeps the program on.
+    Return { depth: usize, leave: Option<usize> },
+    // VM commands and native OS functions write all over memory, so a write is only noticed if it
+    // changes the value at the address.
+    Write { address: Word, value: Word },
+    // Once the ticks get there.
+    Ticks(u64),
+}
+
+impl StepGoal {
+    // Runs a call to completion, other commands are a single step.
+    pub fn step_over(run_state: &RunState) -> Self {
+        StepGoal::Return {
+            depth: run_state.call_stack.len(),
+            leave: Some(run_state.current_command_index),
+        }
+    }
+
+    // Runs until the current function returns, there's nothing to return to from the top frame.
+    pub fn step_out(run_state: &RunState) -> Option<Self> {
+        let depth = run_state.call_stack.len();
+        (depth > 1).then_some(StepGoal::Return {
+            depth: depth - 1,
+            leave: None,
+        })
+    }
+
+    pub fn write(run_state: &RunState, address: Word) -> Self {
+        StepGoal::Write {
+            address,
+            value: run_state.ram[address],
+        }
+    }
+
+    pub fn steps(run_state: &RunState, step_count: u64) -> Self {
+        StepGoal::Ticks(run_state.ticks + step_count)
+    }
+
+    pub fn reached(&self, run_state: &RunState) -> bool {
+        match *self {
+            StepGoal::Return { depth, leave } => {
+                run_state.call_stack.len() <= depth
+                    && leave != Some(run_state.current_command_index)
+            }
+            StepGoal::Write { address, value } => run_state.ram[address] != value,
+            StepGoal::Ticks(ticks) => run_state.ticks >= ticks,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FunctionMetadata {
     pub argument_count: Word,
@@ -420,6 +921,8 @@ pub struct File {
     pub starting_command_index: usize,
     command_count: usize,
     pub static_segment: RangeInclusive<Word>,
+    // Shared between the clones made on reset.
+    pub source: Option<Arc<str>>,
 }
 
 impl File {
@@ -433,6 +936,17 @@ impl File {
         function_metadata: &mut Vec<FunctionMetadata>,
     ) -> Self {
         let mut max_static_index: Word = static_segment_start - 1;
+        // Commands before the first function, like in programs without any, run in a synthetic
+        // top-level frame.
+        if !matches!(commands.first(), None | Some(VMCommand::Function { .. })) {
+            function_metadata.push(FunctionMetadata {
+                argument_count: 0,
+                local_var_count: 0,
+                command_index: starting_command_index,
+                file_index,
+                label_name_to_command_index: HashMap::new(),
+            });
+        }
         for (i, command) in commands.iter().enumerate() {
             match command {
                 VMCommand::Label { name } => {
@@ -485,6 +999,7 @@ impl File {
             starting_command_index,
             static_segment: static_segment_start..=max_static_index,
             command_count: commands.len(),
+            source: None,
         }
     }
 
@@ -840,6 +1355,16 @@ mod tests {
         assert_eq!(vm.test_get(PushSegment::Constant, 1337), 1337);
     }
 
+    #[test]
+    fn test_ticks() {
+        let mut vm = VM::test_instance();
+        vm.step();
+        assert_eq!(vm.run_state.ticks, 1);
+
+        vm.reset();
+        assert_eq!(vm.run_state.ticks, 0);
+    }
+
     #[test]
     fn test_static() {
         let all_file_commands = vec![
@@ -1377,4 +1902,397 @@ mod tests {
         assert_eq!(vm.run_state.current_command_index, 3);
         assert_eq!(*vm.run_state.ram.stack_top(), 2337);
     }
+
+    #[test]
+    fn test_vm_os_classes() {
+        let files = vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 0\npush constant 5\nneg\ncall Math.abs 1\nlabel END\ngoto END\n"
+                    .to_owned(),
+            ),
+            (
+                "Math.vm".to_owned(),
+                "function Math.abs 0\npush constant 7\nreturn\n".to_owned(),
+            ),
+        ];
+
+        let mut vm = VM::from_file_contents(files.clone());
+        vm.run(4);
+        assert_eq!(*vm.run_state.ram.stack_top(), 5);
+
+        let mut vm = VM::from_file_contents(files);
+        vm.program.vm_os_classes.insert("Math".to_owned());
+        vm.run(6);
+        assert_eq!(*vm.run_state.ram.stack_top(), 7);
+    }
+
+    #[test]
+    fn test_link_conflicts() {
+        let mut vm = VM::from_file_contents(vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 0\nlabel LOOP\nlabel LOOP\ngoto LOOP\n".to_owned(),
+            ),
+            (
+                "Foo.vm".to_owned(),
+                "function Foo.bar 0\npush constant 1\nreturn\n".to_owned(),
+            ),
+            (
+                "Bar.vm".to_owned(),
+                "function Foo.bar 0\npush constant 2\nreturn\n".to_owned(),
+            ),
+        ]);
+
+        assert_eq!(
+            vm.program.link_conflicts(),
+            vec![
+                LinkConflict::DuplicateFunction {
+                    name: "Foo.bar".to_owned(),
+                    file_names: vec!["Foo".to_owned(), "Bar".to_owned()],
+                },
+                LinkConflict::DuplicateLabel {
+                    function_name: "Sys.init".to_owned(),
+                    label_name: "LOOP".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(vm.program.function_file_name("Foo.bar"), Some("Bar"));
+        assert!(vm.program.use_function_from("Foo.bar", "Foo"));
+        assert_eq!(vm.program.function_file_name("Foo.bar"), Some("Foo"));
+        assert!(!vm.program.use_function_from("Foo.bar", "Sys"));
+    }
+
+    #[test]
+    fn test_flat_program() {
+        let mut vm = VM::from_file_contents(vec![(
+            "SimpleAdd.vm".to_owned(),
+            "push constant 7\npush constant 8\nadd\nlabel END\ngoto END\n".to_owned(),
+        )]);
+        vm.run(10);
+
+        assert_eq!(vm.run_state.ram[Register::SP], 257);
+        assert_eq!(vm.run_state.ram[256], 15);
+        assert_eq!(vm.run_state.current_command_index, 4);
+    }
+
+    #[test]
+    fn test_iter_commands() {
+        let vm = VM::from_file_contents(vec![(
+            "Main.vm".to_owned(),
+            "// Adds\n  push   constant 7 // seven\nadd\n".to_owned(),
+        )]);
+
+        let entries: Vec<_> = vm.program.iter_commands().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].file.name, "Main");
+        assert_eq!(entries[0].line, Some(2));
+        assert_eq!(entries[0].span, Some(10..27));
+        assert_eq!(entries[0].to_string(), "push constant 7");
+        assert_eq!(format!("{:#}", entries[0]), "  push   constant 7 // seven");
+        assert_eq!(format!("{:#}", entries[1]), "add");
+    }
+
+    #[test]
+    fn test_stack_collision() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Sys.vm".to_owned(),
+            "function Sys.init 0\npush constant 10\ncall Memory.alloc 1\npop temp 0\n\
+             call Sys.down 0\nreturn\n\
+             function Sys.down 0\ncall Sys.down 0\nreturn\n"
+                .to_owned(),
+        )]);
+        vm.run(2000);
+
+        let collision = StackCollision {
+            sp: 2051,
+            address: 2048,
+        };
+        assert_eq!(vm.run_state.stack_collision, Some(collision));
+        assert_eq!(vm.run_state.ram[Register::SP], 2051);
+        vm.run(10);
+        assert_eq!(vm.run_state.stack_collision, Some(collision));
+        assert_eq!(vm.run_state.ram[Register::SP], 2076);
+    }
+
+    #[test]
+    fn test_os_halt() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Sys.vm".to_owned(),
+            "function Sys.init 0\npush constant 7\npop temp 0\ncall Sys.halt 0\nreturn\n"
+                .to_owned(),
+        )]);
+        vm.run(100000);
+        assert!(vm.halted());
+        assert_eq!(vm.run_state.ticks, 4);
+        assert_eq!(vm.run_state.ram[Register::TEMP(0)], 7);
+
+        let mut vm = VM::from_file_contents(vec![(
+            "Sys.vm".to_owned(),
+            "function Sys.init 0\npush constant 3\ncall Sys.error 1\nreturn\n".to_owned(),
+        )]);
+        vm.run(100000);
+        assert!(vm.halted());
+        assert!(vm
+            .run_state
+            .ram
+            .slice(RAM::SCREEN..RAM::KBD)
+            .iter()
+            .any(|&word| word != 0));
+    }
+
+    #[test]
+    fn test_os_fault() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Sys.vm".to_owned(),
+            "function Sys.init 0\npush constant 1\nneg\ncall Sys.wait 1\nreturn\n".to_owned(),
+        )]);
+        vm.run(100000);
+        assert!(!vm.halted());
+        assert_eq!(vm.run_state.ticks, 4);
+        assert_eq!(vm.run_state.current_command_index, 3);
+        assert_eq!(
+            vm.run_state.os_fault.as_deref(),
+            Some("Sys.wait was called with a negative duration, -1")
+        );
+    }
+
+    #[test]
+    fn test_segment_init() {
+        let mut vm = VM::from_file_contents(vec![(
+            "BasicTest.vm".to_owned(),
+            "push constant 10\npop local 0\n".to_owned(),
+        )]);
+        vm.set_segment_init(SegmentInit {
+            lcl: 300,
+            ..Default::default()
+        });
+        vm.run(2);
+        assert_eq!(vm.run_state.ram[300], 10);
+
+        vm.set_segment_init(SegmentInit {
+            sp: 260,
+            lcl: 400,
+            ..Default::default()
+        });
+        assert_eq!(vm.run_state.ram[Register::LCL], 300);
+        vm.reset();
+        assert_eq!(vm.run_state.ram[Register::SP], 260);
+        assert_eq!(vm.run_state.ram[Register::LCL], 400);
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        let mut vm = VM::from_file_contents(vec![
+            (
+                "Main.vm".to_owned(),
+                "function Main.main 0\npush constant 7\nreturn\n".to_owned(),
+            ),
+            (
+                "Boot.vm".to_owned(),
+                "function Sys.init 0\ncall Main.main 0\nreturn\n".to_owned(),
+            ),
+        ]);
+        assert_eq!(vm.run_state.current_file_index, 1);
+        assert_eq!(vm.run_state.ram[Register::SP], 261);
+        assert_eq!(vm.run_state.ram[Register::ARG], 256);
+        assert_eq!(vm.run_state.ram[Register::LCL], 261);
+        assert_eq!(vm.stack_start(), 261);
+
+        vm.run(100);
+        assert!(vm.halted());
+        assert_eq!(vm.run_state.ram[256], 7);
+        assert_eq!(vm.run_state.ram[Register::SP], 257);
+
+        // The native Sys.init calls Main.main.
+        let mut vm = VM::from_file_contents(vec![
+            (
+                "Ball.vm".to_owned(),
+                "function Ball.new 0\npush constant 0\nreturn\n".to_owned(),
+            ),
+            (
+                "Main.vm".to_owned(),
+                "function Main.main 0\npush constant 7\nreturn\n".to_owned(),
+            ),
+        ]);
+        assert_eq!(vm.run_state.current_file_index, 1);
+        assert_eq!(vm.run_state.ram[Register::SP], 261);
+        vm.program.entry_function = Some("Ball.new".to_owned());
+        vm.reset();
+        assert_eq!(vm.run_state.current_file_index, 0);
+        assert_eq!(vm.run_state.ram[Register::SP], 261);
+
+        // Otherwise the first file's first function runs on the stack as it is.
+        let vm = VM::from_file_contents(vec![(
+            "BasicLoop.vm".to_owned(),
+            "push constant 7\nlabel LOOP\ngoto LOOP\n".to_owned(),
+        )]);
+        assert_eq!(vm.run_state.current_command_index, 0);
+        assert_eq!(vm.run_state.ram[Register::SP], 256);
+        assert_eq!(vm.stack_start(), 256);
+    }
+
+    #[test]
+    fn test_blocked_native_call() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\ncall Keyboard.readChar 0\nreturn\n".to_owned(),
+        )]);
+        vm.run(10);
+        assert_eq!(vm.run_state.current_command_index, 1);
+        assert_eq!(vm.run_state.ram[Register::SP], 261);
+        assert_eq!(vm.run_state.ram[Register::LCL], 261);
+
+        vm.run_state.ram[RAM::KBD] = b'k' as Word;
+        vm.step();
+        vm.run_state.ram[RAM::KBD] = 0;
+        vm.run(2);
+        assert!(vm.halted());
+        assert_eq!(vm.run_state.ram[256], b'k' as Word);
+    }
+
+    #[test]
+    fn test_breakpoint_commands() {
+        let mut vm = VM::from_file_contents(vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 0\ncall Main.main 0\nreturn\n".to_owned(),
+            ),
+            (
+                "Main.vm".to_owned(),
+                "function Main.main 0\npush constant 1\nreturn\n".to_owned(),
+            ),
+        ]);
+        for breakpoint in [
+            Breakpoint::CurrentFunction("Main.main".to_owned()),
+            Breakpoint::Line {
+                file_name: "Main".to_owned(),
+                line_number: 1,
+            },
+            Breakpoint::Line {
+                file_name: "Main".to_owned(),
+                line_number: 0,
+            },
+            Breakpoint::CurrentFunction("Main.missing".to_owned()),
+            Breakpoint::Line {
+                file_name: "Main".to_owned(),
+                line_number: 3,
+            },
+            Breakpoint::SP(300),
+        ] {
+            vm.add_breakpoint(&breakpoint);
+        }
+        assert_eq!(vm.breakpoint_commands(), HashMap::from([(3, 0), (4, 1)]));
+    }
+
+    #[test]
+    fn test_run_to_breakpoint() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\nlabel LOOP\npush constant 1\npop temp 0\ngoto LOOP\n".to_owned(),
+        )]);
+        vm.add_breakpoint(&Breakpoint::Line {
+            file_name: "Main".to_owned(),
+            line_number: 2,
+        });
+        assert_eq!(vm.run(100), Some(0));
+        assert_eq!(vm.run_state.current_command_index, 2);
+        assert_eq!(vm.run(100), Some(0));
+        assert_eq!(vm.run_state.ticks, 6);
+        assert_eq!(vm.run(2), None);
+
+        vm.remove_breakpoint(0);
+        assert_eq!(vm.run(100), None);
+        assert_eq!(vm.run_state.ticks, 108);
+    }
+
+    fn run_to_goal(vm: &mut VM, goal: &StepGoal, max_steps: u64) -> bool {
+        for _ in 0..max_steps {
+            vm.step();
+            if goal.reached(&vm.run_state) {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_step_goal() {
+        let mut vm = VM::from_file_contents(vec![
+            (
+                "Main.vm".to_owned(),
+                "function Main.main 0\ncall Main.double 0\npush constant 1\n\
+                 call Keyboard.readChar 0\nreturn\n"
+                    .to_owned(),
+            ),
+            (
+                "Double.vm".to_owned(),
+                "function Main.double 0\npush constant 2\npush constant 2\nadd\nreturn\n"
+                    .to_owned(),
+            ),
+        ]);
+        assert_eq!(StepGoal::step_out(&vm.run_state), None);
+        vm.step();
+        assert_eq!(vm.run_state.current_command_index, 1);
+
+        // Over the call, stopping at the command after it.
+        let goal = StepGoal::step_over(&vm.run_state);
+        assert!(run_to_goal(&mut vm, &goal, 100));
+        assert_eq!(vm.run_state.current_command_index, 2);
+        assert_eq!(vm.run_state.ticks, 7);
+        assert_eq!(vm.run_state.call_stack.len(), 1);
+
+        let goal = StepGoal::step_over(&vm.run_state);
+        assert!(run_to_goal(&mut vm, &goal, 100));
+        assert_eq!(vm.run_state.ticks, 8);
+
+        // Blocked calls count as steps too.
+        let goal = StepGoal::steps(&vm.run_state, 3);
+        assert!(!goal.reached(&vm.run_state));
+        assert!(run_to_goal(&mut vm, &goal, 100));
+        assert_eq!(vm.run_state.ticks, 11);
+        assert_eq!(vm.run_state.current_command_index, 3);
+
+        // No key is pressed, so the call stays on the same command.
+        let goal = StepGoal::step_over(&vm.run_state);
+        assert!(!run_to_goal(&mut vm, &goal, 100));
+        assert_eq!(vm.run_state.current_command_index, 3);
+        vm.run_state.ram[RAM::KBD] = b'k' as Word;
+        vm.step();
+        vm.run_state.ram[RAM::KBD] = 0;
+        assert!(run_to_goal(&mut vm, &goal, 100));
+        assert_eq!(vm.run_state.current_command_index, 4);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\ncall Main.double 0\nreturn\n\
+             function Main.double 0\npush constant 2\npush constant 2\nadd\nreturn\n"
+                .to_owned(),
+        )]);
+        vm.run(4);
+        assert_eq!(vm.run_state.call_stack.len(), 2);
+        let goal = StepGoal::step_out(&vm.run_state).unwrap();
+        assert!(run_to_goal(&mut vm, &goal, 100));
+        assert_eq!(vm.run_state.current_command_index, 2);
+        assert_eq!(*vm.run_state.ram.stack_top(), 4);
+    }
+
+    #[test]
+    fn test_write_goal() {
+        let mut vm = VM::from_file_contents(vec![(
+            "Main.vm".to_owned(),
+            "function Main.main 0\npush constant 0\npop static 0\npush constant 3\n\
+             pop static 0\nlabel END\ngoto END\n"
+                .to_owned(),
+        )]);
+        let goal = StepGoal::write(&vm.run_state, 16);
+        assert!(run_to_goal(&mut vm, &goal, 100));
+        assert_eq!(vm.run_state.ram[16], 3);
+        assert_eq!(vm.run_state.current_command_index, 5);
+        let goal = StepGoal::write(&vm.run_state, 16);
+        assert!(!run_to_goal(&mut vm, &goal, 100));
+    }
 }
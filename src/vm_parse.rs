@@ -1,16 +1,20 @@
 use crate::{
     hardware::Word,
-    parse_utils::{non_comment_lines, IResult, ParsableWord},
+    parse_utils::{code_lines, code_spans, non_comment_lines, IResult, ParsableWord},
     vm::*,
 };
 
+use std::ops::Range;
+
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alphanumeric1, space1},
-    combinator::{map, recognize, value},
-    multi::many1_count,
-    sequence::{pair, preceded, separated_pair},
+    bytes::complete::{tag, tag_no_case},
+    character::complete::{alphanumeric1, multispace0, one_of, space1},
+    combinator::{all_consuming, map, opt, recognize, value},
+    error::convert_error,
+    multi::{many1_count, separated_list0},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
+    Finish,
 };
 
 fn pop_segment(input: &str) -> IResult<&str, PopSegment> {
@@ -25,7 +29,7 @@ fn pop_segment(input: &str) -> IResult<&str, PopSegment> {
     ))(input)
 }
 
-fn push_segment(input: &str) -> IResult<&str, PushSegment> {
+pub(crate) fn push_segment(input: &str) -> IResult<&str, PushSegment> {
     alt((
         value(PushSegment::Constant, tag("constant")),
         value(PushSegment::Static, tag("static")),
@@ -128,6 +132,68 @@ pub fn parse_commands(input: &str) -> IResult<&str, Vec<VMCommand>> {
     non_comment_lines(command)(input)
 }
 
+// Maps every parsed command to the source line it came from.
+pub fn command_line_numbers(input: &str) -> Vec<usize> {
+    code_lines(input).map(|(line, _)| line).collect()
+}
+
+// Maps every parsed command to its line and its byte range in the source.
+pub fn command_spans(input: &str) -> Vec<(usize, Range<usize>)> {
+    code_spans(input).collect()
+}
+
+fn segment_assignment(input: &str) -> IResult<&str, (&str, Word)> {
+    preceded(
+        pair(tag_no_case("set"), space1),
+        separated_pair(
+            alt((
+                tag_no_case("sp"),
+                tag_no_case("local"),
+                tag_no_case("argument"),
+                tag_no_case("this"),
+                tag_no_case("that"),
+            )),
+            space1,
+            Word::parse_word,
+        ),
+    )(input)
+}
+
+// Reads the `set sp 256, set local 300, ...;` lines of course test scripts, segments that
+// aren't set keep their default. `//` comments are skipped.
+pub fn parse_segment_init(input: &str) -> Result<SegmentInit, String> {
+    let code = input
+        .lines()
+        .map(|line| line.split_once("//").map_or(line, |(code, _)| code))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let input = code.as_str();
+    let separator = || delimited(multispace0, one_of(",;"), multispace0);
+    let (_, assignments) = all_consuming(delimited(
+        multispace0,
+        terminated(
+            separated_list0(separator(), segment_assignment),
+            opt(separator()),
+        ),
+        multispace0,
+    ))(input)
+    .finish()
+    .map_err(|error| convert_error(input, error))?;
+
+    let mut segment_init = SegmentInit::default();
+    for (segment, value) in assignments {
+        match segment.to_lowercase().as_str() {
+            "sp" => segment_init.sp = value,
+            "local" => segment_init.lcl = value,
+            "argument" => segment_init.arg = value,
+            "this" => segment_init.this = value,
+            _ => segment_init.that = value,
+        }
+    }
+
+    Ok(segment_init)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -306,4 +372,44 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_command_line_numbers() {
+        let program = "// Main.vm\npush constant 1\n\n  // comment\nadd // inline\n";
+        assert_eq!(command_line_numbers(program), vec![2, 5]);
+        assert_eq!(command_spans(program), vec![(2, 11..26), (5, 41..44)]);
+    }
+
+    #[test]
+    fn test_parse_segment_init() {
+        assert_eq!(
+            parse_segment_init("set sp 256,\nset local 300, set argument 400,\nset THIS 3000;"),
+            Ok(SegmentInit {
+                sp: 256,
+                lcl: 300,
+                arg: 400,
+                this: 3000,
+                that: 0,
+            })
+        );
+        assert_eq!(
+            parse_segment_init(
+                "// Tests BasicTest.vm on the VM emulator.\n\n\
+                 set sp 256,        // stack pointer\n\
+                 set local 300,     // base address of the local segment\n\
+                 set argument 400,  // base address of the argument segment\n\
+                 set this 3000,     // base address of the this segment\n\
+                 set that 3010;     // base address of the that segment\n"
+            ),
+            Ok(SegmentInit {
+                sp: 256,
+                lcl: 300,
+                arg: 400,
+                this: 3000,
+                that: 3010,
+            })
+        );
+        assert_eq!(parse_segment_init(""), Ok(SegmentInit::default()));
+        assert!(parse_segment_init("set pc 3").is_err());
+    }
 }
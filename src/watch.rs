@@ -0,0 +1,180 @@
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{alpha1, alphanumeric1, char, space0, space1},
+    combinator::{all_consuming, map, recognize},
+    multi::many0_count,
+    sequence::{delimited, pair, preceded, separated_pair},
+    Finish,
+};
+
+use crate::expression::{is_address, parse_expression, Expression};
+use crate::hardware::Word;
+use crate::parse_utils::{IResult, ParsableWord};
+use crate::vm::{PushSegment, Register, VM};
+use crate::vm_parse::push_segment;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchTarget {
+    // Statics are the current file's.
+    Segment(PushSegment, Word),
+    Static { file_name: String, offset: Word },
+    Expression(Expression),
+}
+
+// A value shown while a VM program runs, either a segment access like `local[2]`, `this 0` or
+// `static Foo.3`, or an expression over RAM and the segment pointers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub source: String,
+    pub target: WatchTarget,
+}
+
+fn offset(input: &str) -> IResult<&str, Word> {
+    alt((
+        delimited(pair(space0, char('[')), Word::parse_word, char(']')),
+        preceded(space1, Word::parse_word),
+    ))(input)
+}
+
+fn class_name(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0_count(alt((alphanumeric1, tag("_")))),
+    ))(input)
+}
+
+fn segment_access(input: &str) -> IResult<&str, WatchTarget> {
+    alt((
+        map(
+            preceded(
+                pair(tag("static"), space1),
+                separated_pair(class_name, char('.'), Word::parse_word),
+            ),
+            |(file_name, offset)| WatchTarget::Static {
+                file_name: file_name.to_owned(),
+                offset,
+            },
+        ),
+        map(pair(push_segment, offset), |(segment, offset)| {
+            WatchTarget::Segment(segment, offset)
+        }),
+    ))(input)
+}
+
+impl Watch {
+    pub fn new(source: &str) -> Result<Self, String> {
+        let source = source.trim();
+        let target = match all_consuming(segment_access)(source).finish() {
+            Ok((_, target)) => target,
+            Err(_) => WatchTarget::Expression(parse_expression(source)?),
+        };
+        match &target {
+            WatchTarget::Segment(PushSegment::Constant, _) => {
+                return Err("constant isn't stored anywhere".to_owned())
+            }
+            WatchTarget::Segment(_, offset) | WatchTarget::Static { offset, .. } if *offset < 0 => {
+                return Err("Offsets can't be negative".to_owned())
+            }
+            WatchTarget::Segment(PushSegment::Temp, offset) if *offset > 7 => {
+                return Err("temp only goes up to 7".to_owned())
+            }
+            WatchTarget::Segment(PushSegment::Pointer, offset) if *offset > 1 => {
+                return Err("pointer only goes up to 1".to_owned())
+            }
+            _ => {}
+        }
+        Ok(Watch {
+            source: source.to_owned(),
+            target,
+        })
+    }
+
+    // Expressions and statics past those the file uses have no address.
+    pub fn address(&self, vm: &VM) -> Option<Word> {
+        let ram = &vm.run_state.ram;
+        let address = match &self.target {
+            WatchTarget::Segment(PushSegment::Static, offset) => {
+                static_address(vm, vm.run_state.current_file_index, *offset)?
+            }
+            WatchTarget::Static { file_name, offset } => {
+                static_address(vm, *vm.program.file_name_to_index.get(file_name)?, *offset)?
+            }
+            WatchTarget::Segment(segment, offset) => {
+                let base = match segment {
+                    PushSegment::Local => ram[Register::LCL],
+                    PushSegment::Argument => ram[Register::ARG],
+                    PushSegment::This => ram[Register::THIS],
+                    PushSegment::That => ram[Register::THAT],
+                    PushSegment::Temp => Register::TEMP(0).address(),
+                    PushSegment::Pointer => Register::THIS.address(),
+                    PushSegment::Constant | PushSegment::Static => return None,
+                };
+                base as i64 + *offset as i64
+            }
+            WatchTarget::Expression(_) => return None,
+        };
+        is_address(address).then_some(address as Word)
+    }
+
+    pub fn value(&self, vm: &VM) -> Option<i64> {
+        match &self.target {
+            WatchTarget::Expression(expression) => expression.evaluate(&vm.run_state),
+            _ => self
+                .address(vm)
+                .map(|address| vm.run_state.ram[address] as i64),
+        }
+    }
+}
+
+fn static_address(vm: &VM, file_index: usize, offset: Word) -> Option<i64> {
+    let static_segment = &vm.program.files.get(file_index)?.static_segment;
+    let address = *static_segment.start() as i64 + offset as i64;
+    (address <= *static_segment.end() as i64).then_some(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let mut vm = VM::from_file_contents(vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 2\npush constant 7\npop local 1\npush constant 9\n\
+                 pop static 0\npush constant 3000\npop pointer 0\npush constant 5\npop this 2\n\
+                 call Foo.set 0\npop temp 0\nlabel END\ngoto END\n"
+                    .to_owned(),
+            ),
+            (
+                "Foo.vm".to_owned(),
+                "function Foo.set 0\npush constant 11\npop static 1\npush constant 0\nreturn\n"
+                    .to_owned(),
+            ),
+        ]);
+        vm.run(100);
+
+        let value = |source: &str| Watch::new(source).unwrap().value(&vm);
+        assert_eq!(value("local[1]"), Some(7));
+        assert_eq!(value(" local 1 "), Some(7));
+        assert_eq!(value("static 0"), Some(9));
+        assert_eq!(value("static Foo.1"), Some(11));
+        assert_eq!(value("this[2]"), Some(5));
+        assert_eq!(value("pointer 0"), Some(3000));
+        assert_eq!(value("RAM[THIS + 2] * 2"), Some(10));
+        assert_eq!(value("static Foo.2"), None);
+        assert_eq!(value("static Bar.0"), None);
+        assert_eq!(Watch::new("static Foo.1").unwrap().address(&vm), Some(18));
+
+        assert_eq!(
+            Watch::new("temp 8"),
+            Err("temp only goes up to 7".to_owned())
+        );
+        assert_eq!(
+            Watch::new("local -1"),
+            Err("Offsets can't be negative".to_owned())
+        );
+        assert!(Watch::new("local[2").is_err());
+    }
+}